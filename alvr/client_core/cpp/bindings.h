@@ -32,4 +32,5 @@ extern "C" void initGraphicsNative();
 extern "C" void destroyStream();
 extern "C" void streamStartNative(FfiStreamConfig config);
 extern "C" void renderStreamNative(void *streamHardwareBuffer,
-                                   const unsigned int swapchainIndices[2]);
+                                   const unsigned int swapchainIndices[2],
+                                   const float *foveationCenterShift);
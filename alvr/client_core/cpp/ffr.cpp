@@ -6,6 +6,7 @@
 #include "utils.h"
 
 #include <glm/vec2.hpp>
+#include <glm/vec4.hpp>
 
 using namespace std;
 using namespace gl_render_utils;
@@ -17,19 +18,20 @@ const string FFR_COMMON_SHADER_FORMAT = R"glsl(#version 300 es
         const vec2 EYE_SIZE_RATIO = vec2(%f, %f);
         const vec2 EDGE_RATIO = vec2(%f, %f);
 
-        const vec2 c1 = vec2(%f, %f);
         const vec2 c2 = vec2(%f, %f);
-        const vec2 loBound = vec2(%f, %f);
-        const vec2 hiBound = vec2(%f, %f);
-        const vec2 loBoundC = vec2(%f, %f);
-        const vec2 hiBoundC = vec2(%f, %f);
 
-        const vec2 aleft = vec2(%f, %f);
-        const vec2 bleft = vec2(%f, %f);
-
-        const vec2 aright = vec2(%f, %f);
-        const vec2 bright = vec2(%f, %f);
-        const vec2 cright = vec2(%f, %f);
+        // Constants that depend on the center shift, which can change every frame.
+        // xy: left eye, zw: right eye
+        layout(std140) uniform FoveationBlock {
+            vec4 c1Eyes;
+            vec4 loBoundEyes;
+            vec4 hiBoundEyes;
+            vec4 aleftEyes;
+            vec4 bleftEyes;
+            vec4 arightEyes;
+            vec4 brightEyes;
+            vec4 crightEyes;
+        };
 
         vec2 TextureToEyeUV(vec2 textureUV, bool isRightEye) {
             // flip distortion horizontally for right eye
@@ -61,6 +63,15 @@ const string DECOMPRESS_AXIS_ALIGNED_FRAGMENT_SHADER = R"glsl(
             bool isRightEye = uv.x > 0.5;
             vec2 eyeUV = TextureToEyeUV(uv, isRightEye);
 
+            vec2 c1 = isRightEye ? c1Eyes.zw : c1Eyes.xy;
+            vec2 loBound = isRightEye ? loBoundEyes.zw : loBoundEyes.xy;
+            vec2 hiBound = isRightEye ? hiBoundEyes.zw : hiBoundEyes.xy;
+            vec2 aleft = isRightEye ? aleftEyes.zw : aleftEyes.xy;
+            vec2 bleft = isRightEye ? bleftEyes.zw : bleftEyes.xy;
+            vec2 aright = isRightEye ? arightEyes.zw : arightEyes.xy;
+            vec2 bright = isRightEye ? brightEyes.zw : brightEyes.xy;
+            vec2 cright = isRightEye ? crightEyes.zw : crightEyes.xy;
+
             // Now calculate the uncompressed UVs for the various regions of the image.
             // There's three regions to consider: the "left", the middle and the "right"
             vec2 center = (eyeUV - c1) * EDGE_RATIO / c2;
@@ -90,6 +101,71 @@ const string DECOMPRESS_AXIS_ALIGNED_FRAGMENT_SHADER = R"glsl(
             color = texture(tex0, EyeToTextureUV(uncompressedUV * EYE_SIZE_RATIO, isRightEye));
         }
     )glsl";
+
+struct FoveationBlock {
+    glm::vec4 c1;
+    glm::vec4 loBound;
+    glm::vec4 hiBound;
+    glm::vec4 aleft;
+    glm::vec4 bleft;
+    glm::vec4 aright;
+    glm::vec4 bright;
+    glm::vec4 cright;
+};
+
+// Shifts are aligned to whole blocks of the edge regions. The server aligns them in the same way
+float AlignCenterShift(float centerShift,
+                       float targetEyeSize,
+                       float centerSizeAligned,
+                       float edgeRatio) {
+    float edgeSizeAligned = targetEyeSize - centerSizeAligned * targetEyeSize;
+
+    return ceil(centerShift * edgeSizeAligned / (edgeRatio * 2.)) * (edgeRatio * 2.) /
+           edgeSizeAligned;
+}
+
+// Constants of the decompression function of one eye
+struct EyeConstants {
+    glm::vec2 c1;
+    glm::vec2 loBound;
+    glm::vec2 hiBound;
+    glm::vec2 aleft;
+    glm::vec2 bleft;
+    glm::vec2 aright;
+    glm::vec2 bright;
+    glm::vec2 cright;
+};
+
+EyeConstants CalculateEyeConstants(const FoveationVars &fv, glm::vec2 centerShift) {
+    using glm::vec2;
+
+    // Precalculate a bunch of constants that will be used in fragment shader
+    auto CENTER_SIZE = vec2(fv.centerSizeX, fv.centerSizeY); // Size of the center, non-distorted region
+    auto CENTER_SHIFT = centerShift; // How much to shift the center region
+    auto EDGE_RATIO = vec2(fv.edgeRatioX, fv.edgeRatioY); // Ratio of edge region VS center region
+
+    auto c0 = (vec2(1., 1.) - CENTER_SIZE) * vec2(0.5, 0.5);
+    auto c1 = (EDGE_RATIO - vec2(1., 1.)) * c0 * (CENTER_SHIFT + vec2(1., 1.)) / EDGE_RATIO;
+    auto c2 = (EDGE_RATIO - vec2(1., 1.)) * CENTER_SIZE + vec2(1., 1.);
+
+    auto loBound = c0 * (CENTER_SHIFT + vec2(1., 1.)); // Lower bound bellow which "left" edge begins
+    auto hiBound = c0 * (CENTER_SHIFT - vec2(1., 1.)) + vec2(1., 1.); // Upper bound above which "right" edge begins
+    auto loBoundC = c0 * (CENTER_SHIFT + vec2(1., 1.)) / c2; // Same as loBound but rescaled for distorted image
+    auto hiBoundC = c0 * (CENTER_SHIFT - vec2(1., 1.)) / c2 + vec2(1., 1.);  // Same as hiBound but rescaled for distorted image
+
+    // Constants for function:
+    //   leftEdge(x) = (-bleft + sqrt(bleft^2 + 4 * aleft * x)) / (2 * aleft)
+    auto aleft = c2 * (vec2(1., 1.) - EDGE_RATIO) / (EDGE_RATIO * loBoundC);
+    auto bleft = (c1 + c2 * loBoundC) / loBoundC;
+
+    // Constants for function:
+    //   rightEdge(x) = (-bright + sqrt(bright^2 + 4 * (cright - aright * x)) / (2 * aright)
+    auto aright = c2 * (EDGE_RATIO - vec2(1., 1.)) / (EDGE_RATIO * (vec2(1., 1.) - hiBoundC));
+    auto bright = (c2 - EDGE_RATIO * c1 - vec2(2., 2.) * EDGE_RATIO * c2 + c2 * EDGE_RATIO * (vec2(1., 1.) - hiBoundC) + EDGE_RATIO) / (EDGE_RATIO * (vec2(1., 1.) - hiBoundC));
+    auto cright = ((c2 * EDGE_RATIO - c2) * (c1 - hiBoundC + c2 * hiBoundC)) / (EDGE_RATIO * (vec2(1., 1.) - hiBoundC) * (vec2(1., 1.) - hiBoundC));
+
+    return {c1, loBound, hiBound, aleft, bleft, aright, bright, cright};
+}
 } // namespace
 
 FoveationVars CalculateFoveationVars(FFRData data) {
@@ -111,13 +187,10 @@ FoveationVars CalculateFoveationVars(FFRData data) {
     float centerSizeYAligned =
         1. - ceil(edgeSizeY / (edgeRatioY * 2.)) * (edgeRatioY * 2.) / targetEyeHeight;
 
-    float edgeSizeXAligned = targetEyeWidth - centerSizeXAligned * targetEyeWidth;
-    float edgeSizeYAligned = targetEyeHeight - centerSizeYAligned * targetEyeHeight;
-
-    float centerShiftXAligned = ceil(centerShiftX * edgeSizeXAligned / (edgeRatioX * 2.)) *
-                                (edgeRatioX * 2.) / edgeSizeXAligned;
-    float centerShiftYAligned = ceil(centerShiftY * edgeSizeYAligned / (edgeRatioY * 2.)) *
-                                (edgeRatioY * 2.) / edgeSizeYAligned;
+    float centerShiftXAligned =
+        AlignCenterShift(centerShiftX, targetEyeWidth, centerSizeXAligned, edgeRatioX);
+    float centerShiftYAligned =
+        AlignCenterShift(centerShiftY, targetEyeHeight, centerSizeYAligned, edgeRatioY);
 
     float foveationScaleX = (centerSizeXAligned + (1. - centerSizeXAligned) / edgeRatioX);
     float foveationScaleY = (centerSizeYAligned + (1. - centerSizeYAligned) / edgeRatioY);
@@ -151,46 +224,18 @@ FFR::FFR(Texture *inputSurface) : mInputSurface(inputSurface) {}
 void FFR::Initialize(FoveationVars fv) {
     using glm::vec2;
 
-    // Precalculate a bunch of constants that will be used in fragment shader
+    mFoveationVars = fv;
+
     auto CENTER_SIZE = vec2(fv.centerSizeX, fv.centerSizeY); // Size of the center, non-distorted region
-    auto CENTER_SHIFT = vec2(fv.centerShiftX, fv.centerShiftY); // How much to shift the center region
     auto EDGE_RATIO = vec2(fv.edgeRatioX, fv.edgeRatioY); // Ratio of edge region VS center region
 
-    auto c0 = (vec2(1., 1.) - CENTER_SIZE) * vec2(0.5, 0.5);
-    auto c1 = (EDGE_RATIO - vec2(1., 1.)) * c0 * (CENTER_SHIFT + vec2(1., 1.)) / EDGE_RATIO;
     auto c2 = (EDGE_RATIO - vec2(1., 1.)) * CENTER_SIZE + vec2(1., 1.);
 
-    auto loBound = c0 * (CENTER_SHIFT + vec2(1., 1.)); // Lower bound bellow which "left" edge begins
-    auto hiBound = c0 * (CENTER_SHIFT - vec2(1., 1.)) + vec2(1., 1.); // Upper bound above which "right" edge begins
-    auto loBoundC = c0 * (CENTER_SHIFT + vec2(1., 1.)) / c2; // Same as loBound but rescaled for distorted image
-    auto hiBoundC = c0 * (CENTER_SHIFT - vec2(1., 1.)) / c2 + vec2(1., 1.);  // Same as hiBound but rescaled for distorted image
-
-    // Constants for function:
-    //   leftEdge(x) = (-bleft + sqrt(bleft^2 + 4 * aleft * x)) / (2 * aleft)
-    auto aleft = c2 * (vec2(1., 1.) - EDGE_RATIO) / (EDGE_RATIO * loBoundC);
-    auto bleft = (c1 + c2 * loBoundC) / loBoundC;
-
-    // Constants for function:
-    //   rightEdge(x) = (-bright + sqrt(bright^2 + 4 * (cright - aright * x)) / (2 * aright)
-    auto aright = c2 * (EDGE_RATIO - vec2(1., 1.)) / (EDGE_RATIO * (vec2(1., 1.) - hiBoundC));
-    auto bright = (c2 - EDGE_RATIO * c1 - vec2(2., 2.) * EDGE_RATIO * c2 + c2 * EDGE_RATIO * (vec2(1., 1.) - hiBoundC) + EDGE_RATIO) / (EDGE_RATIO * (vec2(1., 1.) - hiBoundC));
-    auto cright = ((c2 * EDGE_RATIO - c2) * (c1 - hiBoundC + c2 * hiBoundC)) / (EDGE_RATIO * (vec2(1., 1.) - hiBoundC) * (vec2(1., 1.) - hiBoundC));
-
-    // Put all the constants into the shader
+    // Put the constants that do not depend on the center shift into the shader
     auto ffrCommonShaderStr = string_format(FFR_COMMON_SHADER_FORMAT,
                                             fv.eyeWidthRatio, fv.eyeHeightRatio,
                                             fv.edgeRatioX, fv.edgeRatioY,
-                                            c1.x, c1.y,
-                                            c2.x, c2.y,
-                                            loBound.x, loBound.y,
-                                            hiBound.x, hiBound.y,
-                                            loBoundC.x, loBoundC.y,
-                                            hiBoundC.x, hiBoundC.y,
-                                            aleft.x, aleft.y,
-                                            bleft.x, bleft.y,
-                                            aright.x, aright.y,
-                                            bright.x, bright.y,
-                                            cright.x, cright.y
+                                            c2.x, c2.y
                                             );
 
     mExpandedTexture.reset(new Texture(false, 0, false, fv.targetEyeWidth * 2, fv.targetEyeHeight));
@@ -199,10 +244,40 @@ void FFR::Initialize(FoveationVars fv) {
     auto decompressAxisAlignedShaderStr =
         ffrCommonShaderStr + DECOMPRESS_AXIS_ALIGNED_FRAGMENT_SHADER;
     mDecompressAxisAlignedPipeline = unique_ptr<RenderPipeline>(
-        new RenderPipeline({mInputSurface}, QUAD_2D_VERTEX_SHADER, decompressAxisAlignedShaderStr));
+        new RenderPipeline({mInputSurface}, QUAD_2D_VERTEX_SHADER, decompressAxisAlignedShaderStr,
+                           sizeof(FoveationBlock)));
 }
 
-void FFR::Render() const {
+void FFR::Render(const float *centerShift) const {
+    using glm::vec2;
+    using glm::vec4;
+
+    const auto &fv = mFoveationVars;
+
+    // If there is no center shift for this frame, the one of the settings is used for both eyes
+    vec2 eyeShifts[2] = {vec2(fv.centerShiftX, fv.centerShiftY),
+                         vec2(fv.centerShiftX, fv.centerShiftY)};
+    if (centerShift != nullptr) {
+        for (int eye = 0; eye < 2; eye++) {
+            eyeShifts[eye] = vec2(
+                AlignCenterShift(
+                    centerShift[eye * 2], fv.targetEyeWidth, fv.centerSizeX, fv.edgeRatioX),
+                AlignCenterShift(
+                    centerShift[eye * 2 + 1], fv.targetEyeHeight, fv.centerSizeY, fv.edgeRatioY));
+        }
+    }
+
+    auto left = CalculateEyeConstants(fv, eyeShifts[0]);
+    auto right = CalculateEyeConstants(fv, eyeShifts[1]);
+    FoveationBlock block = {vec4(left.c1, right.c1),
+                            vec4(left.loBound, right.loBound),
+                            vec4(left.hiBound, right.hiBound),
+                            vec4(left.aleft, right.aleft),
+                            vec4(left.bleft, right.bleft),
+                            vec4(left.aright, right.aright),
+                            vec4(left.bright, right.bright),
+                            vec4(left.cright, right.cright)};
+
     mExpandedTextureState->ClearDepth();
-    mDecompressAxisAlignedPipeline->Render(*mExpandedTextureState);
+    mDecompressAxisAlignedPipeline->Render(*mExpandedTextureState, &block);
 }
@@ -41,12 +41,14 @@ public:
 
     void Initialize(FoveationVars fv);
 
-    void Render() const;
+    // centerShift: left and right eye X and Y shifts of the frame, or null to use the settings
+    void Render(const float *centerShift) const;
 
     gl_render_utils::Texture *GetOutputTexture() { return mExpandedTexture.get(); }
 
 private:
 
+    FoveationVars mFoveationVars;
     gl_render_utils::Texture *mInputSurface;
     std::unique_ptr<gl_render_utils::Texture> mExpandedTexture;
     std::unique_ptr<gl_render_utils::RenderState> mExpandedTextureState;
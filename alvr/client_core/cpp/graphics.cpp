@@ -425,7 +425,11 @@ void streamStartNative(FfiStreamConfig config) {
     );
 }
 
-void renderStreamNative(void* streamHardwareBuffer, const unsigned int swapchainIndices[2]) {
+void renderStreamNative(
+    void* streamHardwareBuffer,
+    const unsigned int swapchainIndices[2],
+    const float* foveationCenterShift
+) {
     auto renderer = g_ctx.streamRenderer.get();
 
     if (streamHardwareBuffer != 0) {
@@ -440,7 +444,7 @@ void renderStreamNative(void* streamHardwareBuffer, const unsigned int swapchain
 
         renderer->srgbCorrectionPass->Render();
         if (renderer->enableFFE) {
-            renderer->ffr->Render();
+            renderer->ffr->Render(foveationCenterShift);
         }
 
         GL(eglDestroyImageKHR(g_ctx.eglDisplay, image));
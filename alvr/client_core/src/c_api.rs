@@ -655,6 +655,28 @@ pub unsafe extern "C" fn alvr_report_compositor_start(
     }
 }
 
+/// out_center_shift must be a vector of 4 elements: left X, left Y, right X, right Y.
+/// Returns false if the frame uses the center shift of the settings, and out_center_shift is not
+/// populated
+#[no_mangle]
+pub unsafe extern "C" fn alvr_get_foveation_center_shift(
+    target_timestamp_ns: u64,
+    out_center_shift: *mut f32,
+) -> bool {
+    if let Some([left, right]) = CLIENT_CORE_CONTEXT.lock().as_ref().and_then(|context| {
+        context.get_foveation_center_shift(Duration::from_nanos(target_timestamp_ns))
+    }) {
+        *out_center_shift = left.x;
+        *out_center_shift.offset(1) = left.y;
+        *out_center_shift.offset(2) = right.x;
+        *out_center_shift.offset(3) = right.y;
+
+        true
+    } else {
+        false
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn alvr_report_submit(target_timestamp_ns: u64, vsync_queue_ns: u64) {
     if let Some(context) = &*CLIENT_CORE_CONTEXT.lock() {
@@ -769,6 +791,7 @@ pub unsafe extern "C" fn alvr_start_stream_opengl(config: AlvrStreamConfig) {
         center_shift_y: config.foveation_center_shift_y,
         edge_ratio_x: config.foveation_edge_ratio_x,
         edge_ratio_y: config.foveation_edge_ratio_y,
+        follow_eye_gaze: false,
     });
 
     STREAM_RENDERER.set(Some(StreamRenderer::new(
@@ -806,16 +829,36 @@ pub unsafe extern "C" fn alvr_render_lobby_opengl(view_inputs: *const AlvrViewIn
     });
 }
 
+/// Uses the center shift of the stream config. Use alvr_render_stream_opengl_foveated() to pass the
+/// center shift of each frame.
 #[no_mangle]
 pub unsafe extern "C" fn alvr_render_stream_opengl(
     hardware_buffer: *mut c_void,
     swapchain_indices: *const u32,
 ) {
+    alvr_render_stream_opengl_foveated(hardware_buffer, swapchain_indices, ptr::null());
+}
+
+/// foveation_center_shift must be null or a vector of 4 elements: left X, left Y, right X, right Y.
+/// If null, the center shift of the stream config is used. It can be obtained with
+/// alvr_get_foveation_center_shift().
+#[no_mangle]
+pub unsafe extern "C" fn alvr_render_stream_opengl_foveated(
+    hardware_buffer: *mut c_void,
+    swapchain_indices: *const u32,
+    foveation_center_shift: *const f32,
+) {
+    let foveation_center_shift = (!foveation_center_shift.is_null()).then(|| {
+        let shift = slice::from_raw_parts(foveation_center_shift, 4);
+        [Vec2::new(shift[0], shift[1]), Vec2::new(shift[2], shift[3])]
+    });
+
     STREAM_RENDERER.with_borrow(|renderer| {
         if let Some(renderer) = renderer {
             renderer.render(
                 hardware_buffer,
                 [*swapchain_indices, *swapchain_indices.offset(1)],
+                foveation_center_shift,
             );
         }
     });
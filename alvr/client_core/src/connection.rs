@@ -2,6 +2,7 @@
 
 use crate::{
     logging_backend::{LogMirrorData, LOG_CHANNEL_SENDER},
+    motion_filter::DeviceMotionFilter,
     platform,
     sockets::AnnouncerSocket,
     statistics::StatisticsManager,
@@ -10,23 +11,27 @@ use crate::{
 };
 use alvr_audio::AudioDevice;
 use alvr_common::{
-    dbg_connection, debug, error, info,
+    dbg_connection, debug, error,
+    glam::Vec2,
+    hash_string, info,
     parking_lot::{Condvar, Mutex, RwLock},
     wait_rwlock, warn, AnyhowToCon, ConResult, ConnectionError, ConnectionState, LifecycleState,
-    Pose, RelaxedAtomic, ALVR_VERSION,
+    Pose, RelaxedAtomic, ALVR_VERSION, HAND_LEFT_ID, HAND_RIGHT_ID,
 };
 use alvr_packets::{
-    ClientConnectionResult, ClientControlPacket, ClientStatistics, Haptics, ServerControlPacket,
-    StreamConfigPacket, Tracking, VideoPacketHeader, VideoStreamingCapabilities, ViewParams, AUDIO,
-    HAPTICS, STATISTICS, TRACKING, VIDEO,
+    ClientConnectionResult, ClientControlPacket, ClientKeyExchange, ClientStatistics, Haptics,
+    ServerControlPacket, ServerKeyConfirmation, ServerKeyExchange, StreamConfigPacket, Tracking,
+    VideoPacketHeader, VideoStreamingCapabilities, ViewParams, AUDIO, HAPTICS, STATISTICS,
+    TRACKING, VIDEO,
 };
-use alvr_session::settings_schema::Switch;
+use alvr_session::{settings_schema::Switch, SocketProtocol};
 use alvr_sockets::{
-    ControlSocketSender, PeerType, ProtoControlSocket, StreamSender, StreamSocketBuilder,
-    KEEPALIVE_INTERVAL, KEEPALIVE_TIMEOUT,
+    ControlSocketSender, HandshakeRole, KeyExchange, LongTermSecret, PeerType, ProtoControlSocket,
+    StreamReceiveStatistics, StreamSender, StreamSocketBuilder, KEEPALIVE_INTERVAL,
+    KEEPALIVE_TIMEOUT,
 };
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     sync::{mpsc, Arc},
     thread,
     time::{Duration, Instant},
@@ -47,6 +52,21 @@ const STREAM_STARTING_MESSAGE: &str = "The stream will begin soon\nPlease wait..
 const SERVER_RESTART_MESSAGE: &str = "The streamer is restarting\nPlease wait...";
 const SERVER_DISCONNECTED_MESSAGE: &str = "The streamer has disconnected.";
 const CONNECTION_TIMEOUT_MESSAGE: &str = "Connection timeout.";
+const PAIRING_REQUIRED_MESSAGE: &str = concat!(
+    "This device is not paired!\n",
+    "Enter the PIN shown above in the ALVR dashboard\n",
+    "using the \"Pair\" button next to the device entry",
+);
+const NOT_PAIRED_MESSAGE: &str = concat!(
+    "The streamer expected this device to be paired.\n",
+    "Pair it again using the PIN shown above",
+);
+const UNENCRYPTED_REFUSED_MESSAGE: &str = concat!(
+    "This device is paired but the streamer\n",
+    "requested an unencrypted connection.\n",
+    "Pair it again using the PIN shown above",
+);
+const AUTHENTICATION_FAILED_MESSAGE: &str = "The streamer failed authentication.";
 
 const SOCKET_INIT_RETRY_INTERVAL: Duration = Duration::from_millis(500);
 const CONNECTION_RETRY_INTERVAL: Duration = Duration::from_secs(1);
@@ -63,19 +83,24 @@ pub struct ConnectionContext {
     pub tracking_sender: Mutex<Option<StreamSender<Tracking>>>,
     pub statistics_sender: Mutex<Option<StreamSender<ClientStatistics>>>,
     pub statistics_manager: Mutex<Option<StatisticsManager>>,
+    pub stream_receive_statistics: Mutex<Option<Arc<Mutex<StreamReceiveStatistics>>>>,
     pub decoder_callback: Mutex<Option<Box<dyn FnMut(Duration, &[u8]) -> bool + Send>>>,
     pub head_pose_queue: RwLock<VecDeque<(Duration, Pose)>>,
+    pub motion_filters: Mutex<HashMap<u64, DeviceMotionFilter>>,
+    pub foveation_center_shift_queue: RwLock<VecDeque<(Duration, [Vec2; 2])>>,
     pub last_good_head_pose: RwLock<Pose>,
     pub view_params: RwLock<[ViewParams; 2]>,
     pub uses_multimodal_protocol: RelaxedAtomic,
 }
 
 fn set_hud_message(event_queue: &Mutex<VecDeque<ClientCoreEvent>>, message: &str) {
+    let config = Config::load();
     let message = format!(
-        "ALVR v{}\nhostname: {}\nIP: {}\n\n{message}",
+        "ALVR v{}\nhostname: {}\nIP: {}\nPairing PIN: {}\n\n{message}",
         *ALVR_VERSION,
-        Config::load().hostname,
+        config.hostname,
         platform::local_ip(),
+        config.pairing_pin,
     );
 
     event_queue
@@ -170,24 +195,109 @@ fn connection_pipeline(
             display_name: platform::platform().to_string(),
             server_ip,
             streaming_capabilities: Some(
-                alvr_packets::encode_video_streaming_capabilities(&VideoStreamingCapabilities {
-                    default_view_resolution: capabilities.default_view_resolution,
-                    supported_refresh_rates: capabilities.refresh_rates,
-                    microphone_sample_rate,
-                    supports_foveated_encoding: capabilities.foveated_encoding,
-                    encoder_high_profile: capabilities.encoder_high_profile,
-                    encoder_10_bits: capabilities.encoder_10_bits,
-                    encoder_av1: capabilities.encoder_av1,
-                    multimodal_protocol: true,
-                    prefer_10bit: capabilities.prefer_10bit,
-                    prefer_full_range: capabilities.prefer_full_range,
-                    preferred_encoding_gamma: capabilities.preferred_encoding_gamma,
-                    prefer_hdr: capabilities.prefer_hdr,
-                })
+                alvr_packets::encode_legacy_video_streaming_capabilities(
+                    &VideoStreamingCapabilities {
+                        default_view_resolution: capabilities.default_view_resolution,
+                        supported_refresh_rates: capabilities.refresh_rates,
+                        microphone_sample_rate,
+                        supports_foveated_encoding: capabilities.foveated_encoding,
+                        encoder_high_profile: capabilities.encoder_high_profile,
+                        encoder_10_bits: capabilities.encoder_10_bits,
+                        encoder_av1: capabilities.encoder_av1,
+                        multimodal_protocol: true,
+                        prefer_10bit: capabilities.prefer_10bit,
+                        prefer_full_range: capabilities.prefer_full_range,
+                        preferred_encoding_gamma: capabilities.preferred_encoding_gamma,
+                        prefer_hdr: capabilities.prefer_hdr,
+                    },
+                )
                 .to_con()?,
             ),
         })
         .to_con()?;
+
+    dbg_connection!("connection_pipeline: Key exchange");
+    let mut config = Config::load();
+    let pairing_key = config
+        .pairing_key
+        .as_deref()
+        .map(alvr_sockets::key_from_string)
+        .transpose()
+        .to_con()?;
+
+    let key_exchange = KeyExchange::new().to_con()?;
+    let (server_public_key, secret) =
+        match proto_control_socket.recv::<ServerKeyExchange>(HANDSHAKE_ACTION_TIMEOUT)? {
+            ServerKeyExchange::Unencrypted => {
+                // Do not allow downgrading the connection once paired
+                if pairing_key.is_some() {
+                    set_hud_message(&event_queue, UNENCRYPTED_REFUSED_MESSAGE);
+                    return Ok(());
+                }
+
+                (None, None)
+            }
+            ServerKeyExchange::PairingRequired => {
+                set_hud_message(&event_queue, PAIRING_REQUIRED_MESSAGE);
+                return Ok(());
+            }
+            ServerKeyExchange::Pairing { public_key } => (
+                Some(public_key),
+                Some(LongTermSecret::PairingPin(&config.pairing_pin)),
+            ),
+            ServerKeyExchange::Paired { public_key } => {
+                if let Some(key) = &pairing_key {
+                    (Some(public_key), Some(LongTermSecret::PairingKey(key)))
+                } else {
+                    proto_control_socket
+                        .send(&ClientKeyExchange::NotPaired)
+                        .to_con()?;
+                    set_hud_message(&event_queue, NOT_PAIRED_MESSAGE);
+                    return Ok(());
+                }
+            }
+        };
+
+    let session_keys = if let (Some(server_public_key), Some(secret)) = (server_public_key, secret)
+    {
+        let is_pairing = matches!(secret, LongTermSecret::PairingPin(_));
+        let public_key = key_exchange.public_key().to_vec();
+        let keys = key_exchange
+            .finish(HandshakeRole::Client, &server_public_key, secret)
+            .to_con()?;
+
+        proto_control_socket
+            .send(&ClientKeyExchange::Accepted {
+                public_key,
+                confirmation: keys.confirmation(),
+            })
+            .to_con()?;
+
+        let server_confirmation =
+            proto_control_socket.recv::<ServerKeyConfirmation>(HANDSHAKE_ACTION_TIMEOUT)?;
+        if keys
+            .verify_peer_confirmation(&server_confirmation.confirmation)
+            .is_err()
+        {
+            set_hud_message(&event_queue, AUTHENTICATION_FAILED_MESSAGE);
+            return Ok(());
+        }
+
+        proto_control_socket.enable_encryption(&keys.control);
+
+        if is_pairing {
+            info!("Paired with the streamer");
+
+            config.pairing_key = Some(alvr_sockets::key_to_string(&keys.pairing_key));
+            config.pairing_pin = alvr_sockets::generate_pairing_pin();
+            config.store();
+        }
+
+        Some(keys)
+    } else {
+        None
+    };
+
     let config_packet =
         proto_control_socket.recv::<StreamConfigPacket>(HANDSHAKE_ACTION_TIMEOUT)?;
     dbg_connection!("connection_pipeline: stream config received");
@@ -202,6 +312,25 @@ fn connection_pipeline(
     let settings = stream_config.settings;
     let negotiated_config = stream_config.negotiated_config;
 
+    {
+        let mut motion_filters = ctx.motion_filters.lock();
+        motion_filters.clear();
+
+        if let Switch::Enabled(controllers) = &settings.headset.controllers {
+            if let Switch::Enabled(config) = &controllers.motion_filter {
+                for id in [*HAND_LEFT_ID, *HAND_RIGHT_ID] {
+                    motion_filters.insert(id, DeviceMotionFilter::new(config.clone()));
+                }
+            }
+        }
+
+        for (path, device_config) in &settings.headset.device_motions {
+            if let Switch::Enabled(config) = &device_config.motion_filter {
+                motion_filters.insert(hash_string(path), DeviceMotionFilter::new(config.clone()));
+            }
+        }
+    }
+
     *ctx.statistics_manager.lock() = Some(StatisticsManager::new(
         settings.connection.statistics_history_size,
         Duration::from_secs_f32(1.0 / negotiated_config.refresh_rate_hint),
@@ -212,6 +341,9 @@ fn connection_pipeline(
         },
     ));
 
+    // The center shifts of the previous stream refer to timestamps of another server session
+    ctx.foveation_center_shift_queue.write().clear();
+
     let (mut control_sender, mut control_receiver) = proto_control_socket
         .split(STREAMING_RECV_TIMEOUT)
         .to_con()?;
@@ -262,8 +394,31 @@ fn connection_pipeline(
         settings.connection.stream_port,
         settings.connection.packet_size as _,
         HANDSHAKE_ACTION_TIMEOUT,
+        session_keys.as_ref().map(|keys| &keys.stream),
+        settings.connection.network_emulation.as_option(),
     )?;
 
+    if let (SocketProtocol::Udp, Switch::Enabled(config)) = (
+        settings.connection.stream_protocol,
+        &settings.connection.packet_retransmission,
+    ) {
+        for stream_id in alvr_packets::retransmitted_streams(config) {
+            stream_socket.enable_retransmission(stream_id, config.window_size);
+        }
+    }
+
+    stream_socket.set_reliable_streams(&alvr_packets::reliable_streams());
+
+    let receive_limits = &settings.connection.stream_receive_limits;
+    for (stream_id, budget) in alvr_packets::stream_memory_budgets(receive_limits) {
+        stream_socket.set_receive_limits(
+            stream_id,
+            budget,
+            Duration::from_millis(receive_limits.stale_packet_timeout_ms),
+        );
+    }
+    *ctx.stream_receive_statistics.lock() = Some(stream_socket.receive_statistics());
+
     info!("Connected to server");
 
     let mut video_receiver =
@@ -292,6 +447,14 @@ fn connection_pipeline(
                     stats.report_video_packet_received(header.timestamp);
                 }
 
+                if let Some(center_shift) = header.foveation_center_shift {
+                    let mut queue = ctx.foveation_center_shift_queue.write();
+                    queue.push_back((header.timestamp, center_shift));
+                    while queue.len() > 1024 {
+                        queue.pop_front();
+                    }
+                }
+
                 if header.is_idr {
                     stream_corrupted = false;
                 } else if data.had_packet_loss() {
@@ -545,6 +708,7 @@ fn connection_pipeline(
     *ctx.control_sender.lock() = None;
     *ctx.tracking_sender.lock() = None;
     *ctx.statistics_sender.lock() = None;
+    *ctx.stream_receive_statistics.lock() = None;
     *LOG_CHANNEL_SENDER.lock() = None;
 
     event_queue
@@ -1,7 +1,7 @@
 use super::{staging::StagingRenderer, GraphicsContext};
-use alvr_common::glam::UVec2;
+use alvr_common::glam::{UVec2, Vec2};
 use alvr_session::FoveatedEncodingConfig;
-use std::{ffi::c_void, iter, rc::Rc};
+use std::{ffi::c_void, iter, ptr, rc::Rc};
 use wgpu::{
     hal::{api, gles},
     include_wgsl, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
@@ -221,8 +221,15 @@ impl StreamRenderer {
         }
     }
 
+    // foveation_center_shift is the center shift of the foveated encoding of this frame, for the
+    // left and right eye. If None, the center shift of the settings is used
     #[allow(unused_variables)]
-    pub unsafe fn render(&self, hardware_buffer: *mut c_void, swapchain_indices: [u32; 2]) {
+    pub unsafe fn render(
+        &self,
+        hardware_buffer: *mut c_void,
+        swapchain_indices: [u32; 2],
+        foveation_center_shift: Option<[Vec2; 2]>,
+    ) {
         if let Some(render_objects) = &self.render_objects {
             // if hardware_buffer is available copy stream to staging texture
             if !hardware_buffer.is_null() {
@@ -263,7 +270,19 @@ impl StreamRenderer {
             self.context.make_current();
 
             #[cfg(all(target_os = "android", feature = "use-cpp"))]
-            super::opengl::renderStreamNative(hardware_buffer, swapchain_indices.as_ptr());
+            {
+                let center_shift =
+                    foveation_center_shift.map(|[left, right]| [left.x, left.y, right.x, right.y]);
+
+                super::opengl::renderStreamNative(
+                    hardware_buffer,
+                    swapchain_indices.as_ptr(),
+                    center_shift
+                        .as_ref()
+                        .map(|shift| shift.as_ptr())
+                        .unwrap_or(ptr::null()),
+                );
+            }
         }
     }
 }
@@ -8,6 +8,7 @@
 mod c_api;
 mod connection;
 mod logging_backend;
+mod motion_filter;
 mod platform;
 mod sockets;
 mod statistics;
@@ -35,6 +36,7 @@ use connection::ConnectionContext;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashSet, VecDeque},
+    mem,
     sync::Arc,
     thread::{self, JoinHandle},
     time::Duration,
@@ -270,7 +272,16 @@ impl ClientCoreContext {
             } else if let Some(stats) = &*self.connection_context.statistics_manager.lock() {
                 let tracker_timestamp = poll_timestamp + stats.tracker_prediction_offset();
 
-                *motion = predict_motion(tracker_timestamp, poll_timestamp, *motion);
+                // Filtered devices are smoothed and extrapolated using the full state of the filter
+                *motion = if let Some(filter) =
+                    self.connection_context.motion_filters.lock().get_mut(id)
+                {
+                    let filtered_motion = filter.process(poll_timestamp, *motion);
+
+                    filter.predict(tracker_timestamp).unwrap_or(filtered_motion)
+                } else {
+                    predict_motion(tracker_timestamp, poll_timestamp, *motion)
+                };
             }
         }
 
@@ -345,6 +356,19 @@ impl ClientCoreContext {
         *self.connection_context.state.write() = ConnectionState::Disconnecting;
     }
 
+    // Center shift of the foveated encoding of the frame, for the left and right eye. If None, the
+    // center shift of the settings is used
+    pub fn get_foveation_center_shift(&self, timestamp: Duration) -> Option<[Vec2; 2]> {
+        dbg_client_core!("get_foveation_center_shift");
+
+        self.connection_context
+            .foveation_center_shift_queue
+            .read()
+            .iter()
+            .find(|(ts, _)| *ts == timestamp)
+            .map(|(_, center_shift)| *center_shift)
+    }
+
     pub fn report_compositor_start(&self, timestamp: Duration) -> [ViewParams; 2] {
         dbg_client_core!("report_compositor_start");
 
@@ -381,7 +405,15 @@ impl ClientCoreContext {
             stats.report_submit(timestamp, vsync_queue);
 
             if let Some(sender) = &mut *self.connection_context.statistics_sender.lock() {
-                if let Some(stats) = stats.summary(timestamp) {
+                if let Some(mut stats) = stats.summary(timestamp) {
+                    if let Some(receive_stats) =
+                        &*self.connection_context.stream_receive_statistics.lock()
+                    {
+                        let receive_stats = mem::take(&mut *receive_stats.lock());
+                        stats.receive_dropped_packets = receive_stats.dropped_packets as _;
+                        stats.receive_evicted_packets = receive_stats.evicted_packets as _;
+                    }
+
                     sender.send_header(&stats).ok();
                 } else {
                     warn!("Statistics summary not ready!");
@@ -0,0 +1,547 @@
+// Motion filters smooth the polled poses and estimate the velocities used for prediction. The
+// velocities are filtered together with the poses, otherwise the prediction would extrapolate the
+// jitter.
+//
+// Angular velocities are in the same reference space as the poses (not local to the device).
+
+use alvr_common::{
+    glam::{Quat, Vec3},
+    DeviceMotion, Pose,
+};
+use alvr_session::MotionFilterConfig;
+use std::{f32::consts::PI, time::Duration};
+
+// Samples further apart than this are not considered continuous and the filter is reset
+const MAX_SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+// The velocity reported by the devices is noisier than the position, proportionally to the sample
+// rate
+const VELOCITY_NOISE_MULTIPLIER: f32 = 10.0;
+
+pub trait MotionFilter: Send {
+    // Returns the filtered motion
+    fn filter(&mut self, delta_time_s: f32, motion: DeviceMotion) -> DeviceMotion;
+
+    // Extrapolates the last filtered motion
+    fn predict(&self, delta_time_s: f32) -> DeviceMotion;
+}
+
+fn create_filter(config: &MotionFilterConfig, motion: DeviceMotion) -> Box<dyn MotionFilter> {
+    match config {
+        MotionFilterConfig::OneEuro {
+            min_cutoff_hz,
+            beta,
+            derivative_cutoff_hz,
+        } => Box::new(OneEuroFilter {
+            min_cutoff_hz: *min_cutoff_hz,
+            beta: *beta,
+            derivative_cutoff_hz: *derivative_cutoff_hz,
+            motion,
+        }),
+        MotionFilterConfig::DoubleExponential {
+            smoothing,
+            trend_smoothing,
+        } => Box::new(DoubleExponentialFilter {
+            smoothing: *smoothing,
+            trend_smoothing: *trend_smoothing,
+            motion,
+        }),
+        MotionFilterConfig::Kalman {
+            process_noise,
+            position_noise_mm,
+            orientation_noise_deg,
+        } => Box::new(KalmanFilter::new(
+            *process_noise,
+            position_noise_mm / 1000.0,
+            orientation_noise_deg * PI / 180.0,
+            motion,
+        )),
+    }
+}
+
+// Constant velocity extrapolation
+fn extrapolate(motion: &DeviceMotion, delta_time_s: f32) -> DeviceMotion {
+    DeviceMotion {
+        pose: Pose {
+            orientation: Quat::from_scaled_axis(motion.angular_velocity * delta_time_s)
+                * motion.pose.orientation,
+            position: motion.pose.position + motion.linear_velocity * delta_time_s,
+        },
+        ..*motion
+    }
+}
+
+// Shortest rotation vector
+fn rotation_vector(rotation: Quat) -> Vec3 {
+    if rotation.w < 0.0 {
+        (-rotation).to_scaled_axis()
+    } else {
+        rotation.to_scaled_axis()
+    }
+}
+
+// Filter state of a single device
+pub struct DeviceMotionFilter {
+    config: MotionFilterConfig,
+    filter: Option<Box<dyn MotionFilter>>,
+    last_timestamp: Duration,
+}
+
+impl DeviceMotionFilter {
+    pub fn new(config: MotionFilterConfig) -> Self {
+        Self {
+            config,
+            filter: None,
+            last_timestamp: Duration::ZERO,
+        }
+    }
+
+    // Samples older than the last one are returned unfiltered
+    pub fn process(&mut self, timestamp: Duration, motion: DeviceMotion) -> DeviceMotion {
+        match &mut self.filter {
+            Some(filter)
+                if timestamp >= self.last_timestamp
+                    && timestamp - self.last_timestamp <= MAX_SAMPLE_INTERVAL =>
+            {
+                let delta_time_s = (timestamp - self.last_timestamp).as_secs_f32();
+                self.last_timestamp = timestamp;
+
+                filter.filter(delta_time_s, motion)
+            }
+            Some(_) if timestamp < self.last_timestamp => motion,
+            _ => {
+                self.filter = Some(create_filter(&self.config, motion));
+                self.last_timestamp = timestamp;
+
+                motion
+            }
+        }
+    }
+
+    pub fn predict(&self, timestamp: Duration) -> Option<DeviceMotion> {
+        let delta_time_s = if timestamp >= self.last_timestamp {
+            (timestamp - self.last_timestamp).as_secs_f32()
+        } else {
+            -(self.last_timestamp - timestamp).as_secs_f32()
+        };
+
+        self.filter
+            .as_ref()
+            .map(|filter| filter.predict(delta_time_s))
+    }
+}
+
+// https://gery.casiez.net/1euro/
+struct OneEuroFilter {
+    min_cutoff_hz: f32,
+    beta: f32,
+    derivative_cutoff_hz: f32,
+    motion: DeviceMotion,
+}
+
+impl OneEuroFilter {
+    fn smoothing_factor(cutoff_hz: f32, delta_time_s: f32) -> f32 {
+        let time_constant = 1.0 / (2.0 * PI * cutoff_hz);
+
+        delta_time_s / (delta_time_s + time_constant)
+    }
+}
+
+impl MotionFilter for OneEuroFilter {
+    fn filter(&mut self, delta_time_s: f32, motion: DeviceMotion) -> DeviceMotion {
+        // The velocities reported by the device are used as the derivative
+        let derivative_factor = Self::smoothing_factor(self.derivative_cutoff_hz, delta_time_s);
+        let linear_velocity = self
+            .motion
+            .linear_velocity
+            .lerp(motion.linear_velocity, derivative_factor);
+        let angular_velocity = self
+            .motion
+            .angular_velocity
+            .lerp(motion.angular_velocity, derivative_factor);
+
+        let position_cutoff_hz = self.min_cutoff_hz + self.beta * linear_velocity.length();
+        let orientation_cutoff_hz = self.min_cutoff_hz + self.beta * angular_velocity.length();
+
+        self.motion = DeviceMotion {
+            pose: Pose {
+                orientation: self.motion.pose.orientation.slerp(
+                    motion.pose.orientation,
+                    Self::smoothing_factor(orientation_cutoff_hz, delta_time_s),
+                ),
+                position: self.motion.pose.position.lerp(
+                    motion.pose.position,
+                    Self::smoothing_factor(position_cutoff_hz, delta_time_s),
+                ),
+            },
+            linear_velocity,
+            angular_velocity,
+        };
+
+        self.motion
+    }
+
+    fn predict(&self, delta_time_s: f32) -> DeviceMotion {
+        extrapolate(&self.motion, delta_time_s)
+    }
+}
+
+// Holt's linear trend method. The trend is stored as the velocity
+struct DoubleExponentialFilter {
+    smoothing: f32,
+    trend_smoothing: f32,
+    motion: DeviceMotion,
+}
+
+impl MotionFilter for DoubleExponentialFilter {
+    fn filter(&mut self, delta_time_s: f32, motion: DeviceMotion) -> DeviceMotion {
+        let predicted = extrapolate(&self.motion, delta_time_s);
+
+        let position = predicted
+            .pose
+            .position
+            .lerp(motion.pose.position, self.smoothing);
+        let orientation = predicted
+            .pose
+            .orientation
+            .slerp(motion.pose.orientation, self.smoothing);
+
+        if delta_time_s > 0.0 {
+            let linear_velocity = (position - self.motion.pose.position) / delta_time_s;
+            let angular_velocity =
+                rotation_vector(orientation * self.motion.pose.orientation.inverse())
+                    / delta_time_s;
+
+            self.motion.linear_velocity = self
+                .motion
+                .linear_velocity
+                .lerp(linear_velocity, self.trend_smoothing);
+            self.motion.angular_velocity = self
+                .motion
+                .angular_velocity
+                .lerp(angular_velocity, self.trend_smoothing);
+        }
+        self.motion.pose = Pose {
+            orientation,
+            position,
+        };
+
+        self.motion
+    }
+
+    fn predict(&self, delta_time_s: f32) -> DeviceMotion {
+        extrapolate(&self.motion, delta_time_s)
+    }
+}
+
+// Row major
+type Mat3 = [[f32; 3]; 3];
+
+fn mat_mul(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+
+    out
+}
+
+fn mat_transpose(a: &Mat3) -> Mat3 {
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = a[j][i];
+        }
+    }
+
+    out
+}
+
+// Kalman filter of a single axis with state (value, velocity, acceleration). Both the value and
+// the velocity are measured.
+#[derive(Clone, Copy)]
+struct AxisKalman {
+    state: [f32; 3],
+    covariance: Mat3,
+}
+
+impl AxisKalman {
+    fn new(value: f32, velocity: f32) -> Self {
+        Self {
+            state: [value, velocity, 0.0],
+            covariance: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    fn predicted_state(&self, dt: f32) -> [f32; 3] {
+        let [x, v, a] = self.state;
+
+        [x + v * dt + 0.5 * a * dt * dt, v + a * dt, a]
+    }
+
+    // process_noise is the spectral density of the jerk
+    fn predict(&mut self, dt: f32, process_noise: f32) {
+        let transition = [[1.0, dt, 0.5 * dt * dt], [0.0, 1.0, dt], [0.0, 0.0, 1.0]];
+
+        let dt2 = dt * dt;
+        let dt3 = dt2 * dt;
+        let noise = [
+            [dt3 * dt2 / 20.0, dt2 * dt2 / 8.0, dt3 / 6.0],
+            [dt2 * dt2 / 8.0, dt3 / 3.0, dt2 / 2.0],
+            [dt3 / 6.0, dt2 / 2.0, dt],
+        ];
+
+        self.state = self.predicted_state(dt);
+
+        let mut covariance = mat_mul(
+            &mat_mul(&transition, &self.covariance),
+            &mat_transpose(&transition),
+        );
+        for (row, noise_row) in covariance.iter_mut().zip(noise) {
+            for (value, noise) in row.iter_mut().zip(noise_row) {
+                *value += noise * process_noise;
+            }
+        }
+        self.covariance = covariance;
+    }
+
+    fn update(&mut self, value: f32, velocity: f32, value_variance: f32, velocity_variance: f32) {
+        let p = &self.covariance;
+
+        // Innovation covariance (2x2) and its inverse
+        let s00 = p[0][0] + value_variance;
+        let s01 = p[0][1];
+        let s10 = p[1][0];
+        let s11 = p[1][1] + velocity_variance;
+        let det = s00 * s11 - s01 * s10;
+        if det.abs() < f32::EPSILON {
+            return;
+        }
+        let inv = [[s11 / det, -s01 / det], [-s10 / det, s00 / det]];
+
+        // Gain (3x2)
+        let mut gain = [[0.0; 2]; 3];
+        for (i, row) in gain.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = p[i][0] * inv[0][j] + p[i][1] * inv[1][j];
+            }
+        }
+
+        let residual = [value - self.state[0], velocity - self.state[1]];
+        for (i, row) in gain.iter().enumerate() {
+            self.state[i] += row[0] * residual[0] + row[1] * residual[1];
+        }
+
+        // (I - KH) P
+        let mut covariance = [[0.0; 3]; 3];
+        for (i, row) in covariance.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = p[i][j] - gain[i][0] * p[0][j] - gain[i][1] * p[1][j];
+            }
+        }
+        self.covariance = covariance;
+    }
+}
+
+// The orientation is filtered as a rotation vector relative to the previous estimate, which is
+// accurate for the small rotations between samples
+struct KalmanFilter {
+    process_noise: f32,
+    position_variance: f32,
+    orientation_variance: f32,
+    position_axes: [AxisKalman; 3],
+    rotation_axes: [AxisKalman; 3],
+    orientation: Quat,
+}
+
+impl KalmanFilter {
+    fn new(
+        process_noise: f32,
+        position_noise_m: f32,
+        orientation_noise_rad: f32,
+        motion: DeviceMotion,
+    ) -> Self {
+        let position = motion.pose.position;
+        let linear_velocity = motion.linear_velocity;
+        let angular_velocity = motion.angular_velocity;
+
+        Self {
+            process_noise,
+            position_variance: position_noise_m * position_noise_m,
+            orientation_variance: orientation_noise_rad * orientation_noise_rad,
+            position_axes: [0, 1, 2].map(|i| AxisKalman::new(position[i], linear_velocity[i])),
+            rotation_axes: [0, 1, 2].map(|i| AxisKalman::new(0.0, angular_velocity[i])),
+            orientation: motion.pose.orientation,
+        }
+    }
+
+    fn motion(&self, delta_time_s: f32) -> DeviceMotion {
+        let position = self
+            .position_axes
+            .map(|axis| axis.predicted_state(delta_time_s));
+        let rotation = self
+            .rotation_axes
+            .map(|axis| axis.predicted_state(delta_time_s));
+
+        DeviceMotion {
+            pose: Pose {
+                orientation: Quat::from_scaled_axis(Vec3::from(rotation.map(|axis| axis[0])))
+                    * self.orientation,
+                position: Vec3::from(position.map(|axis| axis[0])),
+            },
+            linear_velocity: Vec3::from(position.map(|axis| axis[1])),
+            angular_velocity: Vec3::from(rotation.map(|axis| axis[1])),
+        }
+    }
+}
+
+impl MotionFilter for KalmanFilter {
+    fn filter(&mut self, delta_time_s: f32, motion: DeviceMotion) -> DeviceMotion {
+        let velocity_variance_multiplier = VELOCITY_NOISE_MULTIPLIER * VELOCITY_NOISE_MULTIPLIER;
+
+        for (i, axis) in self.position_axes.iter_mut().enumerate() {
+            axis.predict(delta_time_s, self.process_noise);
+            axis.update(
+                motion.pose.position[i],
+                motion.linear_velocity[i],
+                self.position_variance,
+                self.position_variance * velocity_variance_multiplier,
+            );
+        }
+
+        let rotation_measurement =
+            rotation_vector(motion.pose.orientation * self.orientation.inverse());
+        for (i, axis) in self.rotation_axes.iter_mut().enumerate() {
+            axis.predict(delta_time_s, self.process_noise);
+            axis.update(
+                rotation_measurement[i],
+                motion.angular_velocity[i],
+                self.orientation_variance,
+                self.orientation_variance * velocity_variance_multiplier,
+            );
+        }
+
+        // Move the reference orientation to the new estimate
+        let rotation = Vec3::from(self.rotation_axes.map(|axis| axis.state[0]));
+        self.orientation = (Quat::from_scaled_axis(rotation) * self.orientation).normalize();
+        for axis in &mut self.rotation_axes {
+            axis.state[0] = 0.0;
+        }
+
+        self.motion(0.0)
+    }
+
+    fn predict(&self, delta_time_s: f32) -> DeviceMotion {
+        self.motion(delta_time_s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_INTERVAL: Duration = Duration::from_nanos(11_111_111);
+
+    fn configs() -> [MotionFilterConfig; 3] {
+        [
+            MotionFilterConfig::OneEuro {
+                min_cutoff_hz: 1.0,
+                beta: 5.0,
+                derivative_cutoff_hz: 5.0,
+            },
+            MotionFilterConfig::DoubleExponential {
+                smoothing: 0.5,
+                trend_smoothing: 0.3,
+            },
+            MotionFilterConfig::Kalman {
+                process_noise: 500.0,
+                position_noise_mm: 1.0,
+                orientation_noise_deg: 0.2,
+            },
+        ]
+    }
+
+    fn motion(position: Vec3, orientation: Quat) -> DeviceMotion {
+        DeviceMotion {
+            pose: Pose {
+                orientation,
+                position,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn is_finite(motion: &DeviceMotion) -> bool {
+        motion.pose.position.is_finite()
+            && motion.pose.orientation.is_finite()
+            && motion.linear_velocity.is_finite()
+            && motion.angular_velocity.is_finite()
+    }
+
+    #[test]
+    fn constant_input_convergence() {
+        let target = motion(Vec3::new(0.1, 1.0, -0.2), Quat::from_rotation_y(0.3));
+
+        for config in configs() {
+            let mut filter = DeviceMotionFilter::new(config);
+            filter.process(Duration::ZERO, DeviceMotion::default());
+
+            let mut filtered = DeviceMotion::default();
+            for i in 1..=180 {
+                filtered = filter.process(SAMPLE_INTERVAL * i, target);
+                assert!(is_finite(&filtered));
+            }
+
+            assert!(filtered.pose.position.distance(target.pose.position) < 1e-3);
+            assert!(
+                filtered
+                    .pose
+                    .orientation
+                    .angle_between(target.pose.orientation)
+                    < 1e-3
+            );
+        }
+    }
+
+    #[test]
+    fn step_lag() {
+        let start = motion(Vec3::ZERO, Quat::IDENTITY);
+        let target = motion(Vec3::new(0.1, 0.0, 0.0), Quat::IDENTITY);
+
+        for config in configs() {
+            let mut filter = DeviceMotionFilter::new(config);
+            for i in 0..90 {
+                filter.process(SAMPLE_INTERVAL * i, start);
+            }
+
+            // The filtered position moves towards the step without reaching it
+            let first = filter.process(SAMPLE_INTERVAL * 90, target);
+            assert!(first.pose.position.x > 0.0 && first.pose.position.x < 0.09);
+
+            let mut filtered = first;
+            for i in 91..270 {
+                filtered = filter.process(SAMPLE_INTERVAL * i, target);
+            }
+            assert!(filtered.pose.position.distance(target.pose.position) < 1e-3);
+        }
+    }
+
+    #[test]
+    fn zero_delta_time() {
+        let target = motion(Vec3::new(0.1, 1.0, -0.2), Quat::from_rotation_y(0.3));
+
+        for config in configs() {
+            let mut filter = DeviceMotionFilter::new(config);
+            filter.process(Duration::ZERO, DeviceMotion::default());
+            filter.process(SAMPLE_INTERVAL, target);
+
+            // Duplicated timestamp
+            let filtered = filter.process(SAMPLE_INTERVAL, target);
+            assert!(is_finite(&filtered));
+            assert!(is_finite(&filter.predict(SAMPLE_INTERVAL).unwrap()));
+            assert!(is_finite(&filter.predict(SAMPLE_INTERVAL * 2).unwrap()));
+        }
+    }
+}
@@ -20,6 +20,12 @@ fn config_path() -> PathBuf {
 pub struct Config {
     pub hostname: String,
     pub protocol_id: String,
+    // Shown in the lobby, to be entered in the dashboard. Regenerated after each pairing
+    #[serde(default = "alvr_sockets::generate_pairing_pin")]
+    pub pairing_pin: String,
+    // Hex encoded key obtained by pairing with the server
+    #[serde(default)]
+    pub pairing_key: Option<String>,
 }
 
 impl Default for Config {
@@ -35,6 +41,8 @@ impl Default for Config {
                 rng.gen_range(0..10),
             ),
             protocol_id: alvr_common::protocol_id(),
+            pairing_pin: alvr_sockets::generate_pairing_pin(),
+            pairing_key: None,
         }
     }
 }
@@ -1,17 +1,21 @@
 use alvr_client_core::{ClientCapabilities, ClientCoreContext, ClientCoreEvent};
 use alvr_common::{
+    anyhow::Result,
     glam::{Quat, UVec2, Vec3},
     parking_lot::RwLock,
     DeviceMotion, Fov, Pose, RelaxedAtomic, HEAD_ID,
 };
-use alvr_packets::{FaceData, ViewParams};
+use alvr_packets::{FaceData, RecordedPacket, RecordingEntry, SessionRecordingReader, ViewParams};
 use alvr_session::CodecType;
 use eframe::{
     egui::{CentralPanel, Context, RichText, Slider, ViewportBuilder},
     Frame, NativeOptions,
 };
 use std::{
+    env,
     f32::consts::{FRAC_PI_2, PI},
+    fs::File,
+    io::BufReader,
     sync::{
         mpsc::{self, TryRecvError},
         Arc,
@@ -138,11 +142,95 @@ impl eframe::App for Window {
     }
 }
 
+// Only the tracking and buttons are kept. The packets sent by the server (decoder config, video,
+// game audio and haptics) are generated again by the server during the replay, and the statistics
+// and microphone samples are produced by the client core itself
+fn load_replay(path: &str) -> Result<Vec<RecordingEntry>> {
+    let mut reader = SessionRecordingReader::new(BufReader::new(File::open(path)?))?;
+
+    let mut entries = vec![];
+    let mut skipped_server_packets = 0;
+    let mut skipped_client_packets = 0;
+    while let Some(entry) = reader.read()? {
+        match entry.packet {
+            RecordedPacket::Tracking(_) | RecordedPacket::Buttons(_) => entries.push(entry),
+            RecordedPacket::Statistics(_) | RecordedPacket::Microphone(_) => {
+                skipped_client_packets += 1
+            }
+            RecordedPacket::DecoderConfig(_)
+            | RecordedPacket::Video { .. }
+            | RecordedPacket::GameAudio(_)
+            | RecordedPacket::Haptics(_) => skipped_server_packets += 1,
+        }
+    }
+
+    if skipped_client_packets > 0 {
+        println!(
+            "Not replaying {skipped_client_packets} statistics and microphone packets, they are produced by the mock client"
+        );
+    }
+    if skipped_server_packets > 0 {
+        println!(
+            "Not replaying {skipped_server_packets} video, audio and haptics packets, they are sent by the server"
+        );
+    }
+
+    Ok(entries)
+}
+
+// Send the recorded tracking and buttons with the original timing. Tracking timestamps are shifted
+// to the local clock.
+fn replay_session(
+    context: &ClientCoreContext,
+    streaming: &RelaxedAtomic,
+    replay: &[RecordingEntry],
+    timestamp_origin: Instant,
+) {
+    let replay_start = Instant::now();
+    let timestamp_offset = replay_start - timestamp_origin;
+    let first_entry_timestamp = replay.first().map(|e| e.timestamp).unwrap_or_default();
+    let first_target_timestamp = replay
+        .iter()
+        .find_map(|entry| match &entry.packet {
+            RecordedPacket::Tracking(tracking) => Some(tracking.target_timestamp),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    for entry in replay {
+        if !streaming.value() {
+            return;
+        }
+
+        thread::sleep(
+            (replay_start + (entry.timestamp - first_entry_timestamp))
+                .saturating_duration_since(Instant::now()),
+        );
+
+        match entry.packet.clone() {
+            RecordedPacket::Tracking(tracking) => context.send_tracking(
+                timestamp_offset
+                    + tracking
+                        .target_timestamp
+                        .saturating_sub(first_target_timestamp),
+                tracking.device_motions,
+                tracking.hand_skeletons,
+                tracking.face_data,
+            ),
+            RecordedPacket::Buttons(entries) => context.send_buttons(entries),
+            _ => (),
+        }
+    }
+
+    println!("Replay finished");
+}
+
 fn tracking_thread(
     context: Arc<ClientCoreContext>,
     streaming: Arc<RelaxedAtomic>,
     fps: f32,
     input: Arc<RwLock<WindowInput>>,
+    replay: Option<Arc<Vec<RecordingEntry>>>,
 ) {
     let timestamp_origin = Instant::now();
 
@@ -157,6 +245,10 @@ fn tracking_thread(
     };
     context.send_view_params([views_params.clone(), views_params]);
 
+    if let Some(replay) = replay {
+        replay_session(&context, &streaming, &replay, timestamp_origin);
+    }
+
     let mut loop_deadline = Instant::now();
     while streaming.value() {
         let input_lock = input.read();
@@ -202,6 +294,7 @@ fn tracking_thread(
 fn client_thread(
     output_sender: mpsc::Sender<WindowOutput>,
     input_receiver: mpsc::Receiver<WindowInput>,
+    replay: Option<Arc<Vec<RecordingEntry>>>,
 ) {
     let capabilities = ClientCapabilities {
         default_view_resolution: UVec2::new(1920, 1832),
@@ -245,12 +338,14 @@ fn client_thread(
                     let context = Arc::clone(&client_core_context);
                     let streaming = Arc::clone(&streaming);
                     let input = Arc::clone(&window_input);
+                    let replay = replay.clone();
                     maybe_tracking_thread = Some(thread::spawn(move || {
                         tracking_thread(
                             context,
                             streaming,
                             config.negotiated_config.refresh_rate_hint,
                             input,
+                            replay,
                         )
                     }));
                 }
@@ -311,14 +406,35 @@ fn client_thread(
     // client_core_context destroy is called here on drop
 }
 
+// Usage: alvr_client_mock [--replay <session recording>]
+// The replay restarts at every connection, then the window input is used.
 fn main() {
     env_logger::init();
 
+    let args = env::args().collect::<Vec<_>>();
+    let replay = match args.iter().position(|arg| arg == "--replay") {
+        Some(idx) => {
+            let Some(path) = args.get(idx + 1) else {
+                eprintln!("Missing session recording path");
+                return;
+            };
+
+            match load_replay(path) {
+                Ok(entries) => Some(Arc::new(entries)),
+                Err(e) => {
+                    eprintln!("Failed to load session recording: {e}");
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
     let (input_sender, input_receiver) = mpsc::channel::<WindowInput>();
     let (output_sender, output_receiver) = mpsc::channel::<WindowOutput>();
 
     let client_thread = thread::spawn(|| {
-        client_thread(output_sender, input_receiver);
+        client_thread(output_sender, input_receiver, replay);
     });
 
     eframe::run_native(
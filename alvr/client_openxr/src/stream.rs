@@ -347,9 +347,11 @@ impl StreamContext {
             }
         }
 
-        let (timestamp, view_params, buffer_ptr) =
+        let (timestamp, view_params, buffer_ptr, foveation_center_shift) =
             if let Some((timestamp, buffer_ptr)) = frame_result {
                 let view_params = self.core_context.report_compositor_start(timestamp);
+                let foveation_center_shift =
+                    self.core_context.get_foveation_center_shift(timestamp);
 
                 // Avoid passing invalid timestamp to runtime
                 let timestamp =
@@ -357,9 +359,14 @@ impl StreamContext {
 
                 self.last_good_view_params = view_params;
 
-                (timestamp, view_params, buffer_ptr)
+                (timestamp, view_params, buffer_ptr, foveation_center_shift)
             } else {
-                (vsync_time, self.last_good_view_params, ptr::null_mut())
+                (
+                    vsync_time,
+                    self.last_good_view_params,
+                    ptr::null_mut(),
+                    None,
+                )
             };
 
         let left_swapchain_idx = self.swapchains[0].acquire_image().unwrap();
@@ -373,8 +380,11 @@ impl StreamContext {
             .unwrap();
 
         unsafe {
-            self.renderer
-                .render(buffer_ptr, [left_swapchain_idx, right_swapchain_idx])
+            self.renderer.render(
+                buffer_ptr,
+                [left_swapchain_idx, right_swapchain_idx],
+                foveation_center_shift,
+            )
         };
 
         self.swapchains[0].release_image().unwrap();
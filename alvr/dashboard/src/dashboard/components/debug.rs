@@ -4,7 +4,7 @@ use eframe::egui::Ui;
 pub fn debug_tab_ui(ui: &mut Ui) -> Option<ServerRequest> {
     let mut request = None;
 
-    ui.columns(4, |ui| {
+    ui.columns(5, |ui| {
         if ui[0].button("Capture frame").clicked() {
             request = Some(ServerRequest::CaptureFrame);
         }
@@ -20,6 +20,20 @@ pub fn debug_tab_ui(ui: &mut Ui) -> Option<ServerRequest> {
         if ui[3].button("Stop recording").clicked() {
             request = Some(ServerRequest::StopRecording);
         }
+
+        if ui[4].button("Calibrate controllers").clicked() {
+            request = Some(ServerRequest::StartControllerCalibration);
+        }
+    });
+
+    ui.columns(2, |ui| {
+        if ui[0].button("Record left hand pose").clicked() {
+            request = Some(ServerRequest::RecordHandPose { left_hand: true });
+        }
+
+        if ui[1].button("Record right hand pose").clicked() {
+            request = Some(ServerRequest::RecordHandPose { left_hand: false });
+        }
     });
 
     request
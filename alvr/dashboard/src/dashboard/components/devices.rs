@@ -4,7 +4,7 @@ use alvr_gui_common::theme::{self, log_colors};
 use alvr_packets::ClientListAction;
 use alvr_session::{ClientConnectionConfig, SessionConfig};
 use eframe::{
-    egui::{self, Frame, Grid, Layout, RichText, TextEdit, Ui, Window},
+    egui::{self, Button, Frame, Grid, Layout, RichText, TextEdit, Ui, Window},
     emath::{Align, Align2},
     epaint::Color32,
 };
@@ -15,10 +15,16 @@ struct EditPopupState {
     ips: Vec<String>,
 }
 
+struct PairingPopupState {
+    hostname: String,
+    pin: String,
+}
+
 pub struct DevicesTab {
     new_devices: Option<Vec<(String, ClientConnectionConfig)>>,
     trusted_devices: Option<Vec<(String, ClientConnectionConfig)>>,
     edit_popup_state: Option<EditPopupState>,
+    pairing_popup_state: Option<PairingPopupState>,
 }
 
 impl DevicesTab {
@@ -27,6 +33,7 @@ impl DevicesTab {
             new_devices: None,
             trusted_devices: None,
             edit_popup_state: None,
+            pairing_popup_state: None,
         }
     }
 
@@ -84,9 +91,12 @@ impl DevicesTab {
             ui.add_space(10.0);
 
             if let Some(clients) = &mut self.trusted_devices {
-                if let Some(request) =
-                    trusted_clients_section(ui, clients, &mut self.edit_popup_state)
-                {
+                if let Some(request) = trusted_clients_section(
+                    ui,
+                    clients,
+                    &mut self.edit_popup_state,
+                    &mut self.pairing_popup_state,
+                ) {
                     requests.push(request);
                 }
             }
@@ -142,6 +152,33 @@ impl DevicesTab {
                 });
         }
 
+        if let Some(mut state) = self.pairing_popup_state.take() {
+            Window::new("Pair device")
+                .anchor(Align2::CENTER_CENTER, (0.0, 0.0))
+                .resizable(false)
+                .collapsible(false)
+                .show(ui.ctx(), |ui| {
+                    ui.label("Enter the PIN shown in the headset lobby:");
+                    ui.text_edit_singleline(&mut state.pin);
+                    ui.columns(2, |ui| {
+                        if ui[0].button("Cancel").clicked() {
+                            return;
+                        }
+
+                        let pin = state.pin.trim();
+                        let valid_pin = !pin.is_empty() && pin.chars().all(|c| c.is_ascii_digit());
+                        if ui[1].add_enabled(valid_pin, Button::new("Pair")).clicked() {
+                            requests.push(ServerRequest::UpdateClientList {
+                                hostname: state.hostname.clone(),
+                                action: ClientListAction::SetPairingPin(Some(pin.to_owned())),
+                            });
+                        } else {
+                            self.pairing_popup_state = Some(state);
+                        }
+                    })
+                });
+        }
+
         requests
     }
 }
@@ -192,6 +229,7 @@ fn trusted_clients_section(
     ui: &mut Ui,
     clients: &mut [(String, ClientConnectionConfig)],
     edit_popup_state: &mut Option<EditPopupState>,
+    pairing_popup_state: &mut Option<PairingPopupState>,
 ) -> Option<ServerRequest> {
     let mut request = None;
 
@@ -266,6 +304,25 @@ fn trusted_clients_section(
                                                     .collect::<Vec<String>>(),
                                             });
                                         }
+                                        if data.pairing_key.is_some() {
+                                            if ui.button("Unpair").clicked() {
+                                                request = Some(ServerRequest::UpdateClientList {
+                                                    hostname: hostname.clone(),
+                                                    action: ClientListAction::SetPairingKey(None),
+                                                });
+                                            }
+                                        } else if data.pairing_pin.is_some() {
+                                            ui.colored_label(
+                                                log_colors::WARNING_LIGHT,
+                                                "Pairing on next connection",
+                                            );
+                                        }
+                                        if ui.button("Pair").clicked() {
+                                            *pairing_popup_state = Some(PairingPopupState {
+                                                hostname: hostname.to_owned(),
+                                                pin: String::new(),
+                                            });
+                                        }
                                     });
                                 });
                         });
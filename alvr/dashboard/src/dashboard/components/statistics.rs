@@ -280,6 +280,7 @@ impl StatisticsTab {
                 let mut decoder_latency_limiter = Vec::with_capacity(GRAPH_HISTORY_SIZE);
                 let mut network_latency_limiter = Vec::with_capacity(GRAPH_HISTORY_SIZE);
                 let mut encoder_latency_limiter = Vec::with_capacity(GRAPH_HISTORY_SIZE);
+                let mut transport_congestion_limiter = Vec::with_capacity(GRAPH_HISTORY_SIZE);
                 let mut max_throughput = Vec::with_capacity(GRAPH_HISTORY_SIZE);
                 let mut min_throughput = Vec::with_capacity(GRAPH_HISTORY_SIZE);
                 let mut requested_bitrate = Vec::with_capacity(GRAPH_HISTORY_SIZE);
@@ -300,6 +301,10 @@ impl StatisticsTab {
                     if let Some(value) = d.encoder_latency_limiter_bps {
                         encoder_latency_limiter.push(to_screen_trans * pos2(i as f32, value / 1e6))
                     }
+                    if let Some(value) = d.transport_congestion_limiter_bps {
+                        transport_congestion_limiter
+                            .push(to_screen_trans * pos2(i as f32, value / 1e6))
+                    }
                     if let Some(value) = d.manual_max_throughput_bps {
                         max_throughput.push(to_screen_trans * pos2(i as f32, value / 1e6))
                     }
@@ -335,6 +340,11 @@ impl StatisticsTab {
                     decoder_latency_limiter,
                     graph_colors::ENCODER_DECODER_LATENCY_LIMITER,
                 );
+                draw_lines(
+                    painter,
+                    transport_congestion_limiter,
+                    graph_colors::NETWORK_LATENCY_LIMITER,
+                );
                 draw_lines(
                     painter,
                     max_throughput,
@@ -395,6 +405,12 @@ impl StatisticsTab {
                             .filter(|l| *l < stats.throughput_bps),
                         graph_colors::ENCODER_DECODER_LATENCY_LIMITER,
                     );
+                    maybe_label(
+                        ui,
+                        "Transport congestion limiter",
+                        td.transport_congestion_limiter_bps,
+                        graph_colors::NETWORK_LATENCY_LIMITER,
+                    );
                     maybe_label(
                         ui,
                         "Manual max throughput",
@@ -466,6 +482,20 @@ impl StatisticsTab {
                 statistics.packets_lost_total, statistics.packets_lost_per_sec
             ));
 
+            ui[0].label("Packets dropped (memory):");
+            ui[1].label(format!(
+                "{} packets ({} packets/s)",
+                statistics.receive_dropped_packets_total,
+                statistics.receive_dropped_packets_per_sec
+            ));
+
+            ui[0].label("Packets evicted (incomplete):");
+            ui[1].label(format!(
+                "{} packets ({} packets/s)",
+                statistics.receive_evicted_packets_total,
+                statistics.receive_evicted_packets_per_sec
+            ));
+
             ui[0].label("Client FPS:");
             ui[1].label(format!("{} FPS", statistics.client_fps));
 
@@ -16,6 +16,10 @@ pub struct StatisticsSummary {
     pub decode_latency_ms: f32,
     pub packets_lost_total: usize,
     pub packets_lost_per_sec: usize,
+    pub receive_dropped_packets_total: usize,
+    pub receive_dropped_packets_per_sec: usize,
+    pub receive_evicted_packets_total: usize,
+    pub receive_evicted_packets_per_sec: usize,
     pub client_fps: u32,
     pub server_fps: u32,
     pub battery_hmd: u32,
@@ -29,6 +33,7 @@ pub struct BitrateDirectives {
     pub decoder_latency_limiter_bps: Option<f32>,
     pub network_latency_limiter_bps: Option<f32>,
     pub encoder_latency_limiter_bps: Option<f32>,
+    pub transport_congestion_limiter_bps: Option<f32>,
     pub manual_max_throughput_bps: Option<f32>,
     pub manual_min_throughput_bps: Option<f32>,
     pub requested_bitrate_bps: f32,
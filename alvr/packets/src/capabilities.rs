@@ -0,0 +1,443 @@
+// Versioned capability map exchanged during the handshake.
+//
+// Each peer announces the protocol version of the map, the set of features it supports and typed
+// values (preferences, limits, negotiated parameters). Unknown features and values are ignored, so
+// newer peers can announce new entries without breaking older ones. When a peer is older than the
+// version that introduced a feature, the feature state is taken from the compatibility table.
+// Version 0 represents peers that send a plain JSON object (legacy encoding).
+
+use alvr_common::{
+    anyhow::{bail, Result},
+    glam::UVec2,
+};
+use serde::{Deserialize, Serialize};
+use serde_json as json;
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const CAPABILITIES_VERSION: u32 = 1;
+
+pub const FOVEATED_ENCODING: &str = "foveated_encoding";
+pub const ENCODER_HIGH_PROFILE: &str = "encoder_high_profile";
+pub const ENCODER_10_BITS: &str = "encoder_10_bits";
+pub const ENCODER_AV1: &str = "encoder_av1";
+pub const MULTIMODAL_PROTOCOL: &str = "multimodal_protocol";
+
+pub struct FeatureCompatibility {
+    pub name: &'static str,
+    // First capability map version that always announces this feature when supported
+    pub since_version: u32,
+    // Assumed state when the peer predates since_version
+    pub legacy_default: bool,
+}
+
+// When adding a feature, append an entry with since_version set to the new CAPABILITIES_VERSION
+pub const COMPATIBILITY_TABLE: &[FeatureCompatibility] = &[
+    FeatureCompatibility {
+        name: FOVEATED_ENCODING,
+        since_version: 1,
+        legacy_default: true,
+    },
+    FeatureCompatibility {
+        name: ENCODER_HIGH_PROFILE,
+        since_version: 1,
+        legacy_default: true,
+    },
+    FeatureCompatibility {
+        name: ENCODER_10_BITS,
+        since_version: 1,
+        legacy_default: true,
+    },
+    FeatureCompatibility {
+        name: ENCODER_AV1,
+        since_version: 1,
+        legacy_default: true,
+    },
+    FeatureCompatibility {
+        name: MULTIMODAL_PROTOCOL,
+        since_version: 1,
+        legacy_default: false,
+    },
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum CapabilityValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    FloatArray(Vec<f32>),
+    UVec2(UVec2),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    pub version: u32,
+    #[serde(default)]
+    pub features: BTreeSet<String>,
+    #[serde(default)]
+    pub values: BTreeMap<String, CapabilityValue>,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            version: CAPABILITIES_VERSION,
+            features: BTreeSet::new(),
+            values: BTreeMap::new(),
+        }
+    }
+}
+
+impl Capabilities {
+    // All features known by this build
+    pub fn local() -> Self {
+        Self {
+            features: COMPATIBILITY_TABLE
+                .iter()
+                .map(|feature| feature.name.to_owned())
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    // Accepts both the versioned encoding and a plain JSON object sent by legacy peers
+    pub fn from_json_str(string: &str) -> Result<Self> {
+        let value = json::from_str::<json::Value>(string)?;
+
+        if value.get("version").is_some() {
+            Ok(json::from_value(value)?)
+        } else {
+            Self::from_legacy_json(&value)
+        }
+    }
+
+    pub fn to_json_string(&self) -> Result<String> {
+        Ok(json::to_string(self)?)
+    }
+
+    fn from_legacy_json(value: &json::Value) -> Result<Self> {
+        let Some(object) = value.as_object() else {
+            bail!("Invalid legacy capabilities");
+        };
+
+        let mut capabilities = Self {
+            version: 0,
+            ..Default::default()
+        };
+        for (key, value) in object {
+            let value = match value {
+                json::Value::Bool(value) => CapabilityValue::Bool(*value),
+                json::Value::Number(number) => {
+                    if let Some(value) = number.as_i64() {
+                        CapabilityValue::Int(value)
+                    } else {
+                        CapabilityValue::Float(number.as_f64().unwrap_or_default())
+                    }
+                }
+                json::Value::String(value) => CapabilityValue::Text(value.clone()),
+                json::Value::Array(array) => CapabilityValue::FloatArray(
+                    array
+                        .iter()
+                        .filter_map(|value| value.as_f64().map(|v| v as f32))
+                        .collect(),
+                ),
+                _ => continue,
+            };
+
+            if value == CapabilityValue::Bool(true)
+                && COMPATIBILITY_TABLE.iter().any(|f| f.name == key.as_str())
+            {
+                capabilities.features.insert(key.clone());
+            }
+
+            capabilities.values.insert(key.clone(), value);
+        }
+
+        Ok(capabilities)
+    }
+
+    pub fn with_feature(mut self, name: &str, enabled: bool) -> Self {
+        if enabled {
+            self.features.insert(name.to_owned());
+        }
+
+        self
+    }
+
+    pub fn with_value(mut self, key: &str, value: CapabilityValue) -> Self {
+        self.values.insert(key.to_owned(), value);
+
+        self
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        if self.features.contains(feature) {
+            return true;
+        }
+
+        COMPATIBILITY_TABLE
+            .iter()
+            .find(|f| f.name == feature)
+            .map(|f| {
+                // Legacy peers may still state the feature explicitly
+                self.version < f.since_version && self.get_bool(feature).unwrap_or(f.legacy_default)
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.values.get(key)? {
+            CapabilityValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_f32(&self, key: &str) -> Option<f32> {
+        match self.values.get(key)? {
+            CapabilityValue::Float(value) => Some(*value as f32),
+            CapabilityValue::Int(value) => Some(*value as f32),
+            _ => None,
+        }
+    }
+
+    pub fn get_u32(&self, key: &str) -> Option<u32> {
+        match self.values.get(key)? {
+            CapabilityValue::Int(value) => u32::try_from(*value).ok(),
+            CapabilityValue::Float(value) if value.fract() == 0.0 && *value >= 0.0 => {
+                Some(*value as u32)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_f32_array(&self, key: &str) -> Option<Vec<f32>> {
+        match self.values.get(key)? {
+            CapabilityValue::FloatArray(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    // Legacy peers encode vectors as arrays
+    pub fn get_uvec2(&self, key: &str) -> Option<UVec2> {
+        match self.values.get(key)? {
+            CapabilityValue::UVec2(value) => Some(*value),
+            CapabilityValue::FloatArray(value) if value.len() == 2 => {
+                Some(UVec2::new(value[0] as u32, value[1] as u32))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NegotiatedCapabilities {
+    pub version: u32,
+    pub features: BTreeSet<String>,
+}
+
+impl NegotiatedCapabilities {
+    pub fn has(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+}
+
+// A feature is enabled only if both peers support it
+pub fn negotiate_capabilities(
+    local: &Capabilities,
+    remote: &Capabilities,
+) -> NegotiatedCapabilities {
+    let features = local
+        .features
+        .iter()
+        .map(String::as_str)
+        .chain(COMPATIBILITY_TABLE.iter().map(|f| f.name))
+        .filter(|name| local.supports(name) && remote.supports(name))
+        .map(str::to_owned)
+        .collect();
+
+    NegotiatedCapabilities {
+        version: u32::min(local.version, remote.version),
+        features,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NegotiatedStreamingConfig, VideoStreamingCapabilities};
+
+    fn client_capabilities() -> VideoStreamingCapabilities {
+        VideoStreamingCapabilities {
+            default_view_resolution: UVec2::new(1832, 1920),
+            supported_refresh_rates: vec![72.0, 90.0, 120.0],
+            microphone_sample_rate: 48000,
+            supports_foveated_encoding: true,
+            encoder_high_profile: true,
+            encoder_10_bits: false,
+            encoder_av1: false,
+            multimodal_protocol: true,
+            prefer_10bit: false,
+            prefer_full_range: true,
+            preferred_encoding_gamma: 1.5,
+            prefer_hdr: false,
+        }
+    }
+
+    #[test]
+    fn test_streaming_capabilities_roundtrip() {
+        let caps = client_capabilities();
+
+        let encoded = crate::encode_video_streaming_capabilities(&caps).unwrap();
+        let decoded = crate::decode_video_streaming_capabilities(&encoded).unwrap();
+
+        assert_eq!(
+            decoded.default_view_resolution,
+            caps.default_view_resolution
+        );
+        assert_eq!(
+            decoded.supported_refresh_rates,
+            caps.supported_refresh_rates
+        );
+        assert_eq!(decoded.microphone_sample_rate, caps.microphone_sample_rate);
+        assert!(decoded.supports_foveated_encoding);
+        assert!(decoded.encoder_high_profile);
+        assert!(!decoded.encoder_10_bits);
+        assert!(!decoded.encoder_av1);
+        assert!(decoded.multimodal_protocol);
+        assert!(decoded.prefer_full_range);
+        assert_eq!(decoded.preferred_encoding_gamma, 1.5);
+    }
+
+    #[test]
+    fn test_legacy_packet_roundtrip() {
+        let caps = client_capabilities();
+
+        let legacy = crate::encode_legacy_video_streaming_capabilities(&caps).unwrap();
+        assert_eq!(legacy.default_view_resolution, caps.default_view_resolution);
+        assert_eq!(legacy.microphone_sample_rate, caps.microphone_sample_rate);
+        assert_eq!(
+            legacy.supported_refresh_rates_plus_extra_data[..3],
+            caps.supported_refresh_rates
+        );
+
+        let decoded = crate::decode_legacy_video_streaming_capabilities(&legacy).unwrap();
+        assert_eq!(
+            decoded.supported_refresh_rates,
+            caps.supported_refresh_rates
+        );
+        assert!(decoded.multimodal_protocol);
+        assert!(!decoded.encoder_10_bits);
+        assert_eq!(decoded.preferred_encoding_gamma, 1.5);
+    }
+
+    #[test]
+    fn test_newer_client_unknown_entries_are_ignored() {
+        let mut capabilities = Capabilities::from_json_str(
+            &crate::encode_video_streaming_capabilities(&client_capabilities()).unwrap(),
+        )
+        .unwrap();
+        capabilities.version = CAPABILITIES_VERSION + 1;
+        capabilities.features.insert("future_feature".into());
+        capabilities
+            .values
+            .insert("future_value".into(), CapabilityValue::Text("x".into()));
+
+        let decoded =
+            crate::decode_video_streaming_capabilities(&capabilities.to_json_string().unwrap())
+                .unwrap();
+        assert!(decoded.multimodal_protocol);
+        assert!(!decoded.encoder_av1);
+
+        let negotiated = negotiate_capabilities(&Capabilities::local(), &capabilities);
+        assert_eq!(negotiated.version, CAPABILITIES_VERSION);
+        assert!(!negotiated.has("future_feature"));
+    }
+
+    #[test]
+    fn test_legacy_peer_uses_compatibility_defaults() {
+        let legacy = Capabilities::from_json_str(
+            r#"{ "default_view_resolution": [1832, 1920], "supported_refresh_rates": [72.0, 90.0], "microphone_sample_rate": 44100, "encoder_10_bits": false }"#,
+        )
+        .unwrap();
+        assert_eq!(legacy.version, 0);
+
+        let negotiated = negotiate_capabilities(&Capabilities::local(), &legacy);
+        assert_eq!(negotiated.version, 0);
+        assert!(negotiated.has(FOVEATED_ENCODING));
+        assert!(negotiated.has(ENCODER_AV1));
+        assert!(!negotiated.has(ENCODER_10_BITS));
+        assert!(!negotiated.has(MULTIMODAL_PROTOCOL));
+
+        assert_eq!(
+            legacy.get_uvec2("default_view_resolution"),
+            Some(UVec2::new(1832, 1920))
+        );
+        assert_eq!(legacy.get_u32("microphone_sample_rate"), Some(44100));
+    }
+
+    #[test]
+    fn test_features_require_both_peers() {
+        let local = Capabilities::default().with_feature(MULTIMODAL_PROTOCOL, true);
+        let remote = Capabilities::default()
+            .with_feature(MULTIMODAL_PROTOCOL, true)
+            .with_feature(ENCODER_AV1, true);
+
+        let negotiated = negotiate_capabilities(&local, &remote);
+        assert!(negotiated.has(MULTIMODAL_PROTOCOL));
+        assert!(!negotiated.has(ENCODER_AV1));
+    }
+
+    #[test]
+    fn test_missing_required_value_fails() {
+        let capabilities = Capabilities::local().to_json_string().unwrap();
+
+        assert!(crate::decode_video_streaming_capabilities(&capabilities).is_err());
+    }
+
+    #[test]
+    fn test_negotiated_config_from_legacy_server() {
+        let negotiated = crate::decode_negotiated_config(
+            r#"{ "view_resolution": [1600, 1760], "refresh_rate_hint": 90.0, "game_audio_sample_rate": 48000 }"#,
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(negotiated.view_resolution, UVec2::new(1600, 1760));
+        assert_eq!(negotiated.refresh_rate_hint, 90.0);
+        assert_eq!(negotiated.game_audio_sample_rate, 48000);
+        assert!(negotiated.enable_foveated_encoding);
+        assert!(!negotiated.use_multimodal_protocol);
+        assert_eq!(negotiated.encoding_gamma, 1.0);
+        assert!(!negotiated.enable_hdr);
+    }
+
+    #[test]
+    fn test_negotiated_config_roundtrip() {
+        let config = NegotiatedStreamingConfig {
+            view_resolution: UVec2::new(1600, 1760),
+            refresh_rate_hint: 72.0,
+            game_audio_sample_rate: 44100,
+            enable_foveated_encoding: false,
+            use_multimodal_protocol: true,
+            encoding_gamma: 2.2,
+            enable_hdr: true,
+        };
+
+        let decoded = crate::decode_negotiated_config(
+            &crate::encode_negotiated_config(&config).unwrap(),
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(decoded.view_resolution, config.view_resolution);
+        assert_eq!(decoded.refresh_rate_hint, config.refresh_rate_hint);
+        assert_eq!(
+            decoded.game_audio_sample_rate,
+            config.game_audio_sample_rate
+        );
+        assert!(!decoded.enable_foveated_encoding);
+        assert!(decoded.use_multimodal_protocol);
+        assert_eq!(decoded.encoding_gamma, 2.2);
+        assert!(decoded.enable_hdr);
+    }
+}
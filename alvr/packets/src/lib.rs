@@ -1,10 +1,18 @@
+mod capabilities;
+mod session_recording;
+
+pub use capabilities::*;
+pub use session_recording::*;
+
 use alvr_common::{
-    anyhow::Result,
+    anyhow::{Context, Result},
     glam::{UVec2, Vec2},
     semver::Version,
-    ConnectionState, DeviceMotion, Fov, LogEntry, LogSeverity, Pose, ToAny,
+    ConnectionState, DeviceMotion, Fov, LogEntry, LogSeverity, Pose,
+};
+use alvr_session::{
+    CodecType, PacketRetransmissionConfig, SessionConfig, Settings, StreamReceiveLimitsConfig,
 };
-use alvr_session::{CodecType, SessionConfig, Settings};
 use serde::{Deserialize, Serialize};
 use serde_json as json;
 use std::{
@@ -21,6 +29,41 @@ pub const AUDIO: u16 = 2;
 pub const VIDEO: u16 = 3;
 pub const STATISTICS: u16 = 4;
 
+// Memory budget in bytes of each stream
+pub fn stream_memory_budgets(config: &StreamReceiveLimitsConfig) -> Vec<(u16, usize)> {
+    let other_streams_budget = (config.other_streams_memory_budget_mb * 1024 * 1024) as usize;
+
+    vec![
+        (TRACKING, other_streams_budget),
+        (HAPTICS, other_streams_budget),
+        (AUDIO, other_streams_budget),
+        (
+            VIDEO,
+            (config.video_memory_budget_mb * 1024 * 1024) as usize,
+        ),
+        (STATISTICS, other_streams_budget),
+    ]
+}
+
+pub fn retransmitted_streams(config: &PacketRetransmissionConfig) -> Vec<u16> {
+    [
+        (TRACKING, config.tracking),
+        (HAPTICS, config.haptics),
+        (AUDIO, config.audio),
+        (VIDEO, config.video),
+        (STATISTICS, config.statistics),
+    ]
+    .into_iter()
+    .filter_map(|(stream_id, enabled)| enabled.then_some(stream_id))
+    .collect()
+}
+
+// Streams delivered on the reliable channel of transports that have one (QUIC). Losing a packet of
+// these streams is not recovered by the following packets.
+pub fn reliable_streams() -> Vec<u16> {
+    vec![HAPTICS, STATISTICS]
+}
+
 // todo: use simple string
 #[derive(Serialize, Deserialize, Clone)]
 pub struct VideoStreamingCapabilitiesLegacy {
@@ -46,66 +89,95 @@ pub struct VideoStreamingCapabilities {
     pub prefer_hdr: bool,
 }
 
-// Nasty workaround to make the packet extensible, pushing the limits of protocol compatibility
-// Todo: replace VideoStreamingCapabilitiesLegacy with simple json string
-pub fn encode_video_streaming_capabilities(
+pub fn encode_video_streaming_capabilities(caps: &VideoStreamingCapabilities) -> Result<String> {
+    Capabilities::default()
+        .with_feature(FOVEATED_ENCODING, caps.supports_foveated_encoding)
+        .with_feature(ENCODER_HIGH_PROFILE, caps.encoder_high_profile)
+        .with_feature(ENCODER_10_BITS, caps.encoder_10_bits)
+        .with_feature(ENCODER_AV1, caps.encoder_av1)
+        .with_feature(MULTIMODAL_PROTOCOL, caps.multimodal_protocol)
+        .with_value(
+            "default_view_resolution",
+            CapabilityValue::UVec2(caps.default_view_resolution),
+        )
+        .with_value(
+            "supported_refresh_rates",
+            CapabilityValue::FloatArray(caps.supported_refresh_rates.clone()),
+        )
+        .with_value(
+            "microphone_sample_rate",
+            CapabilityValue::Int(caps.microphone_sample_rate as _),
+        )
+        .with_value("prefer_10bit", CapabilityValue::Bool(caps.prefer_10bit))
+        .with_value(
+            "prefer_full_range",
+            CapabilityValue::Bool(caps.prefer_full_range),
+        )
+        .with_value(
+            "preferred_encoding_gamma",
+            CapabilityValue::Float(caps.preferred_encoding_gamma as _),
+        )
+        .with_value("prefer_hdr", CapabilityValue::Bool(caps.prefer_hdr))
+        .to_json_string()
+}
+
+// Features are negotiated against the ones known by this build
+pub fn decode_video_streaming_capabilities(string: &str) -> Result<VideoStreamingCapabilities> {
+    let caps = Capabilities::from_json_str(string)?;
+    let negotiated = negotiate_capabilities(&Capabilities::local(), &caps);
+
+    Ok(VideoStreamingCapabilities {
+        default_view_resolution: caps
+            .get_uvec2("default_view_resolution")
+            .context("Missing default_view_resolution")?,
+        supported_refresh_rates: caps
+            .get_f32_array("supported_refresh_rates")
+            .context("Missing supported_refresh_rates")?,
+        microphone_sample_rate: caps
+            .get_u32("microphone_sample_rate")
+            .context("Missing microphone_sample_rate")?,
+        supports_foveated_encoding: negotiated.has(FOVEATED_ENCODING),
+        encoder_high_profile: negotiated.has(ENCODER_HIGH_PROFILE),
+        encoder_10_bits: negotiated.has(ENCODER_10_BITS),
+        encoder_av1: negotiated.has(ENCODER_AV1),
+        multimodal_protocol: negotiated.has(MULTIMODAL_PROTOCOL),
+        prefer_10bit: caps.get_bool("prefer_10bit").unwrap_or(false),
+        prefer_full_range: caps.get_bool("prefer_full_range").unwrap_or(true),
+        preferred_encoding_gamma: caps.get_f32("preferred_encoding_gamma").unwrap_or(1.0),
+        prefer_hdr: caps.get_bool("prefer_hdr").unwrap_or(false),
+    })
+}
+
+// The layout of the legacy packet must be kept, so older servers can decode it and reject the
+// client with the protocol ID check. The encoded Capabilities are appended to the refresh rates as
+// negative values, which older servers parse as JSON.
+pub fn encode_legacy_video_streaming_capabilities(
     caps: &VideoStreamingCapabilities,
 ) -> Result<VideoStreamingCapabilitiesLegacy> {
-    let caps_json = json::to_value(caps)?;
-
-    let mut supported_refresh_rates_plus_extra_data = vec![];
-    for rate in caps_json["supported_refresh_rates"].as_array().to_any()? {
-        supported_refresh_rates_plus_extra_data.push(rate.as_f64().to_any()? as f32);
-    }
-    for byte in json::to_string(caps)?.as_bytes() {
-        // using negative values is not going to trigger strange behavior for old servers
+    let mut supported_refresh_rates_plus_extra_data = caps.supported_refresh_rates.clone();
+    for byte in encode_video_streaming_capabilities(caps)?.as_bytes() {
         supported_refresh_rates_plus_extra_data.push(-(*byte as f32));
     }
 
-    let default_view_resolution = json::from_value(caps_json["default_view_resolution"].clone())?;
-    let microphone_sample_rate = caps_json["microphone_sample_rate"].as_u64().to_any()? as u32;
-
     Ok(VideoStreamingCapabilitiesLegacy {
-        default_view_resolution,
+        default_view_resolution: caps.default_view_resolution,
         supported_refresh_rates_plus_extra_data,
-        microphone_sample_rate,
+        microphone_sample_rate: caps.microphone_sample_rate,
     })
 }
 
-pub fn decode_video_streaming_capabilities(
+// Older clients append a plain JSON object, which is also accepted
+pub fn decode_legacy_video_streaming_capabilities(
     legacy: &VideoStreamingCapabilitiesLegacy,
 ) -> Result<VideoStreamingCapabilities> {
-    let mut json_bytes = vec![];
-    let mut supported_refresh_rates = vec![];
-    for rate in &legacy.supported_refresh_rates_plus_extra_data {
-        if *rate < 0.0 {
-            json_bytes.push((-*rate) as u8)
-        } else {
-            supported_refresh_rates.push(*rate);
-        }
-    }
+    let json_bytes = legacy
+        .supported_refresh_rates_plus_extra_data
+        .iter()
+        .filter(|rate| **rate < 0.0)
+        .map(|rate| (-*rate) as u8)
+        .collect::<Vec<_>>();
 
-    let caps_json =
-        json::from_str::<json::Value>(&String::from_utf8(json_bytes)?).unwrap_or(json::Value::Null);
-
-    Ok(VideoStreamingCapabilities {
-        default_view_resolution: legacy.default_view_resolution,
-        supported_refresh_rates,
-        microphone_sample_rate: legacy.microphone_sample_rate,
-        supports_foveated_encoding: caps_json["supports_foveated_encoding"]
-            .as_bool()
-            .unwrap_or(true),
-        encoder_high_profile: caps_json["encoder_high_profile"].as_bool().unwrap_or(true),
-        encoder_10_bits: caps_json["encoder_10_bits"].as_bool().unwrap_or(true),
-        encoder_av1: caps_json["encoder_av1"].as_bool().unwrap_or(true),
-        multimodal_protocol: caps_json["multimodal_protocol"].as_bool().unwrap_or(false),
-        prefer_10bit: caps_json["prefer_10bit"].as_bool().unwrap_or(false),
-        prefer_full_range: caps_json["prefer_full_range"].as_bool().unwrap_or(true),
-        preferred_encoding_gamma: caps_json["preferred_encoding_gamma"]
-            .as_f64()
-            .unwrap_or(1.0) as f32,
-        prefer_hdr: caps_json["prefer_hdr"].as_bool().unwrap_or(false),
-    })
+    decode_video_streaming_capabilities(&String::from_utf8(json_bytes)?)
 }
 
 #[derive(Serialize, Deserialize)]
@@ -119,6 +191,31 @@ pub enum ClientConnectionResult {
     ClientStandby,
 }
 
+// Sent by the server after a ConnectionAccepted result. Public keys are X25519 keys.
+#[derive(Serialize, Deserialize)]
+pub enum ServerKeyExchange {
+    Unencrypted,
+    PairingRequired,
+    Pairing { public_key: Vec<u8> },
+    Paired { public_key: Vec<u8> },
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum ClientKeyExchange {
+    Accepted {
+        public_key: Vec<u8>,
+        confirmation: Vec<u8>,
+    },
+    NotPaired,
+}
+
+// Sent by the server after the client confirmation has been verified. All subsequent packets are
+// encrypted.
+#[derive(Serialize, Deserialize)]
+pub struct ServerKeyConfirmation {
+    pub confirmation: Vec<u8>,
+}
+
 // Note: not a network packet
 #[derive(Serialize, Deserialize, Clone)]
 pub struct NegotiatedStreamingConfig {
@@ -136,7 +233,64 @@ pub struct NegotiatedStreamingConfig {
 #[derive(Serialize, Deserialize)]
 pub struct StreamConfigPacket {
     pub session: String,    // JSON session that allows for extrapolation
-    pub negotiated: String, // Encoded NegotiatedVideoStreamingConfig
+    pub negotiated: String, // Encoded Capabilities with the NegotiatedStreamingConfig values
+}
+
+pub fn encode_negotiated_config(negotiated: &NegotiatedStreamingConfig) -> Result<String> {
+    Capabilities::default()
+        .with_value(
+            "view_resolution",
+            CapabilityValue::UVec2(negotiated.view_resolution),
+        )
+        .with_value(
+            "refresh_rate_hint",
+            CapabilityValue::Float(negotiated.refresh_rate_hint as _),
+        )
+        .with_value(
+            "game_audio_sample_rate",
+            CapabilityValue::Int(negotiated.game_audio_sample_rate as _),
+        )
+        .with_value(
+            "enable_foveated_encoding",
+            CapabilityValue::Bool(negotiated.enable_foveated_encoding),
+        )
+        .with_value(
+            "use_multimodal_protocol",
+            CapabilityValue::Bool(negotiated.use_multimodal_protocol),
+        )
+        .with_value(
+            "encoding_gamma",
+            CapabilityValue::Float(negotiated.encoding_gamma as _),
+        )
+        .with_value("enable_hdr", CapabilityValue::Bool(negotiated.enable_hdr))
+        .to_json_string()
+}
+
+// Also accepts the plain JSON object sent by older servers. Foveated encoding defaults to the
+// settings value when not specified.
+pub fn decode_negotiated_config(
+    string: &str,
+    default_foveated_encoding: bool,
+) -> Result<NegotiatedStreamingConfig> {
+    let caps = Capabilities::from_json_str(string)?;
+
+    Ok(NegotiatedStreamingConfig {
+        view_resolution: caps
+            .get_uvec2("view_resolution")
+            .context("Missing view_resolution")?,
+        refresh_rate_hint: caps
+            .get_f32("refresh_rate_hint")
+            .context("Missing refresh_rate_hint")?,
+        game_audio_sample_rate: caps
+            .get_u32("game_audio_sample_rate")
+            .context("Missing game_audio_sample_rate")?,
+        enable_foveated_encoding: caps
+            .get_bool("enable_foveated_encoding")
+            .unwrap_or(default_foveated_encoding),
+        use_multimodal_protocol: caps.get_bool("use_multimodal_protocol").unwrap_or(false),
+        encoding_gamma: caps.get_f32("encoding_gamma").unwrap_or(1.0),
+        enable_hdr: caps.get_bool("enable_hdr").unwrap_or(false),
+    })
 }
 
 pub fn encode_stream_config(
@@ -145,7 +299,7 @@ pub fn encode_stream_config(
 ) -> Result<StreamConfigPacket> {
     Ok(StreamConfigPacket {
         session: json::to_string(session)?,
-        negotiated: json::to_string(negotiated)?,
+        negotiated: encode_negotiated_config(negotiated)?,
     })
 }
 
@@ -161,32 +315,15 @@ pub fn decode_stream_config(packet: &StreamConfigPacket) -> Result<StreamConfig>
     session_config.merge_from_json(&json::from_str(&packet.session)?)?;
     let settings = session_config.to_settings();
 
-    let negotiated_json = json::from_str::<json::Value>(&packet.negotiated)?;
-
-    let view_resolution = json::from_value(negotiated_json["view_resolution"].clone())?;
-    let refresh_rate_hint = json::from_value(negotiated_json["refresh_rate_hint"].clone())?;
-    let game_audio_sample_rate =
-        json::from_value(negotiated_json["game_audio_sample_rate"].clone())?;
-    let enable_foveated_encoding =
-        json::from_value(negotiated_json["enable_foveated_encoding"].clone())
-            .unwrap_or_else(|_| settings.video.foveated_encoding.enabled());
-    let use_multimodal_protocol =
-        json::from_value(negotiated_json["use_multimodal_protocol"].clone()).unwrap_or(false);
-    let encoding_gamma = json::from_value(negotiated_json["encoding_gamma"].clone()).unwrap_or(1.0);
-    let enable_hdr = json::from_value(negotiated_json["enable_hdr"].clone()).unwrap_or(false);
+    let negotiated_config = decode_negotiated_config(
+        &packet.negotiated,
+        settings.video.foveated_encoding.enabled(),
+    )?;
 
     Ok(StreamConfig {
         server_version: session_config.server_version,
         settings,
-        negotiated_config: NegotiatedStreamingConfig {
-            view_resolution,
-            refresh_rate_hint,
-            game_audio_sample_rate,
-            enable_foveated_encoding,
-            use_multimodal_protocol,
-            encoding_gamma,
-            enable_hdr,
-        },
+        negotiated_config,
     })
 }
 
@@ -221,13 +358,13 @@ pub struct BatteryInfo {
     pub is_plugged: bool,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
 pub enum ButtonValue {
     Binary(bool),
     Scalar(f32),
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ButtonEntry {
     pub path_id: u64,
     pub value: ButtonValue,
@@ -272,14 +409,17 @@ pub struct FaceData {
     pub htc_lip_expression: Option<Vec<f32>>, // issue: Serialize does not support [f32; 37]
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct VideoPacketHeader {
     pub timestamp: Duration,
     pub is_idr: bool,
+    // Foveated encoding center shift of the left and right eye, when the center follows the eye
+    // gaze. If None, the center shift of the settings is used
+    pub foveation_center_shift: Option<[Vec2; 2]>,
 }
 
 // Note: face_data does not respect target_timestamp.
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct Tracking {
     pub target_timestamp: Duration,
     pub device_motions: Vec<(u64, DeviceMotion)>,
@@ -287,7 +427,7 @@ pub struct Tracking {
     pub face_data: FaceData,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Haptics {
     pub device_id: u64,
     pub duration: Duration,
@@ -347,6 +487,8 @@ pub enum ClientListAction {
     },
     SetDisplayName(String),
     Trust,
+    SetPairingPin(Option<String>),
+    SetPairingKey(Option<String>), // also clears the pairing PIN
     SetManualIps(Vec<IpAddr>),
     RemoveEntry,
     UpdateCurrentIp(Option<IpAddr>),
@@ -362,6 +504,9 @@ pub struct ClientStatistics {
     pub rendering: Duration,
     pub vsync_queue: Duration,
     pub total_pipeline_latency: Duration,
+    // Counted since the previous statistics packet
+    pub receive_dropped_packets: u32,
+    pub receive_evicted_packets: u32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -391,6 +536,10 @@ pub enum ServerRequest {
     InsertIdr,
     StartRecording,
     StopRecording,
+    StartControllerCalibration,
+    RecordHandPose {
+        left_hand: bool,
+    },
     FirewallRules(FirewallRulesAction),
     RegisterAlvrDriver,
     UnregisterDriver(PathBuf),
@@ -0,0 +1,151 @@
+// Session recording file format.
+//
+// A recording contains timestamped packets exchanged between the server and the driver client.
+// Layout: magic, u32 format version, then a sequence of entries. Each entry is a u32 length
+// followed by the bincode encoded RecordingEntry. All integers outside of bincode are little endian.
+
+use crate::{
+    ButtonEntry, ClientStatistics, DecoderInitializationConfig, Haptics, Tracking,
+    VideoPacketHeader,
+};
+use alvr_common::anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{ErrorKind, Read, Write},
+    mem,
+    time::{Duration, Instant},
+};
+
+pub const SESSION_RECORDING_EXTENSION: &str = "alvrrec";
+
+const MAGIC: &[u8; 8] = b"ALVRSREC";
+// Must be incremented when the layout of a recorded packet changes, since bincode is not self
+// describing. Version 3: VideoPacketHeader has a foveation center shift
+const FORMAT_VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum RecordedPacket {
+    // Client to server
+    Tracking(Tracking),
+    Buttons(Vec<ButtonEntry>),
+    Statistics(ClientStatistics),
+    Microphone(Vec<u8>), // Interleaved i16 samples
+    // Server to client
+    DecoderConfig(DecoderInitializationConfig),
+    Video {
+        header: VideoPacketHeader,
+        nal: Vec<u8>,
+    },
+    GameAudio(Vec<u8>), // Interleaved i16 samples
+    Haptics(Haptics),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RecordingEntry {
+    // Time since the start of the recording
+    pub timestamp: Duration,
+    pub packet: RecordedPacket,
+}
+
+pub struct SessionRecordingWriter<W: Write> {
+    writer: W,
+    start_instant: Instant,
+}
+
+impl<W: Write> SessionRecordingWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            start_instant: Instant::now(),
+        })
+    }
+
+    pub fn write(&mut self, packet: RecordedPacket) -> Result<()> {
+        let entry = RecordingEntry {
+            timestamp: self.start_instant.elapsed(),
+            packet,
+        };
+
+        let size = bincode::serialized_size(&entry)?;
+        self.writer.write_all(&(size as u32).to_le_bytes())?;
+        bincode::serialize_into(&mut self.writer, &entry)?;
+
+        Ok(())
+    }
+}
+
+pub struct SessionRecordingReader<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> SessionRecordingReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("Not a session recording");
+        }
+
+        let mut version = [0; mem::size_of::<u32>()];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != FORMAT_VERSION {
+            bail!("Unsupported session recording version {version}");
+        }
+
+        Ok(Self {
+            reader,
+            buffer: vec![],
+        })
+    }
+
+    // Returns None at the end of the file. A truncated last entry (e.g. the server crashed while
+    // recording) is treated as the end of the file.
+    pub fn read(&mut self) -> Result<Option<RecordingEntry>> {
+        let mut size = [0; mem::size_of::<u32>()];
+        match self.reader.read_exact(&mut size) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        self.buffer.resize(u32::from_le_bytes(size) as usize, 0);
+        match self.reader.read_exact(&mut self.buffer) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(Some(bincode::deserialize(&self.buffer)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut file = vec![];
+        SessionRecordingWriter::new(&mut file)
+            .unwrap()
+            .write(RecordedPacket::Microphone(vec![1, 2, 3, 4]))
+            .unwrap();
+
+        let mut reader = SessionRecordingReader::new(file.as_slice()).unwrap();
+        assert!(matches!(
+            reader.read().unwrap().unwrap().packet,
+            RecordedPacket::Microphone(samples) if samples == [1, 2, 3, 4]
+        ));
+        assert!(reader.read().unwrap().is_none());
+
+        for version in [FORMAT_VERSION - 1, FORMAT_VERSION + 1] {
+            file[MAGIC.len()..][..mem::size_of::<u32>()].copy_from_slice(&version.to_le_bytes());
+            assert!(SessionRecordingReader::new(file.as_slice()).is_err());
+        }
+    }
+}
@@ -0,0 +1,62 @@
+// Replays a bitrate trace recorded by the server and prints the requested bitrate and the limiters
+// as CSV.
+//
+// Usage: alvr_bitrate_simulator <trace.jsonl> [--session <session.json>] [--output <file.csv>]
+// With --session, the bitrate config of the session file is used instead of the recorded one.
+
+use alvr_common::anyhow::{Context, Result};
+use alvr_server_core::bitrate_simulation;
+use alvr_session::SessionConfig;
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    process,
+};
+
+fn run(args: &[String]) -> Result<()> {
+    let trace_path = args
+        .get(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .context("Missing bitrate trace path")?;
+
+    let option_value = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .map(|idx| {
+                args.get(idx + 1)
+                    .with_context(|| format!("Missing {name} value"))
+            })
+            .transpose()
+    };
+
+    let config = if let Some(path) = option_value("--session")? {
+        let mut session = SessionConfig::default();
+        session.merge_from_json(&serde_json::from_str(&fs::read_to_string(path)?)?)?;
+
+        Some(session.to_settings().video.bitrate)
+    } else {
+        None
+    };
+
+    let trace = bitrate_simulation::read_trace(BufReader::new(File::open(trace_path)?))?;
+    let samples = bitrate_simulation::simulate(&trace, config.as_ref())?;
+
+    if let Some(path) = option_value("--output")? {
+        bitrate_simulation::write_csv(&samples, BufWriter::new(File::create(path)?))
+    } else {
+        bitrate_simulation::write_csv(&samples, io::stdout().lock())
+    }
+}
+
+fn main() {
+    let args = env::args().collect::<Vec<_>>();
+
+    if let Err(e) = run(&args) {
+        eprintln!("{e:?}");
+        eprintln!(
+            "Usage: alvr_bitrate_simulator <trace.jsonl> [--session <session.json>] [--output <file.csv>]"
+        );
+        process::exit(1);
+    }
+}
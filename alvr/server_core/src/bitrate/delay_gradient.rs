@@ -0,0 +1,148 @@
+use super::{BitrateController, EncodedThroughput};
+use alvr_events::BitrateDirectives;
+use alvr_session::BitrateMode;
+use std::{collections::VecDeque, time::Duration};
+
+// Number of latency samples used to estimate the latency trend
+const TRENDLINE_WINDOW_SIZE: usize = 20;
+// Smoothing factor of the latency samples before the trend estimation
+const LATENCY_SMOOTHING: f32 = 0.9;
+
+// Delay-based controller modeled after Google Congestion Control. A rising network latency means
+// that a queue is building up along the path, so the bitrate is reduced before packets are lost.
+pub struct DelayGradientController {
+    // (timestamp in seconds, smoothed network latency in seconds)
+    latency_samples: VecDeque<(f32, f32)>,
+    smoothed_latency_s: Option<f32>,
+    encoded_throughput: EncodedThroughput,
+    bitrate_bps: f32,
+    latest_timestamp: Duration,
+    last_update_timestamp: Option<Duration>,
+}
+
+impl DelayGradientController {
+    pub fn new(initial_bitrate_bps: f32) -> Self {
+        Self {
+            latency_samples: VecDeque::new(),
+            smoothed_latency_s: None,
+            encoded_throughput: EncodedThroughput::new(),
+            bitrate_bps: initial_bitrate_bps,
+            latest_timestamp: Duration::ZERO,
+            last_update_timestamp: None,
+        }
+    }
+
+    // Slope of the least squares fit of the latency over time, in seconds per second
+    fn latency_gradient(&self) -> Option<f32> {
+        if self.latency_samples.len() < TRENDLINE_WINDOW_SIZE / 2 {
+            return None;
+        }
+
+        let count = self.latency_samples.len() as f32;
+        let mean_t = self.latency_samples.iter().map(|(t, _)| t).sum::<f32>() / count;
+        let mean_latency = self.latency_samples.iter().map(|(_, l)| l).sum::<f32>() / count;
+
+        let mut numerator = 0.0;
+        let mut denominator = 0.0;
+        for (t, latency) in &self.latency_samples {
+            numerator += (t - mean_t) * (latency - mean_latency);
+            denominator += (t - mean_t) * (t - mean_t);
+        }
+
+        (denominator > 0.0).then(|| numerator / denominator)
+    }
+}
+
+impl BitrateController for DelayGradientController {
+    fn report_frame_encoded(&mut self, timestamp: Duration, _: Duration, size_bytes: usize) {
+        self.encoded_throughput
+            .report_frame_encoded(timestamp, size_bytes);
+
+        self.latest_timestamp = Duration::max(self.latest_timestamp, timestamp);
+    }
+
+    fn report_frame_latencies(
+        &mut self,
+        _: &BitrateMode,
+        timestamp: Duration,
+        network_latency: Duration,
+        _: Duration,
+    ) {
+        if network_latency.is_zero() {
+            return;
+        }
+
+        let latency_s = network_latency.as_secs_f32();
+        let smoothed_latency_s = match self.smoothed_latency_s {
+            Some(smoothed) => LATENCY_SMOOTHING * smoothed + (1.0 - LATENCY_SMOOTHING) * latency_s,
+            None => latency_s,
+        };
+        self.smoothed_latency_s = Some(smoothed_latency_s);
+
+        self.latency_samples
+            .push_back((timestamp.as_secs_f32(), smoothed_latency_s));
+        if self.latency_samples.len() > TRENDLINE_WINDOW_SIZE {
+            self.latency_samples.pop_front();
+        }
+
+        self.latest_timestamp = Duration::max(self.latest_timestamp, timestamp);
+    }
+
+    fn get_bitrate_bps(
+        &mut self,
+        config: &BitrateMode,
+        _: Duration,
+        directives: &mut BitrateDirectives,
+    ) -> f32 {
+        let BitrateMode::DelayGradient {
+            latency_gradient_threshold_ms_per_s,
+            increase_rate,
+            decrease_multiplier,
+            max_throughput_mbps,
+            min_throughput_mbps,
+            ..
+        } = config
+        else {
+            return self.bitrate_bps;
+        };
+
+        let elapsed_s = self
+            .last_update_timestamp
+            .map(|last| self.latest_timestamp.saturating_sub(last).as_secs_f32())
+            .unwrap_or(0.0);
+        self.last_update_timestamp = Some(self.latest_timestamp);
+
+        let threshold = latency_gradient_threshold_ms_per_s / 1000.0;
+        match self.latency_gradient() {
+            Some(gradient) if gradient > threshold => {
+                // Overuse: go below the rate that caused the queue to build up
+                let throughput_bps = self
+                    .encoded_throughput
+                    .bps()
+                    .map(|throughput| f32::min(throughput, self.bitrate_bps))
+                    .unwrap_or(self.bitrate_bps);
+                self.bitrate_bps = throughput_bps * decrease_multiplier;
+
+                // Wait for new samples before judging the effect of the decrease
+                self.latency_samples.clear();
+            }
+            Some(gradient) if gradient < -threshold => {
+                // Underuse: the queue is draining, hold the bitrate
+            }
+            _ => {
+                self.bitrate_bps = self.encoded_throughput.limit_increase(
+                    self.bitrate_bps,
+                    self.bitrate_bps * (1.0 + increase_rate).powf(elapsed_s),
+                )
+            }
+        }
+        directives.scaled_calculated_throughput_bps = Some(self.bitrate_bps);
+
+        super::clamp_controller_bitrate(
+            &mut self.bitrate_bps,
+            max_throughput_mbps,
+            min_throughput_mbps,
+            directives,
+        )
+    }
+}
@@ -0,0 +1,88 @@
+use super::{BitrateController, EncodedThroughput};
+use alvr_events::BitrateDirectives;
+use alvr_session::BitrateMode;
+use std::time::Duration;
+
+// Additive increase, multiplicative decrease controller driven by the fraction of video packets
+// lost since the previous update
+pub struct LossBasedController {
+    encoded_packets: usize,
+    lost_packets: usize,
+    encoded_throughput: EncodedThroughput,
+    bitrate_bps: f32,
+    latest_timestamp: Duration,
+    last_update_timestamp: Option<Duration>,
+}
+
+impl LossBasedController {
+    pub fn new(initial_bitrate_bps: f32) -> Self {
+        Self {
+            encoded_packets: 0,
+            lost_packets: 0,
+            encoded_throughput: EncodedThroughput::new(),
+            bitrate_bps: initial_bitrate_bps,
+            latest_timestamp: Duration::ZERO,
+            last_update_timestamp: None,
+        }
+    }
+}
+
+impl BitrateController for LossBasedController {
+    fn report_frame_encoded(&mut self, timestamp: Duration, _: Duration, size_bytes: usize) {
+        self.encoded_packets += 1;
+        self.encoded_throughput
+            .report_frame_encoded(timestamp, size_bytes);
+        self.latest_timestamp = Duration::max(self.latest_timestamp, timestamp);
+    }
+
+    fn report_frame_latencies(&mut self, _: &BitrateMode, _: Duration, _: Duration, _: Duration) {}
+
+    fn report_packet_loss(&mut self, lost_packets: usize) {
+        self.lost_packets += lost_packets;
+    }
+
+    fn get_bitrate_bps(
+        &mut self,
+        config: &BitrateMode,
+        _: Duration,
+        directives: &mut BitrateDirectives,
+    ) -> f32 {
+        let BitrateMode::LossBased {
+            loss_threshold,
+            additive_increase_mbps_per_s,
+            decrease_multiplier,
+            max_throughput_mbps,
+            min_throughput_mbps,
+            ..
+        } = config
+        else {
+            return self.bitrate_bps;
+        };
+
+        let elapsed_s = self
+            .last_update_timestamp
+            .map(|last| self.latest_timestamp.saturating_sub(last).as_secs_f32())
+            .unwrap_or(0.0);
+        self.last_update_timestamp = Some(self.latest_timestamp);
+
+        let loss_fraction = self.lost_packets as f32 / usize::max(self.encoded_packets, 1) as f32;
+        if loss_fraction > *loss_threshold {
+            self.bitrate_bps *= decrease_multiplier;
+        } else {
+            self.bitrate_bps = self.encoded_throughput.limit_increase(
+                self.bitrate_bps,
+                self.bitrate_bps + additive_increase_mbps_per_s * 1e6 * elapsed_s,
+            );
+        }
+        self.encoded_packets = 0;
+        self.lost_packets = 0;
+        directives.scaled_calculated_throughput_bps = Some(self.bitrate_bps);
+
+        super::clamp_controller_bitrate(
+            &mut self.bitrate_bps,
+            max_throughput_mbps,
+            min_throughput_mbps,
+            directives,
+        )
+    }
+}
@@ -0,0 +1,710 @@
+mod adaptive;
+mod delay_gradient;
+mod loss_based;
+pub mod simulation;
+
+use adaptive::AdaptiveController;
+use alvr_common::{error, SlidingWindowAverage};
+use alvr_events::BitrateDirectives;
+use alvr_session::{
+    settings_schema::Switch, BitrateAdaptiveFramerateConfig, BitrateConfig, BitrateMode,
+};
+use alvr_sockets::TransportCongestionStats;
+use delay_gradient::DelayGradientController;
+use loss_based::LossBasedController;
+use simulation::{BitrateTraceEvent, BitrateTraceWriter};
+use std::{
+    collections::VecDeque,
+    io::Write,
+    mem,
+    time::{Duration, Instant},
+};
+
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+// Window used to measure the encoded throughput
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+// As in Google Congestion Control, the bitrate is not increased above this multiple of the
+// throughput that was actually encoded
+const MAX_INCREASE_THROUGHPUT_MULTIPLIER: f32 = 1.5;
+
+pub struct DynamicEncoderParams {
+    pub bitrate_bps: f32,
+    pub framerate: f32,
+}
+
+// Algorithm that decides the video bitrate. All controllers receive the same reports, and the
+// timestamps of the reports are used as clock, so a controller can be driven by a recorded trace.
+// Until the controller is replaced after a mode change, it can receive a config of another variant,
+// which it should ignore.
+pub trait BitrateController: Send {
+    fn report_frame_encoded(
+        &mut self,
+        timestamp: Duration,
+        encoder_latency: Duration,
+        size_bytes: usize,
+    );
+
+    fn report_frame_latencies(
+        &mut self,
+        config: &BitrateMode,
+        timestamp: Duration,
+        network_latency: Duration,
+        decoder_latency: Duration,
+    );
+
+    // Packets lost or discarded by the client since the previous report
+    fn report_packet_loss(&mut self, _lost_packets: usize) {}
+
+    fn report_transport_congestion(&mut self, _stats: &TransportCongestionStats) {}
+
+    // Whether the bitrate should be updated without waiting for the update interval
+    fn update_needed(&mut self) -> bool {
+        false
+    }
+
+    // The limiters that have been applied are recorded in directives
+    fn get_bitrate_bps(
+        &mut self,
+        config: &BitrateMode,
+        nominal_frame_interval: Duration,
+        directives: &mut BitrateDirectives,
+    ) -> f32;
+}
+
+struct ConstantController;
+
+impl BitrateController for ConstantController {
+    fn report_frame_encoded(&mut self, _: Duration, _: Duration, _: usize) {}
+
+    fn report_frame_latencies(&mut self, _: &BitrateMode, _: Duration, _: Duration, _: Duration) {}
+
+    fn get_bitrate_bps(
+        &mut self,
+        config: &BitrateMode,
+        _: Duration,
+        _: &mut BitrateDirectives,
+    ) -> f32 {
+        if let BitrateMode::ConstantMbps(bitrate_mbps) = config {
+            *bitrate_mbps as f32 * 1e6
+        } else {
+            0.0
+        }
+    }
+}
+
+fn create_controller(mode: &BitrateMode, max_history_size: usize) -> Box<dyn BitrateController> {
+    match mode {
+        BitrateMode::ConstantMbps(_) => Box::new(ConstantController),
+        BitrateMode::Adaptive { .. } => Box::new(AdaptiveController::new(max_history_size)),
+        BitrateMode::DelayGradient {
+            initial_bitrate_mbps,
+            ..
+        } => Box::new(DelayGradientController::new(
+            *initial_bitrate_mbps as f32 * 1e6,
+        )),
+        BitrateMode::LossBased {
+            initial_bitrate_mbps,
+            ..
+        } => Box::new(LossBasedController::new(*initial_bitrate_mbps as f32 * 1e6)),
+    }
+}
+
+// Throughput of the recently encoded frames
+struct EncodedThroughput {
+    // (timestamp, size in bytes)
+    frames: VecDeque<(Duration, usize)>,
+}
+
+impl EncodedThroughput {
+    fn new() -> Self {
+        Self {
+            frames: VecDeque::new(),
+        }
+    }
+
+    fn report_frame_encoded(&mut self, timestamp: Duration, size_bytes: usize) {
+        self.frames.push_back((timestamp, size_bytes));
+        while let Some((first_timestamp, _)) = self.frames.front() {
+            if timestamp.saturating_sub(*first_timestamp) > THROUGHPUT_WINDOW {
+                self.frames.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn bps(&self) -> Option<f32> {
+        let (first_timestamp, _) = self.frames.front()?;
+        let (last_timestamp, _) = self.frames.back()?;
+        let span_s = (*last_timestamp - *first_timestamp).as_secs_f32();
+        if span_s <= 0.0 {
+            return None;
+        }
+
+        // The size of the first frame is excluded, since it was sent before the span starts
+        let bytes = self
+            .frames
+            .iter()
+            .skip(1)
+            .map(|(_, size)| size)
+            .sum::<usize>();
+
+        Some(bytes as f32 * 8.0 / span_s)
+    }
+
+    // Limits an increase of the bitrate. Without this, on a link that is never congested the
+    // bitrate would grow without bound, even if the encoder does not produce it.
+    fn limit_increase(&self, previous_bitrate_bps: f32, bitrate_bps: f32) -> f32 {
+        match self.bps() {
+            Some(throughput_bps) if bitrate_bps > previous_bitrate_bps => f32::min(
+                bitrate_bps,
+                f32::max(
+                    previous_bitrate_bps,
+                    throughput_bps * MAX_INCREASE_THROUGHPUT_MULTIPLIER,
+                ),
+            ),
+            _ => bitrate_bps,
+        }
+    }
+}
+
+// Clamps the bitrate to the limits set by the user
+fn apply_manual_limits(
+    mut bitrate_bps: f32,
+    max_throughput_mbps: &Switch<u64>,
+    min_throughput_mbps: &Switch<u64>,
+    directives: &mut BitrateDirectives,
+) -> f32 {
+    if let Switch::Enabled(max) = max_throughput_mbps {
+        let max_bps = *max as f32 * 1e6;
+        bitrate_bps = f32::min(bitrate_bps, max_bps);
+
+        directives.manual_max_throughput_bps = Some(max_bps);
+    }
+    if let Switch::Enabled(min) = min_throughput_mbps {
+        let min_bps = *min as f32 * 1e6;
+        bitrate_bps = f32::max(bitrate_bps, min_bps);
+
+        directives.manual_min_throughput_bps = Some(min_bps);
+    }
+
+    bitrate_bps
+}
+
+// Used by the controllers that keep the bitrate as their state. Keeping the state inside the
+// limits set by the user avoids the bitrate taking long to come back from beyond them.
+fn clamp_controller_bitrate(
+    bitrate_bps: &mut f32,
+    max_throughput_mbps: &Switch<u64>,
+    min_throughput_mbps: &Switch<u64>,
+    directives: &mut BitrateDirectives,
+) -> f32 {
+    *bitrate_bps = apply_manual_limits(
+        *bitrate_bps,
+        max_throughput_mbps,
+        min_throughput_mbps,
+        directives,
+    );
+
+    *bitrate_bps
+}
+
+pub struct BitrateManager {
+    max_history_size: usize,
+    nominal_frame_interval: Duration,
+    frame_interval_average: SlidingWindowAverage<Duration>,
+    controller: Box<dyn BitrateController>,
+    controller_mode: mem::Discriminant<BitrateMode>,
+    last_frame_instant: Instant,
+    last_update_instant: Instant,
+    previous_config: Option<BitrateConfig>,
+    update_needed: bool,
+    trace: Option<BitrateTraceWriter>,
+}
+
+impl BitrateManager {
+    // The controller is created for the initial mode, so the statistics reported before the first
+    // update are not lost
+    pub fn new(
+        max_history_size: usize,
+        initial_framerate: f32,
+        initial_mode: &BitrateMode,
+    ) -> Self {
+        Self {
+            max_history_size,
+            nominal_frame_interval: Duration::from_secs_f32(1. / initial_framerate),
+            frame_interval_average: SlidingWindowAverage::new(
+                Duration::from_millis(16),
+                max_history_size,
+            ),
+            controller: create_controller(initial_mode, max_history_size),
+            controller_mode: mem::discriminant(initial_mode),
+            last_frame_instant: Instant::now(),
+            last_update_instant: Instant::now(),
+            previous_config: None,
+            update_needed: true,
+            trace: None,
+        }
+    }
+
+    // Record the inputs of the manager, to replay them with the bitrate simulation
+    pub fn start_trace(&mut self, writer: Box<dyn Write + Send>) {
+        self.trace = Some(BitrateTraceWriter::new(writer));
+
+        let max_history_size = self.max_history_size;
+        let framerate = 1.0 / self.nominal_frame_interval.as_secs_f32();
+        self.write_trace(|| BitrateTraceEvent::Start {
+            max_history_size,
+            framerate,
+        });
+        if let Some(config) = self.previous_config.clone() {
+            self.write_trace(|| BitrateTraceEvent::Config(config));
+        }
+    }
+
+    pub fn stop_trace(&mut self) {
+        if let Some(mut trace) = self.trace.take() {
+            if let Err(e) = trace.flush() {
+                error!("Failed to write bitrate trace: {e}");
+            }
+        }
+    }
+
+    // The event is created only if a trace is in progress
+    fn write_trace(&mut self, event: impl FnOnce() -> BitrateTraceEvent) {
+        if let Some(trace) = &mut self.trace {
+            if let Err(e) = trace.write(event()) {
+                error!("Failed to write bitrate trace, stopping: {e}");
+                self.trace = None;
+            }
+        }
+    }
+
+    // Note: This is used to calculate the framerate/frame interval. The frame present is the most
+    // accurate event for this use.
+    pub fn report_frame_present(&mut self, config: &Switch<BitrateAdaptiveFramerateConfig>) {
+        self.frame_present_at(config, Instant::now());
+    }
+
+    fn frame_present_at(&mut self, config: &Switch<BitrateAdaptiveFramerateConfig>, now: Instant) {
+        self.write_trace(|| BitrateTraceEvent::FramePresent);
+
+        let interval = now.saturating_duration_since(self.last_frame_instant);
+        self.last_frame_instant = now;
+
+        if let Some(config) = config.as_option() {
+            let interval_ratio =
+                interval.as_secs_f32() / self.frame_interval_average.get_average().as_secs_f32();
+
+            self.frame_interval_average.submit_sample(interval);
+
+            if interval_ratio > config.framerate_reset_threshold_multiplier
+                || interval_ratio < 1.0 / config.framerate_reset_threshold_multiplier
+            {
+                // Clear most of the samples, keep some for stability
+                self.frame_interval_average.retain(5);
+                self.update_needed = true;
+            }
+        }
+    }
+
+    pub fn report_frame_encoded(
+        &mut self,
+        timestamp: Duration,
+        encoder_latency: Duration,
+        size_bytes: usize,
+    ) {
+        self.write_trace(|| BitrateTraceEvent::FrameEncoded {
+            timestamp,
+            encoder_latency,
+            size_bytes,
+        });
+
+        self.controller
+            .report_frame_encoded(timestamp, encoder_latency, size_bytes);
+    }
+
+    // decoder_latency is used to learn a suitable maximum bitrate bound to avoid decoder runaway
+    // latency
+    pub fn report_frame_latencies(
+        &mut self,
+        config: &BitrateMode,
+        timestamp: Duration,
+        network_latency: Duration,
+        decoder_latency: Duration,
+    ) {
+        self.write_trace(|| BitrateTraceEvent::FrameLatencies {
+            timestamp,
+            network_latency,
+            decoder_latency,
+        });
+
+        self.controller
+            .report_frame_latencies(config, timestamp, network_latency, decoder_latency);
+    }
+
+    pub fn report_packet_loss(&mut self, lost_packets: usize) {
+        self.write_trace(|| BitrateTraceEvent::PacketLoss(lost_packets));
+
+        self.controller.report_packet_loss(lost_packets);
+    }
+
+    pub fn report_transport_congestion(&mut self, stats: &TransportCongestionStats) {
+        self.write_trace(|| BitrateTraceEvent::TransportCongestion {
+            rtt: stats.rtt,
+            congestion_window_bytes: stats.congestion_window_bytes,
+        });
+
+        self.controller.report_transport_congestion(stats);
+    }
+
+    pub fn get_encoder_params(
+        &mut self,
+        config: &BitrateConfig,
+    ) -> Option<(DynamicEncoderParams, BitrateDirectives)> {
+        self.encoder_params_at(config, Instant::now())
+    }
+
+    // now is provided by the caller to be able to replay a trace faster than real time
+    fn encoder_params_at(
+        &mut self,
+        config: &BitrateConfig,
+        now: Instant,
+    ) -> Option<(DynamicEncoderParams, BitrateDirectives)> {
+        let controller_update_needed = self.controller.update_needed();
+
+        if self
+            .previous_config
+            .as_ref()
+            .map(|prev| config != prev)
+            .unwrap_or(true)
+        {
+            if mem::discriminant(&config.mode) != self.controller_mode {
+                self.controller = create_controller(&config.mode, self.max_history_size);
+                self.controller_mode = mem::discriminant(&config.mode);
+            }
+
+            self.previous_config = Some(config.clone());
+            self.write_trace(|| BitrateTraceEvent::Config(config.clone()));
+            // Continue method. Always update bitrate in this case
+        } else if !self.update_needed
+            && !controller_update_needed
+            && (now < self.last_update_instant + UPDATE_INTERVAL
+                || matches!(config.mode, BitrateMode::ConstantMbps(_)))
+        {
+            return None;
+        }
+
+        self.last_update_instant = now;
+        self.update_needed = false;
+
+        let frame_interval = if config.adapt_to_framerate.enabled() {
+            self.frame_interval_average.get_average()
+        } else {
+            self.nominal_frame_interval
+        };
+
+        let mut bitrate_directives = BitrateDirectives::default();
+
+        let bitrate_bps = self.controller.get_bitrate_bps(
+            &config.mode,
+            self.nominal_frame_interval,
+            &mut bitrate_directives,
+        );
+
+        bitrate_directives.requested_bitrate_bps = bitrate_bps;
+
+        Some((
+            DynamicEncoderParams {
+                bitrate_bps,
+                framerate: 1.0 / f32::min(frame_interval.as_secs_f32(), 1.0),
+            },
+            bitrate_directives,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FPS: f32 = 72.0;
+
+    fn frame_interval() -> Duration {
+        Duration::from_secs_f32(1.0 / FPS)
+    }
+
+    // Feeds one second of frames at the given bitrate, with network latency and lost packets
+    // depending on the frame timestamp, then returns the new bitrate
+    fn run_second(
+        controller: &mut dyn BitrateController,
+        config: &BitrateMode,
+        second: u32,
+        bitrate_bps: f32,
+        network_latency: impl Fn(Duration) -> Duration,
+        lost_packets: usize,
+    ) -> (f32, BitrateDirectives) {
+        for frame in 0..FPS as u32 {
+            let timestamp = Duration::from_secs(second as u64) + frame_interval() * frame;
+
+            controller.report_frame_encoded(
+                timestamp,
+                Duration::from_millis(5),
+                (bitrate_bps / 8.0 / FPS) as usize,
+            );
+            controller.report_frame_latencies(
+                config,
+                timestamp,
+                network_latency(timestamp),
+                Duration::from_millis(5),
+            );
+        }
+        controller.report_packet_loss(lost_packets);
+
+        let mut directives = BitrateDirectives::default();
+        let bitrate_bps = controller.get_bitrate_bps(config, frame_interval(), &mut directives);
+
+        (bitrate_bps, directives)
+    }
+
+    fn delay_gradient_config() -> BitrateMode {
+        BitrateMode::DelayGradient {
+            initial_bitrate_mbps: 30,
+            latency_gradient_threshold_ms_per_s: 2.0,
+            increase_rate: 0.08,
+            decrease_multiplier: 0.85,
+            max_throughput_mbps: Switch::Disabled,
+            min_throughput_mbps: Switch::Disabled,
+        }
+    }
+
+    fn loss_based_config() -> BitrateMode {
+        BitrateMode::LossBased {
+            initial_bitrate_mbps: 30,
+            loss_threshold: 0.02,
+            additive_increase_mbps_per_s: 2.0,
+            decrease_multiplier: 0.8,
+            max_throughput_mbps: Switch::Enabled(100),
+            min_throughput_mbps: Switch::Enabled(5),
+        }
+    }
+
+    #[test]
+    fn test_constant() {
+        let config = BitrateMode::ConstantMbps(30);
+        let mut controller = create_controller(&config, 256);
+
+        let (bitrate_bps, _) = run_second(
+            &mut *controller,
+            &config,
+            0,
+            30e6,
+            |_| Duration::from_millis(5),
+            10,
+        );
+
+        assert_eq!(bitrate_bps, 30e6);
+    }
+
+    #[test]
+    fn test_adaptive_limits() {
+        let config = BitrateMode::Adaptive {
+            saturation_multiplier: 0.95,
+            max_throughput_mbps: Switch::Enabled(50),
+            min_throughput_mbps: Switch::Disabled,
+            max_network_latency_ms: Switch::Disabled,
+            encoder_latency_limiter: Switch::Disabled,
+            decoder_latency_limiter: Switch::Disabled,
+        };
+        let mut controller = create_controller(&config, 256);
+
+        // 30 Mbps in 5 ms frames means a throughput well above 50 Mbps
+        let (bitrate_bps, directives) = run_second(
+            &mut *controller,
+            &config,
+            0,
+            30e6,
+            |_| Duration::from_millis(5),
+            0,
+        );
+        assert!(directives.scaled_calculated_throughput_bps.unwrap() > 50e6);
+        assert_eq!(bitrate_bps, 50e6);
+
+        controller.report_transport_congestion(&TransportCongestionStats {
+            rtt: Duration::from_millis(10),
+            congestion_window_bytes: 25_000,
+            ..Default::default()
+        });
+        let (bitrate_bps, directives) = run_second(
+            &mut *controller,
+            &config,
+            1,
+            30e6,
+            |_| Duration::from_millis(5),
+            0,
+        );
+        assert!((directives.transport_congestion_limiter_bps.unwrap() - 20e6).abs() < 1.0);
+        assert!((bitrate_bps - 20e6).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_delay_gradient_stable_latency() {
+        let config = delay_gradient_config();
+        let mut controller = create_controller(&config, 256);
+
+        let mut bitrate_bps = 30e6;
+        let mut previous_bitrate_bps = 0.0;
+        for second in 0..5 {
+            (bitrate_bps, _) = run_second(
+                &mut *controller,
+                &config,
+                second,
+                bitrate_bps,
+                |_| Duration::from_millis(5),
+                0,
+            );
+            assert!(bitrate_bps >= previous_bitrate_bps);
+            previous_bitrate_bps = bitrate_bps;
+        }
+
+        assert!(bitrate_bps > 30e6);
+    }
+
+    #[test]
+    fn test_delay_gradient_rising_latency() {
+        let config = delay_gradient_config();
+        let mut controller = create_controller(&config, 256);
+
+        let mut bitrate_bps = 30e6;
+        for second in 0..3 {
+            (bitrate_bps, _) = run_second(
+                &mut *controller,
+                &config,
+                second,
+                bitrate_bps,
+                |_| Duration::from_millis(5),
+                0,
+            );
+        }
+        let uncongested_bitrate_bps = bitrate_bps;
+
+        // A queue builds up, the latency grows by 20 ms per second
+        let (bitrate_bps, _) = run_second(
+            &mut *controller,
+            &config,
+            3,
+            bitrate_bps,
+            |timestamp| Duration::from_millis(5) + (timestamp - Duration::from_secs(3)) / 50,
+            0,
+        );
+
+        assert!(bitrate_bps < uncongested_bitrate_bps * 0.9);
+    }
+
+    #[test]
+    fn test_statistics_before_first_update() {
+        let config = BitrateConfig {
+            mode: delay_gradient_config(),
+            adapt_to_framerate: Switch::Disabled,
+            history_size: 256,
+            image_corruption_fix: false,
+        };
+        let mut manager = BitrateManager::new(256, FPS, &config.mode);
+
+        // A queue builds up before the encoder asks for the first bitrate
+        for frame in 0..FPS as u32 {
+            let timestamp = frame_interval() * frame;
+            manager.report_frame_encoded(timestamp, Duration::from_millis(5), 50_000);
+            manager.report_frame_latencies(
+                &config.mode,
+                timestamp,
+                Duration::from_millis(5) + timestamp / 50,
+                Duration::from_millis(5),
+            );
+        }
+
+        let (params, _) = manager.get_encoder_params(&config).unwrap();
+        assert!(params.bitrate_bps < 30e6 * 0.9);
+
+        // Changing the mode replaces the controller
+        let config = BitrateConfig {
+            mode: BitrateMode::ConstantMbps(20),
+            ..config
+        };
+        let (params, _) = manager.get_encoder_params(&config).unwrap();
+        assert_eq!(params.bitrate_bps, 20e6);
+    }
+
+    #[test]
+    fn test_increase_limited_by_encoded_throughput() {
+        for config in [delay_gradient_config(), loss_based_config()] {
+            let mut controller = create_controller(&config, 256);
+
+            // The encoder produces 10 Mbps on a clean link, whatever the requested bitrate
+            let mut bitrate_bps = 0.0;
+            for second in 0..30 {
+                (bitrate_bps, _) = run_second(
+                    &mut *controller,
+                    &config,
+                    second,
+                    10e6,
+                    |_| Duration::from_millis(5),
+                    0,
+                );
+            }
+
+            // Not increased above the initial bitrate, since 30 Mbps > 1.5 * 10 Mbps
+            assert_eq!(bitrate_bps, 30e6);
+        }
+    }
+
+    #[test]
+    fn test_loss_based() {
+        let config = loss_based_config();
+        let mut controller = create_controller(&config, 256);
+
+        let (bitrate_bps, _) = run_second(
+            &mut *controller,
+            &config,
+            0,
+            30e6,
+            |_| Duration::from_millis(5),
+            0,
+        );
+        let (bitrate_bps, _) = run_second(
+            &mut *controller,
+            &config,
+            1,
+            bitrate_bps,
+            |_| Duration::from_millis(5),
+            0,
+        );
+        // Additive increase of 2 Mbps over about one second
+        assert!((bitrate_bps - 32e6).abs() < 0.1e6);
+
+        // 10% loss
+        let (bitrate_bps, _) = run_second(
+            &mut *controller,
+            &config,
+            2,
+            bitrate_bps,
+            |_| Duration::from_millis(5),
+            7,
+        );
+        assert!(bitrate_bps < 32e6 * 0.85);
+
+        // Sustained loss is bounded by the minimum bitrate
+        let mut bitrate_bps = bitrate_bps;
+        for second in 3..20 {
+            (bitrate_bps, _) = run_second(
+                &mut *controller,
+                &config,
+                second,
+                bitrate_bps,
+                |_| Duration::from_millis(5),
+                20,
+            );
+        }
+        assert_eq!(bitrate_bps, 5e6);
+    }
+}
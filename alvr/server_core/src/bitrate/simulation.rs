@@ -0,0 +1,386 @@
+// Offline replay of the bitrate controller.
+//
+// A trace contains the inputs of a BitrateManager during a real session, one JSON encoded entry
+// per line. Replaying it with the same or a tweaked BitrateConfig gives the bitrate that would have
+// been requested, together with the limiters that produced it. The simulation does not model the
+// feedback of the bitrate on the network: the recorded latencies and losses are used as they are.
+
+use super::BitrateManager;
+use alvr_common::anyhow::{bail, Result};
+use alvr_events::BitrateDirectives;
+use alvr_session::BitrateConfig;
+use alvr_sockets::TransportCongestionStats;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{BufRead, Write},
+    time::{Duration, Instant},
+};
+
+pub const BITRATE_TRACE_EXTENSION: &str = "jsonl";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum BitrateTraceEvent {
+    Start {
+        max_history_size: usize,
+        framerate: f32,
+    },
+    Config(BitrateConfig),
+    // The time of the entry is used as the present time
+    FramePresent,
+    FrameEncoded {
+        timestamp: Duration,
+        encoder_latency: Duration,
+        size_bytes: usize,
+    },
+    // Derived from the client statistics
+    FrameLatencies {
+        timestamp: Duration,
+        network_latency: Duration,
+        decoder_latency: Duration,
+    },
+    PacketLoss(usize),
+    TransportCongestion {
+        rtt: Duration,
+        congestion_window_bytes: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BitrateTraceEntry {
+    // Time since the start of the trace
+    pub time: Duration,
+    pub event: BitrateTraceEvent,
+}
+
+pub struct BitrateTraceWriter {
+    writer: Box<dyn Write + Send>,
+    start_instant: Instant,
+}
+
+impl BitrateTraceWriter {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer,
+            start_instant: Instant::now(),
+        }
+    }
+
+    pub fn write(&mut self, event: BitrateTraceEvent) -> Result<()> {
+        let entry = BitrateTraceEntry {
+            time: self.start_instant.elapsed(),
+            event,
+        };
+
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        self.writer.write_all(b"\n")?;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+// A truncated last line (e.g. the server crashed while tracing) is ignored
+pub fn read_trace(reader: impl BufRead) -> Result<Vec<BitrateTraceEntry>> {
+    let lines = reader.lines().collect::<Result<Vec<_>, _>>()?;
+
+    let mut entries = vec![];
+    for (idx, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(_) if idx == lines.len() - 1 => break,
+            Err(e) => bail!("Invalid bitrate trace entry at line {}: {e}", idx + 1),
+        }
+    }
+
+    Ok(entries)
+}
+
+pub struct SimulationSample {
+    // Time since the start of the trace
+    pub time: Duration,
+    pub directives: BitrateDirectives,
+}
+
+// Replays the trace through a new BitrateManager. If config is None, the configs recorded in the
+// trace are used. A sample is produced for each bitrate update.
+pub fn simulate(
+    trace: &[BitrateTraceEntry],
+    config: Option<&BitrateConfig>,
+) -> Result<Vec<SimulationSample>> {
+    let Some((max_history_size, framerate)) = trace.iter().find_map(|entry| match entry.event {
+        BitrateTraceEvent::Start {
+            max_history_size,
+            framerate,
+        } => Some((max_history_size, framerate)),
+        _ => None,
+    }) else {
+        bail!("Missing start of the bitrate trace");
+    };
+    let Some(initial_config) = config.or_else(|| {
+        trace.iter().find_map(|entry| match &entry.event {
+            BitrateTraceEvent::Config(config) => Some(config),
+            _ => None,
+        })
+    }) else {
+        bail!("Missing bitrate config in the trace");
+    };
+
+    let start_instant = Instant::now();
+    let mut manager = BitrateManager::new(max_history_size, framerate, &initial_config.mode);
+    manager.last_frame_instant = start_instant;
+    let mut current_config = config.cloned();
+
+    let mut samples = vec![];
+    for entry in trace {
+        match &entry.event {
+            BitrateTraceEvent::Start { .. } => (),
+            BitrateTraceEvent::Config(recorded_config) => {
+                if config.is_none() {
+                    current_config = Some(recorded_config.clone());
+                }
+            }
+            BitrateTraceEvent::FramePresent => {
+                if let Some(config) = &current_config {
+                    manager
+                        .frame_present_at(&config.adapt_to_framerate, start_instant + entry.time);
+                }
+            }
+            BitrateTraceEvent::FrameEncoded {
+                timestamp,
+                encoder_latency,
+                size_bytes,
+            } => {
+                manager.report_frame_encoded(*timestamp, *encoder_latency, *size_bytes);
+
+                // The encoder requests the parameters before each frame
+                if let Some(config) = &current_config {
+                    if let Some((_, directives)) =
+                        manager.encoder_params_at(config, start_instant + entry.time)
+                    {
+                        samples.push(SimulationSample {
+                            time: entry.time,
+                            directives,
+                        });
+                    }
+                }
+            }
+            BitrateTraceEvent::FrameLatencies {
+                timestamp,
+                network_latency,
+                decoder_latency,
+            } => {
+                if let Some(config) = &current_config {
+                    manager.report_frame_latencies(
+                        &config.mode,
+                        *timestamp,
+                        *network_latency,
+                        *decoder_latency,
+                    );
+                }
+            }
+            BitrateTraceEvent::PacketLoss(lost_packets) => {
+                manager.report_packet_loss(*lost_packets);
+            }
+            BitrateTraceEvent::TransportCongestion {
+                rtt,
+                congestion_window_bytes,
+            } => manager.report_transport_congestion(&TransportCongestionStats {
+                rtt: *rtt,
+                congestion_window_bytes: *congestion_window_bytes,
+                ..Default::default()
+            }),
+        }
+    }
+
+    Ok(samples)
+}
+
+// Limiters that were not applied are left empty
+pub fn write_csv(samples: &[SimulationSample], mut writer: impl Write) -> Result<()> {
+    writeln!(
+        writer,
+        "time_s,requested_bitrate_bps,scaled_calculated_throughput_bps,\
+        decoder_latency_limiter_bps,network_latency_limiter_bps,encoder_latency_limiter_bps,\
+        transport_congestion_limiter_bps,manual_max_throughput_bps,manual_min_throughput_bps"
+    )?;
+
+    fn field(value: Option<f32>) -> String {
+        value.map(|value| value.to_string()).unwrap_or_default()
+    }
+
+    for sample in samples {
+        let d = &sample.directives;
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{}",
+            sample.time.as_secs_f32(),
+            d.requested_bitrate_bps,
+            field(d.scaled_calculated_throughput_bps),
+            field(d.decoder_latency_limiter_bps),
+            field(d.network_latency_limiter_bps),
+            field(d.encoder_latency_limiter_bps),
+            field(d.transport_congestion_limiter_bps),
+            field(d.manual_max_throughput_bps),
+            field(d.manual_min_throughput_bps),
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alvr_common::parking_lot::Mutex;
+    use alvr_session::{settings_schema::Switch, BitrateAdaptiveFramerateConfig, BitrateMode};
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn config() -> BitrateConfig {
+        BitrateConfig {
+            mode: BitrateMode::ConstantMbps(30),
+            adapt_to_framerate: Switch::Enabled(BitrateAdaptiveFramerateConfig {
+                framerate_reset_threshold_multiplier: 1.5,
+            }),
+            history_size: 256,
+            image_corruption_fix: false,
+        }
+    }
+
+    fn entry(time: Duration, event: BitrateTraceEvent) -> BitrateTraceEntry {
+        BitrateTraceEntry { time, event }
+    }
+
+    // Frames presented and encoded at 72 fps for two seconds, then at 36 fps
+    fn trace() -> Vec<BitrateTraceEntry> {
+        let mut trace = vec![
+            entry(
+                Duration::ZERO,
+                BitrateTraceEvent::Start {
+                    max_history_size: 256,
+                    framerate: 72.0,
+                },
+            ),
+            entry(Duration::ZERO, BitrateTraceEvent::Config(config())),
+        ];
+
+        let mut time = Duration::ZERO;
+        while time < Duration::from_secs(3) {
+            trace.push(entry(time, BitrateTraceEvent::FramePresent));
+            trace.push(entry(
+                time,
+                BitrateTraceEvent::FrameEncoded {
+                    timestamp: time,
+                    encoder_latency: Duration::from_millis(5),
+                    size_bytes: 50_000,
+                },
+            ));
+
+            time += if time < Duration::from_secs(2) {
+                Duration::from_secs_f32(1.0 / 72.0)
+            } else {
+                Duration::from_secs_f32(1.0 / 36.0)
+            };
+        }
+
+        trace
+    }
+
+    #[test]
+    fn trace_round_trip() {
+        let buffer = SharedBuffer::default();
+
+        let mut writer = BitrateTraceWriter::new(Box::new(buffer.clone()));
+        for entry in trace() {
+            writer.write(entry.event).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let written = buffer.0.lock().clone();
+        let entries = read_trace(written.as_slice()).unwrap();
+        assert_eq!(entries.len(), trace().len());
+        for (read, original) in entries.iter().zip(trace()) {
+            assert_eq!(
+                serde_json::to_string(&read.event).unwrap(),
+                serde_json::to_string(&original.event).unwrap()
+            );
+        }
+
+        // Truncated last line
+        let truncated = &written[..written.len() - 10];
+        assert_eq!(read_trace(truncated).unwrap().len(), trace().len() - 1);
+
+        // Corrupted line in the middle
+        let mut corrupted = b"{\n".to_vec();
+        corrupted.extend_from_slice(&written);
+        assert!(read_trace(corrupted.as_slice()).is_err());
+    }
+
+    #[test]
+    fn simulate_trace() {
+        assert!(simulate(&trace()[1..], None).is_err());
+
+        // Constant bitrate is updated only when the config or the framerate change
+        let samples = simulate(&trace(), None).unwrap();
+        assert_eq!(samples[0].directives.requested_bitrate_bps, 30e6);
+        assert!(!samples
+            .iter()
+            .any(|sample| sample.time > Duration::from_millis(500)
+                && sample.time < Duration::from_secs(2)));
+        assert!(samples
+            .iter()
+            .any(|sample| sample.time >= Duration::from_secs(2)));
+
+        // The config argument replaces the recorded config
+        let samples = simulate(
+            &trace(),
+            Some(&BitrateConfig {
+                mode: BitrateMode::ConstantMbps(50),
+                ..config()
+            }),
+        )
+        .unwrap();
+        assert_eq!(samples[0].directives.requested_bitrate_bps, 50e6);
+    }
+
+    #[test]
+    fn csv_output() {
+        let samples = [SimulationSample {
+            time: Duration::from_millis(1500),
+            directives: BitrateDirectives {
+                manual_max_throughput_bps: Some(100e6),
+                requested_bitrate_bps: 30e6,
+                ..Default::default()
+            },
+        }];
+
+        let mut csv = vec![];
+        write_csv(&samples, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].split(',').count(), 9);
+        assert_eq!(lines[1], "1.5,30000000,,,,,,100000000,");
+    }
+}
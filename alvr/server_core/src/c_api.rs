@@ -536,6 +536,18 @@ pub extern "C" fn alvr_report_present(timestamp_ns: u64, offset_ns: u64) {
     }
 }
 
+/// application_key can be null if no application is running
+#[no_mangle]
+pub unsafe extern "C" fn alvr_report_running_application(application_key: *const c_char) {
+    if let Some(context) = &*SERVER_CORE_CONTEXT.read() {
+        context.report_running_application((!application_key.is_null()).then(|| {
+            CStr::from_ptr(application_key)
+                .to_string_lossy()
+                .into_owned()
+        }));
+    }
+}
+
 /// Retrun true if a valid value is provided
 #[no_mangle]
 pub unsafe extern "C" fn alvr_duration_until_next_vsync(out_ns: *mut u64) -> bool {
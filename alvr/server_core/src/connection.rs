@@ -1,11 +1,12 @@
 use crate::{
     bitrate::BitrateManager,
     hand_gestures::HandGestureManager,
-    input_mapping::ButtonMappingManager,
+    haptics::{self, HAPTICS_MIX_INTERVAL},
+    input_mapping::{self, ButtonMappingManager},
     sockets::WelcomeSocket,
     statistics::StatisticsManager,
     tracking::{self, TrackingManager},
-    ConnectionContext, ServerCoreEvent, ViewsConfig, SESSION_MANAGER,
+    ConnectionContext, ServerCoreEvent, SpectatorContext, ViewsConfig, SESSION_MANAGER,
 };
 use alvr_audio::AudioDevice;
 use alvr_common::{
@@ -19,22 +20,28 @@ use alvr_common::{
 };
 use alvr_events::{ButtonEvent, EventType};
 use alvr_packets::{
-    BatteryInfo, ClientConnectionResult, ClientControlPacket, ClientListAction, ClientStatistics,
-    NegotiatedStreamingConfig, ReservedClientControlPacket, ServerControlPacket, Tracking,
-    VideoPacketHeader, AUDIO, HAPTICS, STATISTICS, TRACKING, VIDEO,
+    BatteryInfo, ClientConnectionResult, ClientControlPacket, ClientKeyExchange, ClientListAction,
+    ClientStatistics, NegotiatedStreamingConfig, RecordedPacket, ReservedClientControlPacket,
+    ServerControlPacket, ServerKeyConfirmation, ServerKeyExchange, Tracking, VideoPacketHeader,
+    AUDIO, HAPTICS, STATISTICS, TRACKING, VIDEO,
 };
 use alvr_session::{
-    BodyTrackingSinkConfig, CodecType, ControllersEmulationMode, FrameSize, H264Profile,
-    OpenvrConfig, SessionConfig,
+    BodyTrackingSinkConfig, ButtonMappingProfile, CodecType, ControllersConfig,
+    ControllersEmulationMode, FrameSize, H264Profile, OpenvrConfig, SessionConfig, SocketProtocol,
 };
 use alvr_sockets::{
-    PeerType, ProtoControlSocket, StreamSocketBuilder, KEEPALIVE_INTERVAL, KEEPALIVE_TIMEOUT,
+    HandshakeRole, KeyExchange, LongTermSecret, PeerType, ProtoControlSocket, StreamSocketBuilder,
+    KEEPALIVE_INTERVAL, KEEPALIVE_TIMEOUT,
 };
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    mem,
     net::IpAddr,
     process::Command,
-    sync::{mpsc::RecvTimeoutError, Arc},
+    sync::{
+        mpsc::{RecvTimeoutError, SyncSender},
+        Arc,
+    },
     thread,
     time::{Duration, Instant},
 };
@@ -42,6 +49,8 @@ use std::{
 const RETRY_CONNECT_MIN_INTERVAL: Duration = Duration::from_secs(1);
 const HANDSHAKE_ACTION_TIMEOUT: Duration = Duration::from_secs(2);
 pub const STREAMING_RECV_TIMEOUT: Duration = Duration::from_millis(500);
+// Button mapping expressions can change over time, for example with long_press()
+const BUTTON_EXPRESSIONS_UPDATE_INTERVAL: Duration = Duration::from_millis(10);
 
 const MAX_UNREAD_PACKETS: usize = 10; // Applies per stream
 
@@ -50,10 +59,66 @@ pub struct VideoPacket {
     pub payload: Vec<u8>,
 }
 
+pub struct VideoChannel {
+    pub sender: SyncSender<VideoPacket>,
+    // Start in the corrupted state, the client didn't receive the initial IDR yet
+    pub stream_corrupted: bool,
+}
+
+// Stream port reserved for a spectator until dropped
+struct SpectatorStreamPort {
+    ctx: Arc<ConnectionContext>,
+    port: u16,
+}
+
+impl Drop for SpectatorStreamPort {
+    fn drop(&mut self) {
+        self.ctx.spectator_stream_ports.lock().remove(&self.port);
+    }
+}
+
 fn align32(value: f32) -> u32 {
     ((value / 32.).floor() * 32.) as u32
 }
 
+// The mappings of a profile have priority over the default manual or automatic mappings.
+// button_set contains the buttons of the active interaction profile, if known
+fn create_button_mapping_manager(
+    config: &ControllersConfig,
+    profile: Option<&ButtonMappingProfile>,
+    button_set: Option<&HashSet<u64>>,
+    emulation_mode: &ControllersEmulationMode,
+) -> Option<ButtonMappingManager> {
+    if let Some(profile) = profile {
+        Some(ButtonMappingManager::new_manual(&profile.button_mappings))
+    } else if let Some(mappings) = &config.button_mappings {
+        Some(ButtonMappingManager::new_manual(mappings))
+    } else {
+        button_set.map(|button_set| {
+            ButtonMappingManager::new_automatic(
+                button_set,
+                emulation_mode,
+                &config.button_mapping_config,
+            )
+        })
+    }
+}
+
+// The new mappings start from released sources, so the outputs held by the old ones would stay
+// pressed
+fn release_button_mappings(ctx: &ConnectionContext, manager: Option<ButtonMappingManager>) {
+    let Some(mut manager) = manager else {
+        return;
+    };
+
+    let button_entries = manager.release_outputs();
+    if !button_entries.is_empty() {
+        ctx.events_sender
+            .send(ServerCoreEvent::Buttons(button_entries))
+            .ok();
+    }
+}
+
 fn is_streaming(client_hostname: &str) -> bool {
     SESSION_MANAGER
         .read()
@@ -468,11 +533,155 @@ fn connection_pipeline(
     };
 
     let streaming_caps = if let Some(streaming_caps) = maybe_streaming_caps {
-        alvr_packets::decode_video_streaming_capabilities(&streaming_caps).to_con()?
+        alvr_packets::decode_legacy_video_streaming_capabilities(&streaming_caps).to_con()?
     } else {
         con_bail!("Only streaming clients are supported for now");
     };
 
+    dbg_connection!("connection_pipeline: Key exchange");
+    let (pairing_pin, pairing_key) = session_manager_lock
+        .client_list()
+        .get(&client_hostname)
+        .map(|c| (c.pairing_pin.clone(), c.pairing_key.clone()))
+        .unwrap_or_default();
+    let pairing_key = pairing_key
+        .map(|key| alvr_sockets::key_from_string(&key))
+        .transpose()
+        .to_con()?;
+
+    let key_exchange = KeyExchange::new().to_con()?;
+    let public_key = key_exchange.public_key().to_vec();
+
+    // A PIN entered in the dashboard takes precedence, to allow pairing again a client
+    let maybe_secret = if let Some(pin) = &pairing_pin {
+        proto_socket
+            .send(&ServerKeyExchange::Pairing { public_key })
+            .to_con()?;
+
+        Some(LongTermSecret::PairingPin(pin))
+    } else if let Some(key) = &pairing_key {
+        proto_socket
+            .send(&ServerKeyExchange::Paired { public_key })
+            .to_con()?;
+
+        Some(LongTermSecret::PairingKey(key))
+    } else if session_manager_lock.settings().connection.require_pairing {
+        proto_socket
+            .send(&ServerKeyExchange::PairingRequired)
+            .to_con()?;
+
+        warn!(
+            "Client {client_hostname} is not paired! Enter the PIN shown in the headset in the dashboard"
+        );
+
+        return Ok(());
+    } else {
+        proto_socket
+            .send(&ServerKeyExchange::Unencrypted)
+            .to_con()?;
+
+        None
+    };
+
+    let session_keys = if let Some(secret) = maybe_secret {
+        let (client_public_key, confirmation) = match proto_socket.recv(HANDSHAKE_ACTION_TIMEOUT)? {
+            ClientKeyExchange::Accepted {
+                public_key,
+                confirmation,
+            } => (public_key, confirmation),
+            ClientKeyExchange::NotPaired => {
+                warn!("Client {client_hostname} lost its pairing key. Pair it again from the dashboard");
+
+                return Ok(());
+            }
+        };
+
+        let keys = key_exchange
+            .finish(HandshakeRole::Server, &client_public_key, secret)
+            .to_con()?;
+
+        if keys.verify_peer_confirmation(&confirmation).is_err() {
+            if pairing_pin.is_some() {
+                // Allow only one attempt per PIN entered in the dashboard
+                session_manager_lock.update_client_list(
+                    client_hostname.clone(),
+                    ClientListAction::SetPairingPin(None),
+                );
+
+                warn!("Pairing with {client_hostname} failed! Check the PIN and try again");
+            } else {
+                warn!("Client {client_hostname} failed authentication! Pair it again from the dashboard");
+            }
+
+            return Ok(());
+        }
+
+        proto_socket
+            .send(&ServerKeyConfirmation {
+                confirmation: keys.confirmation(),
+            })
+            .to_con()?;
+        proto_socket.enable_encryption(&keys.control);
+
+        if pairing_pin.is_some() {
+            info!("Paired with {client_hostname}");
+
+            session_manager_lock.update_client_list(
+                client_hostname.clone(),
+                ClientListAction::SetPairingKey(Some(alvr_sockets::key_to_string(
+                    &keys.pairing_key,
+                ))),
+            );
+        }
+
+        Some(keys)
+    } else {
+        None
+    };
+
+    // The first client that connects drives SteamVR. Clients that connect while the driver is
+    // streaming are spectators: they receive the same encoded video but cannot send input.
+    let is_spectator = ctx.driver_hostname.lock().is_some();
+    let spectators_config = session_manager_lock
+        .settings()
+        .connection
+        .spectators
+        .as_option()
+        .cloned();
+    if is_spectator {
+        let Some(config) = &spectators_config else {
+            debug!("Client {client_hostname} cannot connect while another client is streaming");
+            return Ok(());
+        };
+
+        if ctx.spectators.lock().len() >= config.max_spectators {
+            warn!("Client {client_hostname} rejected: too many spectators");
+            return Ok(());
+        }
+    }
+
+    // Each client streams on its own port: the driver on the configured port, the spectators on
+    // the following ones. The port is sent to the client with the session.
+    let spectator_stream_port = if let (true, Some(config)) = (is_spectator, &spectators_config) {
+        let base_port = session_manager_lock.settings().connection.stream_port;
+        let mut used_ports = ctx.spectator_stream_ports.lock();
+        let Some(port) = (1..=config.max_spectators as u16)
+            .filter_map(|offset| base_port.checked_add(offset))
+            .find(|port| !used_ports.contains(port))
+        else {
+            warn!("Client {client_hostname} rejected: no stream port available");
+            return Ok(());
+        };
+        used_ports.insert(port);
+
+        Some(SpectatorStreamPort {
+            ctx: Arc::clone(&ctx),
+            port,
+        })
+    } else {
+        None
+    };
+
     dbg_connection!("connection_pipeline: setting up negotiated streaming config");
 
     let initial_settings = session_manager_lock.settings().clone();
@@ -619,45 +828,88 @@ fn connection_pipeline(
         initial_settings.video.preferred_codec
     };
 
-    #[cfg_attr(target_os = "linux", allow(unused_variables))]
-    let game_audio_sample_rate =
-        if let Switch::Enabled(game_audio_config) = &initial_settings.audio.game_audio {
-            #[cfg(not(target_os = "linux"))]
-            {
-                let game_audio_device =
-                    AudioDevice::new_output(game_audio_config.device.as_ref()).to_con()?;
-                if let Switch::Enabled(microphone_config) = &initial_settings.audio.microphone {
-                    let (sink, source) =
-                        AudioDevice::new_virtual_microphone_pair(microphone_config.devices.clone())
-                            .to_con()?;
-                    if matches!(
-                        microphone_config.devices,
-                        alvr_session::MicrophoneDevicesConfig::VBCable
-                    ) {
-                        // VoiceMeeter and Custom devices may have arbitrary internal routing.
-                        // Therefore, we cannot detect the loopback issue without knowing the routing.
-                        if alvr_audio::is_same_device(&game_audio_device, &sink)
-                            || alvr_audio::is_same_device(&game_audio_device, &source)
-                        {
-                            con_bail!("Game audio and microphone cannot point to the same device!");
-                        }
-                    }
-                    // else:
-                    // Stream played via VA-CABLE-X will be directly routed to VA-CABLE-X's virtual microphone.
-                    // Game audio will loop back to the game microphone if they are set to the same VA-CABLE-X device.
-                }
+    // Spectators share the encoder with the driver, so they must accept its configuration
+    let (stream_view_resolution, fps, enable_foveated_encoding, enable_hdr, encoding_gamma) =
+        if is_spectator {
+            let config = &session_manager_lock.session().openvr_config;
 
-                game_audio_device.input_sample_rate().to_con()?
+            if (config.codec == CodecType::AV1 as u8 && !streaming_caps.encoder_av1)
+                || (config.use_10bit_encoder && !streaming_caps.encoder_10_bits)
+            {
+                warn!("Spectator {client_hostname} does not support the current encoder configuration");
+                return Ok(());
             }
-            #[cfg(target_os = "linux")]
-            44100
+
+            (
+                UVec2::new(config.eye_resolution_width, config.eye_resolution_height),
+                config.refresh_rate as f32,
+                config.enable_foveated_encoding,
+                config.enable_hdr,
+                config.encoding_gamma,
+            )
         } else {
-            0
+            (
+                stream_view_resolution,
+                fps,
+                enable_foveated_encoding,
+                enable_hdr,
+                encoding_gamma,
+            )
         };
 
+    let enable_game_audio = spectators_config
+        .as_ref()
+        .map(|config| config.game_audio)
+        .unwrap_or(false)
+        || !is_spectator;
+
+    #[cfg_attr(target_os = "linux", allow(unused_variables))]
+    let game_audio_sample_rate = if let (true, Switch::Enabled(game_audio_config)) =
+        (enable_game_audio, &initial_settings.audio.game_audio)
+    {
+        #[cfg(not(target_os = "linux"))]
+        {
+            let game_audio_device =
+                AudioDevice::new_output(game_audio_config.device.as_ref()).to_con()?;
+            if let Switch::Enabled(microphone_config) = &initial_settings.audio.microphone {
+                let (sink, source) =
+                    AudioDevice::new_virtual_microphone_pair(microphone_config.devices.clone())
+                        .to_con()?;
+                if matches!(
+                    microphone_config.devices,
+                    alvr_session::MicrophoneDevicesConfig::VBCable
+                ) {
+                    // VoiceMeeter and Custom devices may have arbitrary internal routing.
+                    // Therefore, we cannot detect the loopback issue without knowing the routing.
+                    if alvr_audio::is_same_device(&game_audio_device, &sink)
+                        || alvr_audio::is_same_device(&game_audio_device, &source)
+                    {
+                        con_bail!("Game audio and microphone cannot point to the same device!");
+                    }
+                }
+                // else:
+                // Stream played via VA-CABLE-X will be directly routed to VA-CABLE-X's virtual microphone.
+                // Game audio will loop back to the game microphone if they are set to the same VA-CABLE-X device.
+            }
+
+            game_audio_device.input_sample_rate().to_con()?
+        }
+        #[cfg(target_os = "linux")]
+        44100
+    } else {
+        0
+    };
+
+    let stream_port = spectator_stream_port
+        .as_ref()
+        .map(|reservation| reservation.port)
+        .unwrap_or(initial_settings.connection.stream_port);
+    let mut client_session = session_manager_lock.session().clone();
+    client_session.session_settings.connection.stream_port = stream_port;
+
     dbg_connection!("connection_pipeline: send streaming config");
     let stream_config_packet = alvr_packets::encode_stream_config(
-        session_manager_lock.session(),
+        &client_session,
         &NegotiatedStreamingConfig {
             view_resolution: stream_view_resolution,
             refresh_rate_hint: fps,
@@ -674,26 +926,28 @@ fn connection_pipeline(
     let (mut control_sender, mut control_receiver) =
         proto_socket.split(STREAMING_RECV_TIMEOUT).to_con()?;
 
-    let mut new_openvr_config = contruct_openvr_config(session_manager_lock.session());
-    new_openvr_config.eye_resolution_width = stream_view_resolution.x;
-    new_openvr_config.eye_resolution_height = stream_view_resolution.y;
-    new_openvr_config.target_eye_resolution_width = target_view_resolution.x;
-    new_openvr_config.target_eye_resolution_height = target_view_resolution.y;
-    new_openvr_config.refresh_rate = fps as _;
-    new_openvr_config.enable_foveated_encoding = enable_foveated_encoding;
-    new_openvr_config.h264_profile = encoder_profile as _;
-    new_openvr_config.use_10bit_encoder = enable_10_bits_encoding;
-    new_openvr_config.use_full_range_encoding = use_full_range;
-    new_openvr_config.enable_hdr = enable_hdr;
-    new_openvr_config.encoding_gamma = encoding_gamma;
-    new_openvr_config.codec = codec as _;
-
-    if session_manager_lock.session().openvr_config != new_openvr_config {
-        session_manager_lock.session_mut().openvr_config = new_openvr_config;
-
-        control_sender.send(&ServerControlPacket::Restarting).ok();
-
-        crate::notify_restart_driver();
+    if !is_spectator {
+        let mut new_openvr_config = contruct_openvr_config(session_manager_lock.session());
+        new_openvr_config.eye_resolution_width = stream_view_resolution.x;
+        new_openvr_config.eye_resolution_height = stream_view_resolution.y;
+        new_openvr_config.target_eye_resolution_width = target_view_resolution.x;
+        new_openvr_config.target_eye_resolution_height = target_view_resolution.y;
+        new_openvr_config.refresh_rate = fps as _;
+        new_openvr_config.enable_foveated_encoding = enable_foveated_encoding;
+        new_openvr_config.h264_profile = encoder_profile as _;
+        new_openvr_config.use_10bit_encoder = enable_10_bits_encoding;
+        new_openvr_config.use_full_range_encoding = use_full_range;
+        new_openvr_config.enable_hdr = enable_hdr;
+        new_openvr_config.encoding_gamma = encoding_gamma;
+        new_openvr_config.codec = codec as _;
+
+        if session_manager_lock.session().openvr_config != new_openvr_config {
+            session_manager_lock.session_mut().openvr_config = new_openvr_config;
+
+            control_sender.send(&ServerControlPacket::Restarting).ok();
+
+            crate::notify_restart_driver();
+        }
     }
 
     dbg_connection!("connection_pipeline: Send StartStream packet");
@@ -707,7 +961,7 @@ fn connection_pipeline(
     }
     dbg_connection!("connection_pipeline: Got StreamReady packet");
 
-    *ctx.statistics_manager.write() = Some(StatisticsManager::new(
+    let statistics_manager = StatisticsManager::new(
         initial_settings.connection.statistics_history_size,
         Duration::from_secs_f32(1.0 / fps),
         if let Switch::Enabled(config) = &initial_settings.headset.controllers {
@@ -715,27 +969,120 @@ fn connection_pipeline(
         } else {
             0.0
         },
-    ));
+        !is_spectator,
+    );
+    let bitrate_manager = BitrateManager::new(
+        initial_settings.video.bitrate.history_size,
+        fps,
+        &initial_settings.video.bitrate.mode,
+    );
 
-    *ctx.bitrate_manager.lock() =
-        BitrateManager::new(initial_settings.video.bitrate.history_size, fps);
+    // The spectator context is registered only once the handshake is finished
+    let spectator_context = if is_spectator {
+        Some(SpectatorContext {
+            statistics_manager,
+            bitrate_manager,
+            last_bitrate_bps: None,
+        })
+    } else {
+        *ctx.statistics_manager.write() = Some(statistics_manager);
+        *ctx.bitrate_manager.lock() = bitrate_manager;
+
+        if initial_settings.extra.capture.record_bitrate_trace {
+            crate::create_bitrate_trace_file(&ctx);
+        }
+
+        None
+    };
 
     dbg_connection!("connection_pipeline: StreamSocket connect_to_client");
     let mut stream_socket = StreamSocketBuilder::connect_to_client(
         HANDSHAKE_ACTION_TIMEOUT,
         client_ip,
-        initial_settings.connection.stream_port,
+        stream_port,
         initial_settings.connection.stream_protocol,
         initial_settings.connection.dscp,
         initial_settings.connection.server_send_buffer_bytes,
         initial_settings.connection.server_recv_buffer_bytes,
         initial_settings.connection.packet_size as _,
+        session_keys.as_ref().map(|keys| &keys.stream),
+        initial_settings.connection.network_emulation.as_option(),
     )?;
 
+    if let (SocketProtocol::Udp, Switch::Enabled(config)) = (
+        initial_settings.connection.stream_protocol,
+        &initial_settings.connection.packet_retransmission,
+    ) {
+        for stream_id in alvr_packets::retransmitted_streams(config) {
+            stream_socket.enable_retransmission(stream_id, config.window_size);
+        }
+    }
+
+    stream_socket.set_reliable_streams(&alvr_packets::reliable_streams());
+
+    let receive_limits = &initial_settings.connection.stream_receive_limits;
+    for (stream_id, budget) in alvr_packets::stream_memory_budgets(receive_limits) {
+        stream_socket.set_receive_limits(
+            stream_id,
+            budget,
+            Duration::from_millis(receive_limits.stale_packet_timeout_ms),
+        );
+    }
+    let receive_statistics = stream_socket.receive_statistics();
+    let congestion_monitor = stream_socket.congestion_monitor();
+
     let mut video_sender = stream_socket.request_stream(VIDEO);
-    let game_audio_sender: alvr_sockets::StreamSender<()> = stream_socket.request_stream(AUDIO);
+    if matches!(
+        initial_settings.connection.stream_protocol,
+        SocketProtocol::Udp
+    ) {
+        video_sender.set_forward_error_correction(
+            initial_settings
+                .connection
+                .video_forward_error_correction
+                .as_option()
+                .cloned(),
+        );
+    }
+    let mut game_audio_sender: alvr_sockets::StreamSender<()> = stream_socket.request_stream(AUDIO);
     let mut microphone_receiver: alvr_sockets::StreamReceiver<()> =
         stream_socket.subscribe_to_stream(AUDIO, MAX_UNREAD_PACKETS);
+    if !is_spectator {
+        // The audio header is empty, the observers receive only the samples
+        game_audio_sender.set_observer(Some(Arc::new({
+            let ctx = Arc::clone(&ctx);
+            move |samples: &[u8]| {
+                if let Some(recording) = &mut *ctx.video_recording.lock() {
+                    recording.write_game_audio(samples);
+                }
+
+                if let Some(config) = SESSION_MANAGER
+                    .read()
+                    .settings()
+                    .headset
+                    .controllers
+                    .as_option()
+                    .and_then(|c| c.haptics.as_option())
+                    .and_then(|c| c.audio_to_haptics.as_option())
+                {
+                    ctx.haptics_manager.lock().report_game_audio(
+                        config,
+                        samples,
+                        game_audio_sample_rate,
+                        Instant::now(),
+                    );
+                }
+
+                crate::record_session_packet(&ctx, || RecordedPacket::GameAudio(samples.to_vec()))
+            }
+        })));
+        microphone_receiver.set_observer(Some(Arc::new({
+            let ctx = Arc::clone(&ctx);
+            move |samples: &[u8]| {
+                crate::record_session_packet(&ctx, || RecordedPacket::Microphone(samples.to_vec()))
+            }
+        })));
+    }
     let tracking_receiver =
         stream_socket.subscribe_to_stream::<Tracking>(TRACKING, MAX_UNREAD_PACKETS);
     let haptics_sender = stream_socket.request_stream(HAPTICS);
@@ -744,8 +1091,6 @@ fn connection_pipeline(
 
     let (video_channel_sender, video_channel_receiver) =
         std::sync::mpsc::sync_channel(initial_settings.connection.max_queued_server_video_frames);
-    *ctx.video_channel_sender.lock() = Some(video_channel_sender);
-    *ctx.haptics_sender.lock() = Some(haptics_sender);
 
     let video_send_thread = thread::spawn({
         let client_hostname = client_hostname.clone();
@@ -769,8 +1114,8 @@ fn connection_pipeline(
     });
 
     #[cfg_attr(target_os = "linux", allow(unused_variables))]
-    let game_audio_thread = if let Switch::Enabled(config) =
-        initial_settings.audio.game_audio.clone()
+    let game_audio_thread = if let (true, Switch::Enabled(config)) =
+        (enable_game_audio, initial_settings.audio.game_audio.clone())
     {
         #[cfg(windows)]
         let ctx = Arc::clone(&ctx);
@@ -803,18 +1148,21 @@ fn connection_pipeline(
                     };
 
                     #[cfg(windows)]
-                    if let Ok(id) = alvr_audio::get_windows_device_id(&device) {
-                        ctx.events_sender
-                            .send(ServerCoreEvent::SetOpenvrProperty {
-                                device_id: *alvr_common::HEAD_ID,
-                                prop: alvr_session::OpenvrProperty::AudioDefaultPlaybackDeviceId(
-                                    id,
-                                ),
-                            })
-                            .ok();
-                    } else {
-                        continue;
-                    };
+                    if !is_spectator {
+                        if let Ok(id) = alvr_audio::get_windows_device_id(&device) {
+                            ctx.events_sender
+                                .send(ServerCoreEvent::SetOpenvrProperty {
+                                    device_id: *alvr_common::HEAD_ID,
+                                    prop:
+                                        alvr_session::OpenvrProperty::AudioDefaultPlaybackDeviceId(
+                                            id,
+                                        ),
+                                })
+                                .ok();
+                        } else {
+                            continue;
+                        }
+                    }
 
                     if let Err(e) = alvr_audio::record_audio_blocking(
                         Arc::new({
@@ -824,15 +1172,17 @@ fn connection_pipeline(
                         game_audio_sender.clone(),
                         &device,
                         2,
-                        config.mute_when_streaming,
+                        config.mute_when_streaming && !is_spectator,
                     ) {
                         error!("Audio record error: {e:?}");
                     }
 
                     #[cfg(windows)]
-                    if let Ok(id) = AudioDevice::new_output(None)
-                        .and_then(|d| alvr_audio::get_windows_device_id(&d))
-                    {
+                    if let (false, Ok(id)) = (
+                        is_spectator,
+                        AudioDevice::new_output(None)
+                            .and_then(|d| alvr_audio::get_windows_device_id(&d)),
+                    ) {
                         ctx.events_sender
                             .send(ServerCoreEvent::SetOpenvrProperty {
                                 device_id: *alvr_common::HEAD_ID,
@@ -849,8 +1199,9 @@ fn connection_pipeline(
         thread::spawn(|| ())
     };
 
-    let microphone_thread = if let Switch::Enabled(config) =
-        initial_settings.audio.microphone.clone()
+    // Only the driver can send microphone audio to SteamVR
+    let microphone_thread = if let (false, Switch::Enabled(config)) =
+        (is_spectator, initial_settings.audio.microphone.clone())
     {
         #[cfg(not(target_os = "linux"))]
         #[allow(unused_variables)]
@@ -896,25 +1247,51 @@ fn connection_pipeline(
         thread::spawn(|| ())
     };
 
-    *ctx.tracking_manager.write() = TrackingManager::new();
-    let hand_gesture_manager = Arc::new(Mutex::new(HandGestureManager::new()));
+    let tracking_receive_thread = if is_spectator {
+        // Tracking of spectators is used only for statistics
+        thread::spawn({
+            let ctx = Arc::clone(&ctx);
+            let client_hostname = client_hostname.clone();
+            let mut tracking_receiver = tracking_receiver;
+            move || {
+                while is_streaming(&client_hostname) {
+                    let data = match tracking_receiver.recv(STREAMING_RECV_TIMEOUT) {
+                        Ok(tracking) => tracking,
+                        Err(ConnectionError::TryAgain(_)) => continue,
+                        Err(ConnectionError::Other(_)) => return,
+                    };
+                    let Ok(tracking) = data.get_header() else {
+                        return;
+                    };
 
-    let tracking_receive_thread = thread::spawn({
-        let ctx = Arc::clone(&ctx);
-        let hand_gesture_manager = Arc::clone(&hand_gesture_manager);
-        let initial_settings = initial_settings.clone();
-        let client_hostname = client_hostname.clone();
-        move || {
-            tracking::tracking_loop(
-                &ctx,
-                initial_settings,
-                streaming_caps.multimodal_protocol,
-                hand_gesture_manager,
-                tracking_receiver,
-                || is_streaming(&client_hostname),
-            );
-        }
-    });
+                    if let Some(spectator) = ctx.spectators.lock().get_mut(&client_hostname) {
+                        spectator
+                            .statistics_manager
+                            .report_tracking_received(tracking.target_timestamp);
+                    }
+                }
+            }
+        })
+    } else {
+        *ctx.tracking_manager.write() = TrackingManager::new(&initial_settings.headset);
+        let hand_gesture_manager = Arc::new(Mutex::new(HandGestureManager::new()));
+
+        thread::spawn({
+            let ctx = Arc::clone(&ctx);
+            let initial_settings = initial_settings.clone();
+            let client_hostname = client_hostname.clone();
+            move || {
+                tracking::tracking_loop(
+                    &ctx,
+                    initial_settings,
+                    streaming_caps.multimodal_protocol,
+                    hand_gesture_manager,
+                    tracking_receiver,
+                    || is_streaming(&client_hostname),
+                );
+            }
+        })
+    };
 
     let statistics_thread = thread::spawn({
         let ctx = Arc::clone(&ctx);
@@ -930,17 +1307,65 @@ fn connection_pipeline(
                     return;
                 };
 
-                if let Some(stats) = &mut *ctx.statistics_manager.write() {
+                // Packets lost in the receive path of the streamer socket
+                let local_receive_stats = mem::take(&mut *receive_statistics.lock());
+
+                if is_spectator {
+                    if let Some(spectator) = ctx.spectators.lock().get_mut(&client_hostname) {
+                        spectator
+                            .statistics_manager
+                            .report_receive_drops(&local_receive_stats);
+
+                        let timestamp = client_stats.target_timestamp;
+                        let decoder_latency = client_stats.video_decode;
+                        let lost_packets = (client_stats.receive_dropped_packets
+                            + client_stats.receive_evicted_packets)
+                            as usize;
+                        let (network_latency, _) =
+                            spectator.statistics_manager.report_statistics(client_stats);
+
+                        spectator.bitrate_manager.report_packet_loss(lost_packets);
+                        if let Some(monitor) = &congestion_monitor {
+                            spectator
+                                .bitrate_manager
+                                .report_transport_congestion(&monitor.stats());
+                        }
+
+                        let session_manager_lock = SESSION_MANAGER.read();
+                        spectator.bitrate_manager.report_frame_latencies(
+                            &session_manager_lock.settings().video.bitrate.mode,
+                            timestamp,
+                            network_latency,
+                            decoder_latency,
+                        );
+                    }
+                } else if let Some(stats) = &mut *ctx.statistics_manager.write() {
+                    crate::record_session_packet(&ctx, || {
+                        RecordedPacket::Statistics(client_stats.clone())
+                    });
+
+                    stats.report_receive_drops(&local_receive_stats);
+
                     let timestamp = client_stats.target_timestamp;
                     let decoder_latency = client_stats.video_decode;
+                    // Incomplete packets are evicted by the client when shards are lost
+                    let lost_packets = (client_stats.receive_dropped_packets
+                        + client_stats.receive_evicted_packets)
+                        as usize;
                     let (network_latency, game_latency) = stats.report_statistics(client_stats);
 
                     ctx.events_sender
                         .send(ServerCoreEvent::GameRenderLatencyFeedback(game_latency))
                         .ok();
 
+                    let mut bitrate_manager = ctx.bitrate_manager.lock();
+                    bitrate_manager.report_packet_loss(lost_packets);
+                    if let Some(monitor) = &congestion_monitor {
+                        bitrate_manager.report_transport_congestion(&monitor.stats());
+                    }
+
                     let session_manager_lock = SESSION_MANAGER.read();
-                    ctx.bitrate_manager.lock().report_frame_latencies(
+                    bitrate_manager.report_frame_latencies(
                         &session_manager_lock.settings().video.bitrate.mode,
                         timestamp,
                         network_latency,
@@ -972,30 +1397,44 @@ fn connection_pipeline(
         }
     });
 
+    // The haptics are mixed and sent once for the driver client
+    let haptics_thread = if !is_spectator {
+        thread::spawn({
+            let ctx = Arc::clone(&ctx);
+            let client_hostname = client_hostname.clone();
+            move || {
+                while is_streaming(&client_hostname) {
+                    haptics::send_mixed_haptics(&ctx);
+
+                    thread::sleep(HAPTICS_MIX_INTERVAL);
+                }
+            }
+        })
+    } else {
+        thread::spawn(|| ())
+    };
+
     let control_receive_thread = thread::spawn({
         let ctx = Arc::clone(&ctx);
 
-        let controllers_config = session_manager_lock
+        let controllers_emulation_mode = session_manager_lock
             .settings()
             .headset
             .controllers
-            .as_option();
-        let mut controller_button_mapping_manager = controllers_config.map(|config| {
-            if let Some(mappings) = &config.button_mappings {
-                ButtonMappingManager::new_manual(mappings)
-            } else {
-                ButtonMappingManager::new_automatic(
-                    &CONTROLLER_PROFILE_INFO
-                        .get(&alvr_common::hash_string(QUEST_CONTROLLER_PROFILE_PATH))
-                        .unwrap()
-                        .button_set,
-                    &config.emulation_mode,
-                    &config.button_mapping_config,
-                )
-            }
-        });
-        let controllers_emulation_mode =
-            controllers_config.map(|config| config.emulation_mode.clone());
+            .as_option()
+            .map(|config| config.emulation_mode.clone());
+        let mut controller_button_set = Some(
+            CONTROLLER_PROFILE_INFO
+                .get(&alvr_common::hash_string(QUEST_CONTROLLER_PROFILE_PATH))
+                .unwrap()
+                .button_set
+                .clone(),
+        );
+        // The manager is created again when the interaction profile or the active button mapping
+        // profile change
+        let mut controller_button_mapping_manager = None;
+        let mut button_mapping_profile = None;
+        let mut rebuild_button_mapping = true;
 
         let disconnect_notif = Arc::clone(&disconnect_notif);
         let control_sender = Arc::clone(&control_sender);
@@ -1003,7 +1442,54 @@ fn connection_pipeline(
         move || {
             let mut disconnection_deadline = Instant::now() + KEEPALIVE_TIMEOUT;
             while is_streaming(&client_hostname) {
-                let packet = match control_receiver.recv(STREAMING_RECV_TIMEOUT) {
+                if let (Switch::Enabled(config), Some(emulation_mode)) = (
+                    &SESSION_MANAGER.read().settings().headset.controllers,
+                    &controllers_emulation_mode,
+                ) {
+                    let profile = input_mapping::active_button_mapping_profile(
+                        config,
+                        ctx.running_application.lock().as_deref(),
+                    );
+                    let profile_name = profile.map(|profile| profile.name.clone());
+
+                    if rebuild_button_mapping || profile_name != button_mapping_profile {
+                        if profile_name != button_mapping_profile {
+                            info!(
+                                "Button mapping profile: {}",
+                                profile_name.as_deref().unwrap_or("default")
+                            );
+                        }
+
+                        release_button_mappings(&ctx, controller_button_mapping_manager.take());
+                        controller_button_mapping_manager = create_button_mapping_manager(
+                            config,
+                            profile,
+                            controller_button_set.as_ref(),
+                            emulation_mode,
+                        );
+                        button_mapping_profile = profile_name;
+                        rebuild_button_mapping = false;
+                    }
+                } else {
+                    release_button_mappings(&ctx, controller_button_mapping_manager.take());
+                    rebuild_button_mapping = true;
+                }
+
+                let mut recv_timeout = STREAMING_RECV_TIMEOUT;
+                if let Some(manager) = &mut controller_button_mapping_manager {
+                    if manager.has_expressions() {
+                        recv_timeout = BUTTON_EXPRESSIONS_UPDATE_INTERVAL;
+
+                        let button_entries = manager.update();
+                        if !button_entries.is_empty() {
+                            ctx.events_sender
+                                .send(ServerCoreEvent::Buttons(button_entries))
+                                .ok();
+                        }
+                    }
+                }
+
+                let packet = match control_receiver.recv(recv_timeout) {
                     Ok(packet) => packet,
                     Err(ConnectionError::TryAgain(_)) => {
                         if Instant::now() > disconnection_deadline {
@@ -1020,6 +1506,14 @@ fn connection_pipeline(
                 };
 
                 match packet {
+                    // Spectators cannot interact with SteamVR
+                    ClientControlPacket::PlayspaceSync(_)
+                    | ClientControlPacket::ViewsConfig(_)
+                    | ClientControlPacket::Battery(_)
+                    | ClientControlPacket::Buttons(_)
+                    | ClientControlPacket::ActiveInteractionProfile { .. }
+                    | ClientControlPacket::Reserved(_)
+                        if is_spectator => {}
                     ClientControlPacket::PlayspaceSync(packet) => {
                         if !initial_settings.headset.tracking_ref_only {
                             let session_manager_lock = SESSION_MANAGER.read();
@@ -1055,12 +1549,19 @@ fn connection_pipeline(
                     }
                     ClientControlPacket::VideoErrorReport => {
                         // legacy endpoint. todo: remove
-                        if let Some(stats) = &mut *ctx.statistics_manager.write() {
+                        if is_spectator {
+                            if let Some(spectator) = ctx.spectators.lock().get_mut(&client_hostname)
+                            {
+                                spectator.statistics_manager.report_packet_loss();
+                            }
+                        } else if let Some(stats) = &mut *ctx.statistics_manager.write() {
                             stats.report_packet_loss();
                         }
                         ctx.events_sender.send(ServerCoreEvent::RequestIDR).ok();
                     }
                     ClientControlPacket::ViewsConfig(config) => {
+                        ctx.foveation_manager.lock().report_views_fov(config.fov);
+
                         ctx.events_sender
                             .send(ServerCoreEvent::ViewsConfig(ViewsConfig {
                                 local_view_transforms: [
@@ -1095,6 +1596,10 @@ fn connection_pipeline(
                         }
                     }
                     ClientControlPacket::Buttons(entries) => {
+                        crate::record_session_packet(&ctx, || {
+                            RecordedPacket::Buttons(entries.clone())
+                        });
+
                         {
                             let session_manager_lock = SESSION_MANAGER.read();
                             if session_manager_lock
@@ -1134,26 +1639,10 @@ fn connection_pipeline(
                         };
                     }
                     ClientControlPacket::ActiveInteractionProfile { profile_id, .. } => {
-                        controller_button_mapping_manager = if let Switch::Enabled(config) =
-                            &SESSION_MANAGER.read().settings().headset.controllers
-                        {
-                            if let Some(mappings) = &config.button_mappings {
-                                Some(ButtonMappingManager::new_manual(mappings))
-                            } else if let (Some(profile_info), Some(emulation_mode)) = (
-                                CONTROLLER_PROFILE_INFO.get(&profile_id),
-                                &controllers_emulation_mode,
-                            ) {
-                                Some(ButtonMappingManager::new_automatic(
-                                    &profile_info.button_set,
-                                    emulation_mode,
-                                    &config.button_mapping_config,
-                                ))
-                            } else {
-                                None
-                            }
-                        } else {
-                            None
-                        };
+                        controller_button_set = CONTROLLER_PROFILE_INFO
+                            .get(&profile_id)
+                            .map(|profile_info| profile_info.button_set.clone());
+                        rebuild_button_mapping = true;
                     }
                     ClientControlPacket::Log { level, message } => {
                         info!("Client {client_hostname}: [{level:?}] {message}")
@@ -1175,23 +1664,8 @@ fn connection_pipeline(
                                 input_ids,
                                 ..
                             } => {
-                                controller_button_mapping_manager = if let Switch::Enabled(config) =
-                                    &SESSION_MANAGER.read().settings().headset.controllers
-                                {
-                                    if let Some(mappings) = &config.button_mappings {
-                                        Some(ButtonMappingManager::new_manual(mappings))
-                                    } else {
-                                        controllers_emulation_mode.as_ref().map(|emulation_mode| {
-                                            ButtonMappingManager::new_automatic(
-                                                &input_ids,
-                                                emulation_mode,
-                                                &config.button_mapping_config,
-                                            )
-                                        })
-                                    }
-                                } else {
-                                    None
-                                };
+                                controller_button_set = Some(input_ids);
+                                rebuild_button_mapping = true;
                             }
                         }
                     }
@@ -1244,7 +1718,7 @@ fn connection_pipeline(
         }
     });
 
-    {
+    if !is_spectator {
         let on_connect_script = initial_settings.connection.on_connect_script;
 
         if !on_connect_script.is_empty() {
@@ -1258,29 +1732,64 @@ fn connection_pipeline(
         }
     }
 
-    if initial_settings.extra.capture.startup_video_recording {
+    if initial_settings.extra.capture.startup_video_recording && !is_spectator {
         info!("Creating recording file");
         crate::create_recording_file(&ctx, session_manager_lock.settings());
     }
 
+    ctx.video_channels.lock().insert(
+        client_hostname.clone(),
+        VideoChannel {
+            sender: video_channel_sender,
+            stream_corrupted: true,
+        },
+    );
+
+    if let Some(spectator_context) = spectator_context {
+        info!("Client {client_hostname} connected as spectator");
+
+        ctx.spectators
+            .lock()
+            .insert(client_hostname.clone(), spectator_context);
+
+        // The stream is already running, a new IDR is needed to start decoding
+        ctx.events_sender.send(ServerCoreEvent::RequestIDR).ok();
+    } else {
+        *ctx.driver_hostname.lock() = Some(client_hostname.clone());
+        *ctx.haptics_sender.lock() = Some(haptics_sender);
+        *ctx.game_audio_sample_rate.lock() =
+            (game_audio_sample_rate != 0).then_some(game_audio_sample_rate);
+    }
+
     session_manager_lock.update_client_list(
         client_hostname.clone(),
         ClientListAction::SetConnectionState(ConnectionState::Streaming),
     );
 
-    ctx.events_sender
-        .send(ServerCoreEvent::ClientConnected)
-        .ok();
+    if !is_spectator {
+        ctx.events_sender
+            .send(ServerCoreEvent::ClientConnected)
+            .ok();
+    }
 
     dbg_connection!("connection_pipeline: handshake finished; unlocking streams");
     alvr_common::wait_rwlock(&disconnect_notif, &mut session_manager_lock);
     dbg_connection!("connection_pipeline: Begin connection shutdown");
 
     // This requests shutdown from threads
-    *ctx.video_channel_sender.lock() = None;
-    *ctx.haptics_sender.lock() = None;
+    ctx.video_channels.lock().remove(&client_hostname);
+    if is_spectator {
+        ctx.spectators.lock().remove(&client_hostname);
+    } else {
+        *ctx.driver_hostname.lock() = None;
+        *ctx.haptics_sender.lock() = None;
 
-    *ctx.video_recording_file.lock() = None;
+        *ctx.game_audio_sample_rate.lock() = None;
+
+        *ctx.video_recording.lock() = None;
+        *ctx.session_recording.lock() = None;
+        ctx.bitrate_manager.lock().stop_trace();
+    }
 
     session_manager_lock.update_client_list(
         client_hostname.clone(),
@@ -1292,7 +1801,7 @@ fn connection_pipeline(
         .connection
         .on_disconnect_script
         .clone();
-    if !on_disconnect_script.is_empty() {
+    if !on_disconnect_script.is_empty() && !is_spectator {
         info!("Running on disconnect script (disconnect): {on_disconnect_script}");
         if let Err(e) = Command::new(&on_disconnect_script)
             .env("ACTION", "disconnect")
@@ -1315,11 +1824,14 @@ fn connection_pipeline(
     control_receive_thread.join().ok();
     stream_receive_thread.join().ok();
     keepalive_thread.join().ok();
+    haptics_thread.join().ok();
     lifecycle_check_thread.join().ok();
 
-    ctx.events_sender
-        .send(ServerCoreEvent::ClientDisconnected)
-        .ok();
+    if !is_spectator {
+        ctx.events_sender
+            .send(ServerCoreEvent::ClientDisconnected)
+            .ok();
+    }
 
     dbg_connection!("connection_pipeline: End");
 
@@ -0,0 +1,177 @@
+// Foveated encoding with a center region that follows the eye gaze. For each tracking sample the
+// center shift is computed from the eye gazes. The compositor uses it when it renders the frame of
+// that sample, and the same shift is sent with the frame, so the client decompresses the frame with
+// the same parameters as the compressed one.
+//
+// The center shift is the one of the settings: in the range [-1, 1], where 0 is the center of the
+// view and ±1 places the center region against the edge. The right eye is mirrored horizontally.
+
+use alvr_common::{
+    glam::{Vec2, Vec3},
+    Fov, Pose,
+};
+use alvr_session::FoveatedEncodingConfig;
+use std::{collections::VecDeque, time::Duration};
+
+const MAX_HISTORY_SIZE: usize = 64;
+
+fn push_to_history(history: &mut VecDeque<(Duration, [Vec2; 2])>, entry: (Duration, [Vec2; 2])) {
+    history.push_back(entry);
+    while history.len() > MAX_HISTORY_SIZE {
+        history.pop_front();
+    }
+}
+
+// gaze is relative to the view. Returns None if the gaze is not directed in front of the view
+fn gaze_center_shift(gaze: Pose, fov: Fov, center_size: Vec2, right_eye: bool) -> Option<Vec2> {
+    let direction = gaze.orientation * -Vec3::Z;
+    if direction.z >= 0.0 {
+        return None;
+    }
+    let tangent_x = direction.x / -direction.z;
+    let tangent_y = direction.y / -direction.z;
+
+    let (left, right) = (fov.left.tan(), fov.right.tan());
+    let (up, down) = (fov.up.tan(), fov.down.tan());
+
+    // Texture UV, with Y pointing down
+    let mut uv = Vec2::new(
+        (tangent_x - left) / (right - left),
+        (up - tangent_y) / (up - down),
+    );
+    if right_eye {
+        uv.x = 1.0 - uv.x;
+    }
+
+    // The center region spans [c0 * (shift + 1), c0 * (shift + 1) + center_size]
+    let c0 = (Vec2::ONE - center_size) / 2.0;
+    let shift = Vec2::select(
+        c0.cmpgt(Vec2::splat(f32::EPSILON)),
+        (uv - 0.5) / c0,
+        Vec2::ZERO,
+    );
+
+    Some(shift.clamp(Vec2::splat(-1.0), Vec2::ONE))
+}
+
+pub struct FoveationManager {
+    views_fov: Option<[Fov; 2]>,
+    gaze_center_shifts: VecDeque<(Duration, [Vec2; 2])>,
+    rendered_center_shifts: VecDeque<(Duration, [Vec2; 2])>,
+}
+
+impl FoveationManager {
+    pub fn new() -> Self {
+        Self {
+            views_fov: None,
+            gaze_center_shifts: VecDeque::new(),
+            rendered_center_shifts: VecDeque::new(),
+        }
+    }
+
+    pub fn report_views_fov(&mut self, fov: [Fov; 2]) {
+        self.views_fov = Some(fov);
+    }
+
+    // eye_gazes are relative to the head. A single gaze is used for both eyes
+    pub fn report_eye_gazes(
+        &mut self,
+        config: &FoveatedEncodingConfig,
+        timestamp: Duration,
+        eye_gazes: [Option<Pose>; 2],
+    ) {
+        let Some(fov) = self.views_fov else {
+            return;
+        };
+        let [Some(left_gaze), Some(right_gaze)] =
+            [eye_gazes[0].or(eye_gazes[1]), eye_gazes[1].or(eye_gazes[0])]
+        else {
+            return;
+        };
+
+        // The views are not rotated relative to the head
+        let center_size = Vec2::new(config.center_size_x, config.center_size_y);
+        if let (Some(left), Some(right)) = (
+            gaze_center_shift(left_gaze, fov[0], center_size, false),
+            gaze_center_shift(right_gaze, fov[1], center_size, true),
+        ) {
+            push_to_history(&mut self.gaze_center_shifts, (timestamp, [left, right]));
+        }
+    }
+
+    // Called by the compositor for the frame of target_timestamp. Returns None if there is no eye
+    // gaze for this frame: the compositor and the client use the center shift of the settings
+    pub fn get_center_shift_for_rendering(
+        &mut self,
+        config: &FoveatedEncodingConfig,
+        target_timestamp: Duration,
+    ) -> Option<[Vec2; 2]> {
+        if !config.follow_eye_gaze {
+            return None;
+        }
+
+        let &(_, shift) = self
+            .gaze_center_shifts
+            .iter()
+            .find(|(timestamp, _)| *timestamp == target_timestamp)?;
+
+        push_to_history(&mut self.rendered_center_shifts, (target_timestamp, shift));
+
+        Some(shift)
+    }
+
+    // Returns the center shift used by the compositor for the frame, if it was not the static one
+    pub fn get_rendered_center_shift(&self, target_timestamp: Duration) -> Option<[Vec2; 2]> {
+        self.rendered_center_shifts
+            .iter()
+            .find(|(timestamp, _)| *timestamp == target_timestamp)
+            .map(|(_, shift)| *shift)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alvr_common::glam::Quat;
+
+    #[test]
+    fn center_shift_follows_gaze() {
+        let fov = Fov {
+            left: -0.8,
+            right: 0.8,
+            up: 0.8,
+            down: -0.8,
+        };
+        let center_size = Vec2::new(0.5, 0.5);
+
+        let forward = Pose::default();
+        let shift = gaze_center_shift(forward, fov, center_size, false).unwrap();
+        assert!(shift.length() < 1e-6);
+
+        // Looking to the right at half of the FOV tangent
+        let right_gaze = Pose {
+            orientation: Quat::from_rotation_y(-(0.8_f32.tan() / 2.0).atan()),
+            position: Vec3::ZERO,
+        };
+        let left_eye_shift = gaze_center_shift(right_gaze, fov, center_size, false).unwrap();
+        let right_eye_shift = gaze_center_shift(right_gaze, fov, center_size, true).unwrap();
+        // UV 0.75, c0 = 0.25
+        assert!((left_eye_shift.x - 1.0).abs() < 1e-5);
+        assert!((right_eye_shift.x + 1.0).abs() < 1e-5);
+        assert!(left_eye_shift.y.abs() < 1e-5);
+
+        // Looking up moves the center towards the top of the texture
+        let up_gaze = Pose {
+            orientation: Quat::from_rotation_x(0.2),
+            position: Vec3::ZERO,
+        };
+        let shift = gaze_center_shift(up_gaze, fov, center_size, false).unwrap();
+        assert!(shift.y < 0.0);
+
+        let backward = Pose {
+            orientation: Quat::from_rotation_y(3.0),
+            position: Vec3::ZERO,
+        };
+        assert!(gaze_center_shift(backward, fov, center_size, false).is_none());
+    }
+}
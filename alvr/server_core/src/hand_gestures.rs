@@ -11,7 +11,7 @@ use alvr_common::{
 };
 
 use alvr_packets::{ButtonEntry, ButtonValue};
-use alvr_session::HandTrackingInteractionConfig;
+use alvr_session::{ControllersConfig, HandGestureShape, HandTrackingInteractionConfig};
 
 use crate::input_mapping::ButtonMappingManager;
 
@@ -52,6 +52,21 @@ pub static HAND_GESTURE_BUTTON_SET: Lazy<HashSet<u64>> = Lazy::new(|| {
     .collect()
 });
 
+// The buttons bound to custom gestures are mapped like the buttons of the built-in gestures
+fn hand_gesture_button_set(config: &HandTrackingInteractionConfig) -> HashSet<u64> {
+    let mut button_set = HAND_GESTURE_BUTTON_SET.clone();
+    button_set.extend(
+        config
+            .custom_gestures
+            .iter()
+            .flat_map(|gesture| [&gesture.left_hand_button, &gesture.right_hand_button])
+            .flatten()
+            .map(|path| hash_string(path)),
+    );
+
+    button_set
+}
+
 #[derive(Debug, Clone)]
 pub struct HandGesture {
     pub id: HandGestureId,
@@ -89,11 +104,51 @@ pub enum HandGestureId {
     // Complex
     JoystickX,
     JoystickY,
+    // Index into the custom gestures of the settings
+    Custom(usize),
+}
+
+// Returns if the joints of the hand match the shape of a custom gesture. The finger curls are
+// ordered from the thumb to the little finger
+fn custom_gesture_in_range(
+    shape: &HandGestureShape,
+    joints: &[Pose; 26],
+    finger_curls: [f32; 5],
+) -> bool {
+    match shape {
+        HandGestureShape::JointDistances(conditions) => {
+            !conditions.is_empty()
+                && conditions.iter().all(|condition| {
+                    let (Some(first), Some(second)) = (
+                        joints.get(condition.first_joint),
+                        joints.get(condition.second_joint),
+                    ) else {
+                        return false;
+                    };
+                    let distance = first.position.distance(second.position) * 100.0;
+
+                    distance >= condition.min_distance && distance <= condition.max_distance
+                })
+        }
+        HandGestureShape::FingerCurls {
+            thumb,
+            index,
+            middle,
+            ring,
+            little,
+            tolerance,
+        } => [thumb, index, middle, ring, little]
+            .into_iter()
+            .zip(finger_curls)
+            .all(|(target, curl)| (curl - target).abs() <= *tolerance),
+    }
 }
 
 pub struct HandGestureManager {
     gesture_data_left: HashMap<HandGestureId, GestureAction>,
     gesture_data_right: HashMap<HandGestureId, GestureAction>,
+    // Created for the set of buttons of the built-in and custom gestures
+    button_mapping: Option<(HashSet<u64>, ButtonMappingManager)>,
 }
 
 impl HandGestureManager {
@@ -101,6 +156,7 @@ impl HandGestureManager {
         Self {
             gesture_data_left: HashMap::new(),
             gesture_data_right: HashMap::new(),
+            button_mapping: None,
         }
     }
 
@@ -364,6 +420,30 @@ impl HandGestureManager {
             },
         });
 
+        // Custom gestures
+        let finger_curls = [thumb_curl, index_curl, middle_curl, ring_curl, little_curl];
+        for (idx, gesture_config) in config.custom_gestures.iter().enumerate() {
+            let in_range = custom_gesture_in_range(&gesture_config.shape, &gj, finger_curls);
+
+            let id = HandGestureId::Custom(idx);
+            let active = self.update_gesture_action(
+                id,
+                in_range,
+                config.repeat_delay,
+                config.activation_delay,
+                config.deactivation_delay,
+                device_id,
+            );
+
+            gestures.push(HandGesture {
+                id,
+                active,
+                clicked: active,
+                touching: active,
+                value: if active { 1.0 } else { 0.0 },
+            });
+        }
+
         gestures
     }
 
@@ -384,6 +464,26 @@ impl HandGestureManager {
         let in_range = first_anchor.position.distance(second_anchor.position)
             < (activation_dist + first_radius + second_radius);
 
+        self.update_gesture_action(
+            gesture_id,
+            in_range,
+            repeat_delay,
+            in_delay,
+            out_delay,
+            device_id,
+        )
+    }
+
+    // Applies the activation, deactivation and repeat delays. Returns if the gesture is active
+    fn update_gesture_action(
+        &mut self,
+        gesture_id: HandGestureId,
+        in_range: bool,
+        repeat_delay: u32,
+        in_delay: u32,
+        out_delay: u32,
+        device_id: u64,
+    ) -> bool {
         let gesture_data = if device_id == *HAND_LEFT_ID {
             &mut self.gesture_data_left
         } else {
@@ -501,6 +601,93 @@ impl HandGestureManager {
             y: (y / joy_radius).clamp(-1.0, 1.0),
         }
     }
+
+    // The gesture buttons are mapped to the emulated controllers like the buttons of the client
+    // controllers. The mapping is created again when the buttons of the custom gestures change
+    pub fn trigger_hand_gesture_actions(
+        &mut self,
+        controllers_config: &ControllersConfig,
+        device_id: u64,
+        gestures: &[HandGesture],
+        config: &HandTrackingInteractionConfig,
+    ) -> Vec<ButtonEntry> {
+        let only_touch = config.only_touch;
+
+        let source_set = hand_gesture_button_set(config);
+        if !matches!(&self.button_mapping, Some((set, _)) if *set == source_set) {
+            let manager = ButtonMappingManager::new_automatic(
+                &source_set,
+                &controllers_config.emulation_mode,
+                &controllers_config.button_mapping_config,
+            );
+            self.button_mapping = Some((source_set, manager));
+        }
+        let Some((_, button_mapping_manager)) = &mut self.button_mapping else {
+            return vec![];
+        };
+
+        let mut button_entries = vec![];
+
+        for gesture in gestures {
+            if let HandGestureId::Custom(idx) = gesture.id {
+                let button_path = config.custom_gestures.get(idx).and_then(|gesture_config| {
+                    if device_id == *HAND_LEFT_ID {
+                        gesture_config.left_hand_button.as_ref()
+                    } else {
+                        gesture_config.right_hand_button.as_ref()
+                    }
+                });
+
+                if let Some(path) = button_path {
+                    let path_id = hash_string(path);
+                    let value = match BUTTON_INFO.get(&path_id).map(|info| &info.button_type) {
+                        Some(ButtonType::Scalar) => ButtonValue::Scalar(gesture.value),
+                        _ => ButtonValue::Binary(gesture.active),
+                    };
+
+                    button_entries.append(
+                        &mut button_mapping_manager.map_button(&ButtonEntry { path_id, value }),
+                    );
+                }
+
+                continue;
+            }
+
+            // Click bind
+            if !only_touch {
+                if let Some(click_bind) = get_click_bind_for_gesture(device_id, gesture.id) {
+                    button_entries.append(&mut button_mapping_manager.map_button(&ButtonEntry {
+                        path_id: click_bind,
+                        value: ButtonValue::Binary(gesture.active && gesture.clicked),
+                    }));
+                }
+            }
+
+            // Touch bind
+            if let Some(touch_bind) = get_touch_bind_for_gesture(device_id, gesture.id) {
+                button_entries.append(&mut button_mapping_manager.map_button(&ButtonEntry {
+                    path_id: touch_bind,
+                    value: ButtonValue::Binary(gesture.active && gesture.touching),
+                }));
+            }
+
+            // Hover bind
+            if !only_touch {
+                if let Some(hover_bind) = get_hover_bind_for_gesture(device_id, gesture.id) {
+                    button_entries.append(&mut button_mapping_manager.map_button(&ButtonEntry {
+                        path_id: hover_bind,
+                        value: ButtonValue::Scalar(if gesture.active {
+                            gesture.value
+                        } else {
+                            0.0
+                        }),
+                    }));
+                }
+            }
+        }
+
+        button_entries
+    }
 }
 
 fn get_click_bind_for_gesture(device_id: u64, gesture_id: HandGestureId) -> Option<u64> {
@@ -568,43 +755,195 @@ fn get_hover_bind_for_gesture(device_id: u64, gesture_id: HandGestureId) -> Opti
     }
 }
 
-pub fn trigger_hand_gesture_actions(
-    button_mapping_manager: &mut ButtonMappingManager,
-    device_id: u64,
-    gestures: &[HandGesture],
-    only_touch: bool,
-) -> Vec<ButtonEntry> {
-    let mut button_entries = vec![];
-
-    for gesture in gestures {
-        // Click bind
-        if !only_touch {
-            if let Some(click_bind) = get_click_bind_for_gesture(device_id, gesture.id) {
-                button_entries.append(&mut button_mapping_manager.map_button(&ButtonEntry {
-                    path_id: click_bind,
-                    value: ButtonValue::Binary(gesture.active && gesture.clicked),
-                }));
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alvr_session::{
+        ControllersEmulationMode, CustomHandGestureConfig, HandJointDistanceCondition,
+        SessionConfig,
+    };
+
+    fn interaction_config(
+        custom_gestures: Vec<CustomHandGestureConfig>,
+    ) -> HandTrackingInteractionConfig {
+        HandTrackingInteractionConfig {
+            only_touch: false,
+            pinch_touch_distance: 0.0,
+            pinch_trigger_distance: 0.25,
+            curl_touch_distance: 2.0,
+            curl_trigger_distance: 2.5,
+            joystick_deadzone: 40.0,
+            joystick_offset_horizontal: 0.0,
+            joystick_offset_vertical: 0.0,
+            joystick_range: 1.0,
+            activation_delay: 50,
+            deactivation_delay: 100,
+            repeat_delay: 100,
+            custom_gestures,
         }
+    }
 
-        // Touch bind
-        if let Some(touch_bind) = get_touch_bind_for_gesture(device_id, gesture.id) {
-            button_entries.append(&mut button_mapping_manager.map_button(&ButtonEntry {
-                path_id: touch_bind,
-                value: ButtonValue::Binary(gesture.active && gesture.touching),
-            }));
+    fn custom_gesture(right_hand_button: Option<&str>) -> CustomHandGestureConfig {
+        CustomHandGestureConfig {
+            name: "Gesture".into(),
+            shape: HandGestureShape::JointDistances(vec![]),
+            left_hand_button: None,
+            right_hand_button: right_hand_button.map(|path| path.into()),
         }
+    }
 
-        // Hover bind
-        if !only_touch {
-            if let Some(hover_bind) = get_hover_bind_for_gesture(device_id, gesture.id) {
-                button_entries.append(&mut button_mapping_manager.map_button(&ButtonEntry {
-                    path_id: hover_bind,
-                    value: ButtonValue::Scalar(if gesture.active { gesture.value } else { 0.0 }),
-                }));
-            }
+    fn controllers_config() -> ControllersConfig {
+        let mut config = SessionConfig::default()
+            .to_settings()
+            .headset
+            .controllers
+            .into_option()
+            .unwrap();
+        config.emulation_mode = ControllersEmulationMode::Quest2Touch;
+
+        config
+    }
+
+    fn custom_gesture_state(idx: usize, active: bool) -> HandGesture {
+        HandGesture {
+            id: HandGestureId::Custom(idx),
+            active,
+            clicked: active,
+            touching: active,
+            value: if active { 1.0 } else { 0.0 },
         }
     }
 
-    button_entries
+    #[test]
+    fn custom_gesture_shapes() {
+        let mut joints = [Pose::default(); 26];
+        joints[5].position = Vec3::new(0.0, 0.0, 0.0);
+        joints[10].position = Vec3::new(0.03, 0.0, 0.0);
+
+        let distance = |min_distance, max_distance| {
+            HandGestureShape::JointDistances(vec![HandJointDistanceCondition {
+                first_joint: 5,
+                second_joint: 10,
+                min_distance,
+                max_distance,
+            }])
+        };
+        // Distances are in centimeters
+        assert!(custom_gesture_in_range(
+            &distance(2.0, 4.0),
+            &joints,
+            [0.0; 5]
+        ));
+        assert!(!custom_gesture_in_range(
+            &distance(4.0, 6.0),
+            &joints,
+            [0.0; 5]
+        ));
+        // Gestures without conditions would always be active
+        assert!(!custom_gesture_in_range(
+            &HandGestureShape::JointDistances(vec![]),
+            &joints,
+            [0.0; 5]
+        ));
+        assert!(!custom_gesture_in_range(
+            &HandGestureShape::JointDistances(vec![HandJointDistanceCondition {
+                first_joint: 5,
+                second_joint: 26,
+                min_distance: 0.0,
+                max_distance: 100.0,
+            }]),
+            &joints,
+            [0.0; 5]
+        ));
+
+        // Thumbs up
+        let curls = HandGestureShape::FingerCurls {
+            thumb: 0.0,
+            index: 1.0,
+            middle: 1.0,
+            ring: 1.0,
+            little: 1.0,
+            tolerance: 0.2,
+        };
+        assert!(custom_gesture_in_range(
+            &curls,
+            &joints,
+            [0.1, 0.9, 1.0, 0.85, 1.0]
+        ));
+        assert!(!custom_gesture_in_range(
+            &curls,
+            &joints,
+            [0.1, 0.5, 1.0, 0.85, 1.0]
+        ));
+    }
+
+    #[test]
+    fn custom_gesture_buttons() {
+        let controllers_config = controllers_config();
+        let config = interaction_config(vec![
+            custom_gesture(Some("/user/hand/right/input/menu/click")),
+            custom_gesture(None),
+        ]);
+        let mut manager = HandGestureManager::new();
+
+        // The right menu button is emulated by the system button of the Quest controllers
+        let entries = manager.trigger_hand_gesture_actions(
+            &controllers_config,
+            *HAND_RIGHT_ID,
+            &[custom_gesture_state(0, true), custom_gesture_state(1, true)],
+            &config,
+        );
+        assert!(entries
+            .iter()
+            .any(|e| e.path_id == *RIGHT_SYSTEM_CLICK_ID && e.value == ButtonValue::Binary(true)));
+
+        // Gestures without a button for this hand are ignored
+        let entries = manager.trigger_hand_gesture_actions(
+            &controllers_config,
+            *HAND_LEFT_ID,
+            &[custom_gesture_state(0, true), custom_gesture_state(1, true)],
+            &config,
+        );
+        assert!(entries.is_empty());
+
+        let entries = manager.trigger_hand_gesture_actions(
+            &controllers_config,
+            *HAND_RIGHT_ID,
+            &[custom_gesture_state(0, false)],
+            &config,
+        );
+        assert!(entries
+            .iter()
+            .any(|e| e.path_id == *RIGHT_SYSTEM_CLICK_ID && e.value == ButtonValue::Binary(false)));
+    }
+
+    #[test]
+    fn mapping_follows_custom_gestures() {
+        let controllers_config = controllers_config();
+        let mut manager = HandGestureManager::new();
+
+        let config = interaction_config(vec![custom_gesture(None)]);
+        assert!(manager
+            .trigger_hand_gesture_actions(
+                &controllers_config,
+                *HAND_RIGHT_ID,
+                &[custom_gesture_state(0, true)],
+                &config,
+            )
+            .is_empty());
+
+        // The menu button was not a source of the previous mapping
+        let config = interaction_config(vec![custom_gesture(Some(
+            "/user/hand/right/input/menu/click",
+        ))]);
+        let entries = manager.trigger_hand_gesture_actions(
+            &controllers_config,
+            *HAND_RIGHT_ID,
+            &[custom_gesture_state(0, true)],
+            &config,
+        );
+        assert!(entries
+            .iter()
+            .any(|e| e.path_id == *RIGHT_SYSTEM_CLICK_ID && e.value == ButtonValue::Binary(true)));
+    }
 }
@@ -0,0 +1,270 @@
+// Recognition of static hand poses, compared against templates recorded by the user.
+//
+// The joint positions are expressed in the space of the wrist and scaled by the size of the hand,
+// so the same template works for any position, orientation and hand size. Right hands are
+// mirrored, so the templates are shared between the two hands.
+
+use crate::{input_mapping::ButtonMappingManager, SESSION_MANAGER};
+use alvr_common::{glam::Vec3, *};
+use alvr_packets::{ButtonEntry, ButtonValue};
+use alvr_session::{
+    settings_schema::{OptionalDefault, VectorDefault},
+    ControllersConfig, HandPoseRecognitionConfig, HandPoseTemplateDefault,
+};
+use std::collections::HashSet;
+
+const JOINT_COUNT: usize = 26;
+
+fn normalized_joint_positions(
+    skeleton: &[Pose; JOINT_COUNT],
+    left_hand: bool,
+) -> [Vec3; JOINT_COUNT] {
+    let inverse_wrist = skeleton[1].inverse();
+
+    let mut positions = skeleton.map(|joint| (inverse_wrist * joint).position);
+    if !left_hand {
+        for position in &mut positions {
+            position.x = -position.x;
+        }
+    }
+
+    let hand_size = positions.iter().map(|p| p.length()).sum::<f32>() / JOINT_COUNT as f32;
+    if hand_size > 0.0 {
+        for position in &mut positions {
+            *position /= hand_size;
+        }
+    }
+
+    positions
+}
+
+// Appends the pose of the skeleton to the templates in the session. The buttons are left unset
+pub fn record_hand_pose_template(skeleton: &[Pose; JOINT_COUNT], left_hand: bool) {
+    let joint_positions = normalized_joint_positions(skeleton, left_hand)
+        .iter()
+        .flat_map(|p| p.to_array())
+        .collect();
+
+    let mut session_manager = SESSION_MANAGER.write();
+    let mut session = session_manager.session_mut();
+    let templates = &mut session
+        .session_settings
+        .headset
+        .controllers
+        .content
+        .hand_pose_recognition
+        .content
+        .templates;
+
+    let name = format!("Pose {}", templates.content.len() + 1);
+    info!("Recorded hand pose template \"{name}\"");
+
+    templates.content.push(HandPoseTemplateDefault {
+        name,
+        left_hand_button: OptionalDefault {
+            set: false,
+            content: templates.element.left_hand_button.content.clone(),
+        },
+        right_hand_button: OptionalDefault {
+            set: false,
+            content: templates.element.right_hand_button.content.clone(),
+        },
+        joint_positions: VectorDefault {
+            gui_collapsed: true,
+            element: 0.0,
+            content: joint_positions,
+        },
+    });
+}
+
+pub struct HandPoseRecognizer {
+    // Created for the set of buttons of the templates
+    button_mapping: Option<(HashSet<u64>, ButtonMappingManager)>,
+}
+
+impl HandPoseRecognizer {
+    pub fn new() -> Self {
+        Self {
+            button_mapping: None,
+        }
+    }
+
+    // Returns the confidence of each template, in the range [0, 1]
+    pub fn recognize(
+        &self,
+        config: &HandPoseRecognitionConfig,
+        skeleton: &[Pose; JOINT_COUNT],
+        left_hand: bool,
+    ) -> Vec<f32> {
+        let positions = normalized_joint_positions(skeleton, left_hand);
+        let max_error = f32::max(config.max_joint_error / 100.0, f32::EPSILON);
+
+        config
+            .templates
+            .iter()
+            .map(|template| {
+                if template.joint_positions.len() != JOINT_COUNT * 3 {
+                    return 0.0;
+                }
+
+                let error = template
+                    .joint_positions
+                    .chunks_exact(3)
+                    .zip(positions)
+                    .map(|(template_position, position)| {
+                        Vec3::from_slice(template_position).distance(position)
+                    })
+                    .sum::<f32>()
+                    / JOINT_COUNT as f32;
+
+                (1.0 - error / max_error).clamp(0.0, 1.0)
+            })
+            .collect()
+    }
+
+    // Only the template with the highest confidence is active. The buttons are mapped to the
+    // emulated controllers like the buttons of the client controllers
+    pub fn trigger_hand_pose_actions(
+        &mut self,
+        config: &HandPoseRecognitionConfig,
+        controllers_config: &ControllersConfig,
+        device_id: u64,
+        skeleton: &[Pose; JOINT_COUNT],
+    ) -> Vec<ButtonEntry> {
+        let left_hand = device_id == *HAND_LEFT_ID;
+
+        let confidences = self.recognize(config, skeleton, left_hand);
+        let active_idx = confidences
+            .iter()
+            .enumerate()
+            .filter(|(_, confidence)| **confidence >= config.min_confidence)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(idx, _)| idx);
+
+        let buttons = config
+            .templates
+            .iter()
+            .map(|template| {
+                if left_hand {
+                    template.left_hand_button.as_ref()
+                } else {
+                    template.right_hand_button.as_ref()
+                }
+                .map(|path| hash_string(path))
+            })
+            .collect::<Vec<_>>();
+
+        let source_set = config
+            .templates
+            .iter()
+            .flat_map(|template| [&template.left_hand_button, &template.right_hand_button])
+            .flatten()
+            .map(|path| hash_string(path))
+            .collect::<HashSet<_>>();
+        if !matches!(&self.button_mapping, Some((set, _)) if *set == source_set) {
+            let manager = ButtonMappingManager::new_automatic(
+                &source_set,
+                &controllers_config.emulation_mode,
+                &controllers_config.button_mapping_config,
+            );
+            self.button_mapping = Some((source_set, manager));
+        }
+        let Some((_, button_mapping_manager)) = &mut self.button_mapping else {
+            return vec![];
+        };
+
+        // Several templates can share a button, which is pressed if any of them is active
+        let mut button_values: Vec<ButtonEntry> = vec![];
+        for (idx, (button, confidence)) in buttons.into_iter().zip(confidences).enumerate() {
+            let Some(path_id) = button else {
+                continue;
+            };
+            let active = active_idx == Some(idx);
+
+            let value = match BUTTON_INFO.get(&path_id).map(|info| &info.button_type) {
+                Some(ButtonType::Scalar) => {
+                    ButtonValue::Scalar(if active { confidence } else { 0.0 })
+                }
+                _ => ButtonValue::Binary(active),
+            };
+
+            if let Some(entry) = button_values
+                .iter_mut()
+                .find(|entry| entry.path_id == path_id)
+            {
+                entry.value = match (entry.value, value) {
+                    (ButtonValue::Binary(a), ButtonValue::Binary(b)) => ButtonValue::Binary(a || b),
+                    (ButtonValue::Scalar(a), ButtonValue::Scalar(b)) => {
+                        ButtonValue::Scalar(f32::max(a, b))
+                    }
+                    (value, _) => value,
+                };
+            } else {
+                button_values.push(ButtonEntry { path_id, value });
+            }
+        }
+
+        button_values
+            .iter()
+            .flat_map(|entry| button_mapping_manager.map_button(entry))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alvr_common::glam::Quat;
+    use alvr_session::HandPoseTemplate;
+
+    fn skeleton(spread: f32) -> [Pose; JOINT_COUNT] {
+        let mut skeleton = [Pose::default(); JOINT_COUNT];
+        for (idx, joint) in skeleton.iter_mut().enumerate() {
+            joint.position = Vec3::new(idx as f32 * spread, 0.01 * (idx % 5) as f32, -0.01);
+        }
+
+        skeleton
+    }
+
+    #[test]
+    fn recognize_template() {
+        let template_skeleton = skeleton(0.004);
+        let config = HandPoseRecognitionConfig {
+            max_joint_error: 15.0,
+            min_confidence: 0.6,
+            templates: vec![HandPoseTemplate {
+                name: "Test".into(),
+                left_hand_button: None,
+                right_hand_button: None,
+                joint_positions: normalized_joint_positions(&template_skeleton, true)
+                    .iter()
+                    .flat_map(|p| p.to_array())
+                    .collect(),
+            }],
+        };
+        let recognizer = HandPoseRecognizer::new();
+
+        // Same pose, moved, rotated and scaled
+        let transform = Pose {
+            orientation: Quat::from_rotation_y(1.0),
+            position: Vec3::new(0.3, 1.2, -0.5),
+        };
+        let moved_skeleton = template_skeleton.map(|mut joint| {
+            joint.position *= 1.2;
+            transform * joint
+        });
+        let confidence = recognizer.recognize(&config, &moved_skeleton, true)[0];
+        assert!(confidence > 0.99);
+
+        // Mirrored pose of the right hand
+        let mirrored_skeleton = template_skeleton.map(|mut joint| {
+            joint.position.x = -joint.position.x;
+            joint
+        });
+        let confidence = recognizer.recognize(&config, &mirrored_skeleton, false)[0];
+        assert!(confidence > 0.99);
+
+        let confidence = recognizer.recognize(&config, &skeleton(0.012), true)[0];
+        assert!(confidence < 0.6);
+    }
+}
@@ -1,6 +1,22 @@
+// Haptics effects pipeline. The vibrations requested by SteamVR and the vibrations derived from the
+// game audio are queued as effects, with an attack and decay envelope. The effects of each device
+// are mixed and sent to the client as short pulses at a fixed interval, so they can overlap and
+// change amplitude over time.
+
+use crate::{record_session_packet, ConnectionContext, RecordedPacket, SESSION_MANAGER};
+use alvr_common::{HAND_LEFT_ID, HAND_RIGHT_ID};
 use alvr_packets::Haptics;
-use alvr_session::HapticsConfig;
-use std::time::Duration;
+use alvr_session::{AudioToHapticsConfig, HapticsConfig, HapticsMixingMode};
+use std::{
+    collections::HashMap,
+    f32::consts::PI,
+    time::{Duration, Instant},
+};
+
+pub const HAPTICS_MIX_INTERVAL: Duration = Duration::from_millis(10);
+
+// Game audio is sent as stereo 16 bit samples
+const GAME_AUDIO_CHANNELS: usize = 2;
 
 pub fn map_haptics(config: &HapticsConfig, haptics: Haptics) -> Haptics {
     Haptics {
@@ -13,3 +29,331 @@ pub fn map_haptics(config: &HapticsConfig, haptics: Haptics) -> Haptics {
         ..haptics
     }
 }
+
+#[derive(Clone, Copy, PartialEq)]
+enum EffectSource {
+    SteamVR,
+    GameAudio,
+}
+
+struct HapticsEffect {
+    source: EffectSource,
+    device_id: u64,
+    start: Instant,
+    duration: Duration,
+    frequency: f32,
+    amplitude: f32,
+    attack: Duration,
+    decay: Duration,
+}
+
+impl HapticsEffect {
+    fn end(&self) -> Instant {
+        self.start + self.duration
+    }
+
+    fn amplitude_at(&self, time: Instant) -> f32 {
+        let elapsed = time.saturating_duration_since(self.start);
+        let remaining = self.end().saturating_duration_since(time);
+
+        let attack = if self.attack.is_zero() {
+            1.0
+        } else {
+            elapsed.as_secs_f32() / self.attack.as_secs_f32()
+        };
+        let decay = if self.decay.is_zero() {
+            1.0
+        } else {
+            remaining.as_secs_f32() / self.decay.as_secs_f32()
+        };
+
+        self.amplitude * f32::min(attack, decay).clamp(0.0, 1.0)
+    }
+}
+
+pub struct HapticsManager {
+    effects: Vec<HapticsEffect>,
+    // State of the low-pass filter of the game audio
+    audio_low_pass: f32,
+}
+
+impl HapticsManager {
+    pub fn new() -> Self {
+        Self {
+            effects: vec![],
+            audio_low_pass: 0.0,
+        }
+    }
+
+    // Vibration requested by SteamVR. Like for a real controller, it replaces the previous
+    // vibration of the device, an event with zero amplitude or duration stops it
+    pub fn push_haptics(&mut self, config: &HapticsConfig, haptics: Haptics, now: Instant) {
+        self.effects.retain(|effect| {
+            effect.source != EffectSource::SteamVR || effect.device_id != haptics.device_id
+        });
+
+        if haptics.amplitude <= 0.0 || haptics.duration.is_zero() {
+            return;
+        }
+
+        let haptics = map_haptics(config, haptics);
+
+        self.effects.push(HapticsEffect {
+            source: EffectSource::SteamVR,
+            device_id: haptics.device_id,
+            start: now,
+            duration: haptics.duration,
+            frequency: haptics.frequency,
+            amplitude: haptics.amplitude,
+            attack: Duration::from_secs_f32(config.attack_s),
+            decay: Duration::from_secs_f32(config.decay_s),
+        });
+    }
+
+    // samples are the bytes of a game audio packet. The vibration lasts until the next packet
+    pub fn report_game_audio(
+        &mut self,
+        config: &AudioToHapticsConfig,
+        samples: &[u8],
+        sample_rate: u32,
+        now: Instant,
+    ) {
+        let frames = samples
+            .chunks_exact(GAME_AUDIO_CHANNELS * 2)
+            .map(|frame| {
+                frame
+                    .chunks_exact(2)
+                    .map(|sample| i16::from_ne_bytes([sample[0], sample[1]]) as f32 / 32768.0)
+                    .sum::<f32>()
+                    / GAME_AUDIO_CHANNELS as f32
+            })
+            .collect::<Vec<_>>();
+        if frames.is_empty() || sample_rate == 0 {
+            return;
+        }
+
+        // One pole low-pass filter
+        let alpha = 1.0 - f32::exp(-2.0 * PI * config.cutoff_frequency / sample_rate as f32);
+        let mut energy = 0.0;
+        for sample in &frames {
+            self.audio_low_pass += alpha * (sample - self.audio_low_pass);
+            energy += self.audio_low_pass * self.audio_low_pass;
+        }
+        let level = f32::sqrt(energy / frames.len() as f32);
+
+        let amplitude = (config.gain * (level - config.threshold)
+            / (1.0 - config.threshold).max(f32::EPSILON))
+        .clamp(0.0, 1.0);
+
+        // Audio packets can arrive late, the vibration is extended to cover the jitter
+        let duration = Duration::from_secs_f32(frames.len() as f32 / sample_rate as f32)
+            + HAPTICS_MIX_INTERVAL;
+
+        self.effects
+            .retain(|effect| effect.source != EffectSource::GameAudio);
+        if amplitude > 0.0 {
+            for device_id in [*HAND_LEFT_ID, *HAND_RIGHT_ID] {
+                self.effects.push(HapticsEffect {
+                    source: EffectSource::GameAudio,
+                    device_id,
+                    start: now,
+                    duration,
+                    frequency: config.vibration_frequency,
+                    amplitude,
+                    attack: Duration::ZERO,
+                    decay: Duration::ZERO,
+                });
+            }
+        }
+    }
+
+    // Returns the vibration of each device with active effects. Each vibration lasts until the next
+    // mix, or until the end of the effects
+    pub fn mix(&mut self, mixing_mode: HapticsMixingMode, now: Instant) -> Vec<Haptics> {
+        self.effects.retain(|effect| effect.end() > now);
+
+        let mut device_haptics = HashMap::<u64, (Haptics, f32)>::new();
+        for effect in &self.effects {
+            let amplitude = effect.amplitude_at(now);
+            let remaining = effect.end().saturating_duration_since(now);
+
+            let (haptics, strongest_amplitude) =
+                device_haptics.entry(effect.device_id).or_insert((
+                    Haptics {
+                        device_id: effect.device_id,
+                        duration: Duration::ZERO,
+                        frequency: effect.frequency,
+                        amplitude: 0.0,
+                    },
+                    0.0,
+                ));
+
+            haptics.duration = Duration::max(haptics.duration, remaining);
+            haptics.amplitude = match mixing_mode {
+                HapticsMixingMode::Maximum => f32::max(haptics.amplitude, amplitude),
+                HapticsMixingMode::Sum => haptics.amplitude + amplitude,
+            };
+            // The frequency cannot be mixed, the one of the strongest effect is used
+            if amplitude > *strongest_amplitude {
+                *strongest_amplitude = amplitude;
+                haptics.frequency = effect.frequency;
+            }
+        }
+
+        device_haptics
+            .into_values()
+            .map(|(haptics, _)| Haptics {
+                duration: Duration::min(haptics.duration, HAPTICS_MIX_INTERVAL * 2),
+                amplitude: haptics.amplitude.min(1.0),
+                ..haptics
+            })
+            .collect()
+    }
+}
+
+pub fn send_mixed_haptics(connection_context: &ConnectionContext) {
+    let Some(config) = SESSION_MANAGER
+        .read()
+        .settings()
+        .headset
+        .controllers
+        .as_option()
+        .and_then(|c| c.haptics.as_option().cloned())
+    else {
+        return;
+    };
+
+    let haptics_list = connection_context
+        .haptics_manager
+        .lock()
+        .mix(config.mixing_mode, Instant::now());
+
+    if let Some(sender) = &mut *connection_context.haptics_sender.lock() {
+        for haptics in haptics_list {
+            record_session_packet(connection_context, || {
+                RecordedPacket::Haptics(haptics.clone())
+            });
+
+            sender.send_header(&haptics).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> HapticsConfig {
+        HapticsConfig {
+            intensity_multiplier: 1.0,
+            amplitude_curve: 1.0,
+            min_duration_s: 0.0,
+            attack_s: 0.1,
+            decay_s: 0.1,
+            mixing_mode: HapticsMixingMode::Sum,
+            audio_to_haptics: alvr_session::settings_schema::Switch::Disabled,
+        }
+    }
+
+    fn haptics() -> Haptics {
+        Haptics {
+            device_id: *HAND_LEFT_ID,
+            duration: Duration::from_millis(400),
+            frequency: 100.0,
+            amplitude: 0.8,
+        }
+    }
+
+    #[test]
+    fn envelope_and_mixing() {
+        let config = config();
+        let start = Instant::now();
+
+        let mut manager = HapticsManager::new();
+        manager.push_haptics(&config, haptics(), start);
+
+        // Half of the attack
+        let mixed = manager.mix(HapticsMixingMode::Sum, start + Duration::from_millis(50));
+        assert_eq!(mixed.len(), 1);
+        assert!((mixed[0].amplitude - 0.4).abs() < 1e-4);
+        assert_eq!(mixed[0].duration, HAPTICS_MIX_INTERVAL * 2);
+
+        // Stronger overlapping effect from another source, with a different frequency
+        manager.effects.push(HapticsEffect {
+            source: EffectSource::GameAudio,
+            device_id: *HAND_LEFT_ID,
+            start: start + Duration::from_millis(100),
+            duration: Duration::from_millis(400),
+            frequency: 200.0,
+            amplitude: 0.9,
+            attack: Duration::from_millis(100),
+            decay: Duration::from_millis(100),
+        });
+        let time = start + Duration::from_millis(200);
+        let mixed = manager.mix(HapticsMixingMode::Sum, time);
+        assert_eq!(mixed[0].amplitude, 1.0);
+        assert_eq!(mixed[0].frequency, 200.0);
+        let mixed = manager.mix(HapticsMixingMode::Maximum, time);
+        assert!((mixed[0].amplitude - 0.9).abs() < 1e-4);
+
+        // Only the second effect is left, during its decay
+        let mixed = manager.mix(HapticsMixingMode::Sum, start + Duration::from_millis(450));
+        assert!((mixed[0].amplitude - 0.45).abs() < 1e-4);
+
+        assert!(manager
+            .mix(HapticsMixingMode::Sum, start + Duration::from_millis(500))
+            .is_empty());
+    }
+
+    #[test]
+    fn steamvr_events_replace_previous() {
+        let config = config();
+        let start = Instant::now();
+
+        let mut manager = HapticsManager::new();
+        manager.push_haptics(&config, haptics(), start);
+        manager.push_haptics(
+            &config,
+            Haptics {
+                amplitude: 0.2,
+                ..haptics()
+            },
+            start + Duration::from_millis(100),
+        );
+
+        // Past the attack of the second event
+        let mixed = manager.mix(
+            HapticsMixingMode::Maximum,
+            start + Duration::from_millis(250),
+        );
+        assert!((mixed[0].amplitude - 0.2).abs() < 1e-4);
+
+        // Stop events
+        for stop_haptics in [
+            Haptics {
+                amplitude: 0.0,
+                ..haptics()
+            },
+            Haptics {
+                duration: Duration::ZERO,
+                ..haptics()
+            },
+        ] {
+            manager.push_haptics(&config, haptics(), start);
+            assert!(!manager
+                .mix(
+                    HapticsMixingMode::Maximum,
+                    start + Duration::from_millis(200)
+                )
+                .is_empty());
+
+            manager.push_haptics(&config, stop_haptics, start + Duration::from_millis(200));
+            assert!(manager
+                .mix(
+                    HapticsMixingMode::Maximum,
+                    start + Duration::from_millis(210)
+                )
+                .is_empty());
+        }
+    }
+}
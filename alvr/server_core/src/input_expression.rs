@@ -0,0 +1,587 @@
+// Expressions used by button mappings. All values are numbers: binary buttons and comparisons are
+// 1 when true and 0 when false, and any non zero value is considered true.
+//
+// Grammar, from the lowest precedence:
+// cond ? a : b, ||, &&, == != < <= > >=, + -, * /, ! -, (expr), literals and functions
+//
+// Variables: value (the source button), time (seconds).
+// Functions: button(/path), min(a, b), max(a, b), clamp(x, min, max), abs(x), toggle(x),
+// long_press(x, seconds), double_tap(x, seconds).
+//
+// toggle, long_press and double_tap keep a state for each place they appear in the expression.
+
+use alvr_common::{
+    anyhow::{bail, Result},
+    hash_string,
+};
+use std::{iter::Peekable, str::Chars};
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(f32),
+    Identifier(String),
+    Path(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 19] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "+", "-", "*", "/", "?", ":", "(", ")", ",",
+    "=",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    fn take_while(chars: &mut Peekable<Chars>, predicate: impl Fn(char) -> bool) -> String {
+        let mut string = String::new();
+        while let Some(c) = chars.next_if(|c| predicate(*c)) {
+            string.push(c);
+        }
+
+        string
+    }
+
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let number = take_while(&mut chars, |c| c.is_ascii_digit() || c == '.');
+            match number.parse() {
+                Ok(number) => tokens.push(Token::Number(number)),
+                Err(_) => bail!("Invalid number \"{number}\""),
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            tokens.push(Token::Identifier(take_while(&mut chars, |c| {
+                c.is_alphanumeric() || c == '_'
+            })));
+        } else if c == '/' && tokens.last() == Some(&Token::Symbol("(")) {
+            // Paths are only allowed as function arguments, so they are not confused with divisions
+            tokens.push(Token::Path(take_while(&mut chars, |c| {
+                c.is_alphanumeric() || c == '/' || c == '_' || c == '-'
+            })));
+        } else {
+            let rest = chars.clone().collect::<String>();
+            let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) else {
+                bail!("Unexpected character '{c}'");
+            };
+            if *symbol == "=" {
+                bail!("Unexpected '=', use '==' for comparisons");
+            }
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push(Token::Symbol(symbol));
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum UnaryOp {
+    Not,
+    Negate,
+    Abs,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Min,
+    Max,
+}
+
+// Functions that depend on the previous evaluations
+#[derive(Clone, Copy, Debug)]
+enum StatefulFunction {
+    Toggle,
+    LongPress,
+    DoubleTap,
+}
+
+#[derive(Debug)]
+enum Node {
+    Number(f32),
+    Value,
+    Time,
+    Button(u64),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+    Conditional(Box<Node>, Box<Node>, Box<Node>),
+    Clamp(Box<Node>, Box<Node>, Box<Node>),
+    Stateful {
+        function: StatefulFunction,
+        state_idx: usize,
+        input: Box<Node>,
+        seconds: Option<Box<Node>>,
+    },
+}
+
+// Limits the nesting of parentheses, operators and function calls, so that long expressions fail
+// to parse instead of overflowing the stack while parsing or evaluating
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    state_count: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+
+        token
+    }
+
+    fn accept(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn enter(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            bail!("The expression is nested more than {MAX_DEPTH} levels");
+        }
+
+        Ok(())
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        if self.accept(symbol) {
+            Ok(())
+        } else {
+            match self.peek() {
+                Some(token) => bail!("Expected '{symbol}', found {token:?}"),
+                None => bail!("Expected '{symbol}' at the end of the expression"),
+            }
+        }
+    }
+
+    fn conditional(&mut self) -> Result<Node> {
+        self.enter()?;
+
+        let condition = self.binary(0)?;
+
+        let node = if self.accept("?") {
+            let if_true = self.conditional()?;
+            self.expect(":")?;
+            let if_false = self.conditional()?;
+
+            Node::Conditional(Box::new(condition), Box::new(if_true), Box::new(if_false))
+        } else {
+            condition
+        };
+
+        self.depth -= 1;
+
+        Ok(node)
+    }
+
+    // Operators grouped by precedence, from the lowest
+    fn binary(&mut self, level: usize) -> Result<Node> {
+        const LEVELS: [&[(&str, BinaryOp)]; 5] = [
+            &[("||", BinaryOp::Or)],
+            &[("&&", BinaryOp::And)],
+            &[
+                ("==", BinaryOp::Equal),
+                ("!=", BinaryOp::NotEqual),
+                ("<=", BinaryOp::LessEqual),
+                (">=", BinaryOp::GreaterEqual),
+                ("<", BinaryOp::Less),
+                (">", BinaryOp::Greater),
+            ],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+            &[("*", BinaryOp::Multiply), ("/", BinaryOp::Divide)],
+        ];
+
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        // Each operator nests the previous ones
+        let depth = self.depth;
+        let mut node = self.binary(level + 1)?;
+        'operators: loop {
+            for (symbol, op) in LEVELS[level] {
+                if self.accept(symbol) {
+                    self.enter()?;
+                    let rhs = self.binary(level + 1)?;
+                    node = Node::Binary(*op, Box::new(node), Box::new(rhs));
+
+                    continue 'operators;
+                }
+            }
+
+            self.depth = depth;
+
+            return Ok(node);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node> {
+        let op = if self.accept("!") {
+            UnaryOp::Not
+        } else if self.accept("-") {
+            UnaryOp::Negate
+        } else {
+            return self.primary();
+        };
+
+        self.enter()?;
+        let node = Node::Unary(op, Box::new(self.unary()?));
+        self.depth -= 1;
+
+        Ok(node)
+    }
+
+    fn arguments(&mut self, count: usize) -> Result<Vec<Node>> {
+        self.expect("(")?;
+        let mut arguments = vec![];
+        for idx in 0..count {
+            if idx > 0 {
+                self.expect(",")?;
+            }
+            arguments.push(self.conditional()?);
+        }
+        self.expect(")")?;
+
+        Ok(arguments)
+    }
+
+    fn primary(&mut self) -> Result<Node> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Node::Number(number)),
+            Some(Token::Symbol("(")) => {
+                let node = self.conditional()?;
+                self.expect(")")?;
+
+                Ok(node)
+            }
+            Some(Token::Identifier(name)) => self.identifier(&name),
+            Some(token) => bail!("Unexpected {token:?}"),
+            None => bail!("Unexpected end of the expression"),
+        }
+    }
+
+    fn stateful(&mut self, function: StatefulFunction, argument_count: usize) -> Result<Node> {
+        let mut arguments = self.arguments(argument_count)?.into_iter();
+        let input = Box::new(arguments.next().unwrap());
+        let seconds = arguments.next().map(Box::new);
+
+        let state_idx = self.state_count;
+        self.state_count += 1;
+
+        Ok(Node::Stateful {
+            function,
+            state_idx,
+            input,
+            seconds,
+        })
+    }
+
+    fn identifier(&mut self, name: &str) -> Result<Node> {
+        let node = match name {
+            "true" => Node::Number(1.0),
+            "false" => Node::Number(0.0),
+            "value" => Node::Value,
+            "time" => Node::Time,
+            "button" => {
+                self.expect("(")?;
+                let Some(Token::Path(path)) = self.next() else {
+                    bail!("button() requires a path, for example button(/user/hand/left/input/a/click)");
+                };
+                self.expect(")")?;
+
+                Node::Button(hash_string(&path))
+            }
+            "abs" => {
+                let [x] = <[Node; 1]>::try_from(self.arguments(1)?).unwrap();
+                Node::Unary(UnaryOp::Abs, Box::new(x))
+            }
+            "min" | "max" => {
+                let [a, b] = <[Node; 2]>::try_from(self.arguments(2)?).unwrap();
+                let op = if name == "min" {
+                    BinaryOp::Min
+                } else {
+                    BinaryOp::Max
+                };
+
+                Node::Binary(op, Box::new(a), Box::new(b))
+            }
+            "clamp" => {
+                let [x, min, max] = <[Node; 3]>::try_from(self.arguments(3)?).unwrap();
+                Node::Clamp(Box::new(x), Box::new(min), Box::new(max))
+            }
+            "toggle" => self.stateful(StatefulFunction::Toggle, 1)?,
+            "long_press" => self.stateful(StatefulFunction::LongPress, 2)?,
+            "double_tap" => self.stateful(StatefulFunction::DoubleTap, 2)?,
+            _ => bail!("Unknown identifier \"{name}\""),
+        };
+
+        Ok(node)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct CallState {
+    previous_input: bool,
+    output: bool,
+    // Time of the last rising edge of the input
+    press_time: Option<f32>,
+}
+
+// Values of the variables at the time of the evaluation
+pub struct ExpressionContext<'a> {
+    pub value: f32,
+    pub time_s: f32,
+    pub button_value: &'a dyn Fn(u64) -> f32,
+}
+
+pub struct Expression {
+    root: Node,
+    states: Vec<CallState>,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            state_count: 0,
+            depth: 0,
+        };
+
+        let root = parser.conditional()?;
+        if let Some(token) = parser.peek() {
+            bail!("Unexpected {token:?}");
+        }
+
+        Ok(Self {
+            root,
+            states: vec![CallState::default(); parser.state_count],
+        })
+    }
+
+    pub fn evaluate(&mut self, context: &ExpressionContext) -> f32 {
+        evaluate_node(&self.root, &mut self.states, context)
+    }
+}
+
+fn from_bool(value: bool) -> f32 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn evaluate_node(node: &Node, states: &mut [CallState], context: &ExpressionContext) -> f32 {
+    let mut eval = |node: &Node| evaluate_node(node, states, context);
+
+    match node {
+        Node::Number(number) => *number,
+        Node::Value => context.value,
+        Node::Time => context.time_s,
+        Node::Button(id) => (context.button_value)(*id),
+        Node::Unary(op, x) => {
+            let x = eval(x);
+            match op {
+                UnaryOp::Not => from_bool(x == 0.0),
+                UnaryOp::Negate => -x,
+                UnaryOp::Abs => x.abs(),
+            }
+        }
+        // Both sides are always evaluated, so the stateful functions are updated consistently
+        Node::Binary(op, a, b) => {
+            let a = eval(a);
+            let b = eval(b);
+            match op {
+                BinaryOp::Or => from_bool(a != 0.0 || b != 0.0),
+                BinaryOp::And => from_bool(a != 0.0 && b != 0.0),
+                BinaryOp::Equal => from_bool(a == b),
+                BinaryOp::NotEqual => from_bool(a != b),
+                BinaryOp::Less => from_bool(a < b),
+                BinaryOp::LessEqual => from_bool(a <= b),
+                BinaryOp::Greater => from_bool(a > b),
+                BinaryOp::GreaterEqual => from_bool(a >= b),
+                BinaryOp::Add => a + b,
+                BinaryOp::Subtract => a - b,
+                BinaryOp::Multiply => a * b,
+                BinaryOp::Divide => a / b,
+                BinaryOp::Min => f32::min(a, b),
+                BinaryOp::Max => f32::max(a, b),
+            }
+        }
+        Node::Conditional(condition, if_true, if_false) => {
+            let condition = eval(condition);
+            let if_true = eval(if_true);
+            let if_false = eval(if_false);
+
+            if condition != 0.0 {
+                if_true
+            } else {
+                if_false
+            }
+        }
+        Node::Clamp(x, min, max) => {
+            let x = eval(x);
+            let min = eval(min);
+            let max = eval(max);
+
+            f32::min(f32::max(x, min), max)
+        }
+        Node::Stateful {
+            function,
+            state_idx,
+            input,
+            seconds,
+        } => {
+            let input = eval(input) != 0.0;
+            let seconds = seconds.as_deref().map(&mut eval).unwrap_or(0.0);
+            let time = context.time_s;
+
+            let state = &mut states[*state_idx];
+            let pressed = input && !state.previous_input;
+            state.previous_input = input;
+
+            match function {
+                StatefulFunction::Toggle => {
+                    if pressed {
+                        state.output = !state.output;
+                    }
+                }
+                StatefulFunction::LongPress => {
+                    if pressed {
+                        state.press_time = Some(time);
+                    }
+                    state.output = input && state.press_time.is_some_and(|t| time - t >= seconds);
+                }
+                StatefulFunction::DoubleTap => {
+                    if pressed {
+                        if state.press_time.is_some_and(|t| time - t <= seconds) {
+                            state.output = true;
+                            state.press_time = None;
+                        } else {
+                            state.press_time = Some(time);
+                        }
+                    }
+                    // The second press is held as long as the button is pressed
+                    state.output &= input;
+                }
+            }
+
+            from_bool(state.output)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(
+        expression: &mut Expression,
+        value: f32,
+        time_s: f32,
+        buttons: &[(&str, f32)],
+    ) -> f32 {
+        let button_value = |id| {
+            buttons
+                .iter()
+                .find(|(path, _)| hash_string(path) == id)
+                .map(|(_, value)| *value)
+                .unwrap_or(0.0)
+        };
+
+        expression.evaluate(&ExpressionContext {
+            value,
+            time_s,
+            button_value: &button_value,
+        })
+    }
+
+    #[test]
+    fn arithmetic_and_buttons() {
+        let mut expression = Expression::parse(
+            "button(/user/hand/left/input/a/click) && value > 0.5 ? clamp(value * 2 - 1, 0, 1) : -1",
+        )
+        .unwrap();
+
+        let buttons = [("/user/hand/left/input/a/click", 1.0)];
+        assert_eq!(evaluate(&mut expression, 0.75, 0.0, &buttons), 0.5);
+        assert_eq!(evaluate(&mut expression, 0.25, 0.0, &buttons), -1.0);
+        assert_eq!(evaluate(&mut expression, 0.75, 0.0, &[]), -1.0);
+
+        assert!(Expression::parse("value = 1").is_err());
+        assert!(Expression::parse("min(value)").is_err());
+        assert!(Expression::parse("(value").is_err());
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |open: &str, inner: &str, close: &str, levels: usize| {
+            format!("{}{inner}{}", open.repeat(levels), close.repeat(levels))
+        };
+
+        assert!(Expression::parse(&nested("(", "value", ")", 32)).is_ok());
+        assert!(Expression::parse(&nested("abs(", "value", ")", 32)).is_ok());
+        assert!(Expression::parse(&nested("", "value", " + 1", 32)).is_ok());
+
+        assert!(Expression::parse(&nested("(", "value", ")", 10_000)).is_err());
+        assert!(Expression::parse(&nested("abs(", "value", ")", 10_000)).is_err());
+        assert!(Expression::parse(&nested("!", "value", "", 10_000)).is_err());
+        assert!(Expression::parse(&nested("value ? 1 : ", "0", "", 10_000)).is_err());
+        // Long chains of operators build deep trees too
+        assert!(Expression::parse(&nested("", "value", " + 1", 10_000)).is_err());
+        assert!(Expression::parse(&nested("", "value", " * 2 - 1", 10_000)).is_err());
+    }
+
+    #[test]
+    fn stateful_functions() {
+        let mut expression = Expression::parse("long_press(value, 0.5)").unwrap();
+        assert_eq!(evaluate(&mut expression, 1.0, 0.0, &[]), 0.0);
+        assert_eq!(evaluate(&mut expression, 1.0, 0.4, &[]), 0.0);
+        assert_eq!(evaluate(&mut expression, 1.0, 0.6, &[]), 1.0);
+        assert_eq!(evaluate(&mut expression, 0.0, 0.7, &[]), 0.0);
+
+        let mut expression = Expression::parse("double_tap(value, 0.3)").unwrap();
+        assert_eq!(evaluate(&mut expression, 1.0, 0.0, &[]), 0.0);
+        assert_eq!(evaluate(&mut expression, 0.0, 0.1, &[]), 0.0);
+        assert_eq!(evaluate(&mut expression, 1.0, 0.2, &[]), 1.0);
+        assert_eq!(evaluate(&mut expression, 0.0, 0.3, &[]), 0.0);
+        // Too late for a double tap
+        assert_eq!(evaluate(&mut expression, 1.0, 1.0, &[]), 0.0);
+        assert_eq!(evaluate(&mut expression, 0.0, 1.1, &[]), 0.0);
+        assert_eq!(evaluate(&mut expression, 1.0, 1.5, &[]), 0.0);
+
+        // Each call has its own state
+        let mut expression = Expression::parse("toggle(value) + toggle(value > 0.5)").unwrap();
+        assert_eq!(evaluate(&mut expression, 0.3, 0.0, &[]), 1.0);
+        assert_eq!(evaluate(&mut expression, 0.8, 0.0, &[]), 2.0);
+        assert_eq!(evaluate(&mut expression, 0.0, 0.0, &[]), 2.0);
+        assert_eq!(evaluate(&mut expression, 1.0, 0.0, &[]), 0.0);
+    }
+}
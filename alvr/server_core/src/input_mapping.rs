@@ -1,10 +1,14 @@
+use crate::input_expression::{Expression, ExpressionContext};
 use alvr_common::*;
 use alvr_packets::{ButtonEntry, ButtonValue};
 use alvr_session::{
-    AutomaticButtonMappingConfig, BinaryToScalarStates, ButtonBindingTarget, ButtonMappingType,
-    ControllersEmulationMode, HysteresisThreshold, Range,
+    AutomaticButtonMappingConfig, BinaryToScalarStates, ButtonBindingTarget, ButtonMappingProfile,
+    ButtonMappingType, ControllersConfig, ControllersEmulationMode, HysteresisThreshold, Range,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
 };
-use std::collections::{HashMap, HashSet};
 
 pub fn registered_button_set(
     controllers_emulation_mode: &ControllersEmulationMode,
@@ -35,10 +39,43 @@ pub fn registered_button_set(
     }
 }
 
+// The forced profile has priority over the profiles of the running application. None means that the
+// default mappings should be used
+pub fn active_button_mapping_profile<'a>(
+    config: &'a ControllersConfig,
+    running_application: Option<&str>,
+) -> Option<&'a ButtonMappingProfile> {
+    if let Some(name) = &config.forced_button_mapping_profile {
+        if let Some(profile) = config
+            .button_mapping_profiles
+            .iter()
+            .find(|profile| profile.name == *name)
+        {
+            return Some(profile);
+        }
+    }
+
+    let running_application = running_application?;
+    config.button_mapping_profiles.iter().find(|profile| {
+        profile
+            .applications
+            .iter()
+            .any(|application| application == running_application)
+    })
+}
+
+struct ExpressionMapping {
+    expression: Expression,
+    // Used to send only the changes of the result
+    last_value: Option<ButtonValue>,
+}
+
 pub struct BindingTarget {
     destination: u64,
     mapping_type: ButtonMappingType,
     binary_conditions: Vec<u64>,
+    // Parsed from ButtonMappingType::Expression
+    expression: Option<ExpressionMapping>,
 }
 
 // Inputs relative to the same physical button
@@ -100,6 +137,7 @@ fn passthrough(target: u64) -> BindingTarget {
         destination: target,
         mapping_type: ButtonMappingType::Passthrough,
         binary_conditions: vec![],
+        expression: None,
     }
 }
 
@@ -108,6 +146,7 @@ fn binary_to_scalar(target: u64, map: BinaryToScalarStates) -> BindingTarget {
         destination: target,
         mapping_type: ButtonMappingType::BinaryToScalar(map),
         binary_conditions: vec![],
+        expression: None,
     }
 }
 
@@ -116,6 +155,7 @@ fn hysteresis_threshold(target: u64, map: HysteresisThreshold) -> BindingTarget
         destination: target,
         mapping_type: ButtonMappingType::HysteresisThreshold(map),
         binary_conditions: vec![],
+        expression: None,
     }
 }
 
@@ -124,6 +164,7 @@ fn remap(target: u64, map: Range) -> BindingTarget {
         destination: target,
         mapping_type: ButtonMappingType::Remap(map),
         binary_conditions: vec![],
+        expression: None,
     }
 }
 
@@ -520,10 +561,60 @@ pub fn automatic_bindings(
     bindings
 }
 
+fn conditions_met(binary_source_states: &HashMap<u64, bool>, binary_conditions: &[u64]) -> bool {
+    binary_conditions
+        .iter()
+        .all(|id| binary_source_states.get(id).copied().unwrap_or(false))
+}
+
+fn source_value(
+    binary_source_states: &HashMap<u64, bool>,
+    scalar_source_states: &HashMap<u64, f32>,
+    id: u64,
+) -> f32 {
+    if let Some(value) = binary_source_states.get(&id) {
+        if *value {
+            1.0
+        } else {
+            0.0
+        }
+    } else {
+        scalar_source_states.get(&id).copied().unwrap_or(0.0)
+    }
+}
+
+// Returns None if the binary conditions are not met or if the result did not change since it was
+// last sent. The expression is always evaluated, since it keeps track of the input history
+fn evaluate_expression(
+    mapping: &mut ExpressionMapping,
+    destination: u64,
+    context: &ExpressionContext,
+    conditions_met: bool,
+) -> Option<ButtonValue> {
+    let result = mapping.expression.evaluate(context);
+
+    let value = match BUTTON_INFO.get(&destination).map(|info| &info.button_type) {
+        Some(ButtonType::Binary) => ButtonValue::Binary(result != 0.0),
+        _ => ButtonValue::Scalar(result),
+    };
+
+    if !conditions_met || mapping.last_value == Some(value) {
+        None
+    } else {
+        mapping.last_value = Some(value);
+
+        Some(value)
+    }
+}
+
 pub struct ButtonMappingManager {
     mappings: HashMap<u64, Vec<BindingTarget>>,
     binary_source_states: HashMap<u64, bool>,
+    scalar_source_states: HashMap<u64, f32>,
     hysteresis_states: HashMap<u64, HashMap<u64, bool>>,
+    // Last values sent to each destination, used to release them when the mappings are replaced
+    output_states: HashMap<u64, ButtonValue>,
+    start_instant: Instant,
 }
 
 impl ButtonMappingManager {
@@ -536,7 +627,10 @@ impl ButtonMappingManager {
         Self {
             mappings: automatic_bindings(source, &button_set, button_mapping_config),
             binary_source_states: HashMap::new(),
+            scalar_source_states: HashMap::new(),
             hysteresis_states: HashMap::new(),
+            output_states: HashMap::new(),
+            start_instant: Instant::now(),
         }
     }
 
@@ -548,14 +642,33 @@ impl ButtonMappingManager {
                     alvr_common::hash_string(key),
                     value
                         .iter()
-                        .map(|b| BindingTarget {
-                            destination: alvr_common::hash_string(&b.destination),
-                            mapping_type: b.mapping_type.clone(),
-                            binary_conditions: b
-                                .binary_conditions
-                                .iter()
-                                .map(|c| alvr_common::hash_string(c))
-                                .collect(),
+                        .filter_map(|b| {
+                            let expression =
+                                if let ButtonMappingType::Expression(source) = &b.mapping_type {
+                                    match Expression::parse(source) {
+                                        Ok(expression) => Some(ExpressionMapping {
+                                            expression,
+                                            last_value: None,
+                                        }),
+                                        Err(e) => {
+                                            error!("Invalid mapping expression for {key}: {e}");
+                                            return None;
+                                        }
+                                    }
+                                } else {
+                                    None
+                                };
+
+                            Some(BindingTarget {
+                                destination: alvr_common::hash_string(&b.destination),
+                                mapping_type: b.mapping_type.clone(),
+                                binary_conditions: b
+                                    .binary_conditions
+                                    .iter()
+                                    .map(|c| alvr_common::hash_string(c))
+                                    .collect(),
+                                expression,
+                            })
                         })
                         .collect(),
                 )
@@ -565,30 +678,51 @@ impl ButtonMappingManager {
         Self {
             mappings,
             binary_source_states: HashMap::new(),
+            scalar_source_states: HashMap::new(),
             hysteresis_states: HashMap::new(),
+            output_states: HashMap::new(),
+            start_instant: Instant::now(),
         }
     }
 
+    // If true, update() should be called periodically
+    pub fn has_expressions(&self) -> bool {
+        self.mappings
+            .values()
+            .flatten()
+            .any(|mapping| mapping.expression.is_some())
+    }
+
     // Apply any button changes that are mapped to this specific button
     pub fn map_button(&mut self, source_button: &ButtonEntry) -> Vec<ButtonEntry> {
-        if let ButtonValue::Binary(value) = source_button.value {
-            let val_ref = self
-                .binary_source_states
-                .entry(source_button.path_id)
-                .or_default();
-
-            if value == *val_ref {
-                return vec![];
-            }
+        self.map_button_at(source_button, self.start_instant.elapsed().as_secs_f32())
+    }
 
-            // NB: Update value
-            *val_ref = value;
+    fn map_button_at(&mut self, source_button: &ButtonEntry, time_s: f32) -> Vec<ButtonEntry> {
+        match source_button.value {
+            ButtonValue::Binary(value) => {
+                let val_ref = self
+                    .binary_source_states
+                    .entry(source_button.path_id)
+                    .or_default();
+
+                if value == *val_ref {
+                    return vec![];
+                }
+
+                // NB: Update value
+                *val_ref = value;
+            }
+            ButtonValue::Scalar(value) => {
+                self.scalar_source_states
+                    .insert(source_button.path_id, value);
+            }
         }
 
         let mut destination_buttons = vec![];
 
-        if let Some(mappings) = self.mappings.get(&source_button.path_id) {
-            'mapping: for mapping in mappings {
+        if let Some(mappings) = self.mappings.get_mut(&source_button.path_id) {
+            for mapping in mappings {
                 let destination_value = match (&mapping.mapping_type, source_button.value) {
                     (ButtonMappingType::Passthrough, value) => value,
                     (
@@ -624,21 +758,46 @@ impl ButtonMappingManager {
                         let value = (value - range.min) / (range.max - range.min);
                         ButtonValue::Scalar(value.clamp(0.0, 1.0))
                     }
+                    (ButtonMappingType::Expression(_), _) => {
+                        let Some(expression) = &mut mapping.expression else {
+                            continue;
+                        };
+
+                        let context = ExpressionContext {
+                            value: source_value(
+                                &self.binary_source_states,
+                                &self.scalar_source_states,
+                                source_button.path_id,
+                            ),
+                            time_s,
+                            button_value: &|id| {
+                                source_value(
+                                    &self.binary_source_states,
+                                    &self.scalar_source_states,
+                                    id,
+                                )
+                            },
+                        };
+
+                        let Some(value) = evaluate_expression(
+                            expression,
+                            mapping.destination,
+                            &context,
+                            conditions_met(&self.binary_source_states, &mapping.binary_conditions),
+                        ) else {
+                            continue;
+                        };
+
+                        value
+                    }
                     _ => {
                         error!("Failed to map button!");
                         continue;
                     }
                 };
 
-                for source_id in &mapping.binary_conditions {
-                    if !self
-                        .binary_source_states
-                        .get(source_id)
-                        .copied()
-                        .unwrap_or(false)
-                    {
-                        continue 'mapping;
-                    }
+                if !conditions_met(&self.binary_source_states, &mapping.binary_conditions) {
+                    continue;
                 }
 
                 destination_buttons.push(ButtonEntry {
@@ -654,6 +813,292 @@ impl ButtonMappingManager {
             info!("Received button not mapped: {button_name}");
         }
 
+        self.record_outputs(&destination_buttons);
+
+        destination_buttons
+    }
+
+    // Evaluates the expressions again, since they can change over time (long_press, double_tap)
+    // or with buttons that are not their source
+    pub fn update(&mut self) -> Vec<ButtonEntry> {
+        self.update_at(self.start_instant.elapsed().as_secs_f32())
+    }
+
+    fn update_at(&mut self, time_s: f32) -> Vec<ButtonEntry> {
+        let mut destination_buttons = vec![];
+        for (source_id, mappings) in &mut self.mappings {
+            for mapping in mappings {
+                let Some(expression) = &mut mapping.expression else {
+                    continue;
+                };
+
+                let context = ExpressionContext {
+                    value: source_value(
+                        &self.binary_source_states,
+                        &self.scalar_source_states,
+                        *source_id,
+                    ),
+                    time_s,
+                    button_value: &|id| {
+                        source_value(&self.binary_source_states, &self.scalar_source_states, id)
+                    },
+                };
+
+                if let Some(value) = evaluate_expression(
+                    expression,
+                    mapping.destination,
+                    &context,
+                    conditions_met(&self.binary_source_states, &mapping.binary_conditions),
+                ) {
+                    destination_buttons.push(ButtonEntry {
+                        path_id: mapping.destination,
+                        value,
+                    });
+                }
+            }
+        }
+
+        self.record_outputs(&destination_buttons);
+
         destination_buttons
     }
+
+    fn record_outputs(&mut self, destination_buttons: &[ButtonEntry]) {
+        for button in destination_buttons {
+            self.output_states.insert(button.path_id, button.value);
+        }
+    }
+
+    // Returns the entries that release the destinations that are pressed or not at rest. To be
+    // sent before the manager is dropped, otherwise the buttons stay held
+    pub fn release_outputs(&mut self) -> Vec<ButtonEntry> {
+        self.output_states
+            .drain()
+            .filter_map(|(path_id, value)| {
+                let value = match value {
+                    ButtonValue::Binary(true) => ButtonValue::Binary(false),
+                    ButtonValue::Scalar(value) if value != 0.0 => ButtonValue::Scalar(0.0),
+                    _ => return None,
+                };
+
+                Some(ButtonEntry { path_id, value })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alvr_session::SessionConfig;
+
+    const SOURCE: &str = "/user/hand/right/input/a/click";
+    const CHORD_SOURCE: &str = "/user/hand/right/input/b/click";
+    const DESTINATION: &str = "/user/hand/left/input/x/click";
+
+    fn expression_manager(expression: &str) -> ButtonMappingManager {
+        ButtonMappingManager::new_manual(&[(
+            SOURCE.into(),
+            vec![ButtonBindingTarget {
+                destination: DESTINATION.into(),
+                mapping_type: ButtonMappingType::Expression(expression.into()),
+                binary_conditions: vec![],
+            }],
+        )])
+    }
+
+    fn press(
+        manager: &mut ButtonMappingManager,
+        path: &str,
+        pressed: bool,
+        time_s: f32,
+    ) -> Vec<(u64, ButtonValue)> {
+        let buttons = manager.map_button_at(
+            &ButtonEntry {
+                path_id: hash_string(path),
+                value: ButtonValue::Binary(pressed),
+            },
+            time_s,
+        );
+
+        buttons.iter().map(|b| (b.path_id, b.value)).collect()
+    }
+
+    fn update(manager: &mut ButtonMappingManager, time_s: f32) -> Vec<(u64, ButtonValue)> {
+        let buttons = update(&mut manager, time_s);
+
+        buttons.iter().map(|b| (b.path_id, b.value)).collect()
+    }
+
+    fn output(value: bool) -> Vec<(u64, ButtonValue)> {
+        vec![(hash_string(DESTINATION), ButtonValue::Binary(value))]
+    }
+
+    #[test]
+    fn invalid_expressions_are_skipped() {
+        let manager = expression_manager("value +");
+        assert!(!manager.has_expressions());
+
+        let manager = expression_manager("value");
+        assert!(manager.has_expressions());
+    }
+
+    #[test]
+    fn toggle_mapping() {
+        let mut manager = expression_manager("toggle(value)");
+
+        assert_eq!(press(&mut manager, SOURCE, true, 0.0), output(true));
+        // Only changes of the result are sent
+        assert!(press(&mut manager, SOURCE, false, 0.1).is_empty());
+        assert!(update(&mut manager, 0.2).is_empty());
+        assert_eq!(press(&mut manager, SOURCE, true, 0.3), output(false));
+        assert!(press(&mut manager, SOURCE, false, 0.4).is_empty());
+    }
+
+    #[test]
+    fn long_press_mapping() {
+        let mut manager = expression_manager("long_press(value, 0.5)");
+
+        // The first evaluation sends the initial value
+        assert_eq!(press(&mut manager, SOURCE, true, 0.0), output(false));
+        assert!(update(&mut manager, 0.4).is_empty());
+        // The button is still held, the result only changes with time
+        assert_eq!(update(&mut manager, 0.6), output(true));
+        assert!(update(&mut manager, 0.7).is_empty());
+        assert_eq!(press(&mut manager, SOURCE, false, 0.8), output(false));
+
+        // Released too early
+        assert!(press(&mut manager, SOURCE, true, 1.0).is_empty());
+        assert!(press(&mut manager, SOURCE, false, 1.3).is_empty());
+        assert!(update(&mut manager, 2.0).is_empty());
+    }
+
+    #[test]
+    fn double_tap_mapping() {
+        let mut manager = expression_manager("double_tap(value, 0.3)");
+
+        assert_eq!(press(&mut manager, SOURCE, true, 0.0), output(false));
+        assert!(press(&mut manager, SOURCE, false, 0.1).is_empty());
+        assert_eq!(press(&mut manager, SOURCE, true, 0.2), output(true));
+        // Held as long as the second press
+        assert!(update(&mut manager, 1.0).is_empty());
+        assert_eq!(press(&mut manager, SOURCE, false, 1.1), output(false));
+
+        // Too slow
+        assert!(press(&mut manager, SOURCE, true, 2.0).is_empty());
+        assert!(press(&mut manager, SOURCE, false, 2.1).is_empty());
+        assert!(press(&mut manager, SOURCE, true, 2.5).is_empty());
+    }
+
+    #[test]
+    fn expressions_follow_other_buttons() {
+        let mut manager = expression_manager(&format!("value && button({CHORD_SOURCE})"));
+
+        assert_eq!(press(&mut manager, SOURCE, true, 0.0), output(false));
+        // The chord button is not the source of the mapping, so only update() sees it
+        assert!(press(&mut manager, CHORD_SOURCE, true, 0.1).is_empty());
+        assert_eq!(update(&mut manager, 0.1), output(true));
+        assert_eq!(press(&mut manager, SOURCE, false, 0.2), output(false));
+    }
+
+    #[test]
+    fn release_held_outputs() {
+        let mut manager = ButtonMappingManager::new_manual(&[
+            (
+                SOURCE.into(),
+                vec![ButtonBindingTarget {
+                    destination: DESTINATION.into(),
+                    mapping_type: ButtonMappingType::Passthrough,
+                    binary_conditions: vec![],
+                }],
+            ),
+            (
+                CHORD_SOURCE.into(),
+                vec![ButtonBindingTarget {
+                    destination: "/user/hand/left/input/trigger/value".into(),
+                    mapping_type: ButtonMappingType::BinaryToScalar(BinaryToScalarStates {
+                        off: 0.0,
+                        on: 0.8,
+                    }),
+                    binary_conditions: vec![],
+                }],
+            ),
+        ]);
+
+        press(&mut manager, SOURCE, true, 0.0);
+        press(&mut manager, CHORD_SOURCE, true, 0.0);
+        let mut released = manager
+            .release_outputs()
+            .iter()
+            .map(|b| (b.path_id, b.value))
+            .collect::<Vec<_>>();
+        released.sort_by_key(|(path_id, _)| *path_id);
+        let mut expected = vec![
+            (hash_string(DESTINATION), ButtonValue::Binary(false)),
+            (
+                hash_string("/user/hand/left/input/trigger/value"),
+                ButtonValue::Scalar(0.0),
+            ),
+        ];
+        expected.sort_by_key(|(path_id, _)| *path_id);
+        assert_eq!(released, expected);
+        assert!(manager.release_outputs().is_empty());
+
+        // Outputs already at rest are not sent again
+        press(&mut manager, SOURCE, false, 1.0);
+        assert!(manager.release_outputs().is_empty());
+    }
+
+    #[test]
+    fn button_mapping_profile_priority() {
+        let profile = |name: &str, applications: &[&str]| ButtonMappingProfile {
+            name: name.into(),
+            applications: applications.iter().map(|a| a.to_string()).collect(),
+            button_mappings: vec![],
+        };
+
+        let mut config = SessionConfig::default()
+            .to_settings()
+            .headset
+            .controllers
+            .into_option()
+            .unwrap();
+        config.button_mapping_profiles = vec![
+            profile("game", &["steam.app.1", "steam.app.2"]),
+            profile("other game", &["steam.app.2"]),
+            profile("forced", &[]),
+        ];
+        config.forced_button_mapping_profile = None;
+
+        let active_name = |config: &ControllersConfig, application: Option<&str>| {
+            active_button_mapping_profile(config, application).map(|profile| profile.name.clone())
+        };
+
+        assert_eq!(active_name(&config, None), None);
+        assert_eq!(active_name(&config, Some("steam.app.3")), None);
+        assert_eq!(
+            active_name(&config, Some("steam.app.1")).as_deref(),
+            Some("game")
+        );
+        // The first matching profile wins
+        assert_eq!(
+            active_name(&config, Some("steam.app.2")).as_deref(),
+            Some("game")
+        );
+
+        config.forced_button_mapping_profile = Some("forced".into());
+        assert_eq!(active_name(&config, None).as_deref(), Some("forced"));
+        assert_eq!(
+            active_name(&config, Some("steam.app.1")).as_deref(),
+            Some("forced")
+        );
+
+        // Unknown forced profiles fall back to the running application
+        config.forced_button_mapping_profile = Some("missing".into());
+        assert_eq!(
+            active_name(&config, Some("steam.app.1")).as_deref(),
+            Some("game")
+        );
+        assert_eq!(active_name(&config, None), None);
+    }
 }
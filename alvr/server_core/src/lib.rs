@@ -1,23 +1,29 @@
 mod bitrate;
 mod c_api;
 mod connection;
+mod foveation;
 mod hand_gestures;
+mod hand_poses;
 mod haptics;
+mod input_expression;
 mod input_mapping;
 mod logging_backend;
 mod sockets;
 mod statistics;
 mod tracking;
+mod video_recording;
 mod web_server;
 
+pub use bitrate::simulation as bitrate_simulation;
 pub use c_api::*;
 pub use logging_backend::init_logging;
 pub use tracking::HandType;
 
-use crate::connection::VideoPacket;
+use crate::connection::{VideoChannel, VideoPacket};
 use alvr_common::{
     dbg_server_core, error,
     glam::Vec2,
+    info,
     once_cell::sync::Lazy,
     parking_lot::{Mutex, RwLock},
     settings_schema::Switch,
@@ -28,22 +34,24 @@ use alvr_events::{EventType, HapticsEvent};
 use alvr_filesystem as afs;
 use alvr_packets::{
     BatteryInfo, ButtonEntry, ClientListAction, DecoderInitializationConfig, Haptics,
-    VideoPacketHeader,
+    RecordedPacket, SessionRecordingWriter, VideoPacketHeader, SESSION_RECORDING_EXTENSION,
 };
 use alvr_server_io::ServerSessionManager;
-use alvr_session::{CodecType, OpenvrProperty, Settings};
+use alvr_session::{CodecType, OpenvrProperty, Settings, VideoRecordingContainer};
 use alvr_sockets::StreamSender;
 use bitrate::{BitrateManager, DynamicEncoderParams};
+use foveation::FoveationManager;
+use haptics::HapticsManager;
 use statistics::StatisticsManager;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     env,
     ffi::OsStr,
     fs::File,
-    io::Write,
+    io::BufWriter,
+    mem,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        mpsc::{self, SyncSender, TrySendError},
+        mpsc::{self, TrySendError},
         Arc, OnceLock,
     },
     thread::{self, JoinHandle},
@@ -52,6 +60,7 @@ use std::{
 use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, RefreshKind};
 use tokio::{runtime::Runtime, sync::broadcast};
 use tracking::TrackingManager;
+use video_recording::{VideoRecording, VideoTrackConfig};
 
 static FILESYSTEM_LAYOUT: OnceLock<afs::Layout> = OnceLock::new();
 
@@ -97,6 +106,15 @@ pub enum ServerCoreEvent {
     RestartPending,
 }
 
+// Statistics of a client that receives the video stream without driving SteamVR
+pub struct SpectatorContext {
+    statistics_manager: StatisticsManager,
+    bitrate_manager: BitrateManager,
+    last_bitrate_bps: Option<f32>,
+}
+
+// Statistics, bitrate, tracking and haptics refer to the driver client, which is the first client
+// that connected. Other clients are spectators.
 pub struct ConnectionContext {
     events_sender: mpsc::Sender<ServerCoreEvent>,
     statistics_manager: RwLock<Option<StatisticsManager>>,
@@ -104,19 +122,100 @@ pub struct ConnectionContext {
     tracking_manager: RwLock<TrackingManager>,
     decoder_config: Mutex<Option<DecoderInitializationConfig>>,
     video_mirror_sender: Mutex<Option<broadcast::Sender<Vec<u8>>>>,
-    video_recording_file: Mutex<Option<File>>,
+    video_recording: Mutex<Option<VideoRecording>>,
+    // Set while the driver client is streaming game audio
+    game_audio_sample_rate: Mutex<Option<u32>>,
+    session_recording: Mutex<Option<SessionRecordingWriter<BufWriter<File>>>>,
     connection_threads: Mutex<Vec<JoinHandle<()>>>,
     clients_to_be_removed: Mutex<HashSet<String>>,
-    video_channel_sender: Mutex<Option<SyncSender<VideoPacket>>>,
+    driver_hostname: Mutex<Option<String>>,
+    spectators: Mutex<HashMap<String, SpectatorContext>>,
+    // Stream ports used by the spectators, see connection_pipeline()
+    spectator_stream_ports: Mutex<HashSet<u16>>,
+    // Contains both the driver and the spectators. The key is the hostname
+    video_channels: Mutex<HashMap<String, VideoChannel>>,
     haptics_sender: Mutex<Option<StreamSender<Haptics>>>,
+    haptics_manager: Mutex<HapticsManager>,
+    // Key of the SteamVR scene application, used to select the button mapping profile
+    running_application: Mutex<Option<String>>,
+    foveation_manager: Mutex<FoveationManager>,
+}
+
+// The packet is created only if a session recording is in progress
+fn record_session_packet(
+    connection_context: &ConnectionContext,
+    packet: impl FnOnce() -> RecordedPacket,
+) {
+    let mut recording_lock = connection_context.session_recording.lock();
+    if let Some(recording) = &mut *recording_lock {
+        if let Err(e) = recording.write(packet()) {
+            error!("Failed to write session recording, stopping: {e}");
+            *recording_lock = None;
+        }
+    }
+}
+
+fn create_session_recording_file(connection_context: &ConnectionContext) {
+    let path = FILESYSTEM_LAYOUT.get().unwrap().log_dir.join(format!(
+        "session.{}.{SESSION_RECORDING_EXTENSION}",
+        chrono::Local::now().format("%F.%H-%M-%S")
+    ));
+
+    match File::create(path)
+        .map_err(Into::into)
+        .and_then(|file| SessionRecordingWriter::new(BufWriter::new(file)))
+    {
+        Ok(recording) => {
+            *connection_context.session_recording.lock() = Some(recording);
+
+            if let Some(config) = connection_context.decoder_config.lock().clone() {
+                record_session_packet(connection_context, || RecordedPacket::DecoderConfig(config));
+            }
+
+            connection_context
+                .events_sender
+                .send(ServerCoreEvent::RequestIDR)
+                .ok();
+        }
+        Err(e) => {
+            error!("Failed to record session on disk: {e}");
+        }
+    }
+}
+
+fn create_bitrate_trace_file(connection_context: &ConnectionContext) {
+    let path = FILESYSTEM_LAYOUT.get().unwrap().log_dir.join(format!(
+        "bitrate_trace.{}.{}",
+        chrono::Local::now().format("%F.%H-%M-%S"),
+        bitrate_simulation::BITRATE_TRACE_EXTENSION
+    ));
+
+    match File::create(path) {
+        Ok(file) => connection_context
+            .bitrate_manager
+            .lock()
+            .start_trace(Box::new(BufWriter::new(file))),
+        Err(e) => error!("Failed to create bitrate trace: {e}"),
+    }
 }
 
+// The session recording is independent from the video recording, which can be split in several
+// files
 pub fn create_recording_file(connection_context: &ConnectionContext, settings: &Settings) {
-    let codec = settings.video.preferred_codec;
-    let ext = match codec {
-        CodecType::H264 => "h264",
-        CodecType::Hevc => "h265",
-        CodecType::AV1 => "av1",
+    if settings.extra.capture.record_session {
+        create_session_recording_file(connection_context);
+    }
+
+    create_video_recording_file(connection_context, settings);
+}
+
+fn create_video_recording_file(connection_context: &ConnectionContext, settings: &Settings) {
+    let container = &settings.extra.capture.video_recording_container;
+    let ext = match (container, settings.video.preferred_codec) {
+        (VideoRecordingContainer::Matroska { .. }, _) => "mkv",
+        (VideoRecordingContainer::AnnexB, CodecType::H264) => "h264",
+        (VideoRecordingContainer::AnnexB, CodecType::Hevc) => "h265",
+        (VideoRecordingContainer::AnnexB, CodecType::AV1) => "av1",
     };
 
     let path = FILESYSTEM_LAYOUT.get().unwrap().log_dir.join(format!(
@@ -125,12 +224,19 @@ pub fn create_recording_file(connection_context: &ConnectionContext, settings: &
     ));
 
     match File::create(path) {
-        Ok(mut file) => {
+        Ok(file) => {
+            let mut recording = match container {
+                VideoRecordingContainer::AnnexB => VideoRecording::AnnexB(file),
+                VideoRecordingContainer::Matroska { game_audio } => {
+                    VideoRecording::new_matroska(file, *game_audio)
+                }
+            };
+
             if let Some(config) = &*connection_context.decoder_config.lock() {
-                file.write_all(&config.config_buffer).ok();
+                recording.write_config(&config.config_buffer);
             }
 
-            *connection_context.video_recording_file.lock() = Some(file);
+            *connection_context.video_recording.lock() = Some(recording);
 
             connection_context
                 .events_sender
@@ -199,18 +305,30 @@ impl ServerCoreContext {
 
         let (events_sender, events_receiver) = mpsc::channel();
 
+        let initial_bitrate_mode = SESSION_MANAGER.read().settings().video.bitrate.mode.clone();
+
         let connection_context = Arc::new(ConnectionContext {
             events_sender,
             statistics_manager: RwLock::new(None),
-            bitrate_manager: Mutex::new(BitrateManager::new(256, 60.0)),
-            tracking_manager: RwLock::new(TrackingManager::new()),
+            bitrate_manager: Mutex::new(BitrateManager::new(256, 60.0, &initial_bitrate_mode)),
+            tracking_manager: RwLock::new(TrackingManager::new(
+                &SESSION_MANAGER.read().settings().headset,
+            )),
             decoder_config: Mutex::new(None),
             video_mirror_sender: Mutex::new(None),
-            video_recording_file: Mutex::new(None),
+            video_recording: Mutex::new(None),
+            game_audio_sample_rate: Mutex::new(None),
+            session_recording: Mutex::new(None),
             connection_threads: Mutex::new(Vec::new()),
             clients_to_be_removed: Mutex::new(HashSet::new()),
-            video_channel_sender: Mutex::new(None),
+            driver_hostname: Mutex::new(None),
+            spectators: Mutex::new(HashMap::new()),
+            spectator_stream_ports: Mutex::new(HashSet::new()),
+            video_channels: Mutex::new(HashMap::new()),
             haptics_sender: Mutex::new(None),
+            haptics_manager: Mutex::new(HapticsManager::new()),
+            running_application: Mutex::new(None),
+            foveation_manager: Mutex::new(FoveationManager::new()),
         });
 
         let webserver_runtime = Runtime::new().unwrap();
@@ -269,7 +387,6 @@ impl ServerCoreContext {
             .tracking_manager
             .read()
             .get_hand_skeleton(hand_type, timestamp)
-            .copied()
     }
 
     pub fn get_motion_to_photon_latency(&self) -> Duration {
@@ -322,13 +439,15 @@ impl ServerCoreContext {
                 .and_then(|c| c.haptics.as_option().cloned())
         };
 
-        if let (Some(config), Some(sender)) = (
-            haptics_config,
-            &mut *self.connection_context.haptics_sender.lock(),
-        ) {
-            sender
-                .send_header(&haptics::map_haptics(&config, haptics))
-                .ok();
+        if let Some(config) = haptics_config {
+            self.connection_context.haptics_manager.lock().push_haptics(
+                &config,
+                haptics,
+                Instant::now(),
+            );
+
+            // Sent right away to not wait for the next mix
+            haptics::send_mixed_haptics(&self.connection_context);
         }
     }
 
@@ -339,28 +458,50 @@ impl ServerCoreContext {
             sender.send(config_buffer.clone()).ok();
         }
 
-        if let Some(file) = &mut *self.connection_context.video_recording_file.lock() {
-            file.write_all(&config_buffer).ok();
+        if let Some(recording) = &mut *self.connection_context.video_recording.lock() {
+            recording.write_config(&config_buffer);
         }
 
-        *self.connection_context.decoder_config.lock() = Some(DecoderInitializationConfig {
+        let config = DecoderInitializationConfig {
             codec,
             config_buffer,
+        };
+
+        record_session_packet(&self.connection_context, || {
+            RecordedPacket::DecoderConfig(config.clone())
         });
+
+        *self.connection_context.decoder_config.lock() = Some(config);
     }
 
     pub fn send_video_nal(&self, target_timestamp: Duration, nal_buffer: Vec<u8>, is_idr: bool) {
         dbg_server_core!("send_video_nal");
 
-        // start in the corrupts state, the client didn't receive the initial IDR yet.
-        static STREAM_CORRUPTED: AtomicBool = AtomicBool::new(true);
         static LAST_IDR_INSTANT: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()));
 
-        if let Some(sender) = &*self.connection_context.video_channel_sender.lock() {
-            let buffer_size = nal_buffer.len();
+        let mut nal_buffer = nal_buffer;
+        let buffer_size = nal_buffer.len();
+
+        let header = VideoPacketHeader {
+            timestamp: target_timestamp,
+            is_idr,
+            foveation_center_shift: self
+                .connection_context
+                .foveation_manager
+                .lock()
+                .get_rendered_center_shift(target_timestamp),
+        };
+
+        {
+            let mut video_channels = self.connection_context.video_channels.lock();
+            if video_channels.is_empty() {
+                return;
+            }
 
             if is_idr {
-                STREAM_CORRUPTED.store(false, Ordering::SeqCst);
+                for channel in video_channels.values_mut() {
+                    channel.stream_corrupted = false;
+                }
             }
 
             if let Switch::Enabled(config) = &SESSION_MANAGER
@@ -379,7 +520,7 @@ impl ServerCoreContext {
                         .ok();
 
                     if is_idr {
-                        create_recording_file(
+                        create_video_recording_file(
                             &self.connection_context,
                             SESSION_MANAGER.read().settings(),
                         );
@@ -388,50 +529,119 @@ impl ServerCoreContext {
                 }
             }
 
-            if !STREAM_CORRUPTED.load(Ordering::SeqCst)
-                || !SESSION_MANAGER
-                    .read()
-                    .settings()
-                    .connection
-                    .avoid_video_glitching
-            {
+            let avoid_video_glitching = SESSION_MANAGER
+                .read()
+                .settings()
+                .connection
+                .avoid_video_glitching;
+
+            if !avoid_video_glitching || video_channels.values().any(|c| !c.stream_corrupted) {
                 if let Some(sender) = &*self.connection_context.video_mirror_sender.lock() {
                     sender.send(nal_buffer.clone()).ok();
                 }
 
-                if let Some(file) = &mut *self.connection_context.video_recording_file.lock() {
-                    file.write_all(&nal_buffer).ok();
+                let mut recording_lock = self.connection_context.video_recording.lock();
+                if let Some(recording) = &mut *recording_lock {
+                    let create_track_config = || {
+                        let decoder_config =
+                            self.connection_context.decoder_config.lock().clone()?;
+                        let session_manager = SESSION_MANAGER.read();
+                        let openvr_config = &session_manager.session().openvr_config;
+
+                        Some((
+                            VideoTrackConfig {
+                                decoder_config,
+                                // The eyes are side by side
+                                width: openvr_config.eye_resolution_width * 2,
+                                height: openvr_config.eye_resolution_height,
+                            },
+                            *self.connection_context.game_audio_sample_rate.lock(),
+                        ))
+                    };
+
+                    if !recording.write_video(
+                        create_track_config,
+                        target_timestamp,
+                        &nal_buffer,
+                        is_idr,
+                    ) {
+                        *recording_lock = None;
+                    }
+                }
+                drop(recording_lock);
+
+                record_session_packet(&self.connection_context, || RecordedPacket::Video {
+                    header: header.clone(),
+                    nal: nal_buffer.clone(),
+                });
+            }
+
+            let channels_count = video_channels.len();
+            for (idx, (hostname, channel)) in video_channels.iter_mut().enumerate() {
+                if channel.stream_corrupted && avoid_video_glitching {
+                    warn!("Dropping video packet for {hostname}. Reason: Waiting for IDR frame");
+                    continue;
                 }
 
+                // Avoid copying the buffer for the last (usually the only) client
+                let payload = if idx + 1 < channels_count {
+                    nal_buffer.clone()
+                } else {
+                    mem::take(&mut nal_buffer)
+                };
+
                 if matches!(
-                    sender.try_send(VideoPacket {
-                        header: VideoPacketHeader {
-                            timestamp: target_timestamp,
-                            is_idr
-                        },
-                        payload: nal_buffer,
+                    channel.sender.try_send(VideoPacket {
+                        header: header.clone(),
+                        payload,
                     }),
                     Err(TrySendError::Full(_))
                 ) {
-                    STREAM_CORRUPTED.store(true, Ordering::SeqCst);
+                    channel.stream_corrupted = true;
                     self.connection_context
                         .events_sender
                         .send(ServerCoreEvent::RequestIDR)
                         .ok();
-                    warn!("Dropping video packet. Reason: Can't push to network");
+                    warn!("Dropping video packet for {hostname}. Reason: Can't push to network");
                 }
-            } else {
-                warn!("Dropping video packet. Reason: Waiting for IDR frame");
             }
+        }
 
-            if let Some(stats) = &mut *self.connection_context.statistics_manager.write() {
-                let encoder_latency = stats.report_frame_encoded(target_timestamp, buffer_size);
+        if let Some(stats) = &mut *self.connection_context.statistics_manager.write() {
+            let encoder_latency = stats.report_frame_encoded(target_timestamp, buffer_size);
 
-                self.connection_context
-                    .bitrate_manager
-                    .lock()
-                    .report_frame_encoded(target_timestamp, encoder_latency, buffer_size);
-            }
+            self.connection_context
+                .bitrate_manager
+                .lock()
+                .report_frame_encoded(target_timestamp, encoder_latency, buffer_size);
+        }
+
+        for spectator in self.connection_context.spectators.lock().values_mut() {
+            let encoder_latency = spectator
+                .statistics_manager
+                .report_frame_encoded(target_timestamp, buffer_size);
+
+            spectator.bitrate_manager.report_frame_encoded(
+                target_timestamp,
+                encoder_latency,
+                buffer_size,
+            );
+        }
+    }
+
+    // Center shift of the foveated encoding for the left and right eye. If None, the center shift
+    // of the settings is used
+    pub fn get_foveation_center_shift(&self, target_timestamp: Duration) -> Option<[Vec2; 2]> {
+        dbg_server_core!("get_foveation_center_shift");
+
+        if let Switch::Enabled(config) = &SESSION_MANAGER.read().settings().video.foveated_encoding
+        {
+            self.connection_context
+                .foveation_manager
+                .lock()
+                .get_center_shift_for_rendering(config, target_timestamp)
+        } else {
+            None
         }
     }
 
@@ -440,10 +650,30 @@ impl ServerCoreContext {
 
         let pair = {
             let session_manager_lock = SESSION_MANAGER.read();
-            self.connection_context
+            let config = &session_manager_lock.settings().video.bitrate;
+
+            let pair = self
+                .connection_context
                 .bitrate_manager
                 .lock()
-                .get_encoder_params(&session_manager_lock.settings().video.bitrate)
+                .get_encoder_params(config);
+
+            // All clients share the same encoder, so the bitrate is limited by the slowest one
+            let mut spectators_lock = self.connection_context.spectators.lock();
+            for spectator in spectators_lock.values_mut() {
+                if let Some((params, _)) = spectator.bitrate_manager.get_encoder_params(config) {
+                    spectator.last_bitrate_bps = Some(params.bitrate_bps);
+                }
+            }
+
+            pair.map(|(mut params, stats)| {
+                params.bitrate_bps = spectators_lock
+                    .values()
+                    .filter_map(|spectator| spectator.last_bitrate_bps)
+                    .fold(params.bitrate_bps, f32::min);
+
+                (params, stats)
+            })
         };
 
         if let Some((params, stats)) = pair {
@@ -463,6 +693,12 @@ impl ServerCoreContext {
         if let Some(stats) = &mut *self.connection_context.statistics_manager.write() {
             stats.report_frame_composed(target_timestamp, offset);
         }
+
+        for spectator in self.connection_context.spectators.lock().values_mut() {
+            spectator
+                .statistics_manager
+                .report_frame_composed(target_timestamp, offset);
+        }
     }
 
     pub fn report_present(&self, target_timestamp: Duration, offset: Duration) {
@@ -473,16 +709,38 @@ impl ServerCoreContext {
         }
 
         let session_manager_lock = SESSION_MANAGER.read();
+        let adapt_to_framerate_config = &session_manager_lock
+            .settings()
+            .video
+            .bitrate
+            .adapt_to_framerate;
         self.connection_context
             .bitrate_manager
             .lock()
-            .report_frame_present(
-                &session_manager_lock
-                    .settings()
-                    .video
-                    .bitrate
-                    .adapt_to_framerate,
+            .report_frame_present(adapt_to_framerate_config);
+
+        for spectator in self.connection_context.spectators.lock().values_mut() {
+            spectator
+                .statistics_manager
+                .report_frame_present(target_timestamp, offset);
+            spectator
+                .bitrate_manager
+                .report_frame_present(adapt_to_framerate_config);
+        }
+    }
+
+    pub fn report_running_application(&self, application_key: Option<String>) {
+        dbg_server_core!("report_running_application");
+
+        let mut running_application = self.connection_context.running_application.lock();
+        if *running_application != application_key {
+            info!(
+                "Running application: {}",
+                application_key.as_deref().unwrap_or("none")
             );
+
+            *running_application = application_key;
+        }
     }
 
     pub fn duration_until_next_vsync(&self) -> Option<Duration> {
@@ -1,6 +1,7 @@
 use alvr_common::{SlidingWindowAverage, HEAD_ID};
 use alvr_events::{BitrateDirectives, EventType, GraphStatistics, StatisticsSummary};
 use alvr_packets::ClientStatistics;
+use alvr_sockets::StreamReceiveStatistics;
 use std::{
     collections::{HashMap, VecDeque},
     time::{Duration, Instant},
@@ -52,12 +53,18 @@ pub struct StatisticsManager {
     video_bytes_partial_sum: usize,
     packets_lost_total: usize,
     packets_lost_partial_sum: usize,
+    receive_dropped_packets_total: usize,
+    receive_dropped_packets_partial_sum: usize,
+    receive_evicted_packets_total: usize,
+    receive_evicted_packets_partial_sum: usize,
     battery_gauges: HashMap<u64, BatteryData>,
     steamvr_pipeline_latency: Duration,
     motion_to_photon_latency_average: SlidingWindowAverage<Duration>,
     last_vsync_time: Instant,
     frame_interval: Duration,
     last_throughput_directives: BitrateDirectives,
+    // Only the statistics of the client driving SteamVR are shown in the dashboard
+    send_events: bool,
 }
 
 impl StatisticsManager {
@@ -66,6 +73,7 @@ impl StatisticsManager {
         max_history_size: usize,
         nominal_server_frame_interval: Duration,
         steamvr_pipeline_frames: f32,
+        send_events: bool,
     ) -> Self {
         Self {
             history_buffer: VecDeque::new(),
@@ -79,6 +87,10 @@ impl StatisticsManager {
             video_bytes_partial_sum: 0,
             packets_lost_total: 0,
             packets_lost_partial_sum: 0,
+            receive_dropped_packets_total: 0,
+            receive_dropped_packets_partial_sum: 0,
+            receive_evicted_packets_total: 0,
+            receive_evicted_packets_partial_sum: 0,
             battery_gauges: HashMap::new(),
             steamvr_pipeline_latency: Duration::from_secs_f32(
                 steamvr_pipeline_frames * nominal_server_frame_interval.as_secs_f32(),
@@ -90,6 +102,7 @@ impl StatisticsManager {
             last_vsync_time: Instant::now(),
             frame_interval: nominal_server_frame_interval,
             last_throughput_directives: BitrateDirectives::default(),
+            send_events,
         }
     }
 
@@ -170,6 +183,14 @@ impl StatisticsManager {
         self.packets_lost_partial_sum += 1;
     }
 
+    // Packets lost in the receive path of the stream socket, on either side
+    pub fn report_receive_drops(&mut self, stats: &StreamReceiveStatistics) {
+        self.receive_dropped_packets_total += stats.dropped_packets;
+        self.receive_dropped_packets_partial_sum += stats.dropped_packets;
+        self.receive_evicted_packets_total += stats.evicted_packets;
+        self.receive_evicted_packets_partial_sum += stats.evicted_packets;
+    }
+
     pub fn report_battery(&mut self, device_id: u64, gauge_value: f32, is_plugged: bool) {
         *self.battery_gauges.entry(device_id).or_default() = BatteryData {
             gauge_value,
@@ -184,6 +205,11 @@ impl StatisticsManager {
     // Called every frame. Some statistics are reported once every frame
     // Returns (network latency, game time latency)
     pub fn report_statistics(&mut self, client_stats: ClientStatistics) -> (Duration, Duration) {
+        self.report_receive_drops(&StreamReceiveStatistics {
+            dropped_packets: client_stats.receive_dropped_packets as _,
+            evicted_packets: client_stats.receive_evicted_packets as _,
+        });
+
         self.motion_to_photon_latency_average
             .submit_sample(client_stats.total_pipeline_latency);
 
@@ -232,41 +258,55 @@ impl StatisticsManager {
 
                 let interval_secs = FULL_REPORT_INTERVAL.as_secs_f32();
 
-                alvr_events::send_event(EventType::StatisticsSummary(StatisticsSummary {
-                    video_packets_total: self.video_packets_total,
-                    video_packets_per_sec: (self.video_packets_partial_sum as f32 / interval_secs)
-                        as _,
-                    video_mbytes_total: (self.video_bytes_total as f32 / 1e6) as usize,
-                    video_mbits_per_sec: self.video_bytes_partial_sum as f32 * 8.
-                        / 1e6
-                        / interval_secs,
-                    total_latency_ms: client_stats.total_pipeline_latency.as_secs_f32() * 1000.,
-                    network_latency_ms: network_latency.as_secs_f32() * 1000.,
-                    encode_latency_ms: encoder_latency.as_secs_f32() * 1000.,
-                    decode_latency_ms: client_stats.video_decode.as_secs_f32() * 1000.,
-                    packets_lost_total: self.packets_lost_total,
-                    packets_lost_per_sec: (self.packets_lost_partial_sum as f32 / interval_secs)
-                        as _,
-                    client_fps: client_fps as _,
-                    server_fps: server_fps as _,
-                    battery_hmd: (self
-                        .battery_gauges
-                        .get(&HEAD_ID)
-                        .cloned()
-                        .unwrap_or_default()
-                        .gauge_value
-                        * 100.) as u32,
-                    hmd_plugged: self
-                        .battery_gauges
-                        .get(&HEAD_ID)
-                        .cloned()
-                        .unwrap_or_default()
-                        .is_plugged,
-                }));
+                if self.send_events {
+                    alvr_events::send_event(EventType::StatisticsSummary(StatisticsSummary {
+                        video_packets_total: self.video_packets_total,
+                        video_packets_per_sec: (self.video_packets_partial_sum as f32
+                            / interval_secs) as _,
+                        video_mbytes_total: (self.video_bytes_total as f32 / 1e6) as usize,
+                        video_mbits_per_sec: self.video_bytes_partial_sum as f32 * 8.
+                            / 1e6
+                            / interval_secs,
+                        total_latency_ms: client_stats.total_pipeline_latency.as_secs_f32() * 1000.,
+                        network_latency_ms: network_latency.as_secs_f32() * 1000.,
+                        encode_latency_ms: encoder_latency.as_secs_f32() * 1000.,
+                        decode_latency_ms: client_stats.video_decode.as_secs_f32() * 1000.,
+                        packets_lost_total: self.packets_lost_total,
+                        packets_lost_per_sec: (self.packets_lost_partial_sum as f32 / interval_secs)
+                            as _,
+                        receive_dropped_packets_total: self.receive_dropped_packets_total,
+                        receive_dropped_packets_per_sec: (self.receive_dropped_packets_partial_sum
+                            as f32
+                            / interval_secs)
+                            as _,
+                        receive_evicted_packets_total: self.receive_evicted_packets_total,
+                        receive_evicted_packets_per_sec: (self.receive_evicted_packets_partial_sum
+                            as f32
+                            / interval_secs)
+                            as _,
+                        client_fps: client_fps as _,
+                        server_fps: server_fps as _,
+                        battery_hmd: (self
+                            .battery_gauges
+                            .get(&HEAD_ID)
+                            .cloned()
+                            .unwrap_or_default()
+                            .gauge_value
+                            * 100.) as u32,
+                        hmd_plugged: self
+                            .battery_gauges
+                            .get(&HEAD_ID)
+                            .cloned()
+                            .unwrap_or_default()
+                            .is_plugged,
+                    }));
+                }
 
                 self.video_packets_partial_sum = 0;
                 self.video_bytes_partial_sum = 0;
                 self.packets_lost_partial_sum = 0;
+                self.receive_dropped_packets_partial_sum = 0;
+                self.receive_evicted_packets_partial_sum = 0;
             }
 
             let packet_bits = frame.video_packet_bytes as f32 * 8.0;
@@ -277,22 +317,24 @@ impl StatisticsManager {
 
             // todo: use target timestamp in nanoseconds. the dashboard needs to use the first
             // timestamp as the graph time origin.
-            alvr_events::send_event(EventType::GraphStatistics(GraphStatistics {
-                total_pipeline_latency_s: client_stats.total_pipeline_latency.as_secs_f32(),
-                game_time_s: game_time_latency.as_secs_f32(),
-                server_compositor_s: server_compositor_latency.as_secs_f32(),
-                encoder_s: encoder_latency.as_secs_f32(),
-                network_s: network_latency.as_secs_f32(),
-                decoder_s: client_stats.video_decode.as_secs_f32(),
-                decoder_queue_s: client_stats.video_decoder_queue.as_secs_f32(),
-                client_compositor_s: client_stats.rendering.as_secs_f32(),
-                vsync_queue_s: client_stats.vsync_queue.as_secs_f32(),
-                client_fps,
-                server_fps,
-                bitrate_directives: self.last_throughput_directives.clone(),
-                throughput_bps,
-                bitrate_bps,
-            }));
+            if self.send_events {
+                alvr_events::send_event(EventType::GraphStatistics(GraphStatistics {
+                    total_pipeline_latency_s: client_stats.total_pipeline_latency.as_secs_f32(),
+                    game_time_s: game_time_latency.as_secs_f32(),
+                    server_compositor_s: server_compositor_latency.as_secs_f32(),
+                    encoder_s: encoder_latency.as_secs_f32(),
+                    network_s: network_latency.as_secs_f32(),
+                    decoder_s: client_stats.video_decode.as_secs_f32(),
+                    decoder_queue_s: client_stats.video_decoder_queue.as_secs_f32(),
+                    client_compositor_s: client_stats.rendering.as_secs_f32(),
+                    vsync_queue_s: client_stats.vsync_queue.as_secs_f32(),
+                    client_fps,
+                    server_fps,
+                    bitrate_directives: self.last_throughput_directives.clone(),
+                    throughput_bps,
+                    bitrate_bps,
+                }));
+            }
 
             (network_latency, game_time_latency)
         } else {
@@ -7,7 +7,7 @@ use alvr_session::BodyTrackingSinkConfig;
 use rosc::{OscMessage, OscPacket, OscType};
 use std::{collections::HashMap, net::UdpSocket};
 
-static BODY_TRACKER_OSC_PATH_MAP: Lazy<HashMap<u64, &'static str>> = Lazy::new(|| {
+pub(super) static BODY_TRACKER_OSC_PATH_MAP: Lazy<HashMap<u64, &'static str>> = Lazy::new(|| {
     HashMap::from([
         (*HEAD_ID, "/tracking/trackers/head/"),
         (*BODY_CHEST_ID, "/tracking/trackers/1/"),
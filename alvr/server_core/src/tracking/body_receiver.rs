@@ -0,0 +1,305 @@
+// Receives body tracker poses from external applications, like webcam pose estimators, through VMC
+// or VRChat OSC. The poses are added to the tracking samples of the client, so they are processed
+// like the body tracking of the headset and they appear in SteamVR as fake Vive trackers.
+
+use super::{body::BODY_TRACKER_OSC_PATH_MAP, vmc::DEVICE_MOTIONS_VMC_MAP};
+use alvr_common::{
+    anyhow::Result,
+    glam::{EulerRot, Quat, Vec3},
+    once_cell::sync::Lazy,
+    DeviceMotion, Pose, BODY_CHEST_ID, BODY_HIPS_ID, BODY_LEFT_ELBOW_ID, BODY_LEFT_FOOT_ID,
+    BODY_LEFT_KNEE_ID, BODY_RIGHT_ELBOW_ID, BODY_RIGHT_FOOT_ID, BODY_RIGHT_KNEE_ID, HEAD_ID,
+};
+use alvr_session::{BodyTrackingReceiverConfig, BodyTrackingReceiverProtocol};
+use rosc::{OscMessage, OscPacket, OscType};
+use std::{
+    collections::HashMap,
+    f32::consts::PI,
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
+
+// Poses that are not updated for this long are not used anymore
+const POSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+static BODY_TRACKER_IDS: Lazy<[u64; 8]> = Lazy::new(|| {
+    [
+        *BODY_CHEST_ID,
+        *BODY_HIPS_ID,
+        *BODY_LEFT_ELBOW_ID,
+        *BODY_RIGHT_ELBOW_ID,
+        *BODY_LEFT_KNEE_ID,
+        *BODY_LEFT_FOOT_ID,
+        *BODY_RIGHT_KNEE_ID,
+        *BODY_RIGHT_FOOT_ID,
+    ]
+});
+
+// Unity HumanBodyBones hierarchy, as (bone, parent). The parent of Hips is the root. Bones that are
+// not received are considered at the same place as their parent
+const VMC_BONE_PARENTS: [(&str, &str); 19] = [
+    ("Spine", "Hips"),
+    ("Chest", "Spine"),
+    ("UpperChest", "Chest"),
+    ("Neck", "UpperChest"),
+    ("Head", "Neck"),
+    ("LeftShoulder", "UpperChest"),
+    ("LeftUpperArm", "LeftShoulder"),
+    ("LeftLowerArm", "LeftUpperArm"),
+    ("LeftHand", "LeftLowerArm"),
+    ("RightShoulder", "UpperChest"),
+    ("RightUpperArm", "RightShoulder"),
+    ("RightLowerArm", "RightUpperArm"),
+    ("RightHand", "RightLowerArm"),
+    ("LeftUpperLeg", "Hips"),
+    ("LeftLowerLeg", "LeftUpperLeg"),
+    ("LeftFoot", "LeftLowerLeg"),
+    ("RightUpperLeg", "Hips"),
+    ("RightLowerLeg", "RightUpperLeg"),
+    ("RightFoot", "RightLowerLeg"),
+];
+
+// Unity uses a left handed space, with +Z forward
+fn from_unity_pose(position: Vec3, orientation: Quat) -> Pose {
+    Pose {
+        orientation: Quat::from_xyzw(-orientation.x, -orientation.y, orientation.z, orientation.w),
+        position: Vec3::new(position.x, position.y, -position.z),
+    }
+}
+
+fn floats(args: &[OscType]) -> Vec<f32> {
+    args.iter()
+        .filter_map(|arg| match arg {
+            OscType::Float(value) => Some(*value),
+            OscType::Double(value) => Some(*value as f32),
+            _ => None,
+        })
+        .collect()
+}
+
+pub struct BodyTrackingReceiver {
+    protocol: BodyTrackingReceiverProtocol,
+    socket: UdpSocket,
+    // VMC bone poses, relative to the parent bone
+    vmc_root: Pose,
+    vmc_bones: HashMap<String, (Instant, Pose)>,
+    // VRChat OSC tracker poses, in the space of the external application
+    osc_trackers: HashMap<u64, (Instant, Pose)>,
+}
+
+impl BodyTrackingReceiver {
+    pub fn new(config: &BodyTrackingReceiverConfig) -> Result<Self> {
+        let socket = UdpSocket::bind(format!("0.0.0.0:{}", config.port))?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            protocol: config.protocol.clone(),
+            socket,
+            vmc_root: Pose::default(),
+            vmc_bones: HashMap::new(),
+            osc_trackers: HashMap::new(),
+        })
+    }
+
+    fn process_packet(&mut self, packet: OscPacket) {
+        match packet {
+            OscPacket::Message(message) => self.process_message(message),
+            OscPacket::Bundle(bundle) => {
+                for packet in bundle.content {
+                    self.process_packet(packet);
+                }
+            }
+        }
+    }
+
+    fn process_message(&mut self, message: OscMessage) {
+        match self.protocol {
+            BodyTrackingReceiverProtocol::Vmc => {
+                let Some(OscType::String(name)) = message.args.first() else {
+                    return;
+                };
+                let [px, py, pz, qx, qy, qz, qw, ..] = floats(&message.args)[..] else {
+                    return;
+                };
+                let pose = from_unity_pose(
+                    Vec3::new(px, py, pz),
+                    Quat::from_xyzw(qx, qy, qz, qw).normalize(),
+                );
+
+                match message.addr.as_str() {
+                    "/VMC/Ext/Root/Pos" => self.vmc_root = pose,
+                    "/VMC/Ext/Bone/Pos" => {
+                        self.vmc_bones.insert(name.clone(), (Instant::now(), pose));
+                    }
+                    _ => (),
+                }
+            }
+            BodyTrackingReceiverProtocol::VrchatOsc => {
+                let Some((id, property)) = BODY_TRACKER_OSC_PATH_MAP
+                    .iter()
+                    .find_map(|(id, path)| Some((*id, message.addr.strip_prefix(path)?)))
+                else {
+                    return;
+                };
+                let [x, y, z, ..] = floats(&message.args)[..] else {
+                    return;
+                };
+
+                let (instant, pose) = self
+                    .osc_trackers
+                    .entry(id)
+                    .or_insert((Instant::now(), Pose::default()));
+                match property {
+                    "position" => {
+                        pose.position = from_unity_pose(Vec3::new(x, y, z), Quat::IDENTITY).position
+                    }
+                    // Unity Euler angles, in degrees, applied in the order Z, X, Y
+                    "rotation" => {
+                        let rotation = Quat::from_euler(
+                            EulerRot::YXZ,
+                            y * PI / 180.0,
+                            x * PI / 180.0,
+                            z * PI / 180.0,
+                        );
+                        pose.orientation = from_unity_pose(Vec3::ZERO, rotation).orientation;
+                    }
+                    _ => return,
+                }
+                *instant = Instant::now();
+            }
+        }
+    }
+
+    fn vmc_world_pose(&self, bone: &str) -> Pose {
+        let parent_pose = VMC_BONE_PARENTS
+            .iter()
+            .find(|(name, _)| *name == bone)
+            .map(|(_, parent)| self.vmc_world_pose(parent))
+            .unwrap_or(self.vmc_root);
+
+        parent_pose
+            * self
+                .vmc_bones
+                .get(bone)
+                .map(|(_, pose)| *pose)
+                .unwrap_or_default()
+    }
+
+    // Poses of the received devices, in the space of the external application
+    fn received_poses(&self) -> HashMap<u64, Pose> {
+        match self.protocol {
+            BodyTrackingReceiverProtocol::Vmc => DEVICE_MOTIONS_VMC_MAP
+                .iter()
+                .filter(|(_, bone)| {
+                    self.vmc_bones
+                        .get(**bone)
+                        .is_some_and(|(instant, _)| instant.elapsed() < POSE_TIMEOUT)
+                })
+                .map(|(id, bone)| (*id, self.vmc_world_pose(bone)))
+                .collect(),
+            BodyTrackingReceiverProtocol::VrchatOsc => self
+                .osc_trackers
+                .iter()
+                .filter(|(_, (instant, _))| instant.elapsed() < POSE_TIMEOUT)
+                .map(|(id, (_, pose))| (*id, *pose))
+                .collect(),
+        }
+    }
+
+    // Receives all pending packets and returns the body tracker poses, in the tracking space of
+    // the client. head_pose is the pose of the headset, used to align the two spaces
+    pub fn poll(
+        &mut self,
+        config: &BodyTrackingReceiverConfig,
+        head_pose: Option<Pose>,
+    ) -> Vec<(u64, DeviceMotion)> {
+        let mut buffer = [0; rosc::decoder::MTU];
+        // The socket is non blocking, this stops when there are no more packets
+        while let Ok(size) = self.socket.recv(&mut buffer) {
+            if let Ok((_, packet)) = rosc::decoder::decode_udp(&buffer[..size]) {
+                self.process_packet(packet);
+            }
+        }
+
+        let received_poses = self.received_poses();
+
+        let p = config.position_offset;
+        let mut transform = Pose {
+            orientation: Quat::from_rotation_y(config.yaw_offset * PI / 180.0),
+            position: Vec3::new(p[0], p[1], p[2]),
+        };
+        if let (true, Some(head_pose), Some(received_head_pose)) = (
+            config.align_to_headset,
+            head_pose,
+            received_poses.get(&*HEAD_ID),
+        ) {
+            let offset = head_pose.position - (transform * *received_head_pose).position;
+            transform.position += Vec3::new(offset.x, 0.0, offset.z);
+        }
+
+        BODY_TRACKER_IDS
+            .iter()
+            .filter_map(|id| {
+                let pose = received_poses.get(id)?;
+
+                Some((
+                    *id,
+                    DeviceMotion {
+                        pose: transform * *pose,
+                        linear_velocity: Vec3::ZERO,
+                        angular_velocity: Vec3::ZERO,
+                    },
+                ))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bone_message(bone: &str, position: [f32; 3], orientation: Quat) -> OscPacket {
+        let mut args = vec![OscType::String(bone.into())];
+        args.extend(position.map(OscType::Float));
+        args.extend(orientation.to_array().map(OscType::Float));
+
+        OscPacket::Message(OscMessage {
+            addr: "/VMC/Ext/Bone/Pos".into(),
+            args,
+        })
+    }
+
+    #[test]
+    fn vmc_bone_hierarchy() {
+        let mut receiver = BodyTrackingReceiver {
+            protocol: BodyTrackingReceiverProtocol::Vmc,
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            vmc_root: Pose::default(),
+            vmc_bones: HashMap::new(),
+            osc_trackers: HashMap::new(),
+        };
+
+        // Hips turned 90° to the left. Spine and upper leg are not received
+        receiver.process_packet(bone_message(
+            "Hips",
+            [0.0, 1.0, 0.2],
+            Quat::from_rotation_y(-PI / 2.0),
+        ));
+        receiver.process_packet(bone_message(
+            "LeftLowerLeg",
+            [-0.1, -0.5, 0.0],
+            Quat::IDENTITY,
+        ));
+
+        let poses = receiver.received_poses();
+        assert_eq!(poses.len(), 2);
+
+        let hips = poses[&*BODY_HIPS_ID];
+        assert!(hips.position.distance(Vec3::new(0.0, 1.0, -0.2)) < 1e-5);
+
+        // Unity forward (+Z) is -Z in the tracking space. The left side of the hips points backward
+        let knee = poses[&*BODY_LEFT_KNEE_ID];
+        assert!(knee.position.distance(Vec3::new(0.0, 0.5, -0.1)) < 1e-5);
+    }
+}
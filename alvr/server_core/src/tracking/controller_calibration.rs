@@ -0,0 +1,255 @@
+// Guided calibration of the controller offsets. While the user holds the controllers, the raw
+// controller poses are compared with the hand tracking poses (multimodal input is required). The
+// offset is solved so that the controllers are placed where hand tracking would place them, so the
+// same hand tracking offsets work for every controller model.
+//
+// The hand skeleton palm joint is used instead of the wrist, since it is the joint that SteamVR
+// uses as the pose of a hand tracked controller.
+
+use super::HandType;
+use alvr_common::{
+    anyhow::{anyhow, bail, Result},
+    glam::{EulerRot, Quat, Vec3},
+    Pose,
+};
+use std::{
+    f32::consts::PI,
+    time::{Duration, Instant},
+};
+
+// About 4 seconds at 72 Hz, with both hands
+const SAMPLE_COUNT: usize = 600;
+const TIMEOUT: Duration = Duration::from_secs(20);
+// Above this, the controllers were not held firmly or hand tracking was lost
+const MAX_POSITION_ERROR_M: f32 = 0.03;
+
+struct CalibrationSample {
+    hand_type: HandType,
+    controller_pose: Pose,
+    palm_pose: Pose,
+}
+
+// The controller offsets in the settings are for the left hand. The right hand uses the offsets
+// mirrored on the YZ plane
+fn mirror_pose(pose: Pose) -> Pose {
+    let q = pose.orientation;
+    let p = pose.position;
+
+    Pose {
+        orientation: Quat::from_xyzw(q.x, -q.y, -q.z, q.w),
+        position: Vec3::new(-p.x, p.y, p.z),
+    }
+}
+
+pub struct ControllerCalibrationOffsets {
+    pub position: [f32; 3],
+    // Degrees, XYZ Euler angles
+    pub rotation: [f32; 3],
+}
+
+pub struct ControllerCalibration {
+    start_instant: Instant,
+    // Recentered controller poses, before the offsets are applied
+    last_controller_poses: [Option<(Duration, Pose)>; 2],
+    samples: Vec<CalibrationSample>,
+}
+
+impl ControllerCalibration {
+    pub fn new() -> Self {
+        Self {
+            start_instant: Instant::now(),
+            last_controller_poses: [None, None],
+            samples: vec![],
+        }
+    }
+
+    pub fn report_controller_pose(&mut self, hand_type: HandType, timestamp: Duration, pose: Pose) {
+        self.last_controller_poses[hand_type as usize] = Some((timestamp, pose));
+    }
+
+    // Only skeletons with a controller pose of the same tracking sample are used
+    pub fn report_hand_skeleton(
+        &mut self,
+        hand_type: HandType,
+        timestamp: Duration,
+        skeleton: &[Pose; 26],
+    ) {
+        if let Some((controller_timestamp, controller_pose)) =
+            self.last_controller_poses[hand_type as usize]
+        {
+            if controller_timestamp == timestamp {
+                self.samples.push(CalibrationSample {
+                    hand_type,
+                    controller_pose,
+                    palm_pose: skeleton[0],
+                });
+            }
+        }
+    }
+
+    // Returns None while the calibration is in progress. hand_tracking_offset is the left hand
+    // tracking offset, as configured in the settings
+    pub fn poll(&self, hand_tracking_offset: Pose) -> Option<Result<ControllerCalibrationOffsets>> {
+        if self.samples.len() >= SAMPLE_COUNT {
+            Some(self.solve(hand_tracking_offset))
+        } else if self.start_instant.elapsed() > TIMEOUT {
+            Some(Err(anyhow!(
+                "Timed out with {} of {SAMPLE_COUNT} samples. Hand tracking and controllers must be tracked at the same time (multimodal input)",
+                self.samples.len()
+            )))
+        } else {
+            None
+        }
+    }
+
+    fn solve(&self, hand_tracking_offset: Pose) -> Result<ControllerCalibrationOffsets> {
+        // Hand tracked controller pose, relative to the palm. See to_openvr_ffi_hand_skeleton()
+        let left_palm_to_target = Pose {
+            orientation: hand_tracking_offset.orientation,
+            position: hand_tracking_offset.orientation * hand_tracking_offset.position,
+        };
+
+        // For each sample, the offset that would place the controller exactly on the target
+        let offsets = self
+            .samples
+            .iter()
+            .map(|sample| {
+                let palm_to_target = match sample.hand_type {
+                    HandType::Left => left_palm_to_target,
+                    HandType::Right => mirror_pose(left_palm_to_target),
+                };
+                let offset = sample.controller_pose.inverse() * sample.palm_pose * palm_to_target;
+
+                match sample.hand_type {
+                    HandType::Left => offset,
+                    HandType::Right => mirror_pose(offset),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let position = offsets.iter().map(|o| o.position).sum::<Vec3>() / offsets.len() as f32;
+
+        // Quaternions are averaged on the same hemisphere. This is accurate for close rotations
+        let reference = offsets[0].orientation;
+        let orientation = offsets
+            .iter()
+            .map(|o| {
+                if o.orientation.dot(reference) < 0.0 {
+                    -o.orientation
+                } else {
+                    o.orientation
+                }
+            })
+            .fold(Quat::from_xyzw(0.0, 0.0, 0.0, 0.0), |acc, q| acc + q)
+            .normalize();
+
+        let position_error = (offsets
+            .iter()
+            .map(|o| o.position.distance_squared(position))
+            .sum::<f32>()
+            / offsets.len() as f32)
+            .sqrt();
+        if position_error > MAX_POSITION_ERROR_M {
+            bail!(
+                "Inconsistent samples ({:.1} cm of error). Hold the controllers firmly and keep the hands visible",
+                position_error * 100.0
+            );
+        }
+
+        let (x, y, z) = orientation.to_euler(EulerRot::XYZ);
+
+        // The position offset is applied after the rotation offset, see report_device_motions()
+        let position = orientation.inverse() * position;
+
+        Ok(ControllerCalibrationOffsets {
+            position: position.to_array(),
+            rotation: [x * 180.0 / PI, y * 180.0 / PI, z * 180.0 / PI],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracking::euler_degrees_to_quat;
+
+    // Same as report_device_motions()
+    fn apply_offsets(
+        hand_type: HandType,
+        controller_pose: Pose,
+        offsets: &ControllerCalibrationOffsets,
+    ) -> Pose {
+        let t = offsets.position;
+        let r = offsets.rotation;
+        let (orientation, position) = match hand_type {
+            HandType::Left => (euler_degrees_to_quat(r), Vec3::new(t[0], t[1], t[2])),
+            HandType::Right => (
+                euler_degrees_to_quat([r[0], -r[1], -r[2]]),
+                Vec3::new(-t[0], t[1], t[2]),
+            ),
+        };
+
+        let mut pose = controller_pose;
+        pose.orientation *= orientation;
+        pose.position += pose.orientation * position;
+
+        pose
+    }
+
+    #[test]
+    fn solve_recovers_offset() {
+        let hand_tracking_offset = Pose {
+            orientation: Quat::from_rotation_x(0.3),
+            position: Vec3::new(0.0, 0.02, 0.05),
+        };
+        let left_palm_to_target = Pose {
+            orientation: hand_tracking_offset.orientation,
+            position: hand_tracking_offset.orientation * hand_tracking_offset.position,
+        };
+        // Hand tracked controller pose relative to the raw controller pose, for the left hand
+        let left_controller_to_target = Pose {
+            orientation: Quat::from_euler(EulerRot::XYZ, -0.4, 0.1, 0.2),
+            position: Vec3::new(0.01, -0.02, -0.1),
+        };
+
+        let controller_pose = |i: usize| Pose {
+            orientation: Quat::from_rotation_y(i as f32 * 0.01),
+            position: Vec3::new(0.0, 1.0, i as f32 * 0.001),
+        };
+        let hand_poses = |hand_type: HandType| match hand_type {
+            HandType::Left => (left_controller_to_target, left_palm_to_target),
+            HandType::Right => (
+                mirror_pose(left_controller_to_target),
+                mirror_pose(left_palm_to_target),
+            ),
+        };
+
+        let mut calibration = ControllerCalibration::new();
+        for i in 0..SAMPLE_COUNT {
+            let timestamp = Duration::from_millis(i as u64 * 10);
+            let hand_type = if i % 2 == 0 {
+                HandType::Left
+            } else {
+                HandType::Right
+            };
+            let (controller_to_target, palm_to_target) = hand_poses(hand_type);
+
+            let target_pose = controller_pose(i) * controller_to_target;
+            let palm_pose = target_pose * palm_to_target.inverse();
+
+            calibration.report_controller_pose(hand_type, timestamp, controller_pose(i));
+            calibration.report_hand_skeleton(hand_type, timestamp, &[palm_pose; 26]);
+        }
+
+        let offsets = calibration.poll(hand_tracking_offset).unwrap().unwrap();
+
+        for (i, hand_type) in [(0, HandType::Left), (100, HandType::Right)] {
+            let (controller_to_target, _) = hand_poses(hand_type);
+            let target_pose = controller_pose(i) * controller_to_target;
+
+            let pose = apply_offsets(hand_type, controller_pose(i), &offsets);
+            assert!(pose.position.distance(target_pose.position) < 1e-4);
+            assert!(pose.orientation.angle_between(target_pose.orientation) < 1e-3);
+        }
+    }
+}
@@ -8,6 +8,199 @@ const RAD_TO_DEG: f32 = 180.0 / PI;
 
 const VRCFT_PORT: u16 = 0xA1F7;
 
+pub const ARKIT_BLENDSHAPES: [&str; 52] = [
+    "eyeBlinkLeft",
+    "eyeLookDownLeft",
+    "eyeLookInLeft",
+    "eyeLookOutLeft",
+    "eyeLookUpLeft",
+    "eyeSquintLeft",
+    "eyeWideLeft",
+    "eyeBlinkRight",
+    "eyeLookDownRight",
+    "eyeLookInRight",
+    "eyeLookOutRight",
+    "eyeLookUpRight",
+    "eyeSquintRight",
+    "eyeWideRight",
+    "jawForward",
+    "jawLeft",
+    "jawRight",
+    "jawOpen",
+    "mouthClose",
+    "mouthFunnel",
+    "mouthPucker",
+    "mouthLeft",
+    "mouthRight",
+    "mouthSmileLeft",
+    "mouthSmileRight",
+    "mouthFrownLeft",
+    "mouthFrownRight",
+    "mouthDimpleLeft",
+    "mouthDimpleRight",
+    "mouthStretchLeft",
+    "mouthStretchRight",
+    "mouthRollLower",
+    "mouthRollUpper",
+    "mouthShrugLower",
+    "mouthShrugUpper",
+    "mouthPressLeft",
+    "mouthPressRight",
+    "mouthLowerDownLeft",
+    "mouthLowerDownRight",
+    "mouthUpperUpLeft",
+    "mouthUpperUpRight",
+    "browDownLeft",
+    "browDownRight",
+    "browInnerUp",
+    "browOuterUpLeft",
+    "browOuterUpRight",
+    "cheekPuff",
+    "cheekSquintLeft",
+    "cheekSquintRight",
+    "noseSneerLeft",
+    "noseSneerRight",
+    "tongueOut",
+];
+
+// Blendshape name and indices of the source weights, which are averaged. Indices are of
+// XrFaceExpression2FB, the first 63 are the same as XrFaceExpressionFB
+const FB_TO_ARKIT: [(&str, &[usize]); 52] = [
+    ("eyeBlinkLeft", &[12]),
+    ("eyeLookDownLeft", &[14]),
+    ("eyeLookInLeft", &[18]),
+    ("eyeLookOutLeft", &[16]),
+    ("eyeLookUpLeft", &[20]),
+    ("eyeSquintLeft", &[28]),
+    ("eyeWideLeft", &[59]),
+    ("eyeBlinkRight", &[13]),
+    ("eyeLookDownRight", &[15]),
+    ("eyeLookInRight", &[17]),
+    ("eyeLookOutRight", &[19]),
+    ("eyeLookUpRight", &[21]),
+    ("eyeSquintRight", &[29]),
+    ("eyeWideRight", &[60]),
+    ("jawForward", &[27]),
+    ("jawLeft", &[25]),
+    ("jawRight", &[26]),
+    ("jawOpen", &[24]),
+    ("mouthClose", &[50]),
+    ("mouthFunnel", &[34, 35, 36, 37]),
+    ("mouthPucker", &[40, 41]),
+    ("mouthLeft", &[53]),
+    ("mouthRight", &[54]),
+    ("mouthSmileLeft", &[32]),
+    ("mouthSmileRight", &[33]),
+    ("mouthFrownLeft", &[30]),
+    ("mouthFrownRight", &[31]),
+    ("mouthDimpleLeft", &[10]),
+    ("mouthDimpleRight", &[11]),
+    ("mouthStretchLeft", &[42]),
+    ("mouthStretchRight", &[43]),
+    ("mouthRollLower", &[44, 46]),
+    ("mouthRollUpper", &[45, 47]),
+    ("mouthShrugLower", &[8]),
+    ("mouthShrugUpper", &[9]),
+    ("mouthPressLeft", &[38]),
+    ("mouthPressRight", &[39]),
+    ("mouthLowerDownLeft", &[51]),
+    ("mouthLowerDownRight", &[52]),
+    ("mouthUpperUpLeft", &[61]),
+    ("mouthUpperUpRight", &[62]),
+    ("browDownLeft", &[0]),
+    ("browDownRight", &[1]),
+    ("browInnerUp", &[22, 23]),
+    ("browOuterUpLeft", &[57]),
+    ("browOuterUpRight", &[58]),
+    ("cheekPuff", &[2, 3]),
+    ("cheekSquintLeft", &[4]),
+    ("cheekSquintRight", &[5]),
+    ("noseSneerLeft", &[55]),
+    ("noseSneerRight", &[56]),
+    // Only with face tracking 2
+    ("tongueOut", &[68]),
+];
+
+// Indices of XrEyeExpressionHTC
+const HTC_EYE_TO_ARKIT: [(&str, &[usize]); 14] = [
+    ("eyeBlinkLeft", &[0]),
+    ("eyeWideLeft", &[1]),
+    ("eyeBlinkRight", &[2]),
+    ("eyeWideRight", &[3]),
+    ("eyeSquintLeft", &[4]),
+    ("eyeSquintRight", &[5]),
+    ("eyeLookDownLeft", &[6]),
+    ("eyeLookDownRight", &[7]),
+    ("eyeLookOutLeft", &[8]),
+    ("eyeLookInRight", &[9]),
+    ("eyeLookInLeft", &[10]),
+    ("eyeLookOutRight", &[11]),
+    ("eyeLookUpLeft", &[12]),
+    ("eyeLookUpRight", &[13]),
+];
+
+// Indices of XrLipExpressionHTC. HTC has no brow, nose, cheek squint and dimple shapes
+const HTC_LIP_TO_ARKIT: [(&str, &[usize]); 22] = [
+    ("jawRight", &[0]),
+    ("jawLeft", &[1]),
+    ("jawForward", &[2]),
+    ("jawOpen", &[3]),
+    ("mouthClose", &[4]),
+    ("mouthRight", &[5, 7]),
+    ("mouthLeft", &[6, 8]),
+    ("mouthFunnel", &[9, 10]),
+    ("mouthPucker", &[11]),
+    ("mouthSmileRight", &[12]),
+    ("mouthSmileLeft", &[13]),
+    ("mouthFrownRight", &[14]),
+    ("mouthFrownLeft", &[15]),
+    ("cheekPuff", &[16, 17]),
+    ("mouthUpperUpRight", &[19]),
+    ("mouthUpperUpLeft", &[20]),
+    ("mouthLowerDownRight", &[21]),
+    ("mouthLowerDownLeft", &[22]),
+    ("mouthRollUpper", &[23]),
+    ("mouthRollLower", &[24]),
+    ("mouthShrugLower", &[25]),
+    ("tongueOut", &[26]),
+];
+
+fn apply_expression_mapping(
+    weights: &mut [Option<f32>; 52],
+    source: &[f32],
+    mapping: &[(&str, &[usize])],
+) {
+    for (name, indices) in mapping {
+        let Some(idx) = ARKIT_BLENDSHAPES.iter().position(|n| n == name) else {
+            continue;
+        };
+        if weights[idx].is_some() || indices.iter().any(|i| *i >= source.len()) {
+            continue;
+        }
+
+        weights[idx] = Some(indices.iter().map(|i| source[*i]).sum::<f32>() / indices.len() as f32);
+    }
+}
+
+// Converts the FB and HTC expressions to ARKit blendshape weights, indexed like ARKIT_BLENDSHAPES.
+// FB expressions take precedence, HTC expressions fill the remaining shapes. Shapes not provided by
+// any source are None
+pub fn normalized_expressions(face_data: &FaceData) -> [Option<f32>; 52] {
+    let mut weights = [None; 52];
+
+    if let Some(source) = &face_data.fb_face_expression {
+        apply_expression_mapping(&mut weights, source, &FB_TO_ARKIT);
+    }
+    if let Some(source) = &face_data.htc_eye_expression {
+        apply_expression_mapping(&mut weights, source, &HTC_EYE_TO_ARKIT);
+    }
+    if let Some(source) = &face_data.htc_lip_expression {
+        apply_expression_mapping(&mut weights, source, &HTC_LIP_TO_ARKIT);
+    }
+
+    weights
+}
+
 pub struct FaceTrackingSink {
     config: FaceTrackingSinkConfig,
     socket: UdpSocket,
@@ -20,6 +213,7 @@ impl FaceTrackingSink {
         let port = match config {
             FaceTrackingSinkConfig::VrchatEyeOsc { port } => port,
             FaceTrackingSinkConfig::VrcFaceTracking => VRCFT_PORT,
+            FaceTrackingSinkConfig::ArkitBlendshapesOsc { port, .. } => port,
         };
 
         let socket = UdpSocket::bind(format!("127.0.0.1:{local_osc_port}"))?;
@@ -62,7 +256,7 @@ impl FaceTrackingSink {
     }
 
     pub fn send_tracking(&mut self, face_data: FaceData) {
-        match self.config {
+        match &self.config {
             FaceTrackingSinkConfig::VrchatEyeOsc { .. } => {
                 if let [Some(left), Some(right)] = face_data.eye_gazes {
                     let (left_pitch, left_yaw, _) = left.orientation.to_euler(EulerRot::XYZ);
@@ -89,15 +283,10 @@ impl FaceTrackingSink {
                     );
                 }
 
-                let left_eye_blink = face_data
-                    .fb_face_expression
-                    .as_ref()
-                    .map(|v| v[12])
-                    .or_else(|| face_data.htc_eye_expression.as_ref().map(|v| v[0]));
-                let right_eye_blink = face_data
-                    .fb_face_expression
-                    .map(|v| v[13])
-                    .or_else(|| face_data.htc_eye_expression.map(|v| v[2]));
+                let weights = normalized_expressions(&face_data);
+                // eyeBlinkLeft and eyeBlinkRight
+                let left_eye_blink = weights[0];
+                let right_eye_blink = weights[7];
 
                 if let (Some(left), Some(right)) = (left_eye_blink, right_eye_blink) {
                     self.send_osc_message(
@@ -144,6 +333,68 @@ impl FaceTrackingSink {
 
                 self.socket.send(&self.packet_buffer).ok();
             }
+            FaceTrackingSinkConfig::ArkitBlendshapesOsc {
+                address_prefix,
+                calibration,
+                ..
+            } => {
+                let weights = normalized_expressions(&face_data);
+
+                for (name, weight) in ARKIT_BLENDSHAPES.iter().zip(weights) {
+                    let Some(mut weight) = weight else {
+                        continue;
+                    };
+
+                    if let Some((_, calibration)) = calibration.iter().find(|(n, _)| n == name) {
+                        weight = calibration.gain * weight.max(0.0).powf(calibration.curve);
+                    }
+
+                    self.send_osc_message(
+                        &format!("{address_prefix}{name}"),
+                        vec![OscType::Float(weight.clamp(0.0, 1.0))],
+                    );
+                }
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fb_expressions_take_precedence() {
+        let mut fb_face_expression = vec![0.0; 63];
+        fb_face_expression[12] = 0.8; // EYES_CLOSED_L
+        fb_face_expression[22] = 0.2; // INNER_BROW_RAISER_L
+        fb_face_expression[23] = 0.6; // INNER_BROW_RAISER_R
+
+        let mut htc_eye_expression = vec![0.0; 14];
+        htc_eye_expression[0] = 0.1; // EYE_LEFT_BLINK
+
+        let mut htc_lip_expression = vec![0.0; 37];
+        htc_lip_expression[26] = 0.5; // TONGUE_LONGSTEP1
+
+        let weights = normalized_expressions(&FaceData {
+            eye_gazes: [None, None],
+            fb_face_expression: Some(fb_face_expression),
+            htc_eye_expression: Some(htc_eye_expression),
+            htc_lip_expression: Some(htc_lip_expression),
+        });
+        let weight = |name| weights[ARKIT_BLENDSHAPES.iter().position(|n| *n == name).unwrap()];
+
+        assert_eq!(weight("eyeBlinkLeft"), Some(0.8));
+        assert!((weight("browInnerUp").unwrap() - 0.4).abs() < 1e-6);
+        // The 63 FB weights end before the tongue, so tongueOut comes from the HTC lip weights
+        assert_eq!(weight("tongueOut"), Some(0.5));
+
+        let weights = normalized_expressions(&FaceData {
+            eye_gazes: [None, None],
+            fb_face_expression: None,
+            htc_eye_expression: None,
+            htc_lip_expression: None,
+        });
+        assert!(weights.iter().all(Option::is_none));
+    }
+}
@@ -0,0 +1,197 @@
+// History of the tracking samples received from the client. SteamVR can query a sample at any
+// time: between two samples the poses are interpolated, outside of the history they are
+// extrapolated for at most a limited time.
+//
+// Angular velocities are local to the device, like the ones stored by the TrackingManager.
+
+use alvr_common::{glam::Quat, DeviceMotion, Pose};
+use std::{collections::VecDeque, time::Duration};
+
+pub trait Interpolate: Copy {
+    // factor is in the range [0, 1]. interval_s is the time between the two samples
+    fn interpolate(&self, other: &Self, factor: f32, interval_s: f32) -> Self;
+
+    fn extrapolate(&self, delta_time_s: f32) -> Self;
+}
+
+fn interpolate_pose(a: &Pose, b: &Pose, factor: f32) -> Pose {
+    Pose {
+        orientation: a.orientation.slerp(b.orientation, factor),
+        position: a.position.lerp(b.position, factor),
+    }
+}
+
+impl Interpolate for DeviceMotion {
+    fn interpolate(&self, other: &Self, factor: f32, interval_s: f32) -> Self {
+        let t = factor;
+        let t2 = t * t;
+        let t3 = t2 * t;
+
+        // Cubic Hermite spline, so the path follows the velocities at both ends
+        let position = (2.0 * t3 - 3.0 * t2 + 1.0) * self.pose.position
+            + (t3 - 2.0 * t2 + t) * interval_s * self.linear_velocity
+            + (-2.0 * t3 + 3.0 * t2) * other.pose.position
+            + (t3 - t2) * interval_s * other.linear_velocity;
+
+        DeviceMotion {
+            pose: Pose {
+                orientation: self.pose.orientation.slerp(other.pose.orientation, factor),
+                position,
+            },
+            linear_velocity: self.linear_velocity.lerp(other.linear_velocity, factor),
+            angular_velocity: self.angular_velocity.lerp(other.angular_velocity, factor),
+        }
+    }
+
+    fn extrapolate(&self, delta_time_s: f32) -> Self {
+        DeviceMotion {
+            pose: Pose {
+                orientation: (self.pose.orientation
+                    * Quat::from_scaled_axis(self.angular_velocity * delta_time_s))
+                .normalize(),
+                position: self.pose.position + self.linear_velocity * delta_time_s,
+            },
+            ..*self
+        }
+    }
+}
+
+// Hand skeletons have no velocities: they are held still outside of the history
+impl Interpolate for [Pose; 26] {
+    fn interpolate(&self, other: &Self, factor: f32, _: f32) -> Self {
+        let mut skeleton = *self;
+        for (pose, other_pose) in skeleton.iter_mut().zip(other) {
+            *pose = interpolate_pose(pose, other_pose, factor);
+        }
+
+        skeleton
+    }
+
+    fn extrapolate(&self, _: f32) -> Self {
+        *self
+    }
+}
+
+pub struct SampleHistory<T> {
+    // Sorted by timestamp
+    samples: VecDeque<(Duration, T)>,
+    max_size: usize,
+}
+
+impl<T: Interpolate> SampleHistory<T> {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            samples: VecDeque::new(),
+            max_size: usize::max(max_size, 1),
+        }
+    }
+
+    // Samples can arrive out of order. A sample with the same timestamp of an existing one
+    // replaces it
+    pub fn push(&mut self, timestamp: Duration, sample: T) {
+        let idx = self.samples.partition_point(|(t, _)| *t < timestamp);
+        if matches!(self.samples.get(idx), Some((t, _)) if *t == timestamp) {
+            self.samples[idx].1 = sample;
+        } else {
+            self.samples.insert(idx, (timestamp, sample));
+        }
+
+        while self.samples.len() > self.max_size {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn latest(&self) -> Option<&(Duration, T)> {
+        self.samples.back()
+    }
+
+    pub fn get(&self, timestamp: Duration, max_extrapolation: Duration) -> Option<T> {
+        let idx = self.samples.partition_point(|(t, _)| *t < timestamp);
+
+        let extrapolate = |(sample_timestamp, sample): &(Duration, T)| {
+            let delta_time_s = if timestamp > *sample_timestamp {
+                Duration::min(timestamp - *sample_timestamp, max_extrapolation).as_secs_f32()
+            } else {
+                -Duration::min(*sample_timestamp - timestamp, max_extrapolation).as_secs_f32()
+            };
+
+            sample.extrapolate(delta_time_s)
+        };
+
+        match (
+            idx.checked_sub(1).and_then(|idx| self.samples.get(idx)),
+            self.samples.get(idx),
+        ) {
+            (_, Some((t, sample))) if *t == timestamp => Some(*sample),
+            (Some((t0, a)), Some((t1, b))) => {
+                let interval = *t1 - *t0;
+                let factor = (timestamp - *t0).as_secs_f32() / interval.as_secs_f32();
+
+                Some(a.interpolate(b, factor, interval.as_secs_f32()))
+            }
+            (Some(previous), None) => Some(extrapolate(previous)),
+            (None, Some(next)) => Some(extrapolate(next)),
+            (None, None) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alvr_common::glam::Vec3;
+
+    fn motion(position: Vec3, linear_velocity: Vec3) -> DeviceMotion {
+        DeviceMotion {
+            pose: Pose {
+                orientation: Quat::IDENTITY,
+                position,
+            },
+            linear_velocity,
+            angular_velocity: Vec3::ZERO,
+        }
+    }
+
+    #[test]
+    fn interpolation_follows_velocity() {
+        let mut history = SampleHistory::new(8);
+
+        // Constant velocity of 1 m/s along x. Pushed out of order
+        history.push(Duration::from_millis(20), motion(Vec3::X * 0.02, Vec3::X));
+        history.push(Duration::from_millis(0), motion(Vec3::ZERO, Vec3::X));
+
+        let sample = history
+            .get(Duration::from_millis(5), Duration::ZERO)
+            .unwrap();
+        assert!((sample.pose.position.x - 0.005).abs() < 1e-6);
+
+        let sample = history
+            .get(Duration::from_millis(20), Duration::ZERO)
+            .unwrap();
+        assert!((sample.pose.position.x - 0.02).abs() < 1e-6);
+    }
+
+    #[test]
+    fn extrapolation_is_limited() {
+        let mut history = SampleHistory::new(2);
+
+        for i in 0..4 {
+            history.push(
+                Duration::from_millis(i * 10),
+                motion(Vec3::X * i as f32 * 0.01, Vec3::X),
+            );
+        }
+        assert_eq!(history.latest().unwrap().0, Duration::from_millis(30));
+
+        let max_extrapolation = Duration::from_millis(10);
+
+        let sample = history
+            .get(Duration::from_millis(100), max_extrapolation)
+            .unwrap();
+        assert!((sample.pose.position.x - 0.04).abs() < 1e-6);
+
+        // The first two samples were evicted
+        let sample = history.get(Duration::ZERO, max_extrapolation).unwrap();
+        assert!((sample.pose.position.x - 0.01).abs() < 1e-6);
+    }
+}
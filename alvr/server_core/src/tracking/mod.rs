@@ -1,5 +1,8 @@
 mod body;
+mod body_receiver;
+mod controller_calibration;
 mod face;
+mod history;
 mod vmc;
 
 pub use body::*;
@@ -7,42 +10,48 @@ pub use face::*;
 pub use vmc::*;
 
 use crate::{
-    connection::STREAMING_RECV_TIMEOUT,
-    hand_gestures::{self, HandGestureManager, HAND_GESTURE_BUTTON_SET},
-    input_mapping::ButtonMappingManager,
-    ConnectionContext, ServerCoreEvent, SESSION_MANAGER,
+    connection::STREAMING_RECV_TIMEOUT, hand_gestures::HandGestureManager,
+    hand_poses::HandPoseRecognizer, ConnectionContext, ServerCoreEvent, SESSION_MANAGER,
 };
 use alvr_common::{
+    anyhow::Result,
     glam::{EulerRot, Quat, Vec3},
+    hash_string, info,
     parking_lot::Mutex,
-    ConnectionError, DeviceMotion, Pose, BODY_CHEST_ID, BODY_HIPS_ID, BODY_LEFT_ELBOW_ID,
+    warn, ConnectionError, DeviceMotion, Pose, BODY_CHEST_ID, BODY_HIPS_ID, BODY_LEFT_ELBOW_ID,
     BODY_LEFT_FOOT_ID, BODY_LEFT_KNEE_ID, BODY_RIGHT_ELBOW_ID, BODY_RIGHT_FOOT_ID,
     BODY_RIGHT_KNEE_ID, DEVICE_ID_TO_PATH, HAND_LEFT_ID, HAND_RIGHT_ID, HEAD_ID,
 };
 use alvr_events::{EventType, TrackingEvent};
-use alvr_packets::{FaceData, Tracking};
+use alvr_packets::{FaceData, PathValuePair, RecordedPacket, Tracking};
 use alvr_session::{
-    settings_schema::Switch, BodyTrackingConfig, HeadsetConfig, PositionRecenteringMode,
-    RotationRecenteringMode, Settings, VMCConfig,
+    settings_schema::Switch, BodyTrackingConfig, DeviceMotionConfig, HeadsetConfig,
+    PositionRecenteringMode, RotationRecenteringMode, Settings, VMCConfig,
 };
 use alvr_sockets::StreamReceiver;
-use std::{
-    collections::{HashMap, VecDeque},
-    f32::consts::PI,
-    sync::Arc,
-    time::Duration,
-};
+use body_receiver::BodyTrackingReceiver;
+use controller_calibration::{ControllerCalibration, ControllerCalibrationOffsets};
+use history::SampleHistory;
+use serde_json as json;
+use std::{collections::HashMap, f32::consts::PI, sync::Arc, time::Duration};
 
 const DEG_TO_RAD: f32 = PI / 180.0;
-const MAX_HISTORY_SIZE: usize = 8;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum HandType {
     Left = 0,
     Right = 1,
 }
 
-// todo: Move this struct to Settings and use it for every tracked device
+fn euler_degrees_to_quat(rotation: [f32; 3]) -> Quat {
+    Quat::from_euler(
+        EulerRot::XYZ,
+        rotation[0] * DEG_TO_RAD,
+        rotation[1] * DEG_TO_RAD,
+        rotation[2] * DEG_TO_RAD,
+    )
+}
+
 #[derive(Default)]
 struct MotionConfig {
     // Position offset applied after rotation offset
@@ -51,22 +60,44 @@ struct MotionConfig {
     angular_velocity_cutoff: f32,
 }
 
+impl MotionConfig {
+    fn from_device_config(config: &DeviceMotionConfig) -> Self {
+        Self {
+            pose_offset: Pose {
+                orientation: euler_degrees_to_quat(config.rotation_offset),
+                position: Vec3::from_array(config.position_offset),
+            },
+            linear_velocity_cutoff: config.linear_velocity_cutoff,
+            angular_velocity_cutoff: config.angular_velocity_cutoff * DEG_TO_RAD,
+        }
+    }
+}
+
 pub struct TrackingManager {
     last_head_pose: Pose,             // client's reference space
     inverse_recentering_origin: Pose, // client's reference space
-    device_motions_history: HashMap<u64, VecDeque<(Duration, DeviceMotion)>>,
-    hand_skeletons_history: [VecDeque<(Duration, [Pose; 26])>; 2],
+    history_size: usize,
+    max_extrapolation: Duration,
+    device_motions_history: HashMap<u64, SampleHistory<DeviceMotion>>,
+    hand_skeletons_history: [SampleHistory<[Pose; 26]>; 2],
     last_face_data: FaceData,
+    controller_calibration: Option<ControllerCalibration>,
 }
 
 impl TrackingManager {
-    pub fn new() -> TrackingManager {
+    pub fn new(config: &HeadsetConfig) -> TrackingManager {
         TrackingManager {
             last_head_pose: Pose::default(),
             inverse_recentering_origin: Pose::default(),
+            history_size: config.tracking_history_size,
+            max_extrapolation: Duration::from_millis(config.max_pose_extrapolation_ms),
             device_motions_history: HashMap::new(),
-            hand_skeletons_history: [VecDeque::new(), VecDeque::new()],
+            hand_skeletons_history: [
+                SampleHistory::new(config.tracking_history_size),
+                SampleHistory::new(config.tracking_history_size),
+            ],
             last_face_data: FaceData::default(),
+            controller_calibration: None,
         }
     }
 
@@ -129,6 +160,8 @@ impl TrackingManager {
         timestamp: Duration,
         device_motions: &[(u64, DeviceMotion)],
     ) {
+        self.max_extrapolation = Duration::from_millis(config.max_pose_extrapolation_ms);
+
         let mut device_motion_configs = HashMap::new();
         device_motion_configs.insert(*HEAD_ID, MotionConfig::default());
         device_motion_configs.extend([
@@ -150,12 +183,7 @@ impl TrackingManager {
                 *HAND_LEFT_ID,
                 MotionConfig {
                     pose_offset: Pose {
-                        orientation: Quat::from_euler(
-                            EulerRot::XYZ,
-                            r[0] * DEG_TO_RAD,
-                            r[1] * DEG_TO_RAD,
-                            r[2] * DEG_TO_RAD,
-                        ),
+                        orientation: euler_degrees_to_quat(r),
                         position: Vec3::new(t[0], t[1], t[2]),
                     },
                     linear_velocity_cutoff: controllers.linear_velocity_cutoff,
@@ -167,12 +195,7 @@ impl TrackingManager {
                 *HAND_RIGHT_ID,
                 MotionConfig {
                     pose_offset: Pose {
-                        orientation: Quat::from_euler(
-                            EulerRot::XYZ,
-                            r[0] * DEG_TO_RAD,
-                            -r[1] * DEG_TO_RAD,
-                            -r[2] * DEG_TO_RAD,
-                        ),
+                        orientation: euler_degrees_to_quat([r[0], -r[1], -r[2]]),
                         position: Vec3::new(-t[0], t[1], t[2]),
                     },
                     linear_velocity_cutoff: controllers.linear_velocity_cutoff,
@@ -181,6 +204,13 @@ impl TrackingManager {
             );
         }
 
+        for (path, device_config) in &config.device_motions {
+            device_motion_configs.insert(
+                hash_string(path),
+                MotionConfig::from_device_config(device_config),
+            );
+        }
+
         let mut transformed_motions = vec![];
         for &(device_id, mut motion) in device_motions {
             if device_id == *HEAD_ID {
@@ -191,6 +221,14 @@ impl TrackingManager {
                 // Recenter
                 motion = self.recenter_motion(motion);
 
+                if let Some(calibration) = &mut self.controller_calibration {
+                    if device_id == *HAND_LEFT_ID {
+                        calibration.report_controller_pose(HandType::Left, timestamp, motion.pose);
+                    } else if device_id == *HAND_RIGHT_ID {
+                        calibration.report_controller_pose(HandType::Right, timestamp, motion.pose);
+                    }
+                }
+
                 // Apply custom transform
                 motion.pose.orientation *= config.pose_offset.orientation;
                 motion.pose.position += motion.pose.orientation * config.pose_offset.position;
@@ -217,32 +255,23 @@ impl TrackingManager {
                 transformed_motions.push((device_id, motion));
             }
 
-            if let Some(motions) = self.device_motions_history.get_mut(&device_id) {
-                motions.push_back((timestamp, motion));
-
-                if motions.len() > MAX_HISTORY_SIZE {
-                    motions.pop_front();
-                }
-            } else {
-                self.device_motions_history
-                    .insert(device_id, VecDeque::from(vec![(timestamp, motion)]));
-            }
+            self.device_motions_history
+                .entry(device_id)
+                .or_insert_with(|| SampleHistory::new(self.history_size))
+                .push(timestamp, motion);
         }
     }
 
+    // Returns the motion at any time, interpolated between the received samples or extrapolated
+    // past them
     pub fn get_device_motion(
         &self,
         device_id: u64,
         sample_timestamp: Duration,
     ) -> Option<DeviceMotion> {
-        self.device_motions_history
-            .get(&device_id)
-            .and_then(|motions| {
-                motions
-                    .iter()
-                    .find(|(timestamp, _)| *timestamp == sample_timestamp)
-                    .map(|(_, motion)| *motion)
-            })
+        let history = self.device_motions_history.get(&device_id)?;
+
+        history.get(sample_timestamp, self.max_extrapolation)
     }
 
     pub fn report_hand_skeleton(
@@ -255,24 +284,45 @@ impl TrackingManager {
             *pose = self.recenter_pose(*pose);
         }
 
-        let skeleton_history = &mut self.hand_skeletons_history[hand_type as usize];
+        if let Some(calibration) = &mut self.controller_calibration {
+            calibration.report_hand_skeleton(hand_type, timestamp, &skeleton);
+        }
 
-        skeleton_history.push_back((timestamp, skeleton));
+        self.hand_skeletons_history[hand_type as usize].push(timestamp, skeleton);
+    }
 
-        if skeleton_history.len() > MAX_HISTORY_SIZE {
-            skeleton_history.pop_front();
-        }
+    pub fn get_latest_hand_skeleton(&self, hand_type: HandType) -> Option<[Pose; 26]> {
+        self.hand_skeletons_history[hand_type as usize]
+            .latest()
+            .map(|(_, skeleton)| *skeleton)
     }
 
     pub fn get_hand_skeleton(
         &self,
         hand_type: HandType,
         sample_timestamp: Duration,
-    ) -> Option<&[Pose; 26]> {
+    ) -> Option<[Pose; 26]> {
         self.hand_skeletons_history[hand_type as usize]
-            .iter()
-            .find(|(timestamp, _)| *timestamp == sample_timestamp)
-            .map(|(_, skeleton)| skeleton)
+            .get(sample_timestamp, self.max_extrapolation)
+    }
+
+    pub fn start_controller_calibration(&mut self) {
+        self.controller_calibration = Some(ControllerCalibration::new());
+    }
+
+    // Returns the result once the calibration is finished. hand_tracking_offset is the left hand
+    // tracking offset
+    pub fn poll_controller_calibration(
+        &mut self,
+        hand_tracking_offset: Pose,
+    ) -> Option<Result<ControllerCalibrationOffsets>> {
+        let result = self
+            .controller_calibration
+            .as_ref()?
+            .poll(hand_tracking_offset)?;
+        self.controller_calibration = None;
+
+        Some(result)
     }
 
     // todo: send eyes in head local space from client directly
@@ -298,18 +348,7 @@ pub fn tracking_loop(
     mut tracking_receiver: StreamReceiver<Tracking>,
     is_streaming: impl Fn() -> bool,
 ) {
-    let mut gestures_button_mapping_manager =
-        initial_settings
-            .headset
-            .controllers
-            .as_option()
-            .map(|config| {
-                ButtonMappingManager::new_automatic(
-                    &HAND_GESTURE_BUTTON_SET,
-                    &config.emulation_mode,
-                    &config.button_mapping_config,
-                )
-            });
+    let mut hand_pose_recognizer = HandPoseRecognizer::new();
 
     let mut face_tracking_sink = initial_settings
         .headset
@@ -319,6 +358,17 @@ pub fn tracking_loop(
             FaceTrackingSink::new(config.sink, initial_settings.connection.osc_local_port).ok()
         });
 
+    let mut body_tracking_receiver = initial_settings
+        .headset
+        .body_tracking
+        .as_option()
+        .and_then(|config| config.sources.external_receiver.as_option())
+        .and_then(|config| {
+            BodyTrackingReceiver::new(config)
+                .map_err(|e| warn!("Failed to start the body tracking receiver: {e}"))
+                .ok()
+        });
+
     let mut body_tracking_sink = initial_settings
         .headset
         .body_tracking
@@ -343,6 +393,8 @@ pub fn tracking_loop(
             return;
         };
 
+        crate::record_session_packet(ctx, || RecordedPacket::Tracking(tracking.clone()));
+
         let timestamp = tracking.target_timestamp;
 
         if let Some(stats) = &mut *ctx.statistics_manager.write() {
@@ -363,6 +415,35 @@ pub fn tracking_loop(
             }
         }
 
+        if let Some(receiver) = &mut body_tracking_receiver {
+            let receiver_config = SESSION_MANAGER
+                .read()
+                .settings()
+                .headset
+                .body_tracking
+                .as_option()
+                .and_then(|config| config.sources.external_receiver.as_option())
+                .cloned();
+
+            if let Some(config) = receiver_config {
+                let head_pose = tracking
+                    .device_motions
+                    .iter()
+                    .find(|(id, _)| *id == *HEAD_ID)
+                    .map(|(_, motion)| motion.pose);
+
+                for (id, motion) in receiver.poll(&config, head_pose) {
+                    if !tracking
+                        .device_motions
+                        .iter()
+                        .any(|(other, _)| *other == id)
+                    {
+                        tracking.device_motions.push((id, motion));
+                    }
+                }
+            }
+        }
+
         let controllers_config = {
             let data_lock = SESSION_MANAGER.read();
             data_lock
@@ -402,6 +483,18 @@ pub fn tracking_loop(
                 sink.send_tracking(tracking_manager_lock.get_face_data().clone());
             }
 
+            if let Switch::Enabled(config) =
+                &session_manager_lock.settings().video.foveated_encoding
+            {
+                if config.follow_eye_gaze {
+                    ctx.foveation_manager.lock().report_eye_gazes(
+                        config,
+                        timestamp,
+                        tracking_manager_lock.get_face_data().eye_gazes,
+                    );
+                }
+            }
+
             if session_manager_lock.settings().extra.logging.log_tracking {
                 let face_data = tracking_manager_lock.get_face_data().clone();
 
@@ -430,46 +523,103 @@ pub fn tracking_loop(
             device_motion_keys
         };
 
+        if let Some(controllers_config) = &controllers_config {
+            let hand_tracking_offset = Pose {
+                orientation: euler_degrees_to_quat(
+                    controllers_config.left_hand_tracking_rotation_offset,
+                ),
+                position: Vec3::from_array(controllers_config.left_hand_tracking_position_offset),
+            };
+
+            let result = ctx
+                .tracking_manager
+                .write()
+                .poll_controller_calibration(hand_tracking_offset);
+            match result {
+                Some(Ok(offsets)) => {
+                    info!(
+                        "Controller calibration finished. Position offset: {:?}, rotation offset: {:?}",
+                        offsets.position, offsets.rotation
+                    );
+
+                    let path = |name: &str| {
+                        alvr_packets::parse_path(&format!(
+                            "session_settings.headset.controllers.content.{name}.content"
+                        ))
+                    };
+                    SESSION_MANAGER
+                        .write()
+                        .set_values(vec![
+                            PathValuePair {
+                                path: path("left_controller_position_offset"),
+                                value: json::json!(offsets.position),
+                            },
+                            PathValuePair {
+                                path: path("left_controller_rotation_offset"),
+                                value: json::json!(offsets.rotation),
+                            },
+                        ])
+                        .ok();
+                }
+                Some(Err(e)) => warn!("Controller calibration failed: {e}"),
+                None => (),
+            }
+        }
+
         // Handle hand gestures
-        if let (Some(gestures_config), Some(gestures_button_mapping_manager)) = (
-            controllers_config
-                .as_ref()
-                .and_then(|c| c.hand_tracking_interaction.as_option()),
-            &mut gestures_button_mapping_manager,
-        ) {
+        if let Some((controllers_config, gestures_config)) = controllers_config
+            .as_ref()
+            .and_then(|c| Some((c, c.hand_tracking_interaction.as_option()?)))
+        {
             let mut hand_gesture_manager_lock = hand_gesture_manager.lock();
 
-            if let Some(hand_skeleton) = tracking.hand_skeletons[0] {
+            for (hand_skeleton, device_id) in [
+                (tracking.hand_skeletons[0], *HAND_LEFT_ID),
+                (tracking.hand_skeletons[1], *HAND_RIGHT_ID),
+            ] {
+                let Some(hand_skeleton) = hand_skeleton else {
+                    continue;
+                };
+
+                let gestures = hand_gesture_manager_lock.get_active_gestures(
+                    hand_skeleton,
+                    gestures_config,
+                    device_id,
+                );
                 ctx.events_sender
                     .send(ServerCoreEvent::Buttons(
-                        hand_gestures::trigger_hand_gesture_actions(
-                            gestures_button_mapping_manager,
-                            *HAND_LEFT_ID,
-                            &hand_gesture_manager_lock.get_active_gestures(
-                                hand_skeleton,
-                                gestures_config,
-                                *HAND_LEFT_ID,
-                            ),
-                            gestures_config.only_touch,
+                        hand_gesture_manager_lock.trigger_hand_gesture_actions(
+                            controllers_config,
+                            device_id,
+                            &gestures,
+                            gestures_config,
                         ),
                     ))
                     .ok();
             }
-            if let Some(hand_skeleton) = tracking.hand_skeletons[1] {
-                ctx.events_sender
-                    .send(ServerCoreEvent::Buttons(
-                        hand_gestures::trigger_hand_gesture_actions(
-                            gestures_button_mapping_manager,
-                            *HAND_RIGHT_ID,
-                            &hand_gesture_manager_lock.get_active_gestures(
-                                hand_skeleton,
-                                gestures_config,
-                                *HAND_RIGHT_ID,
+        }
+
+        // Handle hand poses
+        if let Some((controllers_config, recognition_config)) = controllers_config
+            .as_ref()
+            .and_then(|c| Some((c, c.hand_pose_recognition.as_option()?)))
+        {
+            for (hand_skeleton, device_id) in [
+                (tracking.hand_skeletons[0], *HAND_LEFT_ID),
+                (tracking.hand_skeletons[1], *HAND_RIGHT_ID),
+            ] {
+                if let Some(hand_skeleton) = hand_skeleton {
+                    ctx.events_sender
+                        .send(ServerCoreEvent::Buttons(
+                            hand_pose_recognizer.trigger_hand_pose_actions(
+                                recognition_config,
+                                controllers_config,
+                                device_id,
+                                &hand_skeleton,
                             ),
-                            gestures_config.only_touch,
-                        ),
-                    ))
-                    .ok();
+                        ))
+                        .ok();
+                }
             }
         }
 
@@ -513,6 +663,7 @@ pub fn tracking_loop(
                     sink.send_hand_tracking(HandType::Right, skeleton, orientation_correction);
                 }
                 sink.send_tracking(&device_motions, orientation_correction);
+                sink.send_status();
             }
         }
 
@@ -1,7 +1,10 @@
 use alvr_common::{
-    anyhow::Result, glam::Quat, once_cell::sync::Lazy, DeviceMotion, Pose, BODY_CHEST_ID,
-    BODY_HIPS_ID, BODY_LEFT_ELBOW_ID, BODY_LEFT_FOOT_ID, BODY_LEFT_KNEE_ID, BODY_RIGHT_ELBOW_ID,
-    BODY_RIGHT_FOOT_ID, BODY_RIGHT_KNEE_ID, HAND_LEFT_ID, HAND_RIGHT_ID, HEAD_ID,
+    anyhow::Result,
+    glam::{Quat, Vec3},
+    once_cell::sync::Lazy,
+    DeviceMotion, Pose, BODY_CHEST_ID, BODY_HIPS_ID, BODY_LEFT_ELBOW_ID, BODY_LEFT_FOOT_ID,
+    BODY_LEFT_KNEE_ID, BODY_RIGHT_ELBOW_ID, BODY_RIGHT_FOOT_ID, BODY_RIGHT_KNEE_ID, HAND_LEFT_ID,
+    HAND_RIGHT_ID, HEAD_ID,
 };
 use rosc::{OscMessage, OscPacket, OscType};
 use std::{collections::HashMap, net::UdpSocket};
@@ -12,7 +15,7 @@ pub use crate::tracking::HandType;
 
 // Transform DeviceMotion into Unity HumanBodyBones
 // https://docs.unity3d.com/ScriptReference/HumanBodyBones.html
-static DEVICE_MOTIONS_VMC_MAP: Lazy<HashMap<u64, &'static str>> = Lazy::new(|| {
+pub(super) static DEVICE_MOTIONS_VMC_MAP: Lazy<HashMap<u64, &'static str>> = Lazy::new(|| {
     HashMap::from([
         (*HAND_LEFT_ID, "LeftHand"),
         (*HAND_RIGHT_ID, "RightHand"),
@@ -79,6 +82,49 @@ static HAND_SKELETON_ROTATIONS: Lazy<[HashMap<usize, Quat>; 2]> = Lazy::new(|| {
     ]
 });
 
+// Unity HumanBodyBones finger bones, as (OpenXR joint, parent joint, bone name). The parent is the
+// joint of the parent bone in the Unity hierarchy: the wrist for the proximal bones, since Unity has
+// no metacarpal bones except for the thumb
+const FINGER_BONES: [(usize, usize, &str); 15] = [
+    (2, 1, "ThumbProximal"),
+    (3, 2, "ThumbIntermediate"),
+    (4, 3, "ThumbDistal"),
+    (7, 1, "IndexProximal"),
+    (8, 7, "IndexIntermediate"),
+    (9, 8, "IndexDistal"),
+    (12, 1, "MiddleProximal"),
+    (13, 12, "MiddleIntermediate"),
+    (14, 13, "MiddleDistal"),
+    (17, 1, "RingProximal"),
+    (18, 17, "RingIntermediate"),
+    (19, 18, "RingDistal"),
+    (22, 1, "LittleProximal"),
+    (23, 22, "LittleIntermediate"),
+    (24, 23, "LittleDistal"),
+];
+
+// Pose of a joint relative to its parent, converted from the OpenXR joint space (-Z towards the
+// fingertip, +Y towards the back of the hand, right handed) to the space of a normalized Unity bone
+// (identity in T-pose with the palms facing down, left handed). For the left hand the finger points
+// to -X, for the right hand to +X.
+fn to_unity_local_pose(hand_type: HandType, parent: Pose, joint: Pose) -> Pose {
+    let local = parent.inverse() * joint;
+    let p = local.position;
+    let q = local.orientation;
+
+    // The change of basis flips the handedness, so the rotation axis is also negated
+    match hand_type {
+        HandType::Left => Pose {
+            orientation: Quat::from_xyzw(-q.z, -q.y, -q.x, q.w),
+            position: Vec3::new(p.z, p.y, p.x),
+        },
+        HandType::Right => Pose {
+            orientation: Quat::from_xyzw(q.z, -q.y, q.x, q.w),
+            position: Vec3::new(-p.z, p.y, -p.x),
+        },
+    }
+}
+
 pub struct VMCSink {
     socket: Option<UdpSocket>,
 }
@@ -93,6 +139,22 @@ impl VMCSink {
         })
     }
 
+    fn send_bone_pose(&self, bone: String, position: Vec3, orientation: Quat) {
+        self.send_osc_message(
+            "/VMC/Ext/Bone/Pos",
+            vec![
+                OscType::String(bone),
+                OscType::Float(position.x),
+                OscType::Float(position.y),
+                OscType::Float(position.z),
+                OscType::Float(orientation.x),
+                OscType::Float(orientation.y),
+                OscType::Float(orientation.z),
+                OscType::Float(orientation.w),
+            ],
+        );
+    }
+
     fn send_osc_message(&self, path: &str, args: Vec<OscType>) {
         if let Some(socket) = &self.socket {
             socket
@@ -127,18 +189,24 @@ impl VMCSink {
                 q
             };
 
-            self.send_osc_message(
-                "/VMC/Ext/Bone/Pos",
-                vec![
-                    OscType::String(vmc_str.to_string()),
-                    OscType::Float(skeleton[part].position.x),
-                    OscType::Float(skeleton[part].position.y),
-                    OscType::Float(skeleton[part].position.z),
-                    OscType::Float(corrected_orientation.x),
-                    OscType::Float(corrected_orientation.y),
-                    OscType::Float(corrected_orientation.z),
-                    OscType::Float(corrected_orientation.w),
-                ],
+            self.send_bone_pose(
+                vmc_str.to_string(),
+                skeleton[part].position,
+                corrected_orientation,
+            );
+        }
+
+        let side = match hand_type {
+            HandType::Left => "Left",
+            HandType::Right => "Right",
+        };
+        for (joint, parent, bone) in FINGER_BONES {
+            let local_pose = to_unity_local_pose(hand_type, skeleton[parent], skeleton[joint]);
+
+            self.send_bone_pose(
+                format!("{side}{bone}"),
+                local_pose.position,
+                local_pose.orientation,
             );
         }
     }
@@ -162,20 +230,75 @@ impl VMCSink {
                     q
                 };
 
-                self.send_osc_message(
-                    "/VMC/Ext/Bone/Pos",
-                    vec![
-                        OscType::String(DEVICE_MOTIONS_VMC_MAP.get(id).unwrap().to_string()),
-                        OscType::Float(motion.pose.position.x),
-                        OscType::Float(motion.pose.position.y),
-                        OscType::Float(motion.pose.position.z),
-                        OscType::Float(corrected_orientation.x),
-                        OscType::Float(corrected_orientation.y),
-                        OscType::Float(corrected_orientation.z),
-                        OscType::Float(corrected_orientation.w),
-                    ],
+                self.send_bone_pose(
+                    DEVICE_MOTIONS_VMC_MAP.get(id).unwrap().to_string(),
+                    motion.pose.position,
+                    corrected_orientation,
                 );
             }
         }
     }
+
+    // Sent every frame. The bone poses are relative to the root, which is left at the origin. The
+    // OK message reports the model as loaded, calibrated and tracked
+    pub fn send_status(&mut self) {
+        self.send_osc_message(
+            "/VMC/Ext/Root/Pos",
+            vec![
+                OscType::String("root".into()),
+                OscType::Float(0.0),
+                OscType::Float(0.0),
+                OscType::Float(0.0),
+                OscType::Float(0.0),
+                OscType::Float(0.0),
+                OscType::Float(0.0),
+                OscType::Float(1.0),
+            ],
+        );
+
+        // Loaded, calibration state (3: calibrated), calibration mode (0: normal), tracking status
+        self.send_osc_message(
+            "/VMC/Ext/OK",
+            vec![
+                OscType::Int(1),
+                OscType::Int(3),
+                OscType::Int(0),
+                OscType::Int(1),
+            ],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finger_curl_to_unity() {
+        // Flat hand, with the proximal bone curled towards the palm
+        let wrist = Pose {
+            orientation: Quat::from_rotation_y(0.7),
+            position: Vec3::new(0.1, 1.0, -0.3),
+        };
+        let curl = Pose {
+            orientation: Quat::from_rotation_x(-0.5),
+            position: Vec3::new(0.0, 0.0, -0.08),
+        };
+        let proximal = wrist * curl;
+
+        // The left index finger points to -X and curls towards -Y: positive rotation around Z
+        let local_pose = to_unity_local_pose(HandType::Left, wrist, proximal);
+        let (axis, angle) = local_pose.orientation.to_axis_angle();
+        assert!(axis.distance(Vec3::Z) < 1e-4);
+        assert!((angle - 0.5).abs() < 1e-4);
+        assert!(local_pose.position.distance(Vec3::new(-0.08, 0.0, 0.0)) < 1e-4);
+        let tip_direction = local_pose.orientation * Vec3::NEG_X;
+        assert!(tip_direction.y < 0.0);
+
+        // The right index finger points to +X and curls towards -Y
+        let local_pose = to_unity_local_pose(HandType::Right, wrist, proximal);
+        assert!(local_pose.position.distance(Vec3::new(0.08, 0.0, 0.0)) < 1e-4);
+        let tip_direction = local_pose.orientation * Vec3::X;
+        assert!(tip_direction.y < 0.0);
+    }
 }
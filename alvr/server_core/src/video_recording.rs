@@ -0,0 +1,776 @@
+// Video recordings, either as raw elementary stream or muxed into Matroska.
+//
+// The Matroska muxer writes the segment and the clusters with unknown size, so the file does not
+// need to be finalized and is playable even if the recording is interrupted. The track headers
+// require the codec configuration, so nothing is written until the first IDR frame. H.264 and HEVC
+// frames are converted from Annex-B to length prefixed NAL units.
+
+use alvr_common::{
+    anyhow::{bail, Result},
+    error,
+};
+use alvr_packets::DecoderInitializationConfig;
+use alvr_session::CodecType;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    time::Duration,
+};
+
+const EBML: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const BIT_DEPTH: u32 = 0x6264;
+const CLUSTER: u32 = 0x1F43B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+const VIDEO_TRACK_NUMBER: u8 = 1;
+const AUDIO_TRACK_NUMBER: u8 = 2;
+const AUDIO_CHANNELS: u64 = 2;
+const AUDIO_FRAME_SIZE: usize = AUDIO_CHANNELS as usize * 2;
+// Block timestamps are 16 bit signed offsets from the cluster timestamp
+const MAX_CLUSTER_DURATION_MS: u64 = 30_000;
+
+fn write_id(buffer: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let leading_zeros = (id.leading_zeros() / 8) as usize;
+    buffer.extend_from_slice(&bytes[leading_zeros.min(3)..]);
+}
+
+// Sizes are always written with 8 bytes for simplicity
+fn write_size(buffer: &mut Vec<u8>, size: usize) {
+    buffer.extend_from_slice(&((1 << 56) | size as u64).to_be_bytes());
+}
+
+fn write_element(buffer: &mut Vec<u8>, id: u32, data: &[u8]) {
+    write_id(buffer, id);
+    write_size(buffer, data.len());
+    buffer.extend_from_slice(data);
+}
+
+fn write_uint(buffer: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let leading_zeros = (value.leading_zeros() / 8) as usize;
+    write_element(buffer, id, &bytes[leading_zeros.min(7)..]);
+}
+
+fn write_master(buffer: &mut Vec<u8>, id: u32, fill: impl FnOnce(&mut Vec<u8>)) {
+    let mut content = vec![];
+    fill(&mut content);
+    write_element(buffer, id, &content);
+}
+
+// Returns the NAL units without start codes
+fn split_annex_b(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = vec![];
+    let mut idx = 0;
+    while idx + 3 <= data.len() {
+        if data[idx..idx + 3] == [0, 0, 1] {
+            starts.push(idx + 3);
+            idx += 3;
+        } else {
+            idx += 1;
+        }
+    }
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            let mut end = starts.get(i + 1).map(|next| next - 3).unwrap_or(data.len());
+            // Trailing zero of a 4 byte start code
+            while end > start && data[end - 1] == 0 {
+                end -= 1;
+            }
+
+            &data[start..end]
+        })
+        .filter(|nal| !nal.is_empty())
+        .collect()
+}
+
+fn to_length_prefixed(data: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(data.len() + 16);
+    for nal in split_annex_b(data) {
+        buffer.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+        buffer.extend_from_slice(nal);
+    }
+
+    buffer
+}
+
+fn remove_emulation_prevention(nal: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &byte in nal {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+
+    rbsp
+}
+
+fn push_nal_with_length(buffer: &mut Vec<u8>, nal: &[u8]) {
+    buffer.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(nal);
+}
+
+// AVCDecoderConfigurationRecord
+fn avc_configuration(config_buffer: &[u8]) -> Result<Vec<u8>> {
+    let nals = split_annex_b(config_buffer);
+    let find_nal = |nal_type| nals.iter().find(|nal| nal[0] & 0x1F == nal_type);
+    let (Some(sps), Some(pps)) = (find_nal(7), find_nal(8)) else {
+        bail!("Missing SPS or PPS");
+    };
+    if sps.len() < 4 {
+        bail!("Invalid SPS");
+    }
+
+    let mut record = vec![1, sps[1], sps[2], sps[3], 0xFF, 0xE1];
+    push_nal_with_length(&mut record, sps);
+    record.push(1);
+    push_nal_with_length(&mut record, pps);
+
+    Ok(record)
+}
+
+// HEVCDecoderConfigurationRecord. The profile, tier and level are copied from the SPS, the other
+// fields assume 4:2:0 chroma and no temporal layers.
+fn hevc_configuration(config_buffer: &[u8]) -> Result<Vec<u8>> {
+    let nals = split_annex_b(config_buffer);
+    let find_nal = |nal_type| nals.iter().find(|nal| (nal[0] >> 1) & 0x3F == nal_type);
+    let (Some(vps), Some(sps), Some(pps)) = (find_nal(32), find_nal(33), find_nal(34)) else {
+        bail!("Missing VPS, SPS or PPS");
+    };
+
+    // 2 bytes of NAL header, 1 byte of SPS fields, then the 12 bytes of the general profile
+    let sps_rbsp = remove_emulation_prevention(sps);
+    if sps_rbsp.len() < 15 {
+        bail!("Invalid SPS");
+    }
+    let profile_tier_level = &sps_rbsp[3..15];
+    let bit_depth_minus_8 = if profile_tier_level[0] & 0x1F == 2 {
+        2 // Main 10
+    } else {
+        0
+    };
+
+    let mut record = vec![1];
+    record.extend_from_slice(profile_tier_level);
+    record.extend_from_slice(&[
+        0xF0,
+        0x00, // min_spatial_segmentation_idc
+        0xFC, // parallelism type
+        0xFD, // 4:2:0
+        0xF8 | bit_depth_minus_8,
+        0xF8 | bit_depth_minus_8,
+        0x00,
+        0x00, // average frame rate
+        0x0F, // 1 temporal layer, temporal ID nested, 4 bytes NAL length
+        3,    // number of arrays
+    ]);
+    for (nal_type, nal) in [(32, vps), (33, sps), (34, pps)] {
+        record.push(0x80 | nal_type);
+        record.extend_from_slice(&1_u16.to_be_bytes());
+        push_nal_with_length(&mut record, nal);
+    }
+
+    Ok(record)
+}
+
+// AV1CodecConfigurationRecord. Only the profile is parsed from the sequence header, decoders read
+// the rest from the sequence header OBU itself.
+fn av1_configuration(config_buffer: &[u8]) -> Result<Vec<u8>> {
+    let Some(&obu_header) = config_buffer.first() else {
+        bail!("Missing sequence header");
+    };
+    if (obu_header >> 3) & 0xF != 1 {
+        bail!("Missing sequence header");
+    }
+
+    let mut offset = if obu_header & 0x04 != 0 { 2 } else { 1 };
+    if obu_header & 0x02 != 0 {
+        // leb128 size
+        while config_buffer
+            .get(offset)
+            .is_some_and(|byte| byte & 0x80 != 0)
+        {
+            offset += 1;
+        }
+        offset += 1;
+    }
+    let Some(&first_byte) = config_buffer.get(offset) else {
+        bail!("Invalid sequence header");
+    };
+    let seq_profile = first_byte >> 5;
+
+    // Level 31 means unspecified
+    let mut record = vec![0x81, (seq_profile << 5) | 31, 0x0C, 0];
+    record.extend_from_slice(config_buffer);
+
+    Ok(record)
+}
+
+pub struct VideoTrackConfig {
+    pub decoder_config: DecoderInitializationConfig,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub struct MatroskaWriter<W: Write> {
+    writer: W,
+    codec: CodecType,
+    cluster_timestamp_ms: Option<u64>,
+    audio_sample_rate: Option<u32>,
+    audio_frames_written: u64,
+}
+
+impl<W: Write> MatroskaWriter<W> {
+    pub fn new(
+        mut writer: W,
+        video: &VideoTrackConfig,
+        audio_sample_rate: Option<u32>,
+    ) -> Result<Self> {
+        let codec = video.decoder_config.codec;
+        let (codec_id, codec_private) = match codec {
+            CodecType::H264 => (
+                "V_MPEG4/ISO/AVC",
+                avc_configuration(&video.decoder_config.config_buffer)?,
+            ),
+            CodecType::Hevc => (
+                "V_MPEGH/ISO/HEVC",
+                hevc_configuration(&video.decoder_config.config_buffer)?,
+            ),
+            CodecType::AV1 => (
+                "V_AV1",
+                av1_configuration(&video.decoder_config.config_buffer)?,
+            ),
+        };
+
+        let mut buffer = vec![];
+        write_master(&mut buffer, EBML, |b| {
+            write_uint(b, EBML_VERSION, 1);
+            write_uint(b, EBML_READ_VERSION, 1);
+            write_uint(b, EBML_MAX_ID_LENGTH, 4);
+            write_uint(b, EBML_MAX_SIZE_LENGTH, 8);
+            write_element(b, DOC_TYPE, b"matroska");
+            write_uint(b, DOC_TYPE_VERSION, 4);
+            write_uint(b, DOC_TYPE_READ_VERSION, 2);
+        });
+
+        write_id(&mut buffer, SEGMENT);
+        buffer.extend_from_slice(&UNKNOWN_SIZE);
+
+        write_master(&mut buffer, INFO, |b| {
+            write_uint(b, TIMESTAMP_SCALE, 1_000_000); // milliseconds
+            write_element(b, MUXING_APP, b"ALVR");
+            write_element(b, WRITING_APP, b"ALVR");
+        });
+
+        write_master(&mut buffer, TRACKS, |b| {
+            write_master(b, TRACK_ENTRY, |b| {
+                write_uint(b, TRACK_NUMBER, VIDEO_TRACK_NUMBER as _);
+                write_uint(b, TRACK_UID, VIDEO_TRACK_NUMBER as _);
+                write_uint(b, TRACK_TYPE, 1);
+                write_element(b, CODEC_ID, codec_id.as_bytes());
+                write_element(b, CODEC_PRIVATE, &codec_private);
+                write_master(b, VIDEO, |b| {
+                    write_uint(b, PIXEL_WIDTH, video.width as _);
+                    write_uint(b, PIXEL_HEIGHT, video.height as _);
+                });
+            });
+
+            if let Some(sample_rate) = audio_sample_rate {
+                write_master(b, TRACK_ENTRY, |b| {
+                    write_uint(b, TRACK_NUMBER, AUDIO_TRACK_NUMBER as _);
+                    write_uint(b, TRACK_UID, AUDIO_TRACK_NUMBER as _);
+                    write_uint(b, TRACK_TYPE, 2);
+                    write_element(b, CODEC_ID, b"A_PCM/INT/LIT");
+                    write_master(b, AUDIO, |b| {
+                        write_element(b, SAMPLING_FREQUENCY, &(sample_rate as f64).to_be_bytes());
+                        write_uint(b, CHANNELS, AUDIO_CHANNELS);
+                        write_uint(b, BIT_DEPTH, 16);
+                    });
+                });
+            }
+        });
+
+        writer.write_all(&buffer)?;
+
+        Ok(Self {
+            writer,
+            codec,
+            cluster_timestamp_ms: None,
+            audio_sample_rate,
+            audio_frames_written: 0,
+        })
+    }
+
+    fn write_block(
+        &mut self,
+        track_number: u8,
+        timestamp: Duration,
+        data: &[u8],
+        is_keyframe: bool,
+    ) -> Result<()> {
+        let timestamp_ms = timestamp.as_millis() as u64;
+
+        let mut buffer = vec![];
+
+        let cluster_timestamp_ms = match self.cluster_timestamp_ms {
+            Some(cluster_timestamp_ms)
+                if !(track_number == VIDEO_TRACK_NUMBER && is_keyframe)
+                    && timestamp_ms.abs_diff(cluster_timestamp_ms) < MAX_CLUSTER_DURATION_MS =>
+            {
+                cluster_timestamp_ms
+            }
+            _ => {
+                write_id(&mut buffer, CLUSTER);
+                buffer.extend_from_slice(&UNKNOWN_SIZE);
+                write_uint(&mut buffer, TIMESTAMP, timestamp_ms);
+
+                *self.cluster_timestamp_ms.insert(timestamp_ms)
+            }
+        };
+
+        let relative_timestamp = (timestamp_ms as i64 - cluster_timestamp_ms as i64) as i16;
+
+        write_id(&mut buffer, SIMPLE_BLOCK);
+        write_size(&mut buffer, 4 + data.len());
+        buffer.push(0x80 | track_number);
+        buffer.extend_from_slice(&relative_timestamp.to_be_bytes());
+        buffer.push(if is_keyframe { 0x80 } else { 0 });
+
+        self.writer.write_all(&buffer)?;
+        self.writer.write_all(data)?;
+
+        Ok(())
+    }
+
+    pub fn write_video(&mut self, timestamp: Duration, data: &[u8], is_idr: bool) -> Result<()> {
+        if self.codec == CodecType::AV1 {
+            self.write_block(VIDEO_TRACK_NUMBER, timestamp, data, is_idr)
+        } else {
+            self.write_block(
+                VIDEO_TRACK_NUMBER,
+                timestamp,
+                &to_length_prefixed(data),
+                is_idr,
+            )
+        }
+    }
+
+    // Interleaved 16 bit samples. Blocks are timestamped by the number of samples written so far,
+    // so the audio does not drift with the delivery jitter of the packets
+    pub fn write_audio(&mut self, samples: &[u8]) -> Result<()> {
+        let Some(sample_rate) = self.audio_sample_rate else {
+            bail!("The recording has no audio track");
+        };
+
+        let timestamp = Duration::from_nanos(
+            self.audio_frames_written * 1_000_000_000 / sample_rate.max(1) as u64,
+        );
+        self.audio_frames_written += (samples.len() / AUDIO_FRAME_SIZE) as u64;
+
+        self.write_block(AUDIO_TRACK_NUMBER, timestamp, samples, true)
+    }
+}
+
+pub struct MatroskaRecording {
+    writer: MatroskaWriter<BufWriter<File>>,
+    // Video timestamp of the first frame
+    timestamp_origin: Duration,
+    has_audio: bool,
+}
+
+pub enum VideoRecording {
+    // Raw elementary stream
+    AnnexB(File),
+    Matroska {
+        // The file stays pending until the first IDR frame
+        pending_file: Option<File>,
+        record_game_audio: bool,
+        recording: Option<MatroskaRecording>,
+    },
+}
+
+impl VideoRecording {
+    pub fn new_matroska(file: File, record_game_audio: bool) -> Self {
+        Self::Matroska {
+            pending_file: Some(file),
+            record_game_audio,
+            recording: None,
+        }
+    }
+
+    pub fn write_config(&mut self, config_buffer: &[u8]) {
+        // Matroska takes the configuration from the decoder config when writing the header
+        if let Self::AnnexB(file) = self {
+            file.write_all(config_buffer).ok();
+        }
+    }
+
+    // Returns false if the recording failed and should be stopped
+    pub fn write_video(
+        &mut self,
+        create_track_config: impl FnOnce() -> Option<(VideoTrackConfig, Option<u32>)>,
+        timestamp: Duration,
+        nal_buffer: &[u8],
+        is_idr: bool,
+    ) -> bool {
+        match self {
+            Self::AnnexB(file) => {
+                file.write_all(nal_buffer).ok();
+
+                true
+            }
+            Self::Matroska {
+                pending_file,
+                record_game_audio,
+                recording,
+            } => {
+                if recording.is_none() {
+                    if !is_idr {
+                        return true;
+                    }
+                    let Some((video_config, audio_sample_rate)) = create_track_config() else {
+                        return true;
+                    };
+                    let audio_sample_rate = audio_sample_rate.filter(|_| *record_game_audio);
+
+                    let Some(file) = pending_file.take() else {
+                        return false;
+                    };
+                    match MatroskaWriter::new(
+                        BufWriter::new(file),
+                        &video_config,
+                        audio_sample_rate,
+                    ) {
+                        Ok(writer) => {
+                            *recording = Some(MatroskaRecording {
+                                writer,
+                                timestamp_origin: timestamp,
+                                has_audio: audio_sample_rate.is_some(),
+                            })
+                        }
+                        Err(e) => {
+                            error!("Failed to start video recording: {e}");
+                            return false;
+                        }
+                    }
+                }
+
+                let Some(recording) = recording else {
+                    return false;
+                };
+
+                let timestamp = timestamp.saturating_sub(recording.timestamp_origin);
+                if let Err(e) = recording.writer.write_video(timestamp, nal_buffer, is_idr) {
+                    error!("Failed to write video recording: {e}");
+                    return false;
+                }
+
+                true
+            }
+        }
+    }
+
+    pub fn write_game_audio(&mut self, samples: &[u8]) {
+        if let Self::Matroska {
+            recording:
+                Some(MatroskaRecording {
+                    writer,
+                    has_audio: true,
+                    ..
+                }),
+            ..
+        } = self
+        {
+            writer.write_audio(samples).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size_bytes(size: usize) -> Vec<u8> {
+        let mut buffer = vec![];
+        write_size(&mut buffer, size);
+
+        buffer
+    }
+
+    // Returns the value and the length of a variable size integer. IDs keep the length marker
+    fn read_vint(data: &[u8], keep_marker: bool) -> (u64, usize) {
+        let length = data[0].leading_zeros() as usize + 1;
+        let mut value = if keep_marker {
+            data[0] as u64
+        } else {
+            data[0] as u64 & (0xFF >> length)
+        };
+        for &byte in &data[1..length] {
+            value = (value << 8) | byte as u64;
+        }
+
+        (value, length)
+    }
+
+    // Reads one level of elements. Elements of unknown size have no content, their children
+    // follow them in the same level
+    fn read_elements(mut data: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut elements = vec![];
+        while !data.is_empty() {
+            let (id, id_length) = read_vint(data, true);
+            let (size, size_length) = read_vint(&data[id_length..], false);
+            let header_length = id_length + size_length;
+            let size = if data[id_length..header_length] == UNKNOWN_SIZE {
+                0
+            } else {
+                size as usize
+            };
+
+            elements.push((id as u32, &data[header_length..header_length + size]));
+            data = &data[header_length + size..];
+        }
+
+        elements
+    }
+
+    fn find_element(elements: &[(u32, &[u8])], id: u32) -> Vec<u8> {
+        elements
+            .iter()
+            .find(|(element_id, _)| *element_id == id)
+            .unwrap()
+            .1
+            .to_vec()
+    }
+
+    // Returns the track number, relative timestamp, flags and data of a SimpleBlock
+    fn read_block(content: &[u8]) -> (u8, i16, u8, &[u8]) {
+        (
+            content[0] & 0x7F,
+            i16::from_be_bytes([content[1], content[2]]),
+            content[3],
+            &content[4..],
+        )
+    }
+
+    #[test]
+    fn size_vint_boundaries() {
+        assert_eq!(size_bytes(0), [0x01, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(size_bytes(0x7F), [0x01, 0, 0, 0, 0, 0, 0, 0x7F]);
+        assert_eq!(size_bytes(0x80), [0x01, 0, 0, 0, 0, 0, 0, 0x80]);
+        assert_eq!(size_bytes(0xFFFF), [0x01, 0, 0, 0, 0, 0, 0xFF, 0xFF]);
+
+        // All value bits set is reserved for the unknown size
+        let largest = size_bytes((1 << 56) - 2);
+        assert_eq!(largest, [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE]);
+        assert_ne!(largest, UNKNOWN_SIZE);
+
+        for size in [0, 0x7F, 0x80, 0xFFFF, (1 << 56) - 2] {
+            assert_eq!(read_vint(&size_bytes(size), false), (size as u64, 8));
+        }
+    }
+
+    #[test]
+    fn ids_and_uints() {
+        let mut buffer = vec![];
+        for id in [TRACK_ENTRY, EBML_VERSION, TIMESTAMP_SCALE, EBML] {
+            write_id(&mut buffer, id);
+        }
+        assert_eq!(
+            buffer,
+            [0xAE, 0x42, 0x86, 0x2A, 0xD7, 0xB1, 0x1A, 0x45, 0xDF, 0xA3]
+        );
+
+        for (value, bytes) in [
+            (0, &[0][..]),
+            (0xFF, &[0xFF][..]),
+            (0x100, &[1, 0][..]),
+            (u64::MAX, &[0xFF; 8][..]),
+        ] {
+            let mut buffer = vec![];
+            write_uint(&mut buffer, TRACK_NUMBER, value);
+            assert_eq!(read_elements(&buffer), [(TRACK_NUMBER, bytes)]);
+        }
+    }
+
+    #[test]
+    fn annex_b_start_codes() {
+        let data = [
+            0, 0, 0, 1, 0x67, 1, 2, // 4 byte start code
+            0, 0, 1, 0x68, 3, // 3 byte start code
+            0, 0, 0, 1, 0x65, 4, 0, 5, // zero inside the NAL
+        ];
+
+        assert_eq!(
+            split_annex_b(&data),
+            [&[0x67, 1, 2][..], &[0x68, 3][..], &[0x65, 4, 0, 5][..]]
+        );
+        assert_eq!(
+            to_length_prefixed(&data),
+            [0, 0, 0, 3, 0x67, 1, 2, 0, 0, 0, 2, 0x68, 3, 0, 0, 0, 4, 0x65, 4, 0, 5]
+        );
+
+        assert!(split_annex_b(&[1, 2, 3]).is_empty());
+    }
+
+    #[test]
+    fn header_and_block_layout() {
+        let sps = [0x67, 0x64, 0x00, 0x1F, 0xAC];
+        let pps = [0x68, 0xEE, 0x3C, 0x80];
+        let mut config_buffer = vec![0, 0, 0, 1];
+        config_buffer.extend_from_slice(&sps);
+        config_buffer.extend_from_slice(&[0, 0, 1]);
+        config_buffer.extend_from_slice(&pps);
+
+        let mut writer = MatroskaWriter::new(
+            vec![],
+            &VideoTrackConfig {
+                decoder_config: DecoderInitializationConfig {
+                    codec: CodecType::H264,
+                    config_buffer,
+                },
+                width: 1920,
+                height: 1080,
+            },
+            Some(48_000),
+        )
+        .unwrap();
+
+        // 10ms of audio at 48kHz
+        let audio_buffer = vec![7; 480 * AUDIO_FRAME_SIZE];
+        writer
+            .write_video(Duration::ZERO, &[0, 0, 0, 1, 0x65, 0xAA], true)
+            .unwrap();
+        writer.write_audio(&audio_buffer).unwrap();
+        writer.write_audio(&[1, 2, 3, 4]).unwrap();
+        writer
+            .write_video(Duration::from_millis(16), &[0, 0, 1, 0x41, 0xBB], false)
+            .unwrap();
+        writer
+            .write_video(Duration::from_millis(33), &[0, 0, 0, 1, 0x65, 0xCC], true)
+            .unwrap();
+        writer.write_audio(&[5, 6, 7, 8]).unwrap();
+
+        let elements = read_elements(&writer.writer);
+        let ids = elements.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            [
+                EBML,
+                SEGMENT,
+                INFO,
+                TRACKS,
+                CLUSTER,
+                TIMESTAMP,
+                SIMPLE_BLOCK,
+                SIMPLE_BLOCK,
+                SIMPLE_BLOCK,
+                SIMPLE_BLOCK,
+                CLUSTER,
+                TIMESTAMP,
+                SIMPLE_BLOCK,
+                SIMPLE_BLOCK,
+            ]
+        );
+
+        let header = read_elements(elements[0].1);
+        assert_eq!(find_element(&header, DOC_TYPE), b"matroska");
+
+        let info = read_elements(elements[2].1);
+        assert_eq!(
+            find_element(&info, TIMESTAMP_SCALE),
+            1_000_000_u32.to_be_bytes()[1..]
+        );
+
+        let tracks = read_elements(elements[3].1);
+        assert_eq!(tracks.len(), 2);
+        let video_track = read_elements(tracks[0].1);
+        assert_eq!(find_element(&video_track, TRACK_NUMBER), [1]);
+        assert_eq!(find_element(&video_track, CODEC_ID), b"V_MPEG4/ISO/AVC");
+        let mut avc_record = vec![1, 0x64, 0x00, 0x1F, 0xFF, 0xE1, 0, 5];
+        avc_record.extend_from_slice(&sps);
+        avc_record.extend_from_slice(&[1, 0, 4]);
+        avc_record.extend_from_slice(&pps);
+        assert_eq!(find_element(&video_track, CODEC_PRIVATE), avc_record);
+        let audio_track = read_elements(tracks[1].1);
+        assert_eq!(find_element(&audio_track, TRACK_NUMBER), [2]);
+        assert_eq!(find_element(&audio_track, CODEC_ID), b"A_PCM/INT/LIT");
+
+        assert_eq!(elements[5].1, [0]);
+        assert_eq!(
+            read_block(elements[6].1),
+            (1, 0, 0x80, &[0, 0, 0, 2, 0x65, 0xAA][..])
+        );
+        assert_eq!(read_block(elements[7].1), (2, 0, 0x80, &audio_buffer[..]));
+        // The second audio block starts after the 480 samples of the first one
+        assert_eq!(read_block(elements[8].1), (2, 10, 0x80, &[1, 2, 3, 4][..]));
+        assert_eq!(
+            read_block(elements[9].1),
+            (1, 16, 0, &[0, 0, 0, 2, 0x41, 0xBB][..])
+        );
+
+        // IDR frames start a new cluster, late audio is written with a negative offset
+        assert_eq!(elements[11].1, [33]);
+        assert_eq!(
+            read_block(elements[12].1),
+            (1, 0, 0x80, &[0, 0, 0, 2, 0x65, 0xCC][..])
+        );
+        assert_eq!(
+            read_block(elements[13].1),
+            (2, -23, 0x80, &[5, 6, 7, 8][..])
+        );
+    }
+
+    #[test]
+    fn audio_without_track() {
+        let mut writer = MatroskaWriter::new(
+            vec![],
+            &VideoTrackConfig {
+                decoder_config: DecoderInitializationConfig {
+                    codec: CodecType::AV1,
+                    config_buffer: vec![0x0A, 0x01, 0x00],
+                },
+                width: 1920,
+                height: 1080,
+            },
+            None,
+        )
+        .unwrap();
+
+        assert!(writer.write_audio(&[1, 2, 3, 4]).is_err());
+    }
+}
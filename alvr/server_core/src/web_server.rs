@@ -1,10 +1,10 @@
 use crate::{
-    logging_backend::LOGGING_EVENTS_SENDER, ConnectionContext, ServerCoreEvent, FILESYSTEM_LAYOUT,
-    SESSION_MANAGER,
+    hand_poses, logging_backend::LOGGING_EVENTS_SENDER, tracking::HandType, ConnectionContext,
+    ServerCoreEvent, FILESYSTEM_LAYOUT, SESSION_MANAGER,
 };
 use alvr_common::{
     anyhow::{self, Result},
-    error, info, log, ConnectionState,
+    error, info, log, warn, ConnectionState,
 };
 use alvr_events::{ButtonEvent, EventType};
 use alvr_packets::{ButtonEntry, ClientListAction, ServerRequest};
@@ -152,7 +152,33 @@ async fn http_api(
                         crate::SESSION_MANAGER.read().settings(),
                     ),
                     ServerRequest::StopRecording => {
-                        *connection_context.video_recording_file.lock() = None
+                        *connection_context.video_recording.lock() = None;
+                        *connection_context.session_recording.lock() = None;
+                    }
+                    ServerRequest::RecordHandPose { left_hand } => {
+                        let hand_type = if left_hand {
+                            HandType::Left
+                        } else {
+                            HandType::Right
+                        };
+                        let skeleton = connection_context
+                            .tracking_manager
+                            .read()
+                            .get_latest_hand_skeleton(hand_type);
+
+                        if let Some(skeleton) = skeleton {
+                            hand_poses::record_hand_pose_template(&skeleton, left_hand);
+                        } else {
+                            warn!("Cannot record the hand pose, the {hand_type:?} hand is not tracked");
+                        }
+                    }
+                    ServerRequest::StartControllerCalibration => {
+                        info!("Controller calibration started. Hold the controllers while keeping the hands visible");
+
+                        connection_context
+                            .tracking_manager
+                            .write()
+                            .start_controller_calibration();
                     }
                     ServerRequest::FirewallRules(action) => {
                         if alvr_server_io::firewall_rules(action).is_ok() {
@@ -214,6 +214,8 @@ impl ServerSessionManager {
                         trusted,
                         connection_state: ConnectionState::Disconnected,
                         cabled: false,
+                        pairing_pin: None,
+                        pairing_key: None,
                     };
                     new_entry.insert(client_connection_desc);
 
@@ -234,6 +236,22 @@ impl ServerSessionManager {
                     updated = true;
                 }
             }
+            ClientListAction::SetPairingPin(pin) => {
+                if let Entry::Occupied(mut entry) = maybe_client_entry {
+                    entry.get_mut().pairing_pin = pin;
+
+                    updated = true;
+                }
+            }
+            ClientListAction::SetPairingKey(key) => {
+                if let Entry::Occupied(mut entry) = maybe_client_entry {
+                    // The PIN can be used only once
+                    entry.get_mut().pairing_pin = None;
+                    entry.get_mut().pairing_key = key;
+
+                    updated = true;
+                }
+            }
             ClientListAction::SetManualIps(ips) => {
                 if let Entry::Occupied(mut entry) = maybe_client_entry {
                     entry.get_mut().manual_ips = ips.into_iter().collect();
@@ -99,6 +99,32 @@ void _SetChaperoneArea(float areaWidth, float areaHeight) {
 #endif
 }
 
+// Returns false if no scene application is running
+bool _GetRunningApplicationKey(char* outKey, unsigned int keyBufferSize) {
+#ifndef __APPLE__
+    std::unique_lock<std::mutex> lock(chaperone_mutex);
+
+    if (!isOpenvrInit) {
+        return false;
+    }
+
+    auto applications = vr::VRApplications();
+    if (applications == nullptr) {
+        return false;
+    }
+
+    auto processId = applications->GetCurrentSceneProcessId();
+    if (processId == 0) {
+        return false;
+    }
+
+    return applications->GetApplicationKeyByProcessId(processId, outKey, keyBufferSize)
+        == vr::VRApplicationError_None;
+#else
+    return false;
+#endif
+}
+
 #ifdef __linux__
 std::unique_ptr<vr::HmdMatrix34_t> GetInvZeroPose() {
     Debug("GetInvZeroPose");
@@ -38,6 +38,7 @@ std::unique_ptr<vr::HmdMatrix34_t> GetRawZeroPose() {
 bool IsOpenvrClientReady();
 #endif
 void _SetChaperoneArea(float areaWidth, float areaHeight);
+bool _GetRunningApplicationKey(char* outKey, unsigned int keyBufferSize);
 
 vr::EVREventType VendorEvent_ALVRDriverResync
     = (vr::EVREventType)(vr::VREvent_VendorSpecific_Reserved_Start + ((vr::EVREventType)0xC0));
@@ -285,6 +286,7 @@ unsigned long long (*PathStringToHash)(const char* path);
 void (*ReportPresent)(unsigned long long timestamp_ns, unsigned long long offset_ns);
 void (*ReportComposed)(unsigned long long timestamp_ns, unsigned long long offset_ns);
 FfiDynamicEncoderParams (*GetDynamicEncoderParams)();
+FfiFoveationCenterShift (*GetFoveationCenterShift)(unsigned long long targetTimestampNs);
 unsigned long long (*GetSerialNumber)(unsigned long long deviceID, char* outString);
 void (*SetOpenvrProps)(unsigned long long deviceID);
 void (*RegisterButtons)(unsigned long long deviceID);
@@ -469,6 +471,10 @@ void SetChaperoneArea(float areaWidth, float areaHeight) {
     _SetChaperoneArea(areaWidth, areaHeight);
 }
 
+bool GetRunningApplicationKey(char* outKey, unsigned int keyBufferSize) {
+    return _GetRunningApplicationKey(outKey, keyBufferSize);
+}
+
 void CaptureFrame() {
 #ifndef __APPLE__
     if (g_driver_provider.hmd && g_driver_provider.hmd->m_encoder) {
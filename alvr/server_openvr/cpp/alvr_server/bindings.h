@@ -83,6 +83,12 @@ struct FfiDynamicEncoderParams {
     float framerate;
 };
 
+struct FfiFoveationCenterShift {
+    unsigned int enabled;
+    float left[2];
+    float right[2];
+};
+
 extern "C" const unsigned char* FRAME_RENDER_VS_CSO_PTR;
 extern "C" unsigned int FRAME_RENDER_VS_CSO_LEN;
 extern "C" const unsigned char* FRAME_RENDER_PS_CSO_PTR;
@@ -127,6 +133,7 @@ extern "C" unsigned long long (*PathStringToHash)(const char* path);
 extern "C" void (*ReportPresent)(unsigned long long timestamp_ns, unsigned long long offset_ns);
 extern "C" void (*ReportComposed)(unsigned long long timestamp_ns, unsigned long long offset_ns);
 extern "C" FfiDynamicEncoderParams (*GetDynamicEncoderParams)();
+extern "C" FfiFoveationCenterShift (*GetFoveationCenterShift)(unsigned long long targetTimestampNs);
 extern "C" unsigned long long (*GetSerialNumber)(unsigned long long deviceID, char* outString);
 extern "C" void (*SetOpenvrProps)(unsigned long long deviceID);
 extern "C" void (*RegisterButtons)(unsigned long long deviceID);
@@ -160,6 +167,7 @@ extern "C" void SetButton(unsigned long long buttonID, FfiButtonValue value);
 extern "C" void InitOpenvrClient();
 extern "C" void ShutdownOpenvrClient();
 extern "C" void SetChaperoneArea(float areaWidth, float areaHeight);
+extern "C" bool GetRunningApplicationKey(char* outKey, unsigned int keyBufferSize);
 
 extern "C" void CaptureFrame();
 
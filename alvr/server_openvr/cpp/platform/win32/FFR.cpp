@@ -9,22 +9,15 @@ using namespace d3d_render_utils;
 
 namespace {
 
-struct FoveationVars {
-    uint32_t targetEyeWidth;
-    uint32_t targetEyeHeight;
-    uint32_t optimizedEyeWidth;
-    uint32_t optimizedEyeHeight;
-
-    float eyeWidthRatio;
-    float eyeHeightRatio;
-
-    float centerSizeX;
-    float centerSizeY;
-    float centerShiftX;
-    float centerShiftY;
-    float edgeRatioX;
-    float edgeRatioY;
-};
+// Shifts are aligned to whole blocks of the edge regions. The client aligns them in the same way
+float AlignCenterShift(
+    float centerShift, float targetEyeSize, float centerSizeAligned, float edgeRatio
+) {
+    float edgeSizeAligned = targetEyeSize - centerSizeAligned * targetEyeSize;
+
+    return ceil(centerShift * edgeSizeAligned / (edgeRatio * 2.)) * (edgeRatio * 2.)
+        / edgeSizeAligned;
+}
 
 FoveationVars CalculateFoveationVars() {
     float targetEyeWidth = (float)Settings::Instance().m_renderWidth / 2;
@@ -45,13 +38,10 @@ FoveationVars CalculateFoveationVars() {
     float centerSizeYAligned
         = 1. - ceil(edgeSizeY / (edgeRatioY * 2.)) * (edgeRatioY * 2.) / targetEyeHeight;
 
-    float edgeSizeXAligned = targetEyeWidth - centerSizeXAligned * targetEyeWidth;
-    float edgeSizeYAligned = targetEyeHeight - centerSizeYAligned * targetEyeHeight;
-
-    float centerShiftXAligned = ceil(centerShiftX * edgeSizeXAligned / (edgeRatioX * 2.))
-        * (edgeRatioX * 2.) / edgeSizeXAligned;
-    float centerShiftYAligned = ceil(centerShiftY * edgeSizeYAligned / (edgeRatioY * 2.))
-        * (edgeRatioY * 2.) / edgeSizeYAligned;
+    float centerShiftXAligned
+        = AlignCenterShift(centerShiftX, targetEyeWidth, centerSizeXAligned, edgeRatioX);
+    float centerShiftYAligned
+        = AlignCenterShift(centerShiftY, targetEyeHeight, centerSizeYAligned, edgeRatioY);
 
     float foveationScaleX = (centerSizeXAligned + (1. - centerSizeXAligned) / edgeRatioX);
     float foveationScaleY = (centerSizeYAligned + (1. - centerSizeYAligned) / edgeRatioY);
@@ -92,7 +82,6 @@ FFR::FFR(ID3D11Device* device)
 
 void FFR::Initialize(ID3D11Texture2D* compositionTexture) {
     auto fovVars = CalculateFoveationVars();
-    ComPtr<ID3D11Buffer> foveatedRenderingBuffer = CreateBuffer(mDevice.Get(), fovVars);
 
     std::vector<uint8_t> quadShaderCSO(
         QUAD_SHADER_CSO_PTR, QUAD_SHADER_CSO_PTR + QUAD_SHADER_CSO_LEN
@@ -112,25 +101,77 @@ void FFR::Initialize(ID3D11Texture2D* compositionTexture) {
             COMPRESS_AXIS_ALIGNED_CSO_PTR,
             COMPRESS_AXIS_ALIGNED_CSO_PTR + COMPRESS_AXIS_ALIGNED_CSO_LEN
         );
-        auto compressAxisAlignedPipeline = RenderPipeline(mDevice.Get());
-        compressAxisAlignedPipeline.Initialize(
-            { compositionTexture },
-            mQuadVertexShader.Get(),
-            compressAxisAlignedShaderCSO,
-            mOptimizedTexture.Get(),
-            foveatedRenderingBuffer.Get()
+
+        // The center shift can be different for each eye. Each eye is rendered with its own
+        // buffer, limited to its half of the texture
+        for (int eye = 0; eye < 2; eye++) {
+            mFoveationBuffers[eye] = CreateBuffer(mDevice.Get(), fovVars, D3D11_USAGE_DEFAULT);
+
+            auto compressAxisAlignedPipeline = RenderPipeline(mDevice.Get());
+            compressAxisAlignedPipeline.Initialize(
+                { compositionTexture },
+                mQuadVertexShader.Get(),
+                compressAxisAlignedShaderCSO,
+                mOptimizedTexture.Get(),
+                mFoveationBuffers[eye].Get()
+            );
+
+            mPipelines.push_back(compressAxisAlignedPipeline);
+
+            mEyeScissors[eye].left = eye * fovVars.optimizedEyeWidth;
+            mEyeScissors[eye].top = 0;
+            mEyeScissors[eye].right = (eye + 1) * fovVars.optimizedEyeWidth;
+            mEyeScissors[eye].bottom = fovVars.optimizedEyeHeight;
+        }
+
+        D3D11_RASTERIZER_DESC rasterizerDesc = {};
+        rasterizerDesc.FillMode = D3D11_FILL_SOLID;
+        rasterizerDesc.CullMode = D3D11_CULL_NONE;
+        rasterizerDesc.DepthClipEnable = TRUE;
+        rasterizerDesc.ScissorEnable = TRUE;
+        OK_OR_THROW(
+            mDevice->CreateRasterizerState(&rasterizerDesc, &mScissorRasterizerState),
+            "Failed to create rasterizer state."
         );
 
-        mPipelines.push_back(compressAxisAlignedPipeline);
+        mFoveationVars = fovVars;
     } else {
         mOptimizedTexture = compositionTexture;
     }
 }
 
-void FFR::Render() {
-    for (auto& p : mPipelines) {
-        p.Render();
+void FFR::Render(FfiFoveationCenterShift centerShift) {
+    if (mPipelines.empty()) {
+        return;
+    }
+
+    ComPtr<ID3D11DeviceContext> context;
+    mDevice->GetImmediateContext(&context);
+
+    // If not enabled, the center shift of the settings is used
+    const float* eyeShifts[2] = { centerShift.left, centerShift.right };
+    for (int eye = 0; eye < 2; eye++) {
+        auto fovVars = mFoveationVars;
+        if (centerShift.enabled) {
+            fovVars.centerShiftX = AlignCenterShift(
+                eyeShifts[eye][0], fovVars.targetEyeWidth, fovVars.centerSizeX, fovVars.edgeRatioX
+            );
+            fovVars.centerShiftY = AlignCenterShift(
+                eyeShifts[eye][1],
+                fovVars.targetEyeHeight,
+                fovVars.centerSizeY,
+                fovVars.edgeRatioY
+            );
+        }
+        UpdateBuffer(context.Get(), mFoveationBuffers[eye].Get(), &fovVars);
+    }
+
+    context->RSSetState(mScissorRasterizerState.Get());
+    for (int eye = 0; eye < 2; eye++) {
+        context->RSSetScissorRects(1, &mEyeScissors[eye]);
+        mPipelines[eye].Render();
     }
+    context->RSSetState(nullptr);
 }
 
 ID3D11Texture2D* FFR::GetOutputTexture() { return mOptimizedTexture.Get(); }
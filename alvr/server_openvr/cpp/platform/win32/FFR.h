@@ -1,12 +1,31 @@
 #pragma once
 
+#include "alvr_server/bindings.h"
 #include "d3d-render-utils/RenderPipeline.h"
 
+// Layout of the FoveationVars constant buffer of the shaders
+struct FoveationVars {
+    uint32_t targetEyeWidth;
+    uint32_t targetEyeHeight;
+    uint32_t optimizedEyeWidth;
+    uint32_t optimizedEyeHeight;
+
+    float eyeWidthRatio;
+    float eyeHeightRatio;
+
+    float centerSizeX;
+    float centerSizeY;
+    float centerShiftX;
+    float centerShiftY;
+    float edgeRatioX;
+    float edgeRatioY;
+};
+
 class FFR {
 public:
     FFR(ID3D11Device* device);
     void Initialize(ID3D11Texture2D* compositionTexture);
-    void Render();
+    void Render(FfiFoveationCenterShift centerShift);
     void GetOptimizedResolution(uint32_t* width, uint32_t* height);
     ID3D11Texture2D* GetOutputTexture();
 
@@ -16,4 +35,10 @@ private:
     Microsoft::WRL::ComPtr<ID3D11VertexShader> mQuadVertexShader;
 
     std::vector<d3d_render_utils::RenderPipeline> mPipelines;
+
+    FoveationVars mFoveationVars;
+    // One for each eye
+    Microsoft::WRL::ComPtr<ID3D11Buffer> mFoveationBuffers[2];
+    D3D11_RECT mEyeScissors[2];
+    Microsoft::WRL::ComPtr<ID3D11RasterizerState> mScissorRasterizerState;
 };
@@ -27,13 +27,17 @@ use alvr_packets::{ButtonValue, Haptics};
 use alvr_server_core::{HandType, ServerCoreContext, ServerCoreEvent};
 use alvr_session::{CodecType, ControllersConfig};
 use std::{
-    ffi::{c_char, c_void, CString},
+    ffi::{c_char, c_void, CStr, CString},
     ptr,
     sync::{mpsc, Once},
     thread,
     time::{Duration, Instant},
 };
 
+const RUNNING_APPLICATION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// vr::k_unMaxApplicationKeyLength
+const MAX_APPLICATION_KEY_LENGTH: usize = 128;
+
 static FILESYSTEM_LAYOUT: Lazy<afs::Layout> = Lazy::new(|| {
     afs::filesystem_layout_from_openvr_driver_root_dir(
         &alvr_server_io::get_driver_dir_from_registered().unwrap(),
@@ -64,7 +68,25 @@ extern "C" fn driver_ready_idle(set_default_chap: bool) {
         }
 
         let mut last_resync = Instant::now();
+        let mut last_application_poll = Instant::now();
         loop {
+            if last_application_poll.elapsed() > RUNNING_APPLICATION_POLL_INTERVAL {
+                last_application_poll = Instant::now();
+
+                let mut buffer = [0; MAX_APPLICATION_KEY_LENGTH];
+                let application_key =
+                    unsafe { GetRunningApplicationKey(buffer.as_mut_ptr(), buffer.len() as u32) }
+                        .then(|| {
+                            unsafe { CStr::from_ptr(buffer.as_ptr()) }
+                                .to_string_lossy()
+                                .into_owned()
+                        });
+
+                if let Some(context) = &*SERVER_CORE_CONTEXT.read() {
+                    context.report_running_application(application_key);
+                }
+            }
+
             let event = match events_receiver.recv_timeout(Duration::from_millis(5)) {
                 Ok(event) => event,
                 Err(mpsc::RecvTimeoutError::Timeout) => continue,
@@ -358,6 +380,20 @@ extern "C" fn get_dynamic_encoder_params() -> FfiDynamicEncoderParams {
     }
 }
 
+extern "C" fn get_foveation_center_shift(target_timestamp_ns: u64) -> FfiFoveationCenterShift {
+    if let Some([left, right]) = SERVER_CORE_CONTEXT.read().as_ref().and_then(|context| {
+        context.get_foveation_center_shift(Duration::from_nanos(target_timestamp_ns))
+    }) {
+        FfiFoveationCenterShift {
+            enabled: 1,
+            left: left.to_array(),
+            right: right.to_array(),
+        }
+    } else {
+        FfiFoveationCenterShift::default()
+    }
+}
+
 extern "C" fn report_composed(timestamp_ns: u64, offset_ns: u64) {
     if let Some(context) = &*SERVER_CORE_CONTEXT.read() {
         context.report_composed(
@@ -449,6 +485,7 @@ pub unsafe extern "C" fn HmdDriverFactory(
             SetVideoConfigNals = Some(set_video_config_nals);
             VideoSend = Some(send_video);
             GetDynamicEncoderParams = Some(get_dynamic_encoder_params);
+            GetFoveationCenterShift = Some(get_foveation_center_shift);
             ReportComposed = Some(report_composed);
             ReportPresent = Some(report_present);
             WaitForVSync = Some(wait_for_vsync);
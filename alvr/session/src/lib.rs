@@ -130,6 +130,12 @@ pub struct ClientConnectionConfig {
     pub trusted: bool,
     pub connection_state: ConnectionState,
     pub cabled: bool,
+    // PIN entered in the dashboard, used once during the next connection to pair the client
+    #[serde(default)]
+    pub pairing_pin: Option<String>,
+    // Hex encoded key obtained by pairing, used to authenticate and encrypt the connection
+    #[serde(default)]
+    pub pairing_key: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
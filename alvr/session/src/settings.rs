@@ -1,6 +1,6 @@
 use alvr_common::{
     DebugGroupsConfig, DebugGroupsConfigDefault, LogSeverity, LogSeverityDefault,
-    LogSeverityDefaultVariant,
+    LogSeverityDefaultVariant, BODY_HIPS_PATH,
 };
 use bytemuck::{Pod, Zeroable};
 use serde::{Deserialize, Serialize};
@@ -388,6 +388,82 @@ pub enum BitrateMode {
         #[schema(flag = "real-time")]
         decoder_latency_limiter: Switch<DecoderLatencyLimiter>,
     },
+
+    #[schema(strings(
+        help = "Similar to Google Congestion Control. The bitrate is reduced when the network latency starts rising and is increased otherwise"
+    ))]
+    #[schema(collapsible)]
+    DelayGradient {
+        #[schema(strings(display_name = "Initial bitrate"))]
+        #[schema(gui(slider(min = 1, max = 1000, logarithmic)), suffix = "Mbps")]
+        initial_bitrate_mbps: u64,
+
+        #[schema(strings(
+            display_name = "Latency gradient threshold",
+            help = "Growth rate of the network latency above which the network is considered congested"
+        ))]
+        #[schema(flag = "real-time")]
+        #[schema(gui(slider(min = 0.1, max = 20.0, step = 0.1)), suffix = "ms/s")]
+        latency_gradient_threshold_ms_per_s: f32,
+
+        #[schema(strings(
+            help = "Relative bitrate increase per second while the network is not congested"
+        ))]
+        #[schema(flag = "real-time")]
+        #[schema(gui(slider(min = 0.01, max = 0.5, step = 0.01)))]
+        increase_rate: f32,
+
+        #[schema(strings(help = "Multiplier applied to the measured throughput on congestion"))]
+        #[schema(flag = "real-time")]
+        #[schema(gui(slider(min = 0.5, max = 1.0, step = 0.01)))]
+        decrease_multiplier: f32,
+
+        #[schema(strings(display_name = "Maximum bitrate"))]
+        #[schema(flag = "real-time")]
+        #[schema(gui(slider(min = 1, max = 1000, logarithmic)), suffix = "Mbps")]
+        max_throughput_mbps: Switch<u64>,
+
+        #[schema(strings(display_name = "Minimum bitrate"))]
+        #[schema(flag = "real-time")]
+        #[schema(gui(slider(min = 1, max = 100, logarithmic)), suffix = "Mbps")]
+        min_throughput_mbps: Switch<u64>,
+    },
+
+    #[schema(strings(
+        help = "Additive increase, multiplicative decrease. The bitrate is reduced when video packets are lost"
+    ))]
+    #[schema(collapsible)]
+    LossBased {
+        #[schema(strings(display_name = "Initial bitrate"))]
+        #[schema(gui(slider(min = 1, max = 1000, logarithmic)), suffix = "Mbps")]
+        initial_bitrate_mbps: u64,
+
+        #[schema(strings(
+            help = "Fraction of lost video packets above which the bitrate is reduced"
+        ))]
+        #[schema(flag = "real-time")]
+        #[schema(gui(slider(min = 0.0, max = 0.2, step = 0.005)))]
+        loss_threshold: f32,
+
+        #[schema(strings(display_name = "Additive increase"))]
+        #[schema(flag = "real-time")]
+        #[schema(gui(slider(min = 0.5, max = 50.0, step = 0.5)), suffix = "Mbps/s")]
+        additive_increase_mbps_per_s: f32,
+
+        #[schema(flag = "real-time")]
+        #[schema(gui(slider(min = 0.5, max = 1.0, step = 0.01)))]
+        decrease_multiplier: f32,
+
+        #[schema(strings(display_name = "Maximum bitrate"))]
+        #[schema(flag = "real-time")]
+        #[schema(gui(slider(min = 1, max = 1000, logarithmic)), suffix = "Mbps")]
+        max_throughput_mbps: Switch<u64>,
+
+        #[schema(strings(display_name = "Minimum bitrate"))]
+        #[schema(flag = "real-time")]
+        #[schema(gui(slider(min = 1, max = 100, logarithmic)), suffix = "Mbps")]
+        min_throughput_mbps: Switch<u64>,
+    },
 }
 
 #[derive(SettingsSchema, Serialize, Deserialize, Clone, PartialEq)]
@@ -481,6 +557,12 @@ pub struct FoveatedEncodingConfig {
     #[schema(gui(slider(min = 1.0, max = 10.0, step = 1.0)))]
     #[schema(flag = "steamvr-restart")]
     pub edge_ratio_y: f32,
+
+    #[schema(strings(
+        help = "The center region follows the eye gaze. Eye tracking must be enabled in the face tracking settings. The center shift is used when the eyes are not tracked. Supported only on Windows"
+    ))]
+    #[schema(flag = "real-time")]
+    pub follow_eye_gaze: bool,
 }
 
 #[repr(C)]
@@ -693,12 +775,36 @@ pub struct FaceTrackingSourcesConfig {
     pub lip_expressions_htc: bool,
 }
 
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct BlendshapeCalibration {
+    #[schema(strings(
+        help = "The weight is multiplied by this value, after the curve is applied"
+    ))]
+    #[schema(gui(slider(min = 0.0, max = 3.0, step = 0.05)))]
+    pub gain: f32,
+    #[schema(strings(
+        help = "Exponent applied to the weight. Values below 1 make the shape more sensitive to small movements"
+    ))]
+    #[schema(gui(slider(min = 0.2, max = 3.0, step = 0.05)))]
+    pub curve: f32,
+}
+
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
 pub enum FaceTrackingSinkConfig {
     #[schema(strings(display_name = "VRChat Eye OSC"))]
     VrchatEyeOsc { port: u16 },
     #[schema(strings(display_name = "VRCFaceTracking"))]
     VrcFaceTracking,
+    #[schema(strings(
+        display_name = "ARKit blendshapes OSC",
+        help = "FB and HTC expressions are converted to the 52 ARKit blendshapes and sent as OSC float parameters, like /avatar/parameters/jawOpen"
+    ))]
+    ArkitBlendshapesOsc {
+        port: u16,
+        address_prefix: String,
+        #[schema(strings(help = "Calibration by ARKit blendshape name, like eyeBlinkLeft"))]
+        calibration: Vec<(String, BlendshapeCalibration)>,
+    },
 }
 
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
@@ -708,9 +814,50 @@ pub struct FaceTrackingConfig {
     pub sink: FaceTrackingSinkConfig,
 }
 
+#[derive(SettingsSchema, Serialize, Deserialize, Clone, PartialEq)]
+pub enum BodyTrackingReceiverProtocol {
+    #[schema(strings(
+        display_name = "VMC",
+        help = "Bone poses, with /VMC/Ext/Bone/Pos and /VMC/Ext/Root/Pos"
+    ))]
+    Vmc,
+    #[schema(strings(
+        display_name = "VRChat OSC",
+        help = "Tracker poses, with /tracking/trackers/<1-8 or head>/position and rotation"
+    ))]
+    VrchatOsc,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BodyTrackingReceiverConfig {
+    pub protocol: BodyTrackingReceiverProtocol,
+
+    #[schema(strings(help = "Local UDP port where the poses are received"))]
+    pub port: u16,
+
+    #[schema(strings(
+        help = "Moves the received poses horizontally so the received head is at the position of the headset"
+    ))]
+    #[schema(flag = "real-time")]
+    pub align_to_headset: bool,
+
+    #[schema(flag = "real-time")]
+    #[schema(gui(slider(min = -180.0, max = 180.0, step = 1.0)), suffix = "°")]
+    pub yaw_offset: f32,
+
+    #[schema(flag = "real-time")]
+    #[schema(gui(slider(min = -2.0, max = 2.0, step = 0.01)), suffix = "m")]
+    pub position_offset: [f32; 3],
+}
+
 #[derive(SettingsSchema, Serialize, Deserialize, Clone, PartialEq)]
 pub struct BodyTrackingSourcesConfig {
     pub body_tracking_fb: Switch<BodyTrackingFBConfig>,
+
+    #[schema(strings(
+        help = "Receives body tracker poses from other applications, like webcam pose estimators. The trackers sent by the headset have priority"
+    ))]
+    pub external_receiver: Switch<BodyTrackingReceiverConfig>,
     // todo:
     // pub detached_controllers_as_feet: bool,
     // unfortunately multimodal is incompatible with body tracking. To make this usable we need to
@@ -801,6 +948,11 @@ pub enum ButtonMappingType {
     HysteresisThreshold(HysteresisThreshold),
     BinaryToScalar(BinaryToScalarStates),
     Remap(Range),
+    #[schema(strings(
+        help = r#"Numbers, true/false, value (the source button), time (seconds), button(/path), ! - * / + - < <= > >= == != && || ?: and min, max, clamp, abs.
+toggle(x), long_press(x, seconds) and double_tap(x, seconds) detect combos. Chords can be written as button(/a) && button(/b)"#
+    ))]
+    Expression(String),
 }
 
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
@@ -810,6 +962,20 @@ pub struct ButtonBindingTarget {
     pub binary_conditions: Vec<String>,
 }
 
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+#[schema(collapsible)]
+pub struct ButtonMappingProfile {
+    pub name: String,
+
+    #[schema(strings(
+        help = "SteamVR application keys, for example steam.app.620980. The running application is shown in the logs"
+    ))]
+    pub applications: Vec<String>,
+
+    #[schema(strings(help = "List of OpenXR-syle paths"))]
+    pub button_mappings: Vec<(String, Vec<ButtonBindingTarget>)>,
+}
+
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
 #[schema(collapsible)]
 pub struct AutomaticButtonMappingConfig {
@@ -818,6 +984,102 @@ pub struct AutomaticButtonMappingConfig {
     pub force_threshold: f32,
 }
 
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct HandJointDistanceCondition {
+    #[schema(strings(
+        help = "Index of the joint in the OpenXR hand skeleton. 0: palm, 1: wrist, 5: thumb tip, 10: index tip, 15: middle tip, 20: ring tip, 25: little tip"
+    ))]
+    #[schema(gui(slider(min = 0, max = 25)))]
+    pub first_joint: usize,
+
+    #[schema(gui(slider(min = 0, max = 25)))]
+    pub second_joint: usize,
+
+    #[schema(gui(slider(min = 0.0, max = 30.0, step = 0.1)), suffix = "cm")]
+    pub min_distance: f32,
+
+    #[schema(gui(slider(min = 0.0, max = 30.0, step = 0.1)), suffix = "cm")]
+    pub max_distance: f32,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub enum HandGestureShape {
+    #[schema(strings(help = "The distance of every pair of joints must be inside the range"))]
+    JointDistances(Vec<HandJointDistanceCondition>),
+
+    #[schema(strings(
+        help = "Curl of each finger, from 0 (extended) to 1 (touching the palm). The curl distances of the hand tracking interaction are used"
+    ))]
+    FingerCurls {
+        #[schema(gui(slider(min = 0.0, max = 1.0, step = 0.05)))]
+        thumb: f32,
+        #[schema(gui(slider(min = 0.0, max = 1.0, step = 0.05)))]
+        index: f32,
+        #[schema(gui(slider(min = 0.0, max = 1.0, step = 0.05)))]
+        middle: f32,
+        #[schema(gui(slider(min = 0.0, max = 1.0, step = 0.05)))]
+        ring: f32,
+        #[schema(gui(slider(min = 0.0, max = 1.0, step = 0.05)))]
+        little: f32,
+        #[schema(strings(help = "Maximum difference of the curl of each finger"))]
+        #[schema(gui(slider(min = 0.0, max = 1.0, step = 0.05)))]
+        tolerance: f32,
+    },
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct CustomHandGestureConfig {
+    pub name: String,
+
+    pub shape: HandGestureShape,
+
+    #[schema(strings(
+        help = "Button pressed by the gesture of the left hand, for example /user/hand/left/input/menu/click"
+    ))]
+    pub left_hand_button: Option<String>,
+
+    #[schema(strings(
+        help = "Button pressed by the gesture of the right hand, for example /user/hand/right/input/a/click"
+    ))]
+    pub right_hand_button: Option<String>,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct HandPoseTemplate {
+    pub name: String,
+
+    #[schema(strings(
+        help = "Button pressed while the pose of the left hand is recognized. Scalar buttons receive the confidence"
+    ))]
+    pub left_hand_button: Option<String>,
+
+    #[schema(strings(
+        help = "Button pressed while the pose of the right hand is recognized. Scalar buttons receive the confidence"
+    ))]
+    pub right_hand_button: Option<String>,
+
+    // x, y, z of each joint, in the normalized space of a left hand
+    #[schema(flag = "hidden")]
+    pub joint_positions: Vec<f32>,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct HandPoseRecognitionConfig {
+    #[schema(strings(
+        help = "Average distance of the joints from the template at which the confidence drops to zero, relative to the size of the hand"
+    ))]
+    #[schema(gui(slider(min = 1.0, max = 50.0)), suffix = "%")]
+    pub max_joint_error: f32,
+
+    #[schema(gui(slider(min = 0.0, max = 1.0, step = 0.01)))]
+    pub min_confidence: f32,
+
+    #[schema(strings(
+        help = "Record new templates with the buttons in the debug tab while holding the pose"
+    ))]
+    pub templates: Vec<HandPoseTemplate>,
+}
+
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
 pub struct HandTrackingInteractionConfig {
     #[schema(flag = "real-time")]
@@ -890,6 +1152,12 @@ pub struct HandTrackingInteractionConfig {
     ))]
     #[schema(gui(slider(min = 0, max = 1000)), suffix = "ms")]
     pub repeat_delay: u32,
+
+    #[schema(flag = "real-time")]
+    #[schema(strings(
+        help = "Additional gestures, bound to any button. They use the same activation, deactivation and repeat delays"
+    ))]
+    pub custom_gestures: Vec<CustomHandGestureConfig>,
 }
 
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
@@ -907,6 +1175,62 @@ pub struct HapticsConfig {
     #[schema(flag = "real-time")]
     #[schema(gui(slider(min = 0.0, max = 0.1, step = 0.001)), suffix = "s")]
     pub min_duration_s: f32,
+
+    #[schema(strings(
+        display_name = "Attack",
+        help = "Time for the vibrations requested by the game to ramp up to their amplitude"
+    ))]
+    #[schema(flag = "real-time")]
+    #[schema(gui(slider(min = 0.0, max = 0.1, step = 0.001)), suffix = "s")]
+    pub attack_s: f32,
+
+    #[schema(strings(
+        display_name = "Decay",
+        help = "Time for the vibrations requested by the game to ramp down at their end"
+    ))]
+    #[schema(flag = "real-time")]
+    #[schema(gui(slider(min = 0.0, max = 0.1, step = 0.001)), suffix = "s")]
+    pub decay_s: f32,
+
+    #[schema(strings(help = "How overlapping vibrations on the same controller are combined"))]
+    #[schema(flag = "real-time")]
+    pub mixing_mode: HapticsMixingMode,
+
+    #[schema(strings(
+        help = "Vibrate the controllers with the bass of the game audio. Game audio must be enabled"
+    ))]
+    #[schema(flag = "real-time")]
+    pub audio_to_haptics: Switch<AudioToHapticsConfig>,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone, Copy)]
+pub enum HapticsMixingMode {
+    #[schema(strings(help = "The strongest vibration is used"))]
+    Maximum,
+    #[schema(strings(help = "The amplitudes are added, up to the maximum amplitude"))]
+    Sum,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct AudioToHapticsConfig {
+    #[schema(strings(help = "Only the audio below this frequency is used"))]
+    #[schema(flag = "real-time")]
+    #[schema(gui(slider(min = 20.0, max = 250.0, step = 5.0)), suffix = "Hz")]
+    pub cutoff_frequency: f32,
+
+    #[schema(strings(help = "Audio levels below this are ignored"))]
+    #[schema(flag = "real-time")]
+    #[schema(gui(slider(min = 0.0, max = 0.5, step = 0.01)))]
+    pub threshold: f32,
+
+    #[schema(flag = "real-time")]
+    #[schema(gui(slider(min = 0.0, max = 10.0, step = 0.1)))]
+    pub gain: f32,
+
+    #[schema(strings(help = "Frequency of the vibration. 0 uses the default of the headset"))]
+    #[schema(flag = "real-time")]
+    #[schema(gui(slider(min = 0.0, max = 320.0, step = 10.0)), suffix = "Hz")]
+    pub vibration_frequency: f32,
 }
 
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
@@ -943,6 +1267,12 @@ Because of runtime limitations, this option is ignored when body tracking is act
     ))]
     pub hand_tracking_interaction: Switch<HandTrackingInteractionConfig>,
 
+    #[schema(flag = "real-time")]
+    #[schema(strings(
+        help = "Recognizes static hand poses similar to the recorded templates, and uses them to emulate controller inputs."
+    ))]
+    pub hand_pose_recognition: Switch<HandPoseRecognitionConfig>,
+
     #[schema(strings(
         display_name = "Prediction",
         help = r"Higher values make the controllers track smoother.
@@ -971,6 +1301,11 @@ Currently this cannot be reliably estimated automatically. The correct value sho
     #[schema(gui(slider(min = 0.0, max = 100.0, step = 1.0)), suffix = "°/s")]
     pub angular_velocity_cutoff: f32,
 
+    #[schema(strings(
+        help = "Smooths the controller poses to reduce jitter, for example while aiming slowly. The client predicts the poses using the filtered velocities"
+    ))]
+    pub motion_filter: Switch<MotionFilterConfig>,
+
     #[schema(flag = "real-time")]
     // note: logarithmic scale seems to be glitchy for this control
     #[schema(gui(slider(min = -0.5, max = 0.5, step = 0.001)), suffix = "m")]
@@ -992,9 +1327,78 @@ Currently this cannot be reliably estimated automatically. The correct value sho
     #[schema(strings(help = "List of OpenXR-syle paths"))]
     pub button_mappings: Option<Vec<(String, Vec<ButtonBindingTarget>)>>,
 
+    #[schema(strings(
+        help = "Used instead of the button mappings above while one of the listed SteamVR applications is running"
+    ))]
+    pub button_mapping_profiles: Vec<ButtonMappingProfile>,
+
+    #[schema(strings(help = "Name of the profile to use regardless of the running application"))]
+    #[schema(flag = "real-time")]
+    pub forced_button_mapping_profile: Option<String>,
+
     pub button_mapping_config: AutomaticButtonMappingConfig,
 }
 
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub enum MotionFilterConfig {
+    #[schema(strings(
+        display_name = "One Euro",
+        help = "Low pass filter with a cutoff frequency that rises with the speed. Removes jitter at low speed while keeping the latency low for fast movements"
+    ))]
+    #[schema(collapsible)]
+    OneEuro {
+        #[schema(strings(
+            display_name = "Minimum cutoff",
+            help = "Cutoff frequency at rest. Lower values remove more jitter"
+        ))]
+        #[schema(gui(slider(min = 0.1, max = 10.0, logarithmic)), suffix = "Hz")]
+        min_cutoff_hz: f32,
+
+        #[schema(strings(
+            help = "Increase of the cutoff frequency with the speed. Higher values reduce the lag of fast movements"
+        ))]
+        #[schema(gui(slider(min = 0.0, max = 50.0, step = 0.1)))]
+        beta: f32,
+
+        #[schema(strings(display_name = "Velocity cutoff"))]
+        #[schema(gui(slider(min = 0.1, max = 30.0, logarithmic)), suffix = "Hz")]
+        derivative_cutoff_hz: f32,
+    },
+
+    #[schema(strings(
+        display_name = "Double exponential",
+        help = "Exponential smoothing of the pose and of its trend"
+    ))]
+    #[schema(collapsible)]
+    DoubleExponential {
+        #[schema(strings(help = "Weight of each new sample. Lower values smooth more"))]
+        #[schema(gui(slider(min = 0.01, max = 1.0, step = 0.01)))]
+        smoothing: f32,
+
+        #[schema(strings(help = "Weight of each new sample for the velocity estimation"))]
+        #[schema(gui(slider(min = 0.01, max = 1.0, step = 0.01)))]
+        trend_smoothing: f32,
+    },
+
+    #[schema(strings(help = "Kalman filter with a constant acceleration model"))]
+    #[schema(collapsible)]
+    Kalman {
+        #[schema(strings(
+            help = "Expected variation of the acceleration. Higher values follow sudden movements more closely"
+        ))]
+        #[schema(gui(slider(min = 1.0, max = 10000.0, logarithmic)))]
+        process_noise: f32,
+
+        #[schema(strings(help = "Standard deviation of the position jitter"))]
+        #[schema(gui(slider(min = 0.1, max = 10.0, logarithmic)), suffix = "mm")]
+        position_noise_mm: f32,
+
+        #[schema(strings(help = "Standard deviation of the orientation jitter"))]
+        #[schema(gui(slider(min = 0.01, max = 5.0, logarithmic)), suffix = "°")]
+        orientation_noise_deg: f32,
+    },
+}
+
 #[derive(SettingsSchema, Serialize, Deserialize, Clone, Copy)]
 pub enum PositionRecenteringMode {
     Disabled,
@@ -1012,6 +1416,29 @@ pub enum RotationRecenteringMode {
     Tilted,
 }
 
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct DeviceMotionConfig {
+    #[schema(strings(
+        help = "Applied in the local space of the device, after the rotation offset"
+    ))]
+    // note: logarithmic scale seems to be glitchy for this control
+    #[schema(gui(slider(min = -0.5, max = 0.5, step = 0.001)), suffix = "m")]
+    pub position_offset: [f32; 3],
+
+    #[schema(gui(slider(min = -180.0, max = 180.0, step = 1.0)), suffix = "°")]
+    pub rotation_offset: [f32; 3],
+
+    // note: logarithmic scale seems to be glitchy for this control
+    #[schema(gui(slider(min = 0.0, max = 1.0, step = 0.01)), suffix = "m/s")]
+    pub linear_velocity_cutoff: f32,
+
+    // note: logarithmic scale seems to be glitchy for this control
+    #[schema(gui(slider(min = 0.0, max = 100.0, step = 1.0)), suffix = "°/s")]
+    pub angular_velocity_cutoff: f32,
+
+    pub motion_filter: Switch<MotionFilterConfig>,
+}
+
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
 pub struct HeadsetConfig {
     #[schema(strings(
@@ -1030,6 +1457,25 @@ Tilted: the world gets tilted when long pressing the oculus button. This is usef
     #[schema(flag = "real-time")]
     pub rotation_recentering_mode: RotationRecenteringMode,
 
+    #[schema(strings(
+        help = "Number of tracking samples kept for each device. The poses requested by SteamVR are interpolated between these samples"
+    ))]
+    #[schema(gui(slider(min = 2, max = 64)))]
+    pub tracking_history_size: usize,
+
+    #[schema(strings(
+        help = "Maximum time the poses are extrapolated beyond the tracking samples, when SteamVR requests a pose outside of the history"
+    ))]
+    #[schema(gui(slider(min = 0, max = 100)), suffix = "ms")]
+    #[schema(flag = "real-time")]
+    pub max_pose_extrapolation_ms: u64,
+
+    #[schema(strings(
+        help = "Motion settings of single tracked devices, by OpenXR-style path (for example /user/body/waist or /user/body/left_foot). An entry replaces the offsets, cutoffs and filter derived from the head and controllers settings"
+    ))]
+    #[schema(flag = "real-time")]
+    pub device_motions: Vec<(String, DeviceMotionConfig)>,
+
     #[schema(flag = "steamvr-restart")]
     pub controllers: Switch<ControllersConfig>,
 
@@ -1062,6 +1508,8 @@ pub enum SocketProtocol {
     Udp,
     #[schema(strings(display_name = "TCP"))]
     Tcp,
+    #[schema(strings(display_name = "QUIC"))]
+    Quic,
 }
 
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
@@ -1072,6 +1520,15 @@ pub struct DiscoveryConfig {
     pub auto_trust_clients: bool,
 }
 
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct SpectatorsConfig {
+    #[schema(gui(slider(min = 1, max = 8)))]
+    pub max_spectators: usize,
+
+    #[schema(strings(help = "Stream game audio also to spectators"))]
+    pub game_audio: bool,
+}
+
 #[derive(SettingsSchema, Serialize, Deserialize, Clone, Copy)]
 pub enum SocketBufferSize {
     Default,
@@ -1079,16 +1536,108 @@ pub enum SocketBufferSize {
     Custom(#[schema(suffix = "B")] u32),
 }
 
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct ForwardErrorCorrectionConfig {
+    #[schema(strings(
+        help = "Amount of parity data sent on top of each video packet. Higher values allow recovering from more lost shards, at the cost of bandwidth."
+    ))]
+    #[schema(gui(slider(min = 5, max = 100, step = 5)), suffix = "%")]
+    pub overhead_percentage: u32,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+#[schema(collapsible)]
+pub struct StreamReceiveLimitsConfig {
+    #[schema(strings(
+        help = "Maximum memory used for video packets that are still being received"
+    ))]
+    #[schema(gui(slider(min = 1, max = 256, logarithmic)), suffix = "MB")]
+    pub video_memory_budget_mb: u64,
+
+    #[schema(strings(help = "Maximum memory used by each of the other streams"))]
+    #[schema(gui(slider(min = 1, max = 64, logarithmic)), suffix = "MB")]
+    pub other_streams_memory_budget_mb: u64,
+
+    #[schema(strings(
+        help = "Packets that did not receive any new shard for this time are discarded"
+    ))]
+    #[schema(gui(slider(min = 10, max = 2000, logarithmic)), suffix = "ms")]
+    pub stale_packet_timeout_ms: u64,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+#[schema(collapsible)]
+pub struct NetworkEmulationConfig {
+    #[schema(strings(help = "One way delay added to each shard"))]
+    #[schema(gui(slider(min = 0, max = 500)), suffix = "ms")]
+    pub latency_ms: u64,
+
+    #[schema(strings(help = "Maximum random delay added on top of the latency"))]
+    #[schema(gui(slider(min = 0, max = 100)), suffix = "ms")]
+    pub jitter_ms: u64,
+
+    #[schema(gui(slider(min = 1, max = 1000, logarithmic)), suffix = "Mbps")]
+    pub bandwidth_limit_mbps: Switch<u64>,
+
+    #[schema(strings(
+        help = "Shards that would wait longer than this on the bandwidth limited link are dropped (UDP) or block the sender (TCP)"
+    ))]
+    #[schema(gui(slider(min = 1, max = 1000, logarithmic)), suffix = "ms")]
+    pub max_queue_delay_ms: u64,
+
+    #[schema(strings(help = "Probability of dropping each shard. Ignored for TCP"))]
+    #[schema(gui(slider(min = 0.0, max = 0.5, step = 0.001)))]
+    pub shard_loss_probability: f32,
+
+    #[schema(strings(
+        help = "Probability of delaying a shard past the following ones. Ignored for TCP"
+    ))]
+    #[schema(gui(slider(min = 0.0, max = 0.5, step = 0.001)))]
+    pub reorder_probability: f32,
+
+    #[schema(gui(slider(min = 1, max = 100)), suffix = "ms")]
+    pub reorder_delay_ms: u64,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+#[schema(collapsible)]
+pub struct PacketRetransmissionConfig {
+    pub tracking: bool,
+    pub haptics: bool,
+    pub statistics: bool,
+    pub audio: bool,
+    pub video: bool,
+
+    #[schema(strings(
+        help = "Number of recently sent packets kept for each stream, that can be resent on request."
+    ))]
+    #[schema(gui(slider(min = 1, max = 64)), suffix = " packets")]
+    pub window_size: usize,
+}
+
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
 pub struct ConnectionConfig {
     #[schema(strings(
         help = r#"UDP: Faster, but less stable than TCP. Try this if your network is well optimized and free of interference.
-TCP: Slower than UDP, but more stable. Pick this if you experience video or audio stutters with UDP."#
+TCP: Slower than UDP, but more stable. Pick this if you experience video or audio stutters with UDP.
+QUIC: Sends video over UDP datagrams and haptics and statistics reliably. Provides congestion feedback to the adaptive bitrate."#
     ))]
     pub stream_protocol: SocketProtocol,
 
     pub client_discovery: Switch<DiscoveryConfig>,
 
+    #[schema(strings(
+        help = r#"Refuse to connect to clients that have not been paired with the PIN shown in the headset lobby.
+Paired clients always use encrypted control and stream traffic, regardless of this setting."#
+    ))]
+    pub require_pairing: bool,
+
+    #[schema(strings(
+        help = r#"Allow other clients to connect while a client is streaming. Spectators receive the same video as the first connected client, which remains the only one driving SteamVR tracking and input.
+The bitrate is limited by the slowest client. Each spectator streams on one of the ports that follow the stream port."#
+    ))]
+    pub spectators: Switch<SpectatorsConfig>,
+
     #[schema(strings(
         help = "This script will be ran when the headset connects. Env var ACTION will be set to `connect`."
     ))]
@@ -1106,6 +1655,18 @@ For now works only on Windows+Nvidia"#
     ))]
     pub avoid_video_glitching: bool,
 
+    #[schema(strings(
+        help = r#"Send parity shards together with video packets, so the client can reconstruct a frame when some shards are lost, without waiting for a new IDR frame.
+Only used with UDP."#
+    ))]
+    pub video_forward_error_correction: Switch<ForwardErrorCorrectionConfig>,
+
+    #[schema(strings(
+        help = r#"When some shards of a packet are lost, ask the other side to resend only the missing shards. Packets completed by resent shards can be delivered after newer packets.
+Only used with UDP."#
+    ))]
+    pub packet_retransmission: Switch<PacketRetransmissionConfig>,
+
     #[schema(gui(slider(min = 1024, max = 65507, logarithmic)), suffix = "B")]
     pub packet_size: i32,
 
@@ -1131,6 +1692,11 @@ This could happen on TCP. A IDR frame is requested in this case."#
     ))]
     pub max_queued_server_video_frames: usize,
 
+    #[schema(strings(
+        help = "Limits the memory used to receive packets when the network stalls and then floods. Packets that don't fit are dropped."
+    ))]
+    pub stream_receive_limits: StreamReceiveLimitsConfig,
+
     #[schema(suffix = " frames")]
     pub statistics_history_size: usize,
 
@@ -1140,6 +1706,12 @@ This could happen on TCP. A IDR frame is requested in this case."#
     pub minimum_idr_interval_ms: u64,
 
     pub dscp: Option<DscpTos>,
+
+    #[cfg_attr(not(debug_assertions), schema(flag = "hidden"))]
+    #[schema(strings(
+        help = "Emulate a degraded network on the stream socket, for testing. The streamer shapes the traffic it sends, the client shapes the traffic it sends."
+    ))]
+    pub network_emulation: Switch<NetworkEmulationConfig>,
 }
 
 #[derive(SettingsSchema, Serialize, Deserialize, Clone, Copy)]
@@ -1235,6 +1807,17 @@ pub struct RollingVideoFilesConfig {
     pub duration_s: u64,
 }
 
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub enum VideoRecordingContainer {
+    #[schema(strings(
+        display_name = "Raw stream",
+        help = "Encoded video without timestamps. Some players cannot open it"
+    ))]
+    AnnexB,
+    #[schema(strings(display_name = "Matroska (.mkv)"))]
+    Matroska { game_audio: bool },
+}
+
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
 pub struct CaptureConfig {
     #[schema(strings(display_name = "Start video recording at client connection"))]
@@ -1242,6 +1825,21 @@ pub struct CaptureConfig {
 
     pub rolling_video_files: Switch<RollingVideoFilesConfig>,
 
+    #[schema(strings(
+        help = "Matroska recordings contain the frame timestamps and optionally the game audio. The recording starts at the first IDR frame"
+    ))]
+    pub video_recording_container: VideoRecordingContainer,
+
+    #[schema(strings(
+        help = "Record tracking, buttons, haptics, statistics, audio and video of the streaming client in a single .alvrrec file, alongside the video recording. The tracking and buttons can be replayed with the mock client."
+    ))]
+    pub record_session: bool,
+
+    #[schema(strings(
+        help = "Write the inputs of the bitrate controller to a .jsonl trace in the log folder for each streaming session. The trace can be replayed with alvr_bitrate_simulator to compare bitrate configurations."
+    ))]
+    pub record_bitrate_trace: bool,
+
     #[schema(flag = "steamvr-restart")]
     pub capture_frame_dir: String,
 }
@@ -1300,6 +1898,53 @@ pub fn session_settings_default() -> SettingsDefault {
         element: OPENVR_PROPS_DEFAULT.clone(),
         content: vec![],
     };
+    let default_motion_filter = MotionFilterConfigDefault {
+        OneEuro: MotionFilterConfigOneEuroDefault {
+            gui_collapsed: false,
+            min_cutoff_hz: 1.0,
+            beta: 5.0,
+            derivative_cutoff_hz: 5.0,
+        },
+        DoubleExponential: MotionFilterConfigDoubleExponentialDefault {
+            gui_collapsed: false,
+            smoothing: 0.5,
+            trend_smoothing: 0.3,
+        },
+        Kalman: MotionFilterConfigKalmanDefault {
+            gui_collapsed: false,
+            process_noise: 500.0,
+            position_noise_mm: 1.0,
+            orientation_noise_deg: 0.2,
+        },
+        variant: MotionFilterConfigDefaultVariant::OneEuro,
+    };
+    let default_button_mappings = DictionaryDefault {
+        gui_collapsed: false,
+        key: "/user/hand/left/input/a/click".into(),
+        value: VectorDefault {
+            gui_collapsed: false,
+            element: ButtonBindingTargetDefault {
+                destination: "/user/hand/left/input/a/click".into(),
+                mapping_type: ButtonMappingTypeDefault {
+                    HysteresisThreshold: HysteresisThresholdDefault {
+                        value: 0.5,
+                        deviation: 0.05,
+                    },
+                    BinaryToScalar: BinaryToScalarStatesDefault { off: 0.0, on: 1.0 },
+                    Remap: RangeDefault { min: 0.0, max: 1.0 },
+                    Expression: "value".into(),
+                    variant: ButtonMappingTypeDefaultVariant::Passthrough,
+                },
+                binary_conditions: VectorDefault {
+                    gui_collapsed: true,
+                    element: "/user/hand/left/input/trigger/touch".into(),
+                    content: vec![],
+                },
+            },
+            content: vec![],
+        },
+        content: vec![],
+    };
     let socket_buffer = SocketBufferSizeDefault {
         Custom: 100000,
         variant: SocketBufferSizeDefaultVariant::Maximum,
@@ -1349,6 +1994,36 @@ pub fn session_settings_default() -> SettingsDefault {
                             },
                         },
                     },
+                    DelayGradient: BitrateModeDelayGradientDefault {
+                        gui_collapsed: true,
+                        initial_bitrate_mbps: 30,
+                        latency_gradient_threshold_ms_per_s: 2.0,
+                        increase_rate: 0.08,
+                        decrease_multiplier: 0.85,
+                        max_throughput_mbps: SwitchDefault {
+                            enabled: true,
+                            content: 100,
+                        },
+                        min_throughput_mbps: SwitchDefault {
+                            enabled: false,
+                            content: 5,
+                        },
+                    },
+                    LossBased: BitrateModeLossBasedDefault {
+                        gui_collapsed: true,
+                        initial_bitrate_mbps: 30,
+                        loss_threshold: 0.02,
+                        additive_increase_mbps_per_s: 2.0,
+                        decrease_multiplier: 0.8,
+                        max_throughput_mbps: SwitchDefault {
+                            enabled: true,
+                            content: 100,
+                        },
+                        min_throughput_mbps: SwitchDefault {
+                            enabled: false,
+                            content: 5,
+                        },
+                    },
                     variant: BitrateModeDefaultVariant::ConstantMbps,
                 },
                 adapt_to_framerate: SwitchDefault {
@@ -1468,6 +2143,7 @@ pub fn session_settings_default() -> SettingsDefault {
                     center_shift_y: 0.1,
                     edge_ratio_x: 4.,
                     edge_ratio_y: 5.,
+                    follow_eye_gaze: false,
                 },
             },
             clientside_foveation: SwitchDefault {
@@ -1560,6 +2236,19 @@ pub fn session_settings_default() -> SettingsDefault {
                     },
                     sink: FaceTrackingSinkConfigDefault {
                         VrchatEyeOsc: FaceTrackingSinkConfigVrchatEyeOscDefault { port: 9000 },
+                        ArkitBlendshapesOsc: FaceTrackingSinkConfigArkitBlendshapesOscDefault {
+                            port: 9000,
+                            address_prefix: "/avatar/parameters/".into(),
+                            calibration: DictionaryDefault {
+                                gui_collapsed: true,
+                                key: "jawOpen".into(),
+                                value: BlendshapeCalibrationDefault {
+                                    gain: 1.0,
+                                    curve: 1.0,
+                                },
+                                content: vec![],
+                            },
+                        },
                         variant: FaceTrackingSinkConfigDefaultVariant::VrchatEyeOsc,
                     },
                 },
@@ -1573,6 +2262,21 @@ pub fn session_settings_default() -> SettingsDefault {
                             enabled: true,
                             content: BodyTrackingFBConfigDefault { full_body: true },
                         },
+                        external_receiver: SwitchDefault {
+                            enabled: false,
+                            content: BodyTrackingReceiverConfigDefault {
+                                protocol: BodyTrackingReceiverProtocolDefault {
+                                    variant: BodyTrackingReceiverProtocolDefaultVariant::Vmc,
+                                },
+                                port: 39540,
+                                align_to_headset: true,
+                                yaw_offset: 0.0,
+                                position_offset: ArrayDefault {
+                                    gui_collapsed: true,
+                                    content: [0.0, 0.0, 0.0],
+                                },
+                            },
+                        },
                     },
                     sink: BodyTrackingSinkConfigDefault {
                         VrchatBodyOsc: BodyTrackingSinkConfigVrchatBodyOscDefault { port: 9000 },
@@ -1617,35 +2321,25 @@ pub fn session_settings_default() -> SettingsDefault {
                     extra_openvr_props: default_custom_openvr_props,
                     button_mappings: OptionalDefault {
                         set: false,
-                        content: DictionaryDefault {
+                        content: default_button_mappings.clone(),
+                    },
+                    button_mapping_profiles: VectorDefault {
+                        gui_collapsed: true,
+                        element: ButtonMappingProfileDefault {
                             gui_collapsed: false,
-                            key: "/user/hand/left/input/a/click".into(),
-                            value: VectorDefault {
+                            name: "".into(),
+                            applications: VectorDefault {
                                 gui_collapsed: false,
-                                element: ButtonBindingTargetDefault {
-                                    destination: "/user/hand/left/input/a/click".into(),
-                                    mapping_type: ButtonMappingTypeDefault {
-                                        HysteresisThreshold: HysteresisThresholdDefault {
-                                            value: 0.5,
-                                            deviation: 0.05,
-                                        },
-                                        BinaryToScalar: BinaryToScalarStatesDefault {
-                                            off: 0.0,
-                                            on: 1.0,
-                                        },
-                                        Remap: RangeDefault { min: 0.0, max: 1.0 },
-                                        variant: ButtonMappingTypeDefaultVariant::Passthrough,
-                                    },
-                                    binary_conditions: VectorDefault {
-                                        gui_collapsed: true,
-                                        element: "/user/hand/left/input/trigger/touch".into(),
-                                        content: vec![],
-                                    },
-                                },
+                                element: "steam.app.".into(),
                                 content: vec![],
                             },
-                            content: vec![],
+                            button_mappings: default_button_mappings,
                         },
+                        content: vec![],
+                    },
+                    forced_button_mapping_profile: OptionalDefault {
+                        set: false,
+                        content: "".into(),
                     },
                     button_mapping_config: AutomaticButtonMappingConfigDefault {
                         gui_collapsed: true,
@@ -1674,11 +2368,78 @@ pub fn session_settings_default() -> SettingsDefault {
                             repeat_delay: 100,
                             activation_delay: 50,
                             deactivation_delay: 100,
+                            custom_gestures: VectorDefault {
+                                gui_collapsed: true,
+                                element: CustomHandGestureConfigDefault {
+                                    name: "Thumbs up".into(),
+                                    shape: HandGestureShapeDefault {
+                                        JointDistances: VectorDefault {
+                                            gui_collapsed: false,
+                                            element: HandJointDistanceConditionDefault {
+                                                first_joint: 5,
+                                                second_joint: 10,
+                                                min_distance: 0.0,
+                                                max_distance: 1.0,
+                                            },
+                                            content: vec![],
+                                        },
+                                        FingerCurls: HandGestureShapeFingerCurlsDefault {
+                                            thumb: 0.0,
+                                            index: 1.0,
+                                            middle: 1.0,
+                                            ring: 1.0,
+                                            little: 1.0,
+                                            tolerance: 0.3,
+                                        },
+                                        variant: HandGestureShapeDefaultVariant::FingerCurls,
+                                    },
+                                    left_hand_button: OptionalDefault {
+                                        set: true,
+                                        content: "/user/hand/left/input/menu/click".into(),
+                                    },
+                                    right_hand_button: OptionalDefault {
+                                        set: false,
+                                        content: "/user/hand/right/input/a/click".into(),
+                                    },
+                                },
+                                content: vec![],
+                            },
+                        },
+                    },
+                    hand_pose_recognition: SwitchDefault {
+                        enabled: false,
+                        content: HandPoseRecognitionConfigDefault {
+                            max_joint_error: 15.0,
+                            min_confidence: 0.6,
+                            templates: VectorDefault {
+                                gui_collapsed: false,
+                                element: HandPoseTemplateDefault {
+                                    name: "".into(),
+                                    left_hand_button: OptionalDefault {
+                                        set: false,
+                                        content: "/user/hand/left/input/x/click".into(),
+                                    },
+                                    right_hand_button: OptionalDefault {
+                                        set: false,
+                                        content: "/user/hand/right/input/a/click".into(),
+                                    },
+                                    joint_positions: VectorDefault {
+                                        gui_collapsed: true,
+                                        element: 0.0,
+                                        content: vec![],
+                                    },
+                                },
+                                content: vec![],
+                            },
                         },
                     },
                     steamvr_pipeline_frames: 3.0,
                     linear_velocity_cutoff: 0.05,
                     angular_velocity_cutoff: 10.0,
+                    motion_filter: SwitchDefault {
+                        enabled: false,
+                        content: default_motion_filter.clone(),
+                    },
                     left_controller_position_offset: ArrayDefault {
                         gui_collapsed: true,
                         content: [0.0, 0.0, -0.11],
@@ -1702,6 +2463,21 @@ pub fn session_settings_default() -> SettingsDefault {
                             intensity_multiplier: 1.0,
                             amplitude_curve: 1.0,
                             min_duration_s: 0.01,
+                            attack_s: 0.0,
+                            decay_s: 0.0,
+                            mixing_mode: HapticsMixingModeDefault {
+                                variant: HapticsMixingModeDefaultVariant::Maximum,
+                            },
+                            audio_to_haptics: SwitchDefault {
+                                enabled: false,
+                                content: AudioToHapticsConfigDefault {
+                                    gui_collapsed: false,
+                                    cutoff_frequency: 80.0,
+                                    threshold: 0.05,
+                                    gain: 3.0,
+                                    vibration_frequency: 0.0,
+                                },
+                            },
                         },
                     },
                 },
@@ -1713,6 +2489,29 @@ pub fn session_settings_default() -> SettingsDefault {
             rotation_recentering_mode: RotationRecenteringModeDefault {
                 variant: RotationRecenteringModeDefaultVariant::Yaw,
             },
+            tracking_history_size: 8,
+            max_pose_extrapolation_ms: 20,
+            device_motions: DictionaryDefault {
+                gui_collapsed: true,
+                key: BODY_HIPS_PATH.into(),
+                value: DeviceMotionConfigDefault {
+                    position_offset: ArrayDefault {
+                        gui_collapsed: false,
+                        content: [0.0, 0.0, 0.0],
+                    },
+                    rotation_offset: ArrayDefault {
+                        gui_collapsed: false,
+                        content: [0.0, 0.0, 0.0],
+                    },
+                    linear_velocity_cutoff: 0.0,
+                    angular_velocity_cutoff: 0.0,
+                    motion_filter: SwitchDefault {
+                        enabled: false,
+                        content: default_motion_filter,
+                    },
+                },
+                content: vec![],
+            },
         },
         connection: ConnectionConfigDefault {
             stream_protocol: SocketProtocolDefault {
@@ -1724,6 +2523,14 @@ pub fn session_settings_default() -> SettingsDefault {
                     auto_trust_clients: cfg!(debug_assertions),
                 },
             },
+            require_pairing: false,
+            spectators: SwitchDefault {
+                enabled: false,
+                content: SpectatorsConfigDefault {
+                    max_spectators: 1,
+                    game_audio: true,
+                },
+            },
             web_server_port: 8082,
             stream_port: 9944,
             osc_local_port: 9942,
@@ -1745,12 +2552,52 @@ pub fn session_settings_default() -> SettingsDefault {
             client_send_buffer_bytes: socket_buffer.clone(),
             client_recv_buffer_bytes: socket_buffer,
             max_queued_server_video_frames: 1024,
+            stream_receive_limits: StreamReceiveLimitsConfigDefault {
+                gui_collapsed: true,
+                video_memory_budget_mb: 64,
+                other_streams_memory_budget_mb: 4,
+                stale_packet_timeout_ms: 500,
+            },
             avoid_video_glitching: false,
+            video_forward_error_correction: SwitchDefault {
+                enabled: false,
+                content: ForwardErrorCorrectionConfigDefault {
+                    overhead_percentage: 10,
+                },
+            },
+            packet_retransmission: SwitchDefault {
+                enabled: false,
+                content: PacketRetransmissionConfigDefault {
+                    gui_collapsed: true,
+                    tracking: true,
+                    haptics: true,
+                    statistics: true,
+                    audio: false,
+                    video: false,
+                    window_size: 16,
+                },
+            },
             minimum_idr_interval_ms: 100,
             on_connect_script: "".into(),
             on_disconnect_script: "".into(),
             packet_size: 1400,
             statistics_history_size: 256,
+            network_emulation: SwitchDefault {
+                enabled: false,
+                content: NetworkEmulationConfigDefault {
+                    gui_collapsed: true,
+                    latency_ms: 5,
+                    jitter_ms: 2,
+                    bandwidth_limit_mbps: SwitchDefault {
+                        enabled: false,
+                        content: 100,
+                    },
+                    max_queue_delay_ms: 50,
+                    shard_loss_probability: 0.01,
+                    reorder_probability: 0.0,
+                    reorder_delay_ms: 5,
+                },
+            },
         },
         extra: ExtraConfigDefault {
             logging: LoggingConfigDefault {
@@ -1804,6 +2651,12 @@ pub fn session_settings_default() -> SettingsDefault {
                     enabled: false,
                     content: RollingVideoFilesConfigDefault { duration_s: 5 },
                 },
+                video_recording_container: VideoRecordingContainerDefault {
+                    Matroska: VideoRecordingContainerMatroskaDefault { game_audio: true },
+                    variant: VideoRecordingContainerDefaultVariant::Matroska,
+                },
+                record_session: false,
+                record_bitrate_trace: false,
                 capture_frame_dir: if !cfg!(target_os = "linux") {
                     "/tmp".into()
                 } else {
@@ -0,0 +1,314 @@
+// Debug wrapper that emulates a degraded network on the sending side. Each buffer passed to send()
+// (one shard) is delayed by latency and jitter, serialized on a link with limited bandwidth, and
+// optionally dropped or reordered. Shards are delivered to the inner socket by a separate thread.
+// Loss and reordering are applied only to datagram sockets, since a stream socket would be
+// corrupted by them.
+
+use super::SocketWriter;
+use alvr_common::{
+    anyhow::{Error, Result},
+    parking_lot::{Condvar, Mutex, MutexGuard},
+};
+use alvr_session::NetworkEmulationConfig;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+struct DeliveryQueue {
+    // Ordered by deadline, then by send order. Contains the stream ID of shards sent with
+    // send_shard()
+    shards: BinaryHeap<Reverse<(Instant, u64, Option<u16>, Vec<u8>)>>,
+    error: Option<Error>,
+    running: bool,
+}
+
+struct SharedQueue {
+    queue: Mutex<DeliveryQueue>,
+    condvar: Condvar,
+}
+
+fn delivery_loop(shared: &SharedQueue, mut inner: Box<dyn SocketWriter>) {
+    let mut queue = shared.queue.lock();
+    while queue.running {
+        match queue.shards.peek() {
+            Some(Reverse((deadline, ..))) if *deadline <= Instant::now() => {
+                let Reverse((_, _, stream_id, shard)) = queue.shards.pop().unwrap();
+
+                let res = MutexGuard::unlocked(&mut queue, || match stream_id {
+                    Some(stream_id) => inner.send_shard(stream_id, &shard),
+                    None => inner.send(&shard),
+                });
+                if let Err(e) = res {
+                    queue.error = Some(e);
+                }
+            }
+            Some(Reverse((deadline, ..))) => {
+                let deadline = *deadline;
+                shared.condvar.wait_until(&mut queue, deadline);
+            }
+            None => shared.condvar.wait(&mut queue),
+        }
+    }
+}
+
+pub struct EmulatedWriter {
+    config: NetworkEmulationConfig,
+    allow_loss_and_reordering: bool,
+    shared: Arc<SharedQueue>,
+    delivery_thread: Option<JoinHandle<()>>,
+    rng: StdRng,
+    // Time at which the emulated link finishes transmitting the shards sent so far
+    link_free_instant: Instant,
+    last_deadline: Instant,
+    sequence: u64,
+}
+
+impl EmulatedWriter {
+    pub fn new(
+        inner: Box<dyn SocketWriter>,
+        config: NetworkEmulationConfig,
+        is_datagram_socket: bool,
+    ) -> Self {
+        Self::with_rng(inner, config, is_datagram_socket, StdRng::from_entropy())
+    }
+
+    fn with_rng(
+        inner: Box<dyn SocketWriter>,
+        config: NetworkEmulationConfig,
+        is_datagram_socket: bool,
+        rng: StdRng,
+    ) -> Self {
+        let shared = Arc::new(SharedQueue {
+            queue: Mutex::new(DeliveryQueue {
+                shards: BinaryHeap::new(),
+                error: None,
+                running: true,
+            }),
+            condvar: Condvar::new(),
+        });
+
+        let delivery_thread = thread::spawn({
+            let shared = Arc::clone(&shared);
+            move || delivery_loop(&shared, inner)
+        });
+
+        Self {
+            config,
+            allow_loss_and_reordering: is_datagram_socket,
+            shared,
+            delivery_thread: Some(delivery_thread),
+            rng,
+            link_free_instant: Instant::now(),
+            last_deadline: Instant::now(),
+            sequence: 0,
+        }
+    }
+}
+
+impl EmulatedWriter {
+    fn enqueue(&mut self, now: Instant, stream_id: Option<u16>, buffer: &[u8]) -> Result<()> {
+        if let Some(e) = self.shared.queue.lock().error.take() {
+            return Err(e);
+        }
+
+        if self.allow_loss_and_reordering
+            && self.rng.gen::<f32>() < self.config.shard_loss_probability
+        {
+            return Ok(());
+        }
+
+        let mut deadline = now;
+        if let Some(bandwidth_mbps) = self.config.bandwidth_limit_mbps.as_option() {
+            let start = Instant::max(now, self.link_free_instant);
+
+            let max_queue_delay = Duration::from_millis(self.config.max_queue_delay_ms);
+            if start > now + max_queue_delay {
+                if self.allow_loss_and_reordering {
+                    // Router buffer overflow
+                    return Ok(());
+                } else {
+                    thread::sleep(start - now - max_queue_delay);
+                }
+            }
+
+            let transmission_time =
+                Duration::from_secs_f64(buffer.len() as f64 * 8.0 / (*bandwidth_mbps as f64 * 1e6));
+            self.link_free_instant = start + transmission_time;
+            deadline = self.link_free_instant;
+        }
+
+        deadline += Duration::from_millis(self.config.latency_ms);
+        if self.config.jitter_ms > 0 {
+            deadline += Duration::from_micros(self.rng.gen_range(0..=self.config.jitter_ms * 1000));
+        }
+
+        if self.allow_loss_and_reordering && self.rng.gen::<f32>() < self.config.reorder_probability
+        {
+            // The following shards overtake this one
+            deadline = Instant::max(deadline, self.last_deadline)
+                + Duration::from_millis(self.config.reorder_delay_ms);
+        } else {
+            // Jitter alone does not reorder shards
+            deadline = Instant::max(deadline, self.last_deadline);
+            self.last_deadline = deadline;
+        }
+
+        self.shared.queue.lock().shards.push(Reverse((
+            deadline,
+            self.sequence,
+            stream_id,
+            buffer.to_vec(),
+        )));
+        self.sequence += 1;
+        self.shared.condvar.notify_one();
+
+        Ok(())
+    }
+}
+
+impl SocketWriter for EmulatedWriter {
+    fn send(&mut self, buffer: &[u8]) -> Result<()> {
+        self.enqueue(Instant::now(), None, buffer)
+    }
+
+    fn send_shard(&mut self, stream_id: u16, buffer: &[u8]) -> Result<()> {
+        self.enqueue(Instant::now(), Some(stream_id), buffer)
+    }
+}
+
+impl Drop for EmulatedWriter {
+    fn drop(&mut self) {
+        self.shared.queue.lock().running = false;
+        self.shared.condvar.notify_one();
+
+        if let Some(thread) = self.delivery_thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alvr_session::settings_schema::Switch;
+    use std::mem;
+
+    struct NullWriter;
+
+    impl SocketWriter for NullWriter {
+        fn send(&mut self, _: &[u8]) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn config() -> NetworkEmulationConfig {
+        NetworkEmulationConfig {
+            latency_ms: 0,
+            jitter_ms: 0,
+            bandwidth_limit_mbps: Switch::Disabled,
+            max_queue_delay_ms: 100,
+            shard_loss_probability: 0.0,
+            reorder_probability: 0.0,
+            reorder_delay_ms: 10,
+        }
+    }
+
+    // Sends one shard per millisecond and returns the indices of the shards in delivery order. The
+    // shards are scheduled far in the future, so the delivery thread doesn't take any.
+    fn delivery_order(
+        config: NetworkEmulationConfig,
+        is_datagram_socket: bool,
+        seed: u64,
+        shards_count: u32,
+    ) -> Vec<u32> {
+        let mut writer = EmulatedWriter::with_rng(
+            Box::new(NullWriter),
+            config,
+            is_datagram_socket,
+            StdRng::seed_from_u64(seed),
+        );
+
+        let start = Instant::now() + Duration::from_secs(3600);
+        for idx in 0..shards_count {
+            let now = start + Duration::from_millis(idx as u64);
+            writer.enqueue(now, None, &idx.to_be_bytes()).unwrap();
+        }
+
+        let mut shards = mem::take(&mut writer.shared.queue.lock().shards);
+        let mut order = vec![];
+        while let Some(Reverse((_, _, _, shard))) = shards.pop() {
+            order.push(u32::from_be_bytes(shard.try_into().unwrap()));
+        }
+
+        order
+    }
+
+    #[test]
+    fn loss_rate() {
+        let config = NetworkEmulationConfig {
+            shard_loss_probability: 0.1,
+            ..config()
+        };
+
+        let delivered = delivery_order(config.clone(), true, 42, 10_000);
+        assert!((8_800..9_200).contains(&delivered.len()));
+        assert!(delivered.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // The same shards are dropped for the same seed
+        assert_eq!(delivery_order(config.clone(), true, 42, 10_000), delivered);
+        assert_ne!(delivery_order(config.clone(), true, 7, 10_000), delivered);
+
+        // Stream sockets never lose shards
+        assert_eq!(delivery_order(config, false, 42, 10_000).len(), 10_000);
+    }
+
+    #[test]
+    fn jitter_does_not_reorder() {
+        let config = NetworkEmulationConfig {
+            latency_ms: 20,
+            jitter_ms: 30,
+            ..config()
+        };
+
+        let delivered = delivery_order(config, true, 42, 1_000);
+        assert_eq!(delivered, (0..1_000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reordered_shards_are_overtaken() {
+        let config = NetworkEmulationConfig {
+            reorder_probability: 0.1,
+            reorder_delay_ms: 5,
+            ..config()
+        };
+
+        let delivered = delivery_order(config.clone(), true, 42, 1_000);
+        assert_eq!(delivery_order(config.clone(), true, 42, 1_000), delivered);
+
+        let mut sorted = delivered.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..1_000).collect::<Vec<_>>());
+
+        // Each delayed shard is overtaken by the shards sent during the reorder delay
+        let overtaken = delivered
+            .iter()
+            .enumerate()
+            .filter(|(position, idx)| **idx < *position as u32)
+            .count();
+        assert!((50..150).contains(&overtaken));
+        for (position, idx) in delivered.iter().enumerate() {
+            assert!(position as u32 <= idx + 5);
+        }
+
+        // Stream sockets keep the order
+        assert_eq!(
+            delivery_order(config, false, 42, 1_000),
+            (0..1_000).collect::<Vec<_>>()
+        );
+    }
+}
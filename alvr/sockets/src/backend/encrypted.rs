@@ -0,0 +1,198 @@
+// Wrapper that encrypts each buffer passed to send() into a self-contained record. Records can be
+// received out of order or lost (UDP), so the nonce counter is sent explicitly.
+// Record layout: [u32 length of the rest of the record][u64 counter][ciphertext][tag]
+
+use super::{SocketReader, SocketWriter};
+use crate::encryption::{CipherKeys, OpeningCipher, SealingCipher, TAG_LENGTH};
+use alvr_common::{
+    anyhow::{bail, Result},
+    con_bail, debug, ConResult,
+};
+use std::{mem, ops::Range};
+
+const RECORD_LENGTH_SIZE: usize = mem::size_of::<u32>();
+const RECORD_PREFIX_SIZE: usize = RECORD_LENGTH_SIZE + mem::size_of::<u64>();
+
+pub const ENCRYPTION_OVERHEAD: usize = RECORD_PREFIX_SIZE + TAG_LENGTH;
+
+// Writes the record of the plaintext into record
+pub(crate) fn seal_record(
+    cipher: &mut SealingCipher,
+    plaintext: &[u8],
+    record: &mut Vec<u8>,
+) -> Result<()> {
+    let record_size = ENCRYPTION_OVERHEAD + plaintext.len();
+
+    record.resize(record_size, 0);
+    record[RECORD_PREFIX_SIZE..record_size - TAG_LENGTH].copy_from_slice(plaintext);
+
+    let counter = cipher.seal(&mut record[RECORD_PREFIX_SIZE..])?;
+
+    record[0..RECORD_LENGTH_SIZE]
+        .copy_from_slice(&((record_size - RECORD_LENGTH_SIZE) as u32).to_be_bytes());
+    record[RECORD_LENGTH_SIZE..RECORD_PREFIX_SIZE].copy_from_slice(&counter.to_be_bytes());
+
+    Ok(())
+}
+
+// Decrypts in place a whole record and returns the range of the plaintext. If in_order is set,
+// only the record that follows the previous one is accepted.
+pub(crate) fn open_record(
+    cipher: &mut OpeningCipher,
+    record: &mut [u8],
+    in_order: bool,
+) -> Result<Range<usize>> {
+    if record.len() < ENCRYPTION_OVERHEAD {
+        bail!("Truncated record ({} bytes)", record.len());
+    }
+
+    let counter = u64::from_be_bytes(
+        record[RECORD_LENGTH_SIZE..RECORD_PREFIX_SIZE]
+            .try_into()
+            .unwrap(),
+    );
+    let ciphertext = &mut record[RECORD_PREFIX_SIZE..];
+    let size = if in_order {
+        cipher.open_in_order(counter, ciphertext)?
+    } else {
+        cipher.open(counter, ciphertext)?
+    };
+
+    Ok(RECORD_PREFIX_SIZE..RECORD_PREFIX_SIZE + size)
+}
+
+pub struct EncryptedWriter {
+    inner: Box<dyn SocketWriter>,
+    cipher: SealingCipher,
+    buffer: Vec<u8>,
+}
+
+impl EncryptedWriter {
+    pub fn new(inner: Box<dyn SocketWriter>, keys: &CipherKeys) -> Self {
+        Self {
+            inner,
+            cipher: SealingCipher::new(keys),
+            buffer: vec![],
+        }
+    }
+}
+
+impl SocketWriter for EncryptedWriter {
+    fn send(&mut self, buffer: &[u8]) -> Result<()> {
+        seal_record(&mut self.cipher, buffer, &mut self.buffer)?;
+        self.inner.send(&self.buffer)
+    }
+
+    fn send_shard(&mut self, stream_id: u16, buffer: &[u8]) -> Result<()> {
+        seal_record(&mut self.cipher, buffer, &mut self.buffer)?;
+        self.inner.send_shard(stream_id, &self.buffer)
+    }
+}
+
+pub struct EncryptedReader {
+    inner: Box<dyn SocketReader>,
+    cipher: OpeningCipher,
+    max_record_size: usize,
+    is_datagram_socket: bool,
+    record: Vec<u8>,
+    record_length: Option<usize>,
+    record_cursor: usize,
+    plaintext: Range<usize>,
+}
+
+impl EncryptedReader {
+    // max_record_size includes the length field. The length is read before the record can be
+    // authenticated, so it is checked against this limit before allocating.
+    pub fn new(
+        inner: Box<dyn SocketReader>,
+        keys: &CipherKeys,
+        max_record_size: usize,
+        is_datagram_socket: bool,
+    ) -> Self {
+        Self {
+            inner,
+            cipher: OpeningCipher::new(keys),
+            max_record_size,
+            is_datagram_socket,
+            record: vec![],
+            record_length: None,
+            record_cursor: 0,
+            plaintext: 0..0,
+        }
+    }
+
+    // Receive and decrypt records until there is some plaintext available. Records that fail
+    // authentication are dropped.
+    fn fill_plaintext(&mut self) -> ConResult {
+        while self.plaintext.is_empty() {
+            let record_length = if let Some(length) = self.record_length {
+                length
+            } else {
+                let mut length_bytes = [0; RECORD_LENGTH_SIZE];
+                if self.inner.peek(&mut length_bytes)? < RECORD_LENGTH_SIZE {
+                    return alvr_common::try_again();
+                }
+
+                let length = RECORD_LENGTH_SIZE + u32::from_be_bytes(length_bytes) as usize;
+                if length > self.max_record_size {
+                    if self.is_datagram_socket {
+                        // Each record is a whole datagram. Receiving into a small buffer discards
+                        // it, the result is ignored since the datagram is dropped anyway
+                        self.inner.recv(&mut length_bytes).ok();
+                        debug!("Dropped oversized stream record ({length} bytes)");
+
+                        continue;
+                    } else {
+                        // The stream cannot be resynchronized
+                        con_bail!("Received oversized stream record ({length} bytes)");
+                    }
+                }
+
+                if self.record.len() < length {
+                    self.record.resize(length, 0);
+                }
+
+                *self.record_length.insert(length)
+            };
+
+            // This loop may bail out at any time if a timeout is reached. The state is kept for
+            // the next call.
+            while self.record_cursor < record_length {
+                self.record_cursor += self
+                    .inner
+                    .recv(&mut self.record[self.record_cursor..record_length])?;
+            }
+
+            self.record_length = None;
+            self.record_cursor = 0;
+
+            match open_record(&mut self.cipher, &mut self.record[..record_length], false) {
+                Ok(plaintext) => self.plaintext = plaintext,
+                Err(e) => debug!("Dropped stream record: {e}"),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl SocketReader for EncryptedReader {
+    fn recv(&mut self, buffer: &mut [u8]) -> ConResult<usize> {
+        self.fill_plaintext()?;
+
+        let size = usize::min(buffer.len(), self.plaintext.len());
+        buffer[..size].copy_from_slice(&self.record[self.plaintext.start..][..size]);
+        self.plaintext.start += size;
+
+        Ok(size)
+    }
+
+    fn peek(&mut self, buffer: &mut [u8]) -> ConResult<usize> {
+        self.fill_plaintext()?;
+
+        let size = usize::min(buffer.len(), self.plaintext.len());
+        buffer[..size].copy_from_slice(&self.record[self.plaintext.start..][..size]);
+
+        Ok(size)
+    }
+}
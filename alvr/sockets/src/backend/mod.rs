@@ -1,3 +1,6 @@
+pub mod emulated;
+pub mod encrypted;
+pub mod quic;
 pub mod tcp;
 pub mod udp;
 
@@ -5,6 +8,13 @@ use alvr_common::{anyhow::Result, ConResult};
 
 pub trait SocketWriter: Send {
     fn send(&mut self, buffer: &[u8]) -> Result<()>;
+
+    // Used by StreamSocket to send a shard. The stream ID is passed separately, since the shard
+    // prefix is not readable once encrypted. Only needed by sockets that handle streams
+    // differently.
+    fn send_shard(&mut self, _stream_id: u16, buffer: &[u8]) -> Result<()> {
+        self.send(buffer)
+    }
 }
 
 // Trait used to abstract different socket (or other input/output) implementations. The funtionality
@@ -15,5 +25,5 @@ pub trait SocketReader: Send {
     // packet (size of MTU) otherwise data will be corrupted. The size of the data is
     fn recv(&mut self, buffer: &mut [u8]) -> ConResult<usize>;
 
-    fn peek(&self, buffer: &mut [u8]) -> ConResult<usize>;
+    fn peek(&mut self, buffer: &mut [u8]) -> ConResult<usize>;
 }
@@ -0,0 +1,617 @@
+// QUIC backend. Shards of streams marked as reliable are sent on a single QUIC unidirectional
+// stream, which is ordered and retransmitted by QUIC, all other shards are sent as unreliable QUIC
+// datagrams. Shards and records are framed on the stream by their own length field.
+// As for the other protocols, the client listens and the server connects. The client uses a self
+// signed certificate which is not verified: QUIC encrypts the traffic, peer authentication is
+// provided by the pairing keys.
+// With stream encryption, shards are sealed into records by this backend instead of the
+// EncryptedWriter used by the other protocols, since each path needs its own keys and counters:
+// datagrams can be lost or reordered and are accepted within the replay window, while the records
+// of the stream are accepted only in order. With shared counters, a burst of datagrams would push
+// the replay window past the records queued on the stream.
+// The QUIC connection runs on a small tokio runtime owned by the socket halves.
+
+use super::{
+    encrypted::{self, ENCRYPTION_OVERHEAD},
+    udp, SocketReader, SocketWriter,
+};
+use crate::encryption::{CipherKeys, OpeningCipher, SealingCipher};
+use alvr_common::{
+    anyhow::{anyhow, Result},
+    con_bail, debug,
+    parking_lot::RwLock,
+    warn, AnyhowToCon, ConResult, ConnectionError, ToCon,
+};
+use alvr_session::{DscpTos, SocketBufferSize};
+use quinn::{
+    crypto::rustls::QuicClientConfig, ClientConfig, Connection, Endpoint, EndpointConfig,
+    IdleTimeout, SendDatagramError, ServerConfig, TokioRuntime, TransportConfig,
+};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+use std::{
+    collections::HashSet,
+    mem,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    runtime::Runtime,
+    sync::mpsc::{self as async_mpsc, error::TrySendError},
+};
+
+// Shards bigger than this are sent on the reliable stream. The size is chosen to fit the minimum
+// QUIC MTU together with the QUIC packet overhead.
+pub const MAX_DATAGRAM_SIZE: usize = 1100;
+
+const SERVER_NAME: &str = "alvr";
+const DATAGRAM_KEYS_LABEL: &[u8] = b"QUIC datagrams";
+const STREAM_KEYS_LABEL: &[u8] = b"QUIC stream";
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+const DATAGRAM_BUFFER_SIZE: usize = 8 * 1024 * 1024;
+// Maximum number of shards queued between the sockets and the connection tasks. Received datagrams
+// are dropped when the queue is full, reliable shards are held back
+const SHARD_QUEUE_SIZE: usize = DATAGRAM_BUFFER_SIZE / MAX_DATAGRAM_SIZE;
+
+#[derive(Clone, Copy, Default)]
+pub struct TransportCongestionStats {
+    pub rtt: Duration,
+    pub congestion_window_bytes: u64,
+    pub sent_packets: u64,
+    pub lost_packets: u64,
+}
+
+// Reads the congestion control state of the QUIC connection
+#[derive(Clone)]
+pub struct CongestionMonitor {
+    connection: Connection,
+}
+
+impl CongestionMonitor {
+    pub fn stats(&self) -> TransportCongestionStats {
+        let stats = self.connection.stats();
+
+        TransportCongestionStats {
+            rtt: stats.path.rtt,
+            congestion_window_bytes: stats.path.cwnd,
+            sent_packets: stats.path.sent_packets,
+            lost_packets: stats.path.lost_packets,
+        }
+    }
+}
+
+// Accepts any certificate, the self signed certificate of the client is not known in advance. This
+// relies on the pairing layer for peer authentication: when stream encryption is enabled, shards
+// from an impostor fail authentication in EncryptedReader. Without pairing, the QUIC connection is
+// as unauthenticated as the UDP and TCP backends.
+#[derive(Debug)]
+struct SkipServerVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+fn transport_config() -> Result<Arc<TransportConfig>> {
+    let mut config = TransportConfig::default();
+    config
+        .keep_alive_interval(Some(KEEPALIVE_INTERVAL))
+        .max_idle_timeout(Some(IdleTimeout::try_from(IDLE_TIMEOUT)?))
+        .datagram_receive_buffer_size(Some(DATAGRAM_BUFFER_SIZE))
+        .datagram_send_buffer_size(DATAGRAM_BUFFER_SIZE);
+
+    Ok(Arc::new(config))
+}
+
+fn create_runtime() -> Result<Arc<Runtime>> {
+    Ok(Arc::new(
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?,
+    ))
+}
+
+struct PathKeys {
+    datagram: CipherKeys,
+    stream: CipherKeys,
+}
+
+impl PathKeys {
+    fn new(keys: &CipherKeys) -> Result<Self> {
+        Ok(Self {
+            datagram: keys.derive(DATAGRAM_KEYS_LABEL)?,
+            stream: keys.derive(STREAM_KEYS_LABEL)?,
+        })
+    }
+}
+
+// Returns the shard unchanged if there is no cipher
+fn seal_shard(cipher: &mut Option<SealingCipher>, shard: &[u8]) -> Result<Vec<u8>> {
+    if let Some(cipher) = cipher {
+        let mut record = Vec::with_capacity(shard.len() + ENCRYPTION_OVERHEAD);
+        encrypted::seal_record(cipher, shard, &mut record)?;
+
+        Ok(record)
+    } else {
+        Ok(shard.to_vec())
+    }
+}
+
+fn open_shard(
+    cipher: &mut Option<OpeningCipher>,
+    mut record: Vec<u8>,
+    in_order: bool,
+) -> Result<Vec<u8>> {
+    if let Some(cipher) = cipher {
+        let plaintext = encrypted::open_record(cipher, &mut record, in_order)?;
+        record.truncate(plaintext.end);
+        record.drain(..plaintext.start);
+    }
+
+    Ok(record)
+}
+
+pub struct QuicListener {
+    runtime: Arc<Runtime>,
+    endpoint: Endpoint,
+}
+
+pub fn bind(
+    port: u16,
+    dscp: Option<DscpTos>,
+    send_buffer_bytes: SocketBufferSize,
+    recv_buffer_bytes: SocketBufferSize,
+) -> Result<QuicListener> {
+    let runtime = create_runtime()?;
+
+    let certificate = rcgen::generate_simple_self_signed(vec![SERVER_NAME.into()])?;
+    let mut server_config = ServerConfig::with_single_cert(
+        vec![certificate.cert.der().clone()],
+        PrivatePkcs8KeyDer::from(certificate.key_pair.serialize_der()).into(),
+    )?;
+    server_config.transport_config(transport_config()?);
+
+    let socket = udp::bind(port, dscp, send_buffer_bytes, recv_buffer_bytes)?;
+
+    let _guard = runtime.enter();
+    let endpoint = Endpoint::new(
+        EndpointConfig::default(),
+        Some(server_config),
+        socket,
+        Arc::new(TokioRuntime),
+    )?;
+
+    Ok(QuicListener { runtime, endpoint })
+}
+
+pub fn accept_from_server(
+    listener: QuicListener,
+    server_ip: IpAddr,
+    timeout: Duration,
+    reliable_streams: Arc<RwLock<HashSet<u16>>>,
+    stream_keys: Option<&CipherKeys>,
+) -> ConResult<(QuicWriter, QuicReader, CongestionMonitor)> {
+    let QuicListener { runtime, endpoint } = listener;
+    let path_keys = stream_keys.map(PathKeys::new).transpose().to_con()?;
+
+    let connection = runtime.block_on(async {
+        let incoming = tokio::time::timeout(timeout, endpoint.accept())
+            .await
+            .map_err(|_| ConnectionError::TryAgain(anyhow!("Accept timeout")))?
+            .to_con()?;
+
+        if incoming.remote_address().ip() != server_ip {
+            incoming.refuse();
+            con_bail!(
+                "Connected to wrong client: Expected: {server_ip}, Found {}",
+                incoming.remote_address().ip()
+            );
+        }
+
+        incoming.await.to_con()
+    })?;
+
+    Ok(split_connection(
+        runtime,
+        endpoint,
+        connection,
+        timeout,
+        reliable_streams,
+        path_keys,
+    ))
+}
+
+pub fn connect_to_client(
+    timeout: Duration,
+    client_ip: IpAddr,
+    port: u16,
+    dscp: Option<DscpTos>,
+    send_buffer_bytes: SocketBufferSize,
+    recv_buffer_bytes: SocketBufferSize,
+    reliable_streams: Arc<RwLock<HashSet<u16>>>,
+    stream_keys: Option<&CipherKeys>,
+) -> ConResult<(QuicWriter, QuicReader, CongestionMonitor)> {
+    let path_keys = stream_keys.map(PathKeys::new).transpose().to_con()?;
+    let runtime = create_runtime().to_con()?;
+
+    let crypto_provider = Arc::new(rustls::crypto::ring::default_provider());
+    let crypto = rustls::ClientConfig::builder_with_provider(Arc::clone(&crypto_provider))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .to_con()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification(crypto_provider)))
+        .with_no_client_auth();
+    let mut client_config =
+        ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto).to_con()?));
+    client_config.transport_config(transport_config().to_con()?);
+
+    // Use an ephemeral port, so multiple clients can be connected at the same time
+    let socket = udp::bind(0, dscp, send_buffer_bytes, recv_buffer_bytes).to_con()?;
+
+    let connection = runtime.block_on(async {
+        let endpoint = Endpoint::new(
+            EndpointConfig::default(),
+            None,
+            socket,
+            Arc::new(TokioRuntime),
+        )
+        .to_con()?;
+
+        let connecting = endpoint
+            .connect_with(client_config, SocketAddr::new(client_ip, port), SERVER_NAME)
+            .to_con()?;
+        let connection = tokio::time::timeout(timeout, connecting)
+            .await
+            .map_err(|_| ConnectionError::TryAgain(anyhow!("Connect timeout")))?
+            .to_con()?;
+
+        Ok((endpoint, connection))
+    });
+    let (endpoint, connection) = connection?;
+
+    Ok(split_connection(
+        runtime,
+        endpoint,
+        connection,
+        timeout,
+        reliable_streams,
+        path_keys,
+    ))
+}
+
+fn split_connection(
+    runtime: Arc<Runtime>,
+    endpoint: Endpoint,
+    connection: Connection,
+    timeout: Duration,
+    reliable_streams: Arc<RwLock<HashSet<u16>>>,
+    path_keys: Option<PathKeys>,
+) -> (QuicWriter, QuicReader, CongestionMonitor) {
+    let (shard_sender, shard_receiver) = async_mpsc::channel(SHARD_QUEUE_SIZE);
+
+    // Datagrams
+    runtime.spawn({
+        let connection = connection.clone();
+        let shard_sender = shard_sender.clone();
+        let mut cipher = path_keys
+            .as_ref()
+            .map(|keys| OpeningCipher::new(&keys.datagram));
+        async move {
+            loop {
+                match connection.read_datagram().await {
+                    Ok(datagram) => {
+                        let shard = match open_shard(&mut cipher, datagram.to_vec(), false) {
+                            Ok(shard) => shard,
+                            Err(e) => {
+                                debug!("Dropped QUIC datagram: {e}");
+                                continue;
+                            }
+                        };
+
+                        match shard_sender.try_send(Ok(shard)) {
+                            Ok(()) => (),
+                            // Like a full UDP socket buffer
+                            Err(TrySendError::Full(_)) => debug!("Dropped QUIC datagram"),
+                            Err(TrySendError::Closed(_)) => return,
+                        }
+                    }
+                    Err(e) => {
+                        shard_sender.send(Err(e.to_string())).await.ok();
+                        return;
+                    }
+                }
+            }
+        }
+    });
+
+    // Reliable stream
+    runtime.spawn({
+        let connection = connection.clone();
+        let mut cipher = path_keys
+            .as_ref()
+            .map(|keys| OpeningCipher::new(&keys.stream));
+        async move {
+            let Ok(mut stream) = connection.accept_uni().await else {
+                return;
+            };
+
+            loop {
+                let mut length_bytes = [0; mem::size_of::<u32>()];
+                if stream.read_exact(&mut length_bytes).await.is_err() {
+                    return;
+                }
+
+                // Shards and records are never bigger than a datagram, see StreamSocketBuilder
+                let length = length_bytes.len() + u32::from_be_bytes(length_bytes) as usize;
+                if length > MAX_DATAGRAM_SIZE {
+                    shard_sender
+                        .send(Err(format!(
+                            "Oversized shard on QUIC stream ({length} bytes)"
+                        )))
+                        .await
+                        .ok();
+                    return;
+                }
+
+                let mut shard = vec![0; length];
+                shard[..length_bytes.len()].copy_from_slice(&length_bytes);
+                if stream
+                    .read_exact(&mut shard[length_bytes.len()..])
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+
+                // The stream cannot be resynchronized
+                let shard = match open_shard(&mut cipher, shard, true) {
+                    Ok(shard) => Ok(shard),
+                    Err(e) => Err(format!("Invalid record on QUIC stream: {e}")),
+                };
+                let is_err = shard.is_err();
+                if shard_sender.send(shard).await.is_err() || is_err {
+                    return;
+                }
+            }
+        }
+    });
+
+    let (reliable_sender, mut reliable_receiver) = async_mpsc::channel::<Vec<u8>>(SHARD_QUEUE_SIZE);
+    runtime.spawn({
+        let connection = connection.clone();
+        async move {
+            let mut stream = match connection.open_uni().await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("Failed to open QUIC stream: {e}");
+                    return;
+                }
+            };
+
+            while let Some(shard) = reliable_receiver.recv().await {
+                if let Err(e) = stream.write_all(&shard).await {
+                    debug!("Failed to write QUIC stream: {e}");
+                    return;
+                }
+            }
+        }
+    });
+
+    (
+        QuicWriter {
+            _runtime: Arc::clone(&runtime),
+            _endpoint: endpoint.clone(),
+            connection: connection.clone(),
+            reliable_sender,
+            reliable_streams,
+            datagram_cipher: path_keys
+                .as_ref()
+                .map(|keys| SealingCipher::new(&keys.datagram)),
+            stream_cipher: path_keys
+                .as_ref()
+                .map(|keys| SealingCipher::new(&keys.stream)),
+            oversized_shards_count: 0,
+        },
+        QuicReader {
+            runtime,
+            _endpoint: endpoint,
+            shard_receiver,
+            shard: None,
+            timeout,
+        },
+        CongestionMonitor { connection },
+    )
+}
+
+pub struct QuicWriter {
+    // Keeps the connection tasks alive
+    _runtime: Arc<Runtime>,
+    _endpoint: Endpoint,
+    connection: Connection,
+    reliable_sender: async_mpsc::Sender<Vec<u8>>,
+    reliable_streams: Arc<RwLock<HashSet<u16>>>,
+    datagram_cipher: Option<SealingCipher>,
+    // Records must be sealed in the order they are written to the stream
+    stream_cipher: Option<SealingCipher>,
+    // Shards of unreliable streams sent on the reliable stream because they did not fit in a
+    // datagram
+    oversized_shards_count: u64,
+}
+
+impl QuicWriter {
+    // Blocks while the stream is congested, like a TCP socket
+    fn send_reliable(&mut self, buffer: &[u8]) -> Result<()> {
+        let record = seal_shard(&mut self.stream_cipher, buffer)?;
+
+        self.reliable_sender
+            .blocking_send(record)
+            .map_err(|_| anyhow!("QUIC stream closed"))
+    }
+}
+
+impl SocketWriter for QuicWriter {
+    // Buffers sent without a stream ID are sent on the reliable stream
+    fn send(&mut self, buffer: &[u8]) -> Result<()> {
+        self.send_reliable(buffer)
+    }
+
+    fn send_shard(&mut self, stream_id: u16, buffer: &[u8]) -> Result<()> {
+        if !self.reliable_streams.read().contains(&stream_id) {
+            // If the datagram is too large, the shard is sealed again for the stream. The
+            // skipped datagram counter is seen as a lost datagram by the peer
+            let record = seal_shard(&mut self.datagram_cipher, buffer)?;
+            match self.connection.send_datagram(record.into()) {
+                Ok(()) => return Ok(()),
+                Err(SendDatagramError::TooLarge) => {
+                    self.oversized_shards_count += 1;
+                    if self.oversized_shards_count.is_power_of_two() {
+                        warn!(
+                            "{} shards of stream {stream_id} ({} bytes) were too large for a QUIC datagram and were sent reliably",
+                            self.oversized_shards_count,
+                            buffer.len()
+                        );
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        self.send_reliable(buffer)
+    }
+}
+
+pub struct QuicReader {
+    runtime: Arc<Runtime>,
+    _endpoint: Endpoint,
+    shard_receiver: async_mpsc::Receiver<Result<Vec<u8>, String>>,
+    shard: Option<Vec<u8>>,
+    timeout: Duration,
+}
+
+impl QuicReader {
+    fn fill_shard(&mut self) -> ConResult<&[u8]> {
+        if self.shard.is_none() {
+            let res = self.runtime.block_on(tokio::time::timeout(
+                self.timeout,
+                self.shard_receiver.recv(),
+            ));
+            match res {
+                Ok(Some(Ok(shard))) => self.shard = Some(shard),
+                Ok(Some(Err(e))) => con_bail!("QUIC connection closed: {e}"),
+                Ok(None) => con_bail!("QUIC connection closed"),
+                Err(_) => return alvr_common::try_again(),
+            }
+        }
+
+        Ok(self.shard.as_deref().unwrap())
+    }
+}
+
+// Like UDP sockets, each recv() consumes a whole shard
+impl SocketReader for QuicReader {
+    fn recv(&mut self, buffer: &mut [u8]) -> ConResult<usize> {
+        let shard = self.fill_shard()?;
+
+        let size = usize::min(buffer.len(), shard.len());
+        buffer[..size].copy_from_slice(&shard[..size]);
+        self.shard = None;
+
+        Ok(size)
+    }
+
+    fn peek(&mut self, buffer: &mut [u8]) -> ConResult<usize> {
+        let shard = self.fill_shard()?;
+
+        let size = usize::min(buffer.len(), shard.len());
+        buffer[..size].copy_from_slice(&shard[..size]);
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_records_after_datagrams() {
+        let (server_keys, client_keys) = CipherKeys::test_pair();
+        let server_keys = PathKeys::new(&server_keys).unwrap();
+        let client_keys = PathKeys::new(&client_keys).unwrap();
+
+        let mut datagram_sealing = Some(SealingCipher::new(&server_keys.datagram));
+        let mut stream_sealing = Some(SealingCipher::new(&server_keys.stream));
+        let mut datagram_opening = Some(OpeningCipher::new(&client_keys.datagram));
+        let mut stream_opening = Some(OpeningCipher::new(&client_keys.stream));
+
+        // The stream record is delivered after many more datagrams than the replay window
+        let record = seal_shard(&mut stream_sealing, b"haptics").unwrap();
+        for _ in 0..200 {
+            let datagram = seal_shard(&mut datagram_sealing, b"video").unwrap();
+            assert_eq!(
+                open_shard(&mut datagram_opening, datagram, false).unwrap(),
+                b"video"
+            );
+        }
+        assert_eq!(
+            open_shard(&mut stream_opening, record.clone(), true).unwrap(),
+            b"haptics"
+        );
+
+        // Replayed records and datagrams injected in the stream are rejected
+        assert!(open_shard(&mut stream_opening, record, true).is_err());
+        let datagram = seal_shard(&mut datagram_sealing, b"video").unwrap();
+        assert!(open_shard(&mut stream_opening, datagram, true).is_err());
+
+        // Without encryption the shards are unchanged
+        let shard = seal_shard(&mut None, b"shard").unwrap();
+        assert_eq!(open_shard(&mut None, shard, true).unwrap(), b"shard");
+    }
+}
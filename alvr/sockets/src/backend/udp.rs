@@ -56,7 +56,7 @@ impl SocketReader for Socket {
         .handle_try_again()
     }
 
-    fn peek(&self, buffer: &mut [u8]) -> ConResult<usize> {
+    fn peek(&mut self, buffer: &mut [u8]) -> ConResult<usize> {
         #[cfg(windows)]
         const FLAGS: c_int = 0x02 | 0x8000; // MSG_PEEK | MSG_PARTIAL
         #[cfg(not(windows))]
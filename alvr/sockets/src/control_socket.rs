@@ -1,4 +1,7 @@
-use crate::backend::{tcp, SocketReader, SocketWriter};
+use crate::{
+    backend::{tcp, SocketReader, SocketWriter},
+    encryption::{CipherKeys, OpeningCipher, SealingCipher, TAG_LENGTH},
+};
 
 use super::CONTROL_PORT;
 use alvr_common::{anyhow::Result, ConResult, HandleTryAgain, ToCon};
@@ -19,20 +22,31 @@ struct RecvState {
     packet_cursor: usize, // counts also the length prefix bytes
 }
 
+// When encryption is enabled, the payload is followed by the authentication tag. The nonce counter
+// is implicit since TCP is reliable and ordered.
 fn framed_send<S: Serialize>(
     socket: &mut TcpStream,
     buffer: &mut Vec<u8>,
+    cipher: Option<&mut SealingCipher>,
     packet: &S,
 ) -> Result<()> {
     let serialized_size = bincode::serialized_size(&packet)? as usize;
-    let packet_size = serialized_size + FRAMED_PREFIX_LENGTH;
+    let payload_size = serialized_size + if cipher.is_some() { TAG_LENGTH } else { 0 };
+    let packet_size = payload_size + FRAMED_PREFIX_LENGTH;
 
     if buffer.len() < packet_size {
         buffer.resize(packet_size, 0);
     }
 
-    buffer[0..FRAMED_PREFIX_LENGTH].copy_from_slice(&(serialized_size as u32).to_be_bytes());
-    bincode::serialize_into(&mut buffer[FRAMED_PREFIX_LENGTH..packet_size], &packet)?;
+    buffer[0..FRAMED_PREFIX_LENGTH].copy_from_slice(&(payload_size as u32).to_be_bytes());
+    bincode::serialize_into(
+        &mut buffer[FRAMED_PREFIX_LENGTH..FRAMED_PREFIX_LENGTH + serialized_size],
+        &packet,
+    )?;
+
+    if let Some(cipher) = cipher {
+        cipher.seal(&mut buffer[FRAMED_PREFIX_LENGTH..packet_size])?;
+    }
 
     socket.send(&buffer[0..packet_size])?;
 
@@ -42,6 +56,7 @@ fn framed_send<S: Serialize>(
 fn framed_recv<R: DeserializeOwned>(
     socket: &mut TcpStream,
     buffer: &mut Vec<u8>,
+    cipher: Option<&mut OpeningCipher>,
     maybe_recv_state: &mut Option<RecvState>,
     timeout: Duration,
 ) -> ConResult<R> {
@@ -87,29 +102,44 @@ fn framed_recv<R: DeserializeOwned>(
         }
     }
 
-    let packet = bincode::deserialize(&buffer[FRAMED_PREFIX_LENGTH..recv_state_mut.packet_length])
-        .to_con()?;
-
+    let packet_length = recv_state_mut.packet_length;
     *maybe_recv_state = None;
 
+    let payload_size = if let Some(cipher) = cipher {
+        cipher
+            .open_next(&mut buffer[FRAMED_PREFIX_LENGTH..packet_length])
+            .to_con()?
+    } else {
+        packet_length - FRAMED_PREFIX_LENGTH
+    };
+
+    let packet = bincode::deserialize(&buffer[FRAMED_PREFIX_LENGTH..][..payload_size]).to_con()?;
+
     Ok(packet)
 }
 
 pub struct ControlSocketSender<T> {
     inner: TcpStream,
     buffer: Vec<u8>,
+    cipher: Option<SealingCipher>,
     _phantom: PhantomData<T>,
 }
 
 impl<S: Serialize> ControlSocketSender<S> {
     pub fn send(&mut self, packet: &S) -> Result<()> {
-        framed_send(&mut self.inner, &mut self.buffer, packet)
+        framed_send(
+            &mut self.inner,
+            &mut self.buffer,
+            self.cipher.as_mut(),
+            packet,
+        )
     }
 }
 
 pub struct ControlSocketReceiver<T> {
     inner: TcpStream,
     buffer: Vec<u8>,
+    cipher: Option<OpeningCipher>,
     recv_state: Option<RecvState>,
     _phantom: PhantomData<T>,
 }
@@ -119,6 +149,7 @@ impl<R: DeserializeOwned> ControlSocketReceiver<R> {
         framed_recv(
             &mut self.inner,
             &mut self.buffer,
+            self.cipher.as_mut(),
             &mut self.recv_state,
             timeout,
         )
@@ -141,6 +172,8 @@ pub fn get_server_listener(timeout: Duration) -> Result<TcpListener> {
 // the specified types can be exchanged
 pub struct ProtoControlSocket {
     inner: TcpStream,
+    sealing_cipher: Option<SealingCipher>,
+    opening_cipher: Option<OpeningCipher>,
 }
 
 pub enum PeerType<'a> {
@@ -166,15 +199,40 @@ impl ProtoControlSocket {
 
         let peer_ip = socket.peer_addr().to_con()?.ip();
 
-        Ok((Self { inner: socket }, peer_ip))
+        Ok((
+            Self {
+                inner: socket,
+                sealing_cipher: None,
+                opening_cipher: None,
+            },
+            peer_ip,
+        ))
+    }
+
+    // All packets sent and received after this call are encrypted, including the ones exchanged
+    // by the split sockets. Both peers must enable encryption at the same point of the handshake.
+    pub fn enable_encryption(&mut self, keys: &CipherKeys) {
+        self.sealing_cipher = Some(SealingCipher::new(keys));
+        self.opening_cipher = Some(OpeningCipher::new(keys));
     }
 
     pub fn send<S: Serialize>(&mut self, packet: &S) -> Result<()> {
-        framed_send(&mut self.inner, &mut vec![], packet)
+        framed_send(
+            &mut self.inner,
+            &mut vec![],
+            self.sealing_cipher.as_mut(),
+            packet,
+        )
     }
 
     pub fn recv<R: DeserializeOwned>(&mut self, timeout: Duration) -> ConResult<R> {
-        framed_recv(&mut self.inner, &mut vec![], &mut None, timeout)
+        framed_recv(
+            &mut self.inner,
+            &mut vec![],
+            self.opening_cipher.as_mut(),
+            &mut None,
+            timeout,
+        )
     }
 
     pub fn split<S: Serialize, R: DeserializeOwned>(
@@ -187,11 +245,13 @@ impl ProtoControlSocket {
             ControlSocketSender {
                 inner: self.inner.try_clone()?,
                 buffer: vec![],
+                cipher: self.sealing_cipher,
                 _phantom: PhantomData,
             },
             ControlSocketReceiver {
                 inner: self.inner,
                 buffer: vec![],
+                cipher: self.opening_cipher,
                 recv_state: None,
                 _phantom: PhantomData,
             },
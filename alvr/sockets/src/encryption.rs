@@ -0,0 +1,513 @@
+// Pairing and authenticated encryption of control and stream traffic.
+//
+// Peers perform an ephemeral X25519 key exchange for every connection. The shared secret is mixed
+// with a long term secret: during pairing this is the PIN shown in the client lobby, afterwards it
+// is the pairing key derived during the pairing connection and persisted by both peers. Each side
+// proves knowledge of the long term secret with a confirmation tag over the exchanged public keys,
+// then all traffic is encrypted with ChaCha20-Poly1305 using per-direction keys.
+// Note: the PIN protects against passive eavesdroppers and blind impersonation, but it is not a
+// PAKE: an active attacker present during pairing could brute force it offline. Pairing should be
+// done once, on a network the user trusts at that moment.
+
+use alvr_common::anyhow::{anyhow, bail, Result};
+use rand::Rng;
+use ring::{
+    aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305},
+    agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519},
+    hkdf, hmac,
+    rand::SystemRandom,
+};
+use std::fmt::Write;
+
+pub const PAIRING_PIN_LENGTH: usize = 6;
+pub const KEY_LENGTH: usize = 32;
+pub const TAG_LENGTH: usize = aead::MAX_TAG_LEN;
+
+const TRANSCRIPT_LABEL: &[u8] = b"ALVR key exchange v1";
+const REPLAY_WINDOW_SIZE: u64 = u64::BITS as u64;
+
+pub type Key = [u8; KEY_LENGTH];
+
+pub fn generate_pairing_pin() -> String {
+    let mut rng = rand::thread_rng();
+
+    (0..PAIRING_PIN_LENGTH)
+        .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+        .collect()
+}
+
+pub fn key_to_string(key: &Key) -> String {
+    key.iter().fold(String::new(), |mut string, byte| {
+        write!(string, "{byte:02x}").ok();
+        string
+    })
+}
+
+pub fn key_from_string(string: &str) -> Result<Key> {
+    if string.len() != KEY_LENGTH * 2 || !string.is_ascii() {
+        bail!("Invalid key length");
+    }
+
+    let mut key = [0; KEY_LENGTH];
+    for (idx, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&string[idx * 2..idx * 2 + 2], 16)?;
+    }
+
+    Ok(key)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeRole {
+    Server,
+    Client,
+}
+
+pub enum LongTermSecret<'a> {
+    PairingPin(&'a str),
+    PairingKey(&'a Key),
+}
+
+#[derive(Clone)]
+pub struct CipherKeys {
+    send: Key,
+    recv: Key,
+}
+
+impl CipherKeys {
+    // Independent keys for a channel that needs its own counters. Both peers derive the same keys
+    // for each direction.
+    pub(crate) fn derive(&self, label: &[u8]) -> Result<Self> {
+        let derive_key =
+            |key: &Key| expand_key(&hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, key), &[label]);
+
+        Ok(Self {
+            send: derive_key(&self.send)?,
+            recv: derive_key(&self.recv)?,
+        })
+    }
+
+    // Keys of the two peers of a connection
+    #[cfg(test)]
+    pub(crate) fn test_pair() -> (Self, Self) {
+        let mut rng = rand::thread_rng();
+        let server_key = rng.gen::<Key>();
+        let client_key = rng.gen::<Key>();
+
+        (
+            Self {
+                send: server_key,
+                recv: client_key,
+            },
+            Self {
+                send: client_key,
+                recv: server_key,
+            },
+        )
+    }
+}
+
+pub struct SessionKeys {
+    pub pairing_key: Key,
+    pub control: CipherKeys,
+    pub stream: CipherKeys,
+    transcript: Vec<u8>,
+    local_confirmation_key: hmac::Key,
+    peer_confirmation_key: hmac::Key,
+}
+
+impl SessionKeys {
+    pub fn confirmation(&self) -> Vec<u8> {
+        hmac::sign(&self.local_confirmation_key, &self.transcript)
+            .as_ref()
+            .to_vec()
+    }
+
+    pub fn verify_peer_confirmation(&self, tag: &[u8]) -> Result<()> {
+        hmac::verify(&self.peer_confirmation_key, &self.transcript, tag)
+            .map_err(|_| anyhow!("Peer key confirmation failed"))
+    }
+}
+
+fn expand_key(prk: &hkdf::Prk, info: &[&[u8]]) -> Result<Key> {
+    let mut key = [0; KEY_LENGTH];
+    prk.expand(info, hkdf::HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut key))
+        .map_err(|_| anyhow!("Key derivation failed"))?;
+
+    Ok(key)
+}
+
+pub struct KeyExchange {
+    private_key: EphemeralPrivateKey,
+    public_key: Vec<u8>,
+}
+
+impl KeyExchange {
+    pub fn new() -> Result<Self> {
+        let private_key = EphemeralPrivateKey::generate(&X25519, &SystemRandom::new())
+            .map_err(|_| anyhow!("Failed to generate key pair"))?;
+        let public_key = private_key
+            .compute_public_key()
+            .map_err(|_| anyhow!("Failed to compute public key"))?
+            .as_ref()
+            .to_vec();
+
+        Ok(Self {
+            private_key,
+            public_key,
+        })
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    pub fn finish(
+        self,
+        role: HandshakeRole,
+        peer_public_key: &[u8],
+        secret: LongTermSecret,
+    ) -> Result<SessionKeys> {
+        let (server_public_key, client_public_key) = match role {
+            HandshakeRole::Server => (self.public_key.as_slice(), peer_public_key),
+            HandshakeRole::Client => (peer_public_key, self.public_key.as_slice()),
+        };
+        let transcript = [TRANSCRIPT_LABEL, server_public_key, client_public_key].concat();
+
+        agreement::agree_ephemeral(
+            self.private_key,
+            &UnparsedPublicKey::new(&X25519, peer_public_key),
+            |shared_secret| {
+                let pairing_key = match secret {
+                    LongTermSecret::PairingPin(pin) => expand_key(
+                        &hkdf::Salt::new(hkdf::HKDF_SHA256, pin.as_bytes()).extract(shared_secret),
+                        &[transcript.as_slice(), b"pairing key"],
+                    )?,
+                    LongTermSecret::PairingKey(key) => *key,
+                };
+
+                let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &pairing_key).extract(shared_secret);
+                let derive = |label: &[u8]| expand_key(&prk, &[transcript.as_slice(), label]);
+
+                let server_control = derive(b"server control")?;
+                let client_control = derive(b"client control")?;
+                let server_stream = derive(b"server stream")?;
+                let client_stream = derive(b"client stream")?;
+                let server_confirmation =
+                    hmac::Key::new(hmac::HMAC_SHA256, &derive(b"server confirmation")?);
+                let client_confirmation =
+                    hmac::Key::new(hmac::HMAC_SHA256, &derive(b"client confirmation")?);
+
+                Ok(match role {
+                    HandshakeRole::Server => SessionKeys {
+                        pairing_key,
+                        control: CipherKeys {
+                            send: server_control,
+                            recv: client_control,
+                        },
+                        stream: CipherKeys {
+                            send: server_stream,
+                            recv: client_stream,
+                        },
+                        transcript,
+                        local_confirmation_key: server_confirmation,
+                        peer_confirmation_key: client_confirmation,
+                    },
+                    HandshakeRole::Client => SessionKeys {
+                        pairing_key,
+                        control: CipherKeys {
+                            send: client_control,
+                            recv: server_control,
+                        },
+                        stream: CipherKeys {
+                            send: client_stream,
+                            recv: server_stream,
+                        },
+                        transcript,
+                        local_confirmation_key: client_confirmation,
+                        peer_confirmation_key: server_confirmation,
+                    },
+                })
+            },
+        )
+        .map_err(|_| anyhow!("Invalid peer public key"))?
+    }
+}
+
+fn nonce_from_counter(counter: u64) -> Nonce {
+    let mut nonce = [0; aead::NONCE_LEN];
+    nonce[aead::NONCE_LEN - 8..].copy_from_slice(&counter.to_be_bytes());
+
+    Nonce::assume_unique_for_key(nonce)
+}
+
+fn create_key(key: &Key) -> LessSafeKey {
+    // Note: unwrap is safe because the key length matches the algorithm
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).unwrap())
+}
+
+pub(crate) struct SealingCipher {
+    key: LessSafeKey,
+    counter: u64,
+}
+
+impl SealingCipher {
+    pub fn new(keys: &CipherKeys) -> Self {
+        Self {
+            key: create_key(&keys.send),
+            counter: 0,
+        }
+    }
+
+    // The last TAG_LENGTH bytes of the buffer are reserved for the tag. Returns the counter used
+    // for the nonce.
+    pub fn seal(&mut self, buffer: &mut [u8]) -> Result<u64> {
+        let counter = self.counter;
+        self.counter += 1;
+
+        let (data, tag_buffer) = buffer.split_at_mut(buffer.len() - TAG_LENGTH);
+        let tag = self
+            .key
+            .seal_in_place_separate_tag(nonce_from_counter(counter), Aad::empty(), data)
+            .map_err(|_| anyhow!("Encryption failed"))?;
+        tag_buffer.copy_from_slice(tag.as_ref());
+
+        Ok(counter)
+    }
+}
+
+pub(crate) struct OpeningCipher {
+    key: LessSafeKey,
+    highest_counter: Option<u64>,
+    // Bit N is set if the packet with counter highest_counter - N has been received
+    replay_mask: u64,
+}
+
+impl OpeningCipher {
+    pub fn new(keys: &CipherKeys) -> Self {
+        Self {
+            key: create_key(&keys.recv),
+            highest_counter: None,
+            replay_mask: 0,
+        }
+    }
+
+    // Decrypts in place a buffer that ends with the tag. Returns the length of the plaintext.
+    pub fn open(&mut self, counter: u64, buffer: &mut [u8]) -> Result<usize> {
+        if let Some(highest) = self.highest_counter {
+            if counter <= highest {
+                let distance = highest - counter;
+                if distance >= REPLAY_WINDOW_SIZE || self.replay_mask & (1 << distance) != 0 {
+                    bail!("Discarded replayed or stale packet");
+                }
+            }
+        }
+
+        let plaintext_length = self
+            .key
+            .open_in_place(nonce_from_counter(counter), Aad::empty(), buffer)
+            .map_err(|_| anyhow!("Packet authentication failed"))?
+            .len();
+
+        match self.highest_counter {
+            Some(highest) if counter <= highest => {
+                self.replay_mask |= 1 << (highest - counter);
+            }
+            Some(highest) => {
+                let shift = counter - highest;
+                self.replay_mask = if shift < REPLAY_WINDOW_SIZE {
+                    (self.replay_mask << shift) | 1
+                } else {
+                    1
+                };
+                self.highest_counter = Some(counter);
+            }
+            None => {
+                self.replay_mask = 1;
+                self.highest_counter = Some(counter);
+            }
+        }
+
+        Ok(plaintext_length)
+    }
+
+    fn next_counter(&self) -> u64 {
+        self.highest_counter.map(|c| c + 1).unwrap_or(0)
+    }
+
+    // For ordered transports, where the counter is implicit
+    pub fn open_next(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        self.open(self.next_counter(), buffer)
+    }
+
+    // For ordered transports that send the counter. Any counter other than the next one means that
+    // a record was lost, replayed or injected, which cannot happen on a reliable channel.
+    pub fn open_in_order(&mut self, counter: u64, buffer: &mut [u8]) -> Result<usize> {
+        let next_counter = self.next_counter();
+        if counter != next_counter {
+            bail!("Unexpected record counter {counter}, expected {next_counter}");
+        }
+
+        self.open(counter, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange_keys(server_pin: &str, client_pin: &str) -> (SessionKeys, SessionKeys) {
+        let server = KeyExchange::new().unwrap();
+        let client = KeyExchange::new().unwrap();
+        let server_public_key = server.public_key().to_vec();
+        let client_public_key = client.public_key().to_vec();
+
+        (
+            server
+                .finish(
+                    HandshakeRole::Server,
+                    &client_public_key,
+                    LongTermSecret::PairingPin(server_pin),
+                )
+                .unwrap(),
+            client
+                .finish(
+                    HandshakeRole::Client,
+                    &server_public_key,
+                    LongTermSecret::PairingPin(client_pin),
+                )
+                .unwrap(),
+        )
+    }
+
+    fn seal(cipher: &mut SealingCipher, plaintext: &[u8]) -> (u64, Vec<u8>) {
+        let mut buffer = [plaintext, &[0; TAG_LENGTH]].concat();
+        let counter = cipher.seal(&mut buffer).unwrap();
+
+        (counter, buffer)
+    }
+
+    #[test]
+    fn seal_open_round_trip() {
+        let (server_keys, client_keys) = exchange_keys("123456", "123456");
+        assert_eq!(server_keys.pairing_key, client_keys.pairing_key);
+
+        let mut sealing = SealingCipher::new(&server_keys.stream);
+        let mut opening = OpeningCipher::new(&client_keys.stream);
+
+        for plaintext in [&b"first"[..], &b"second"[..]] {
+            let (counter, mut buffer) = seal(&mut sealing, plaintext);
+            assert_ne!(&buffer[..plaintext.len()], plaintext);
+
+            let size = opening.open(counter, &mut buffer).unwrap();
+            assert_eq!(&buffer[..size], plaintext);
+        }
+
+        // Tampered ciphertext
+        let (counter, mut buffer) = seal(&mut sealing, b"third");
+        buffer[0] ^= 1;
+        assert!(opening.open(counter, &mut buffer).is_err());
+
+        // Each direction uses its own key
+        let (counter, mut buffer) = seal(&mut sealing, b"fourth");
+        assert!(OpeningCipher::new(&server_keys.stream)
+            .open(counter, &mut buffer)
+            .is_err());
+    }
+
+    #[test]
+    fn replayed_counters() {
+        let (server_keys, client_keys) = exchange_keys("123456", "123456");
+        let mut sealing = SealingCipher::new(&server_keys.stream);
+        let mut opening = OpeningCipher::new(&client_keys.stream);
+
+        let records = (0..REPLAY_WINDOW_SIZE + 8)
+            .map(|_| seal(&mut sealing, b"data"))
+            .collect::<Vec<_>>();
+
+        // Out of order delivery is accepted, duplicates are not
+        for idx in [1, 0, 2] {
+            let (counter, buffer) = &records[idx];
+            assert!(opening.open(*counter, &mut buffer.clone()).is_ok());
+        }
+        for idx in [0, 1, 2] {
+            let (counter, buffer) = &records[idx];
+            assert!(opening.open(*counter, &mut buffer.clone()).is_err());
+        }
+
+        // Counters older than the replay window are rejected, even if never received
+        let (counter, buffer) = records.last().unwrap();
+        assert!(opening.open(*counter, &mut buffer.clone()).is_ok());
+        let (counter, buffer) = &records[3];
+        assert!(opening.open(*counter, &mut buffer.clone()).is_err());
+    }
+
+    #[test]
+    fn ordered_counters() {
+        let (server_keys, client_keys) = CipherKeys::test_pair();
+        let mut sealing = SealingCipher::new(&server_keys);
+        let mut opening = OpeningCipher::new(&client_keys);
+
+        let records = (0..3)
+            .map(|_| seal(&mut sealing, b"data"))
+            .collect::<Vec<_>>();
+
+        let (counter, buffer) = &records[0];
+        assert!(opening.open_in_order(*counter, &mut buffer.clone()).is_ok());
+        assert!(opening
+            .open_in_order(*counter, &mut buffer.clone())
+            .is_err());
+
+        // Gaps are not accepted
+        let (counter, buffer) = &records[2];
+        assert!(opening
+            .open_in_order(*counter, &mut buffer.clone())
+            .is_err());
+        let (counter, buffer) = &records[1];
+        assert!(opening.open_in_order(*counter, &mut buffer.clone()).is_ok());
+    }
+
+    #[test]
+    fn derived_keys() {
+        let (server_keys, client_keys) = CipherKeys::test_pair();
+
+        let mut sealing = SealingCipher::new(&server_keys.derive(b"channel").unwrap());
+        let (counter, buffer) = seal(&mut sealing, b"data");
+
+        assert!(OpeningCipher::new(&client_keys.derive(b"channel").unwrap())
+            .open(counter, &mut buffer.clone())
+            .is_ok());
+        assert!(
+            OpeningCipher::new(&client_keys.derive(b"other channel").unwrap())
+                .open(counter, &mut buffer.clone())
+                .is_err()
+        );
+        assert!(OpeningCipher::new(&client_keys)
+            .open(counter, &mut buffer.clone())
+            .is_err());
+    }
+
+    #[test]
+    fn confirmation_mismatch() {
+        let (server_keys, client_keys) = exchange_keys("123456", "123456");
+        server_keys
+            .verify_peer_confirmation(&client_keys.confirmation())
+            .unwrap();
+        client_keys
+            .verify_peer_confirmation(&server_keys.confirmation())
+            .unwrap();
+
+        // A peer can't reflect the confirmation of the other peer
+        assert!(server_keys
+            .verify_peer_confirmation(&server_keys.confirmation())
+            .is_err());
+
+        let (server_keys, client_keys) = exchange_keys("123456", "654321");
+        assert!(server_keys
+            .verify_peer_confirmation(&client_keys.confirmation())
+            .is_err());
+        assert!(client_keys
+            .verify_peer_confirmation(&server_keys.confirmation())
+            .is_err());
+    }
+}
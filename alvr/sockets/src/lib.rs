@@ -1,5 +1,6 @@
 mod backend;
 mod control_socket;
+mod encryption;
 mod stream_socket;
 
 use alvr_common::{anyhow::Result, info};
@@ -11,6 +12,7 @@ use std::{
 };
 
 pub use control_socket::*;
+pub use encryption::*;
 pub use stream_socket::*;
 
 pub const LOCAL_IP: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
@@ -16,28 +16,104 @@
 // Note: We can't clone the underlying socket for each StreamSender and the mutex around the socket
 // cannot be removed. This is because we need to make sure at least shards are written whole.
 
-use crate::backend::{tcp, udp, SocketReader, SocketWriter};
+// Forward error correction:
+// When enabled on a StreamSender, data shards are split in groups and for each group a parity
+// shard (XOR of all data shards of the group) is sent right after the last shard of the group. The
+// receiver can then rebuild one lost shard per group. Parity shards are marked by setting the
+// highest bit of the shard index, which also encodes the group size and the group index. In place
+// of the shards count, parity shards carry the size of the packet (without prefix), so the length
+// of a lost last shard can be recovered. This makes the receiver side configuration free.
+
+// Retransmission:
+// For streams with retransmission enabled, the sender keeps the last few sent buffers instead of
+// recycling them immediately, together with the data overwritten by the shard prefixes. When the
+// receiver detects a gap in the shard indices of a packet, or a whole packet missing, it sends a
+// NACK on a reserved stream ID with the list of missing shards (an empty list means the whole
+// packet), and the socket on the other side resends only those shards. A packet completed by
+// resent shards is delivered even if newer packets of the same stream have already been delivered,
+// so the receivers of these streams must handle reordering. Only the newer packet that skipped the
+// late one is marked as having packet loss.
+
+// Encryption:
+// When the stream keys are provided, the underlying socket is wrapped so that each shard is sent
+// as an authenticated and encrypted record. The max packet size is reduced by the encryption
+// overhead, so UDP datagrams keep the same size as with unencrypted streams.
+
+// Receive limits:
+// Each stream can be given a memory budget. A new packet is admitted only if the size of all its
+// shards, together with the in progress packets, fits in the budget. Older in progress packets
+// are evicted to make room, and packets that don't fit at all are dropped. In progress packets
+// that didn't receive new shards for too long are evicted as stale. Recycled buffers much bigger
+// than the packet they are reused for are shrunk, so memory is returned after a spike.
+
+// QUIC:
+// Shards of the streams set as reliable are sent on an ordered QUIC stream, the others as QUIC
+// datagrams. The packet size is capped so shards fit in a datagram. Both peers derive the same
+// size from the settings, which is required to locate the shards in the packet.
+
+// Network emulation:
+// For testing, the raw send socket can be wrapped to add latency, jitter, a bandwidth limit, loss
+// and reordering to the outgoing shards. The wrapper sits below the encryption layer, so it sees
+// the datagrams as they are sent on the wire.
+
+use crate::{
+    backend::{
+        emulated::EmulatedWriter,
+        encrypted::{self, EncryptedReader, EncryptedWriter},
+        quic, tcp, udp, SocketReader, SocketWriter,
+    },
+    encryption::CipherKeys,
+};
 use alvr_common::{
-    anyhow::Result, debug, parking_lot::Mutex, AnyhowToCon, ConResult, HandleTryAgain, ToCon,
+    anyhow::Result,
+    debug,
+    parking_lot::{Mutex, RwLock},
+    AnyhowToCon, ConResult, HandleTryAgain, ToCon,
+};
+use alvr_session::{
+    DscpTos, ForwardErrorCorrectionConfig, NetworkEmulationConfig, SocketBufferSize, SocketProtocol,
 };
-use alvr_session::{DscpTos, SocketBufferSize, SocketProtocol};
 use serde::{de::DeserializeOwned, Serialize};
 use std::{
     cmp::Ordering,
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     marker::PhantomData,
     mem,
     net::{IpAddr, TcpListener, UdpSocket},
     sync::{mpsc, Arc},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+pub use crate::backend::quic::{CongestionMonitor, TransportCongestionStats};
+
 const SHARD_PREFIX_SIZE: usize = mem::size_of::<u32>() // packet length - field itself (4 bytes)
     + mem::size_of::<u16>() // stream ID
     + mem::size_of::<u32>() // packet index
     + mem::size_of::<u32>() // shards count
     + mem::size_of::<u32>(); // shards index
 
+const PARITY_SHARD_FLAG: u32 = 1 << 31;
+const MAX_FEC_GROUP_SIZE: usize = 0x7FFF; // 15 bits
+const MAX_FEC_GROUPS_COUNT: usize = 0xFFFF; // 16 bits
+
+// Stream ID reserved for retransmission requests
+const NACK_STREAM_ID: u16 = u16::MAX;
+const NACK_HEADER_SIZE: usize = mem::size_of::<u16>(); // stream ID of the requested packet
+
+/// Counters of packets lost in the receive path of a stream socket
+#[derive(Default, Clone)]
+pub struct StreamReceiveStatistics {
+    /// Packets that were not received because they exceeded the memory budget or because no
+    /// buffer was available
+    pub dropped_packets: usize,
+    /// Incomplete packets discarded because they became stale or to make room for newer ones
+    pub evicted_packets: usize,
+}
+
+/// Called with the serialized header followed by the payload of each packet sent or received by
+/// a stream endpoint. Used for inspecting the traffic, for example for session recordings.
+pub type PacketObserver = Arc<dyn Fn(&[u8]) + Send + Sync>;
+
 /// Memory buffer that contains a hidden prefix
 #[derive(Default)]
 pub struct Buffer<H = ()> {
@@ -84,6 +160,116 @@ impl<H> Buffer<H> {
     }
 }
 
+struct SentPacket {
+    index: u32,
+    buffer: Vec<u8>,
+    data_size: usize, // size of the packet without prefix
+    // Data of each shard that has been overwritten by the prefix of the next shard
+    overwritten_data: Vec<[u8; SHARD_PREFIX_SIZE]>,
+}
+
+struct RetransmitWindow {
+    max_packets: usize,
+    packets: VecDeque<SentPacket>,
+}
+
+impl RetransmitWindow {
+    // An empty list of shard indices means that all shards are requested
+    fn resend(
+        &mut self,
+        socket: &Mutex<Box<dyn SocketWriter>>,
+        max_packet_size: usize,
+        stream_id: u16,
+        packet_index: u32,
+        shard_indices: &[usize],
+    ) -> Result<()> {
+        let Some(packet) = self
+            .packets
+            .iter_mut()
+            .rev()
+            .find(|packet| packet.index == packet_index)
+        else {
+            debug!("Packet {packet_index} of stream {stream_id} is not available for resend");
+            return Ok(());
+        };
+
+        let max_shard_data_size = max_packet_size - SHARD_PREFIX_SIZE;
+        let actual_buffer_size = SHARD_PREFIX_SIZE + packet.data_size;
+        let shards_count = packet.overwritten_data.len();
+
+        let all_shards = (0..shards_count).collect::<Vec<_>>();
+        let shard_indices = if shard_indices.is_empty() {
+            &all_shards
+        } else {
+            shard_indices
+        };
+
+        for &idx in shard_indices.iter().filter(|idx| **idx < shards_count) {
+            let packet_start_position = idx * max_shard_data_size;
+            let packet_length =
+                usize::min(max_packet_size, actual_buffer_size - packet_start_position);
+
+            // Restore the tail of this shard, which contains the prefix of the next shard
+            if idx + 1 < shards_count {
+                packet.buffer[(idx + 1) * max_shard_data_size..][..SHARD_PREFIX_SIZE]
+                    .copy_from_slice(&packet.overwritten_data[idx + 1]);
+            }
+
+            let sub_buffer = &mut packet.buffer[packet_start_position..];
+            sub_buffer[0..4]
+                .copy_from_slice(&((packet_length - mem::size_of::<u32>()) as u32).to_be_bytes());
+            sub_buffer[4..6].copy_from_slice(&stream_id.to_be_bytes());
+            sub_buffer[6..10].copy_from_slice(&packet_index.to_be_bytes());
+            sub_buffer[10..14].copy_from_slice(&(shards_count as u32).to_be_bytes());
+            sub_buffer[14..18].copy_from_slice(&(idx as u32).to_be_bytes());
+
+            socket
+                .lock()
+                .send_shard(stream_id, &sub_buffer[..packet_length])?;
+        }
+
+        Ok(())
+    }
+}
+
+fn send_nack(
+    socket: &Mutex<Box<dyn SocketWriter>>,
+    max_packet_size: usize,
+    stream_id: u16,
+    packet_index: u32,
+    shard_indices: &[usize],
+) -> Result<()> {
+    let max_indices_count =
+        (max_packet_size - SHARD_PREFIX_SIZE - NACK_HEADER_SIZE) / mem::size_of::<u32>();
+
+    let mut buffer = Vec::with_capacity(max_packet_size);
+
+    // A request for the whole packet is sent with no indices
+    let whole_packet_request = shard_indices.is_empty().then_some(&[][..]);
+    for indices in whole_packet_request
+        .into_iter()
+        .chain(shard_indices.chunks(max_indices_count))
+    {
+        let packet_length =
+            SHARD_PREFIX_SIZE + NACK_HEADER_SIZE + indices.len() * mem::size_of::<u32>();
+
+        buffer.clear();
+        buffer.extend_from_slice(&((packet_length - mem::size_of::<u32>()) as u32).to_be_bytes());
+        buffer.extend_from_slice(&NACK_STREAM_ID.to_be_bytes());
+        buffer.extend_from_slice(&packet_index.to_be_bytes());
+        buffer.extend_from_slice(&1_u32.to_be_bytes()); // shards count
+        buffer.extend_from_slice(&0_u32.to_be_bytes()); // shard index
+        buffer.extend_from_slice(&stream_id.to_be_bytes());
+        for idx in indices {
+            buffer.extend_from_slice(&(*idx as u32).to_be_bytes());
+        }
+
+        socket.lock().send_shard(NACK_STREAM_ID, &buffer)?;
+    }
+
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct StreamSender<H> {
     inner: Arc<Mutex<Box<dyn SocketWriter>>>,
@@ -92,10 +278,87 @@ pub struct StreamSender<H> {
     // if the packet index overflows the worst that happens is a false positive packet loss
     next_packet_index: u32,
     used_buffers: Vec<Vec<u8>>,
+    fec_group_size: Option<usize>,
+    parity_buffers: Vec<Vec<u8>>,
+    retransmit_window: Option<Arc<Mutex<RetransmitWindow>>>,
+    observer: Option<PacketObserver>,
     _phantom: PhantomData<H>,
 }
 
 impl<H> StreamSender<H> {
+    pub fn set_observer(&mut self, observer: Option<PacketObserver>) {
+        self.observer = observer;
+    }
+
+    /// Enable or disable sending parity shards for each packet. The overhead percentage is
+    /// converted to the number of data shards covered by each parity shard.
+    pub fn set_forward_error_correction(&mut self, config: Option<ForwardErrorCorrectionConfig>) {
+        self.fec_group_size = config.map(|config| {
+            usize::clamp(
+                100_usize.div_ceil(config.overhead_percentage.max(1) as usize),
+                1,
+                MAX_FEC_GROUP_SIZE,
+            )
+        });
+    }
+
+    // Computes the parity shards for all groups. This must be done before sending the data shards
+    // because sending overwrites the tail of each shard with the prefix of the next one.
+    fn prepare_parity_shards(
+        &mut self,
+        buffer: &[u8],
+        data_size: usize,
+        shards_count: usize,
+        group_size: usize,
+    ) {
+        let max_shard_data_size = self.max_packet_size - SHARD_PREFIX_SIZE;
+        let groups_count = shards_count.div_ceil(group_size);
+
+        if self.parity_buffers.len() < groups_count {
+            self.parity_buffers.resize_with(groups_count, Vec::new);
+        }
+
+        for (group_idx, parity) in self.parity_buffers[..groups_count].iter_mut().enumerate() {
+            let first_shard_idx = group_idx * group_size;
+            let end_shard_idx = usize::min(first_shard_idx + group_size, shards_count);
+
+            // Only the last group can have a smaller parity shard, if it contains only the last
+            // data shard
+            let parity_data_size = usize::min(
+                max_shard_data_size,
+                data_size - first_shard_idx * max_shard_data_size,
+            );
+            let parity_length = SHARD_PREFIX_SIZE + parity_data_size;
+
+            parity.clear();
+            parity.resize(parity_length, 0);
+
+            for shard_idx in first_shard_idx..end_shard_idx {
+                let shard_data_size = usize::min(
+                    max_shard_data_size,
+                    data_size - shard_idx * max_shard_data_size,
+                );
+                let shard_data = &buffer[SHARD_PREFIX_SIZE + shard_idx * max_shard_data_size..]
+                    [..shard_data_size];
+
+                for (parity_byte, data_byte) in
+                    parity[SHARD_PREFIX_SIZE..].iter_mut().zip(shard_data)
+                {
+                    *parity_byte ^= data_byte;
+                }
+            }
+
+            let shard_index = PARITY_SHARD_FLAG | ((group_size as u32) << 16) | group_idx as u32;
+
+            parity[0..4]
+                .copy_from_slice(&((parity_length - mem::size_of::<u32>()) as u32).to_be_bytes());
+            parity[4..6].copy_from_slice(&self.stream_id.to_be_bytes());
+            parity[6..10].copy_from_slice(&self.next_packet_index.to_be_bytes());
+            parity[10..14].copy_from_slice(&(data_size as u32).to_be_bytes());
+            parity[14..18].copy_from_slice(&shard_index.to_be_bytes());
+        }
+    }
+
     /// Shard and send a buffer with zero copies and zero allocations.
     /// The prefix of each shard is written over the previously sent shard to avoid reallocations.
     pub fn send(&mut self, mut buffer: Buffer<H>) -> Result<()> {
@@ -104,6 +367,29 @@ impl<H> StreamSender<H> {
         let data_size = actual_buffer_size - SHARD_PREFIX_SIZE;
         let shards_count = (data_size as f32 / max_shard_data_size as f32).ceil() as usize;
 
+        if let Some(observer) = &self.observer {
+            observer(&buffer.inner[SHARD_PREFIX_SIZE..actual_buffer_size]);
+        }
+
+        let fec_group_size = self
+            .fec_group_size
+            .filter(|group_size| shards_count.div_ceil(*group_size) <= MAX_FEC_GROUPS_COUNT);
+        if let Some(group_size) = fec_group_size {
+            self.prepare_parity_shards(&buffer.inner, data_size, shards_count, group_size);
+        }
+
+        let overwritten_data = if self.retransmit_window.is_some() {
+            (0..shards_count)
+                .map(|idx| {
+                    buffer.inner[idx * max_shard_data_size..][..SHARD_PREFIX_SIZE]
+                        .try_into()
+                        .unwrap()
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
         for idx in 0..shards_count {
             // this overlaps with the previous shard, this is intended behavior and allows to
             // reduce allocations
@@ -125,12 +411,37 @@ impl<H> StreamSender<H> {
             sub_buffer[10..14].copy_from_slice(&(shards_count as u32).to_be_bytes());
             sub_buffer[14..18].copy_from_slice(&(idx as u32).to_be_bytes());
 
-            self.inner.lock().send(&sub_buffer[..packet_length])?;
+            self.inner
+                .lock()
+                .send_shard(self.stream_id, &sub_buffer[..packet_length])?;
+
+            if let Some(group_size) = fec_group_size {
+                if (idx + 1) % group_size == 0 || idx + 1 == shards_count {
+                    self.inner
+                        .lock()
+                        .send_shard(self.stream_id, &self.parity_buffers[idx / group_size])?;
+                }
+            }
         }
 
-        self.next_packet_index += 1;
+        if let Some(window) = &self.retransmit_window {
+            let mut window = window.lock();
+
+            window.packets.push_back(SentPacket {
+                index: self.next_packet_index,
+                buffer: buffer.inner,
+                data_size,
+                overwritten_data,
+            });
+            while window.packets.len() > window.max_packets {
+                let packet = window.packets.pop_front().unwrap();
+                self.used_buffers.push(packet.buffer);
+            }
+        } else {
+            self.used_buffers.push(buffer.inner);
+        }
 
-        self.used_buffers.push(buffer.inner);
+        self.next_packet_index += 1;
 
         Ok(())
     }
@@ -208,9 +519,24 @@ pub struct StreamReceiver<H> {
     packet_receiver: mpsc::Receiver<ReconstructedPacket>,
     used_buffer_queue: mpsc::Sender<Vec<u8>>,
     last_packet_index: Option<u32>,
+    // Retransmitted packets may be reconstructed after newer ones
+    accept_late_packets: bool,
+    observer: Option<PacketObserver>,
     _phantom: PhantomData<H>,
 }
 
+impl<H> StreamReceiver<H> {
+    pub fn set_observer(&mut self, observer: Option<PacketObserver>) {
+        self.observer = observer;
+    }
+
+    fn notify_observer(&self, packet: &ReconstructedPacket) {
+        if let Some(observer) = &self.observer {
+            observer(&packet.buffer[SHARD_PREFIX_SIZE..packet.size]);
+        }
+    }
+}
+
 fn wrapping_cmp(lhs: u32, rhs: u32) -> Ordering {
     let diff = lhs.wrapping_sub(rhs);
     if diff == 0 {
@@ -242,6 +568,17 @@ impl<H: DeserializeOwned + Serialize> StreamReceiver<H> {
                     // Skipped some indices
                     had_packet_loss = true
                 }
+                Ordering::Less if self.accept_late_packets => {
+                    self.notify_observer(&packet);
+
+                    return Ok(ReceiverData {
+                        buffer: Some(packet.buffer),
+                        size: packet.size,
+                        used_buffer_queue: self.used_buffer_queue.clone(),
+                        had_packet_loss,
+                        _phantom: PhantomData,
+                    });
+                }
                 Ordering::Less => {
                     // Old packet, discard
                     self.used_buffer_queue.send(packet.buffer).to_con()?;
@@ -251,6 +588,8 @@ impl<H: DeserializeOwned + Serialize> StreamReceiver<H> {
         }
         self.last_packet_index = Some(packet.index);
 
+        self.notify_observer(&packet);
+
         Ok(ReceiverData {
             buffer: Some(packet.buffer),
             size: packet.size,
@@ -264,6 +603,27 @@ impl<H: DeserializeOwned + Serialize> StreamReceiver<H> {
 pub enum StreamSocketBuilder {
     Tcp(TcpListener),
     Udp(UdpSocket),
+    Quic(quic::QuicListener),
+}
+
+// Returns the size of the shards including the prefix, which must be the same on both peers.
+// max_packet_size is the size of the shards sent on the wire, minus 4. With encryption, each shard
+// is sent as a record which is ENCRYPTION_OVERHEAD bytes bigger, and with QUIC each record must fit
+// in a datagram.
+fn shard_size_for_protocol(max_packet_size: usize, is_quic: bool, is_encrypted: bool) -> usize {
+    // +4 is a workaround to retain compatibilty with old protocol
+    // todo: remove +4
+    let mut record_size = max_packet_size + 4;
+
+    if is_quic {
+        record_size = usize::min(record_size, quic::MAX_DATAGRAM_SIZE);
+    }
+
+    if is_encrypted {
+        record_size - encrypted::ENCRYPTION_OVERHEAD
+    } else {
+        record_size
+    }
 }
 
 impl StreamSocketBuilder {
@@ -289,6 +649,12 @@ impl StreamSocketBuilder {
                 send_buffer_bytes,
                 recv_buffer_bytes,
             )?),
+            SocketProtocol::Quic => StreamSocketBuilder::Quic(quic::bind(
+                port,
+                stream_tos_config,
+                send_buffer_bytes,
+                recv_buffer_bytes,
+            )?),
         })
     }
 
@@ -298,7 +664,16 @@ impl StreamSocketBuilder {
         port: u16,
         max_packet_size: usize,
         timeout: Duration,
+        stream_keys: Option<&CipherKeys>,
+        network_emulation: Option<&NetworkEmulationConfig>,
     ) -> ConResult<StreamSocket> {
+        let is_datagram_socket = !matches!(self, StreamSocketBuilder::Tcp(_));
+        let is_quic = matches!(self, Self::Quic(_));
+        let max_packet_size =
+            shard_size_for_protocol(max_packet_size, is_quic, stream_keys.is_some());
+        let reliable_streams = Arc::new(RwLock::new(HashSet::new()));
+        let mut congestion_monitor = None;
+
         let (send_socket, receive_socket): (Box<dyn SocketWriter>, Box<dyn SocketReader>) =
             match self {
                 StreamSocketBuilder::Udp(socket) => {
@@ -311,19 +686,35 @@ impl StreamSocketBuilder {
                     let (send_socket, receive_socket) =
                         tcp::accept_from_server(&listener, Some(server_ip), timeout)?;
 
+                    (Box::new(send_socket), Box::new(receive_socket))
+                }
+                StreamSocketBuilder::Quic(listener) => {
+                    let (send_socket, receive_socket, monitor) = quic::accept_from_server(
+                        listener,
+                        server_ip,
+                        timeout,
+                        Arc::clone(&reliable_streams),
+                        stream_keys,
+                    )?;
+                    congestion_monitor = Some(monitor);
+
                     (Box::new(send_socket), Box::new(receive_socket))
                 }
             };
 
-        Ok(StreamSocket {
-            // +4 is a workaround to retain compatibilty with old protocol
-            // todo: remove +4
-            max_packet_size: max_packet_size + 4,
-            send_socket: Arc::new(Mutex::new(send_socket)),
+        // The QUIC backend encrypts the shards itself
+        let mut socket = StreamSocket::new(
+            send_socket,
             receive_socket,
-            shard_recv_state: None,
-            stream_recv_components: HashMap::new(),
-        })
+            max_packet_size,
+            is_datagram_socket,
+            stream_keys.filter(|_| !is_quic),
+            network_emulation,
+        );
+        socket.reliable_streams = reliable_streams;
+        socket.congestion_monitor = congestion_monitor;
+
+        Ok(socket)
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -336,7 +727,15 @@ impl StreamSocketBuilder {
         send_buffer_bytes: SocketBufferSize,
         recv_buffer_bytes: SocketBufferSize,
         max_packet_size: usize,
+        stream_keys: Option<&CipherKeys>,
+        network_emulation: Option<&NetworkEmulationConfig>,
     ) -> ConResult<StreamSocket> {
+        let is_quic = matches!(protocol, SocketProtocol::Quic);
+        let max_packet_size =
+            shard_size_for_protocol(max_packet_size, is_quic, stream_keys.is_some());
+        let reliable_streams = Arc::new(RwLock::new(HashSet::new()));
+        let mut congestion_monitor = None;
+
         let (send_socket, receive_socket): (Box<dyn SocketWriter>, Box<dyn SocketReader>) =
             match protocol {
                 SocketProtocol::Udp => {
@@ -356,37 +755,154 @@ impl StreamSocketBuilder {
                         recv_buffer_bytes,
                     )?;
 
+                    (Box::new(send_socket), Box::new(receive_socket))
+                }
+                SocketProtocol::Quic => {
+                    let (send_socket, receive_socket, monitor) = quic::connect_to_client(
+                        timeout,
+                        client_ip,
+                        port,
+                        dscp,
+                        send_buffer_bytes,
+                        recv_buffer_bytes,
+                        Arc::clone(&reliable_streams),
+                        stream_keys,
+                    )?;
+                    congestion_monitor = Some(monitor);
+
                     (Box::new(send_socket), Box::new(receive_socket))
                 }
             };
 
-        Ok(StreamSocket {
-            // +4 is a workaround to retain compatibilty with old protocol
-            // todo: remove +4
-            max_packet_size: max_packet_size + 4,
-            send_socket: Arc::new(Mutex::new(send_socket)),
+        // The QUIC backend encrypts the shards itself
+        let mut socket = StreamSocket::new(
+            send_socket,
             receive_socket,
-            shard_recv_state: None,
-            stream_recv_components: HashMap::new(),
-        })
+            max_packet_size,
+            !matches!(protocol, SocketProtocol::Tcp),
+            stream_keys.filter(|_| !is_quic),
+            network_emulation,
+        );
+        socket.reliable_streams = reliable_streams;
+        socket.congestion_monitor = congestion_monitor;
+
+        Ok(socket)
     }
 }
 
+struct ParityGroup {
+    index: usize,
+    size: usize,
+    data_size: usize, // size of the packet without prefix
+}
+
 struct RecvState {
     shard_length: usize, // contains prefix length itself
     stream_id: u16,
     packet_index: u32,
     shards_count: usize,
     shard_index: usize,
+    parity_group: Option<ParityGroup>,
     packet_cursor: usize, // counts also the prefix bytes
     overwritten_data_backup: Option<[u8; SHARD_PREFIX_SIZE]>,
     should_discard: bool,
 }
 
+struct ParityShard {
+    group_size: usize,
+    buffer: Vec<u8>, // contains prefix
+    length: usize,   // contains prefix
+}
+
 struct InProgressPacket {
     buffer: Vec<u8>,
+    last_shard_instant: Instant,
     buffer_length: usize,
     received_shard_indices: HashSet<usize>,
+    parity_shards: HashMap<usize, ParityShard>,
+    data_size: Option<usize>, // known only if a parity shard has been received
+    shards_count: usize,
+    next_shard_index: usize, // used to detect gaps in the received shards
+}
+
+impl InProgressPacket {
+    fn new(buffer: Vec<u8>, shards_count: usize) -> Self {
+        Self {
+            buffer,
+            last_shard_instant: Instant::now(),
+            buffer_length: 0,
+            // todo: find a way to skipping this allocation
+            received_shard_indices: HashSet::with_capacity(shards_count),
+            parity_shards: HashMap::new(),
+            data_size: None,
+            shards_count,
+            next_shard_index: 0,
+        }
+    }
+
+    // Rebuild the only missing data shard of a group, if the parity shard for the group is
+    // available.
+    fn recover_shard(
+        &mut self,
+        group_index: usize,
+        shards_count: usize,
+        max_shard_data_size: usize,
+    ) {
+        let (Some(parity), Some(data_size)) =
+            (self.parity_shards.get(&group_index), self.data_size)
+        else {
+            return;
+        };
+
+        let first_shard_idx = group_index * parity.group_size;
+        let end_shard_idx = usize::min(first_shard_idx + parity.group_size, shards_count);
+
+        let mut missing_indices = (first_shard_idx..end_shard_idx)
+            .filter(|idx| !self.received_shard_indices.contains(idx));
+        let (Some(missing_idx), None) = (missing_indices.next(), missing_indices.next()) else {
+            return;
+        };
+
+        let shard_data_size =
+            |idx: usize| usize::min(max_shard_data_size, data_size - idx * max_shard_data_size);
+
+        let missing_offset = SHARD_PREFIX_SIZE + missing_idx * max_shard_data_size;
+        let missing_size = shard_data_size(missing_idx);
+        if parity.length < SHARD_PREFIX_SIZE + missing_size {
+            return;
+        }
+
+        self.buffer_length = usize::max(self.buffer_length, missing_offset + missing_size);
+        if self.buffer.len() < self.buffer_length {
+            self.buffer.resize(self.buffer_length, 0);
+        }
+
+        self.buffer[missing_offset..][..missing_size]
+            .copy_from_slice(&parity.buffer[SHARD_PREFIX_SIZE..][..missing_size]);
+        for idx in (first_shard_idx..end_shard_idx).filter(|idx| *idx != missing_idx) {
+            let offset = SHARD_PREFIX_SIZE + idx * max_shard_data_size;
+            for i in 0..usize::min(shard_data_size(idx), missing_size) {
+                let byte = self.buffer[offset + i];
+                self.buffer[missing_offset + i] ^= byte;
+            }
+        }
+
+        self.received_shard_indices.insert(missing_idx);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ReceiveLimits {
+    memory_budget_bytes: usize,
+    stale_packet_timeout: Duration,
+}
+
+// Upper bound of the size of the buffer needed by a packet, including the prefix. The shards count
+// is read from the network, so the size saturates instead of overflowing.
+fn packet_buffer_size(shards_count: usize, max_shard_data_size: usize) -> usize {
+    shards_count
+        .saturating_mul(max_shard_data_size)
+        .saturating_add(SHARD_PREFIX_SIZE)
 }
 
 struct StreamRecvComponents {
@@ -395,19 +911,242 @@ struct StreamRecvComponents {
     packet_queue: mpsc::Sender<ReconstructedPacket>,
     in_progress_packets: HashMap<u32, InProgressPacket>,
     discarded_shards_sink: InProgressPacket,
+    used_parity_buffers: Vec<Vec<u8>>,
+    last_reconstructed_packet_index: Option<u32>,
+    retransmission_window_size: Option<usize>,
+    highest_packet_index: Option<u32>,
+    // Used to discard duplicate shards, which can be received after a retransmission request
+    recently_reconstructed_indices: VecDeque<u32>,
+    receive_limits: Option<ReceiveLimits>,
+    // Used to count each dropped packet only once
+    last_dropped_packet_index: Option<u32>,
+    statistics: Arc<Mutex<StreamReceiveStatistics>>,
 }
 
-// Note: used buffers don't *have* to be split by stream ID, but doing so improves memory usage
-// todo: impose cap on number of created buffers to avoid OOM crashes
+impl StreamRecvComponents {
+    // Called when receiving the first shard of a packet. Requests the missing trailing shards of
+    // older packets and the packets that have been skipped entirely.
+    fn request_lost_packets(
+        &mut self,
+        socket: &Mutex<Box<dyn SocketWriter>>,
+        max_packet_size: usize,
+        stream_id: u16,
+        packet_index: u32,
+        window_size: usize,
+    ) -> Result<()> {
+        for (idx, packet) in &mut self.in_progress_packets {
+            if wrapping_cmp(*idx, packet_index) == Ordering::Less
+                && packet.next_shard_index < packet.shards_count
+            {
+                let missing_indices = (packet.next_shard_index..packet.shards_count)
+                    .filter(|idx| !packet.received_shard_indices.contains(idx))
+                    .collect::<Vec<_>>();
+                packet.next_shard_index = packet.shards_count;
+
+                if !missing_indices.is_empty() {
+                    send_nack(socket, max_packet_size, stream_id, *idx, &missing_indices)?;
+                }
+            }
+        }
+
+        if let Some(highest_idx) = self.highest_packet_index {
+            if wrapping_cmp(packet_index, highest_idx) != Ordering::Greater {
+                return Ok(());
+            }
+
+            let skipped_count = packet_index.wrapping_sub(highest_idx) as usize - 1;
+            for offset in 1..=usize::min(skipped_count, window_size) {
+                let idx = packet_index.wrapping_sub(offset as u32);
+                if !self.in_progress_packets.contains_key(&idx) {
+                    send_nack(socket, max_packet_size, stream_id, idx, &[])?;
+                }
+            }
+        }
+        self.highest_packet_index = Some(packet_index);
+
+        Ok(())
+    }
+
+    fn report_dropped_packet(&mut self, packet_index: u32) {
+        if self.last_dropped_packet_index != Some(packet_index) {
+            self.last_dropped_packet_index = Some(packet_index);
+            self.statistics.lock().dropped_packets += 1;
+        }
+    }
+
+    fn evict_packet(&mut self, packet_index: u32) {
+        if let Some(packet) = self.in_progress_packets.remove(&packet_index) {
+            self.recycle_packet(packet);
+            self.statistics.lock().evicted_packets += 1;
+        }
+    }
+
+    // Called before admitting a new packet. Evicts stale packets, then the oldest packets until the
+    // new one fits in the memory budget. Returns false if the new packet should be dropped.
+    fn make_room_for_packet(&mut self, packet_size: usize, max_shard_data_size: usize) -> bool {
+        let Some(limits) = self.receive_limits else {
+            return true;
+        };
+
+        if packet_size > limits.memory_budget_bytes {
+            return false;
+        }
+
+        let stale_indices = self
+            .in_progress_packets
+            .iter()
+            .filter(|(_, packet)| packet.last_shard_instant.elapsed() > limits.stale_packet_timeout)
+            .map(|(idx, _)| *idx)
+            .collect::<Vec<_>>();
+        for idx in stale_indices {
+            self.evict_packet(idx);
+        }
+
+        loop {
+            let used_size = self
+                .in_progress_packets
+                .values()
+                .map(|packet| packet_buffer_size(packet.shards_count, max_shard_data_size))
+                .sum::<usize>();
+            if used_size + packet_size <= limits.memory_budget_bytes {
+                return true;
+            }
+
+            let Some(oldest_idx) = self
+                .in_progress_packets
+                .keys()
+                .copied()
+                .min_by(|a, b| wrapping_cmp(*a, *b))
+            else {
+                return true;
+            };
+            self.evict_packet(oldest_idx);
+        }
+    }
+
+    fn recycle_packet(&mut self, packet: InProgressPacket) {
+        self.used_parity_buffers.extend(
+            packet
+                .parity_shards
+                .into_values()
+                .map(|parity| parity.buffer),
+        );
+        self.used_buffer_sender.send(packet.buffer).ok();
+    }
+}
+
+// Note: used buffers don't *have* to be split by stream ID, but doing so improves memory usage.
+// The number of buffers is capped by subscribe_to_stream(), their size by set_receive_limits().
 pub struct StreamSocket {
     max_packet_size: usize,
     send_socket: Arc<Mutex<Box<dyn SocketWriter>>>,
     receive_socket: Box<dyn SocketReader>,
     shard_recv_state: Option<RecvState>,
     stream_recv_components: HashMap<u16, StreamRecvComponents>,
+    retransmit_windows: HashMap<u16, Arc<Mutex<RetransmitWindow>>>,
+    receive_limits: HashMap<u16, ReceiveLimits>,
+    receive_statistics: Arc<Mutex<StreamReceiveStatistics>>,
+    reliable_streams: Arc<RwLock<HashSet<u16>>>,
+    congestion_monitor: Option<CongestionMonitor>,
+    nack_buffer: Vec<u8>,
 }
 
 impl StreamSocket {
+    // max_packet_size is the size of the shards, see shard_size_for_protocol()
+    fn new(
+        send_socket: Box<dyn SocketWriter>,
+        receive_socket: Box<dyn SocketReader>,
+        max_packet_size: usize,
+        is_datagram_socket: bool,
+        stream_keys: Option<&CipherKeys>,
+        network_emulation: Option<&NetworkEmulationConfig>,
+    ) -> Self {
+        let send_socket: Box<dyn SocketWriter> = if let Some(config) = network_emulation {
+            Box::new(EmulatedWriter::new(
+                send_socket,
+                config.clone(),
+                is_datagram_socket,
+            ))
+        } else {
+            send_socket
+        };
+
+        let (send_socket, receive_socket): (Box<dyn SocketWriter>, Box<dyn SocketReader>) =
+            if let Some(keys) = stream_keys {
+                (
+                    Box::new(EncryptedWriter::new(send_socket, keys)),
+                    Box::new(EncryptedReader::new(
+                        receive_socket,
+                        keys,
+                        max_packet_size + encrypted::ENCRYPTION_OVERHEAD,
+                        is_datagram_socket,
+                    )),
+                )
+            } else {
+                (send_socket, receive_socket)
+            };
+
+        Self {
+            max_packet_size,
+            send_socket: Arc::new(Mutex::new(send_socket)),
+            receive_socket,
+            shard_recv_state: None,
+            stream_recv_components: HashMap::new(),
+            retransmit_windows: HashMap::new(),
+            receive_limits: HashMap::new(),
+            receive_statistics: Arc::new(Mutex::new(StreamReceiveStatistics::default())),
+            reliable_streams: Arc::new(RwLock::new(HashSet::new())),
+            congestion_monitor: None,
+            nack_buffer: vec![],
+        }
+    }
+
+    // Enable selective retransmission of lost shards for a stream. window_size is the number of
+    // sent packets kept for resending. Must be called with the same parameters on both peers and
+    // before requesting or subscribing to the stream.
+    pub fn enable_retransmission(&mut self, stream_id: u16, window_size: usize) {
+        self.retransmit_windows.insert(
+            stream_id,
+            Arc::new(Mutex::new(RetransmitWindow {
+                max_packets: window_size,
+                packets: VecDeque::new(),
+            })),
+        );
+    }
+
+    // Limit the memory used by the in progress packets of a stream and discard packets that
+    // stopped receiving shards. Must be called before subscribing to the stream.
+    pub fn set_receive_limits(
+        &mut self,
+        stream_id: u16,
+        memory_budget_bytes: usize,
+        stale_packet_timeout: Duration,
+    ) {
+        self.receive_limits.insert(
+            stream_id,
+            ReceiveLimits {
+                memory_budget_bytes,
+                stale_packet_timeout,
+            },
+        );
+    }
+
+    // With QUIC, shards of these streams are delivered reliably and in order. Ignored by the other
+    // protocols. Must be called with the same parameters on both peers.
+    pub fn set_reliable_streams(&mut self, stream_ids: &[u16]) {
+        *self.reliable_streams.write() = stream_ids.iter().copied().collect();
+    }
+
+    // Congestion control state of the transport. Available only with QUIC.
+    pub fn congestion_monitor(&self) -> Option<CongestionMonitor> {
+        self.congestion_monitor.clone()
+    }
+
+    // Counters shared by all streams. The owner can reset them after reading.
+    pub fn receive_statistics(&self) -> Arc<Mutex<StreamReceiveStatistics>> {
+        Arc::clone(&self.receive_statistics)
+    }
+
     pub fn request_stream<T>(&self, stream_id: u16) -> StreamSender<T> {
         StreamSender {
             inner: Arc::clone(&self.send_socket),
@@ -415,6 +1154,10 @@ impl StreamSocket {
             max_packet_size: self.max_packet_size,
             next_packet_index: 0,
             used_buffers: vec![],
+            fec_group_size: None,
+            parity_buffers: vec![],
+            retransmit_window: self.retransmit_windows.get(&stream_id).cloned(),
+            observer: None,
             _phantom: PhantomData,
         }
     }
@@ -434,6 +1177,11 @@ impl StreamSocket {
             used_buffer_sender.send(vec![]).ok();
         }
 
+        let retransmission_window_size = self
+            .retransmit_windows
+            .get(&stream_id)
+            .map(|window| window.lock().max_packets);
+
         self.stream_recv_components.insert(
             stream_id,
             StreamRecvComponents {
@@ -441,11 +1189,15 @@ impl StreamSocket {
                 used_buffer_receiver,
                 packet_queue: packet_sender,
                 in_progress_packets: HashMap::new(),
-                discarded_shards_sink: InProgressPacket {
-                    buffer: vec![],
-                    buffer_length: 0,
-                    received_shard_indices: HashSet::new(),
-                },
+                discarded_shards_sink: InProgressPacket::new(vec![], 0),
+                used_parity_buffers: vec![],
+                last_reconstructed_packet_index: None,
+                retransmission_window_size,
+                highest_packet_index: None,
+                recently_reconstructed_indices: VecDeque::new(),
+                receive_limits: self.receive_limits.get(&stream_id).copied(),
+                last_dropped_packet_index: None,
+                statistics: Arc::clone(&self.receive_statistics),
             },
         );
 
@@ -454,10 +1206,14 @@ impl StreamSocket {
             used_buffer_queue: used_buffer_sender,
             _phantom: PhantomData,
             last_packet_index: None,
+            accept_late_packets: retransmission_window_size.is_some(),
+            observer: None,
         }
     }
 
     pub fn recv(&mut self) -> ConResult {
+        let max_shard_data_size = self.max_packet_size - SHARD_PREFIX_SIZE;
+
         let shard_recv_state_mut = if let Some(state) = &mut self.shard_recv_state {
             state
         } else {
@@ -474,20 +1230,119 @@ impl StreamSocket {
             let stream_id = u16::from_be_bytes(bytes[4..6].try_into().unwrap());
             let packet_index = u32::from_be_bytes(bytes[6..10].try_into().unwrap());
             let shards_count = u32::from_be_bytes(bytes[10..14].try_into().unwrap()) as usize;
-            let shard_index = u32::from_be_bytes(bytes[14..18].try_into().unwrap()) as usize;
+            let shard_index = u32::from_be_bytes(bytes[14..18].try_into().unwrap());
+
+            let (shards_count, shard_index, parity_group) = if shard_index & PARITY_SHARD_FLAG != 0
+            {
+                // For parity shards, the shards count field contains the packet size
+                let data_size = shards_count;
+                (
+                    data_size.div_ceil(max_shard_data_size),
+                    0,
+                    Some(ParityGroup {
+                        index: (shard_index & 0xFFFF) as usize,
+                        size: ((shard_index & !PARITY_SHARD_FLAG) >> 16) as usize,
+                        data_size,
+                    }),
+                )
+            } else {
+                (shards_count, shard_index as usize, None)
+            };
+
+            // Malformed shards are discarded. Otherwise they would be written outside of the
+            // packet buffer, which is not accounted by the memory budget, and the packet could
+            // never be completed
+            let is_malformed = shard_index >= shards_count
+                || self
+                    .stream_recv_components
+                    .get(&stream_id)
+                    .and_then(|components| components.in_progress_packets.get(&packet_index))
+                    .is_some_and(|packet| packet.shards_count != shards_count);
+            if is_malformed {
+                debug!(
+                    "Discarded malformed shard {shard_index} of {shards_count} for packet {packet_index} of stream {stream_id}"
+                );
+            }
+
+            let should_discard = is_malformed
+                || if let Some(components) = self.stream_recv_components.get(&stream_id) {
+                    if parity_group.is_some() {
+                        // Parity shards are sent after the data shards of the group, so they often
+                        // arrive after the packet has already been reconstructed
+                        components
+                            .last_reconstructed_packet_index
+                            .map(|idx| wrapping_cmp(packet_index, idx) != Ordering::Greater)
+                            .unwrap_or(false)
+                    } else {
+                        components
+                            .recently_reconstructed_indices
+                            .contains(&packet_index)
+                    }
+                } else {
+                    false
+                };
 
             self.shard_recv_state.insert(RecvState {
                 shard_length,
                 stream_id,
                 packet_index,
                 shards_count,
-                shard_index,
+                // Discarded shards are always written at the start of the discarded shards sink
+                shard_index: if is_malformed { 0 } else { shard_index },
+                parity_group,
                 packet_cursor: 0,
                 overwritten_data_backup: None,
-                should_discard: false,
+                should_discard,
             })
         };
 
+        if shard_recv_state_mut.stream_id == NACK_STREAM_ID {
+            let shard_length = shard_recv_state_mut.shard_length;
+            if self.nack_buffer.len() < shard_length {
+                self.nack_buffer.resize(shard_length, 0);
+            }
+
+            while shard_recv_state_mut.packet_cursor < shard_length {
+                let size = self.receive_socket.recv(
+                    &mut self.nack_buffer[shard_recv_state_mut.packet_cursor..shard_length],
+                )?;
+                shard_recv_state_mut.packet_cursor += size;
+            }
+
+            let packet_index = shard_recv_state_mut.packet_index;
+            self.shard_recv_state = None;
+
+            if shard_length < SHARD_PREFIX_SIZE + NACK_HEADER_SIZE {
+                return Ok(());
+            }
+
+            let stream_id = u16::from_be_bytes(
+                self.nack_buffer[SHARD_PREFIX_SIZE..][..NACK_HEADER_SIZE]
+                    .try_into()
+                    .unwrap(),
+            );
+            let shard_indices = self.nack_buffer
+                [SHARD_PREFIX_SIZE + NACK_HEADER_SIZE..shard_length]
+                .chunks_exact(mem::size_of::<u32>())
+                .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+                .collect::<Vec<_>>();
+
+            if let Some(window) = self.retransmit_windows.get(&stream_id) {
+                window
+                    .lock()
+                    .resend(
+                        &self.send_socket,
+                        self.max_packet_size,
+                        stream_id,
+                        packet_index,
+                        &shard_indices,
+                    )
+                    .to_con()?;
+            }
+
+            return Ok(());
+        }
+
         let Some(components) = self
             .stream_recv_components
             .get_mut(&shard_recv_state_mut.stream_id)
@@ -499,31 +1354,74 @@ impl StreamSocket {
             return alvr_common::try_again();
         };
 
+        if let (Some(window_size), false) = (
+            components.retransmission_window_size,
+            shard_recv_state_mut.should_discard,
+        ) {
+            if !components
+                .in_progress_packets
+                .contains_key(&shard_recv_state_mut.packet_index)
+            {
+                components
+                    .request_lost_packets(
+                        &self.send_socket,
+                        self.max_packet_size,
+                        shard_recv_state_mut.stream_id,
+                        shard_recv_state_mut.packet_index,
+                        window_size,
+                    )
+                    .to_con()?;
+            }
+        }
+
+        let packet_size =
+            packet_buffer_size(shard_recv_state_mut.shards_count, max_shard_data_size);
+        let is_new_packet = !shard_recv_state_mut.should_discard
+            && !components
+                .in_progress_packets
+                .contains_key(&shard_recv_state_mut.packet_index);
+        if is_new_packet && !components.make_room_for_packet(packet_size, max_shard_data_size) {
+            components.report_dropped_packet(shard_recv_state_mut.packet_index);
+            shard_recv_state_mut.should_discard = true;
+            shard_recv_state_mut.packet_cursor = 0;
+            shard_recv_state_mut.shard_index = 0;
+        }
+
         let in_progress_packet = if shard_recv_state_mut.should_discard {
             &mut components.discarded_shards_sink
         } else if let Some(packet) = components
             .in_progress_packets
             .get_mut(&shard_recv_state_mut.packet_index)
         {
+            packet.last_shard_instant = Instant::now();
+
             packet
-        } else if let Some(buffer) = components.used_buffer_receiver.try_recv().ok().or_else(|| {
-            // By default, try to dequeue a used buffer. In case none were found, recycle one of the
-            // in progress packets, chances are these buffers are "dead" because one of their shards
-            // has been dropped by the network.
-            let idx = *components.in_progress_packets.iter().next()?.0;
-            Some(components.in_progress_packets.remove(&idx).unwrap().buffer)
-        }) {
+        } else if let Some(mut buffer) =
+            components.used_buffer_receiver.try_recv().ok().or_else(|| {
+                // By default, try to dequeue a used buffer. In case none were found, recycle one of
+                // the in progress packets, chances are these buffers are "dead" because one of
+                // their shards has been dropped by the network.
+                let idx = *components.in_progress_packets.iter().next()?.0;
+                let packet = components.in_progress_packets.remove(&idx).unwrap();
+                components.used_parity_buffers.extend(
+                    packet
+                        .parity_shards
+                        .into_values()
+                        .map(|parity| parity.buffer),
+                );
+                components.statistics.lock().evicted_packets += 1;
+                Some(packet.buffer)
+            })
+        {
+            if components.receive_limits.is_some() && buffer.capacity() > 2 * packet_size {
+                buffer.truncate(packet_size);
+                buffer.shrink_to(packet_size);
+            }
+
             // NB: Can't use entry pattern because we want to allow bailing out on the line above
             components.in_progress_packets.insert(
                 shard_recv_state_mut.packet_index,
-                InProgressPacket {
-                    buffer,
-                    buffer_length: 0,
-                    // todo: find a way to skipping this allocation
-                    received_shard_indices: HashSet::with_capacity(
-                        shard_recv_state_mut.shards_count,
-                    ),
-                },
+                InProgressPacket::new(buffer, shard_recv_state_mut.shards_count),
             );
             components
                 .in_progress_packets
@@ -531,6 +1429,7 @@ impl StreamSocket {
                 .unwrap()
         } else {
             // This branch may be hit in case the thread related to the stream hangs for some reason
+            components.report_dropped_packet(shard_recv_state_mut.packet_index);
             shard_recv_state_mut.should_discard = true;
             shard_recv_state_mut.packet_cursor = 0; // reset cursor from old shards
                                                     // always write at the start of the packet so the buffer doesn't grow much
@@ -539,82 +1438,180 @@ impl StreamSocket {
             &mut components.discarded_shards_sink
         };
 
-        let max_shard_data_size = self.max_packet_size - SHARD_PREFIX_SIZE;
-        // Note: there is no prefix offset, since we want to write the prefix too.
-        let packet_start_index = shard_recv_state_mut.shard_index * max_shard_data_size;
-
-        // Prepare buffer to accomodate receiving shard
-        {
-            // Note: this contains the prefix offset
-            in_progress_packet.buffer_length = usize::max(
-                in_progress_packet.buffer_length,
-                packet_start_index + shard_recv_state_mut.shard_length,
-            );
-
-            if in_progress_packet.buffer.len() < in_progress_packet.buffer_length {
-                in_progress_packet
-                    .buffer
-                    .resize(in_progress_packet.buffer_length, 0);
+        if let (Some(group), false) = (
+            &shard_recv_state_mut.parity_group,
+            shard_recv_state_mut.should_discard,
+        ) {
+            // Read parity shard into its own buffer. The prefix is kept, so no backup is needed.
+            if !in_progress_packet.parity_shards.contains_key(&group.index) {
+                in_progress_packet.parity_shards.insert(
+                    group.index,
+                    ParityShard {
+                        group_size: group.size,
+                        buffer: components.used_parity_buffers.pop().unwrap_or_default(),
+                        length: 0,
+                    },
+                );
             }
-        }
-
-        let sub_buffer = &mut in_progress_packet.buffer[packet_start_index..];
+            let parity = in_progress_packet
+                .parity_shards
+                .get_mut(&group.index)
+                .unwrap();
 
-        // Read shard into the single contiguous buffer
-        {
-            // Backup the small section of bytes that will be overwritten by reading from socket.
-            if shard_recv_state_mut.overwritten_data_backup.is_none() {
-                shard_recv_state_mut.overwritten_data_backup =
-                    Some(sub_buffer[..SHARD_PREFIX_SIZE].try_into().unwrap())
+            if parity.buffer.len() < shard_recv_state_mut.shard_length {
+                parity.buffer.resize(shard_recv_state_mut.shard_length, 0);
             }
 
-            // This loop may bail out at any time if a timeout is reached. This is correctly handled by
-            // the previous code.
             while shard_recv_state_mut.packet_cursor < shard_recv_state_mut.shard_length {
                 let size = self.receive_socket.recv(
-                    &mut sub_buffer
+                    &mut parity.buffer
                         [shard_recv_state_mut.packet_cursor..shard_recv_state_mut.shard_length],
                 )?;
                 shard_recv_state_mut.packet_cursor += size;
             }
+            parity.length = shard_recv_state_mut.shard_length;
 
-            // Restore backed up bytes
-            // Safety: overwritten_data_backup is always set just before receiving the packet
-            sub_buffer[..SHARD_PREFIX_SIZE]
-                .copy_from_slice(&shard_recv_state_mut.overwritten_data_backup.take().unwrap());
-        }
+            in_progress_packet.data_size = Some(group.data_size);
+            in_progress_packet.recover_shard(
+                group.index,
+                shard_recv_state_mut.shards_count,
+                max_shard_data_size,
+            );
+        } else {
+            // Note: there is no prefix offset, since we want to write the prefix too.
+            let packet_start_index = shard_recv_state_mut.shard_index * max_shard_data_size;
+
+            // Prepare buffer to accomodate receiving shard
+            {
+                // Note: this contains the prefix offset
+                in_progress_packet.buffer_length = usize::max(
+                    in_progress_packet.buffer_length,
+                    packet_start_index + shard_recv_state_mut.shard_length,
+                );
+
+                if in_progress_packet.buffer.len() < in_progress_packet.buffer_length {
+                    in_progress_packet
+                        .buffer
+                        .resize(in_progress_packet.buffer_length, 0);
+                }
+            }
+
+            let sub_buffer = &mut in_progress_packet.buffer[packet_start_index..];
+
+            // Read shard into the single contiguous buffer
+            {
+                // Backup the small section of bytes that will be overwritten by reading from socket.
+                if shard_recv_state_mut.overwritten_data_backup.is_none() {
+                    shard_recv_state_mut.overwritten_data_backup =
+                        Some(sub_buffer[..SHARD_PREFIX_SIZE].try_into().unwrap())
+                }
 
-        if !shard_recv_state_mut.should_discard {
-            in_progress_packet
-                .received_shard_indices
-                .insert(shard_recv_state_mut.shard_index);
+                // This loop may bail out at any time if a timeout is reached. This is correctly handled by
+                // the previous code.
+                while shard_recv_state_mut.packet_cursor < shard_recv_state_mut.shard_length {
+                    let size = self.receive_socket.recv(
+                        &mut sub_buffer
+                            [shard_recv_state_mut.packet_cursor..shard_recv_state_mut.shard_length],
+                    )?;
+                    shard_recv_state_mut.packet_cursor += size;
+                }
+
+                // Restore backed up bytes
+                // Safety: overwritten_data_backup is always set just before receiving the packet
+                sub_buffer[..SHARD_PREFIX_SIZE]
+                    .copy_from_slice(&shard_recv_state_mut.overwritten_data_backup.take().unwrap());
+            }
+
+            if !shard_recv_state_mut.should_discard {
+                in_progress_packet
+                    .received_shard_indices
+                    .insert(shard_recv_state_mut.shard_index);
+
+                if components.retransmission_window_size.is_some() {
+                    let shard_index = shard_recv_state_mut.shard_index;
+                    if shard_index > in_progress_packet.next_shard_index {
+                        let missing_indices = (in_progress_packet.next_shard_index..shard_index)
+                            .filter(|idx| !in_progress_packet.received_shard_indices.contains(idx))
+                            .collect::<Vec<_>>();
+
+                        send_nack(
+                            &self.send_socket,
+                            self.max_packet_size,
+                            shard_recv_state_mut.stream_id,
+                            shard_recv_state_mut.packet_index,
+                            &missing_indices,
+                        )
+                        .to_con()?;
+                    }
+                    in_progress_packet.next_shard_index =
+                        usize::max(in_progress_packet.next_shard_index, shard_index + 1);
+                }
+
+                // If a parity shard was already received, try to recover a lost shard in the same
+                // group
+                if let Some(group_size) = in_progress_packet
+                    .parity_shards
+                    .values()
+                    .next()
+                    .map(|parity| parity.group_size)
+                    .filter(|group_size| *group_size > 0)
+                {
+                    in_progress_packet.recover_shard(
+                        shard_recv_state_mut.shard_index / group_size,
+                        shard_recv_state_mut.shards_count,
+                        max_shard_data_size,
+                    );
+                }
+            }
         }
 
         // Check if packet is complete and send
-        if in_progress_packet.received_shard_indices.len() == shard_recv_state_mut.shards_count {
+        if !shard_recv_state_mut.should_discard
+            && in_progress_packet.received_shard_indices.len() == shard_recv_state_mut.shards_count
+        {
             let size = in_progress_packet.buffer_length;
+            let packet = components
+                .in_progress_packets
+                .remove(&shard_recv_state_mut.packet_index)
+                .unwrap();
+            components.used_parity_buffers.extend(
+                packet
+                    .parity_shards
+                    .into_values()
+                    .map(|parity| parity.buffer),
+            );
             components
                 .packet_queue
                 .send(ReconstructedPacket {
                     index: shard_recv_state_mut.packet_index,
-                    buffer: components
-                        .in_progress_packets
-                        .remove(&shard_recv_state_mut.packet_index)
-                        .unwrap()
-                        .buffer,
+                    buffer: packet.buffer,
                     size,
                 })
                 .ok();
+            components.last_reconstructed_packet_index = Some(shard_recv_state_mut.packet_index);
+
+            if let Some(window_size) = components.retransmission_window_size {
+                components
+                    .recently_reconstructed_indices
+                    .push_back(shard_recv_state_mut.packet_index);
+                if components.recently_reconstructed_indices.len() > window_size {
+                    components.recently_reconstructed_indices.pop_front();
+                }
+            }
 
-            // Keep only shards with later packet index (using wrapping logic)
+            // Keep only shards with later packet index (using wrapping logic). If retransmission
+            // is enabled, also keep the packets that can still be completed by resent shards.
+            let kept_packets_count = components.retransmission_window_size.unwrap_or(0) as u32;
             while let Some((idx, _)) = components.in_progress_packets.iter().find(|(idx, _)| {
-                wrapping_cmp(**idx, shard_recv_state_mut.packet_index) == Ordering::Less
+                wrapping_cmp(
+                    idx.wrapping_add(kept_packets_count),
+                    shard_recv_state_mut.packet_index,
+                ) == Ordering::Less
             }) {
                 let idx = *idx; // fix borrow rule
-                let packet = components.in_progress_packets.remove(&idx).unwrap();
 
                 // Recycle buffer
-                components.used_buffer_sender.send(packet.buffer).ok();
+                components.evict_packet(idx);
             }
         }
 
@@ -624,3 +1621,469 @@ impl StreamSocket {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alvr_common::ConnectionError;
+    use std::thread;
+
+    const STREAM_ID: u16 = 1;
+    // 10 bytes of data per shard
+    const MAX_PACKET_SIZE: usize = SHARD_PREFIX_SIZE + 10;
+
+    // Datagram socket that delivers the shards in order, unless a test removes them first
+    #[derive(Clone, Default)]
+    struct MemoryChannel(Arc<Mutex<VecDeque<Vec<u8>>>>);
+
+    impl MemoryChannel {
+        fn is_empty(&self) -> bool {
+            self.0.lock().is_empty()
+        }
+
+        fn push(&self, shards: Vec<Vec<u8>>) {
+            self.0.lock().extend(shards);
+        }
+
+        // Removes the data shards with the given indices from the queue and returns them
+        fn take_data_shards(&self, shard_indices: &[usize]) -> Vec<Vec<u8>> {
+            let mut shards = self.0.lock();
+            let (taken, kept): (VecDeque<_>, _) = shards.drain(..).partition(|shard| {
+                let shard_index = u32::from_be_bytes(shard[14..18].try_into().unwrap());
+                shard_index & PARITY_SHARD_FLAG == 0
+                    && shard_indices.contains(&(shard_index as usize))
+            });
+            *shards = kept;
+
+            taken.into()
+        }
+
+        fn drop_data_shards(&self, shard_indices: &[usize]) {
+            self.take_data_shards(shard_indices);
+        }
+    }
+
+    impl SocketWriter for MemoryChannel {
+        fn send(&mut self, buffer: &[u8]) -> Result<()> {
+            self.0.lock().push_back(buffer.to_vec());
+
+            Ok(())
+        }
+    }
+
+    impl SocketReader for MemoryChannel {
+        fn recv(&mut self, buffer: &mut [u8]) -> ConResult<usize> {
+            let size = self.peek(buffer)?;
+            self.0.lock().pop_front();
+
+            Ok(size)
+        }
+
+        fn peek(&mut self, buffer: &mut [u8]) -> ConResult<usize> {
+            let shards = self.0.lock();
+            let Some(shard) = shards.front() else {
+                return alvr_common::try_again();
+            };
+
+            let size = usize::min(buffer.len(), shard.len());
+            buffer[..size].copy_from_slice(&shard[..size]);
+
+            Ok(size)
+        }
+    }
+
+    struct SocketPair {
+        sender: StreamSocket,
+        receiver: StreamSocket,
+        // Carries the shards from the sender to the receiver
+        forward: MemoryChannel,
+        // Carries the NACKs from the receiver to the sender
+        backward: MemoryChannel,
+    }
+
+    impl SocketPair {
+        fn new() -> Self {
+            let forward = MemoryChannel::default();
+            let backward = MemoryChannel::default();
+
+            let socket = |writer: &MemoryChannel, reader: &MemoryChannel| {
+                StreamSocket::new(
+                    Box::new(writer.clone()),
+                    Box::new(reader.clone()),
+                    MAX_PACKET_SIZE,
+                    true,
+                    None,
+                    None,
+                )
+            };
+
+            Self {
+                sender: socket(&forward, &backward),
+                receiver: socket(&backward, &forward),
+                forward,
+                backward,
+            }
+        }
+
+        fn with_retransmission(
+            window_size: usize,
+        ) -> (Self, StreamSender<u32>, StreamReceiver<u32>) {
+            let mut sockets = Self::new();
+            sockets.sender.enable_retransmission(STREAM_ID, window_size);
+            sockets
+                .receiver
+                .enable_retransmission(STREAM_ID, window_size);
+
+            let sender = sockets.sender.request_stream(STREAM_ID);
+            let receiver = sockets.receiver.subscribe_to_stream(STREAM_ID, 8);
+
+            (sockets, sender, receiver)
+        }
+
+        fn deliver_shards(&mut self) {
+            receive_all(&mut self.receiver, &self.forward);
+        }
+
+        fn deliver_nacks(&mut self) {
+            receive_all(&mut self.sender, &self.backward);
+        }
+
+        // Returns the packet index and the requested shard indices of the NACKs not yet delivered
+        fn pending_nacks(&self) -> Vec<(u32, Vec<usize>)> {
+            self.backward
+                .0
+                .lock()
+                .iter()
+                .map(|nack| {
+                    assert_eq!(
+                        u16::from_be_bytes(nack[4..6].try_into().unwrap()),
+                        NACK_STREAM_ID
+                    );
+                    assert_eq!(
+                        u16::from_be_bytes(nack[SHARD_PREFIX_SIZE..][..2].try_into().unwrap()),
+                        STREAM_ID
+                    );
+
+                    let packet_index = u32::from_be_bytes(nack[6..10].try_into().unwrap());
+                    let shard_indices = nack[SHARD_PREFIX_SIZE + NACK_HEADER_SIZE..]
+                        .chunks_exact(mem::size_of::<u32>())
+                        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+                        .collect();
+
+                    (packet_index, shard_indices)
+                })
+                .collect()
+        }
+    }
+
+    fn receive_all(socket: &mut StreamSocket, channel: &MemoryChannel) {
+        while !channel.is_empty() {
+            match socket.recv() {
+                Ok(()) | Err(ConnectionError::TryAgain(_)) => (),
+                Err(ConnectionError::Other(e)) => panic!("{e}"),
+            }
+        }
+    }
+
+    // The header takes 4 bytes of the packet
+    fn test_payload(packet_index: u32, data_size: usize) -> Vec<u8> {
+        (0..data_size - 4)
+            .map(|i| (i as u8).wrapping_mul(31).wrapping_add(packet_index as u8))
+            .collect()
+    }
+
+    fn send_packet(sender: &mut StreamSender<u32>, packet_index: u32, payload: &[u8]) {
+        let mut buffer = sender.get_buffer(&packet_index).unwrap();
+        buffer
+            .get_range_mut(0, payload.len())
+            .copy_from_slice(payload);
+        sender.send(buffer).unwrap();
+    }
+
+    fn recv_packet(receiver: &mut StreamReceiver<u32>) -> Option<(u32, Vec<u8>)> {
+        let data = receiver.recv(Duration::ZERO).ok()?;
+        let (header, payload) = data.get().unwrap();
+
+        Some((header, payload.to_vec()))
+    }
+
+    // Returns the index of the next delivered packet and whether it was marked as having packet
+    // loss
+    fn recv_packet_index(receiver: &mut StreamReceiver<u32>) -> Option<(u32, bool)> {
+        let data = receiver.recv(Duration::ZERO).ok()?;
+
+        Some((data.get_header().unwrap(), data.had_packet_loss()))
+    }
+
+    // Sends a single packet with FEC, drops some of its data shards and returns whether the packet
+    // could be rebuilt
+    fn send_with_fec(overhead_percentage: u32, data_size: usize, lost_shards: &[usize]) -> bool {
+        let mut sockets = SocketPair::new();
+        let mut sender = sockets.sender.request_stream::<u32>(STREAM_ID);
+        sender.set_forward_error_correction(Some(ForwardErrorCorrectionConfig {
+            overhead_percentage,
+        }));
+        let mut receiver = sockets.receiver.subscribe_to_stream::<u32>(STREAM_ID, 4);
+
+        let payload = test_payload(0, data_size);
+        send_packet(&mut sender, 0, &payload);
+        sockets.forward.drop_data_shards(lost_shards);
+        sockets.deliver_shards();
+
+        let Some((header, received_payload)) = recv_packet(&mut receiver) else {
+            return false;
+        };
+        assert_eq!(header, 0);
+        assert_eq!(received_payload, payload);
+
+        true
+    }
+
+    #[test]
+    fn fec_recovers_one_lost_shard_per_group() {
+        // Groups of 2 shards: {0, 1}, {2, 3}, {4, 5}, {6}
+        assert!(send_with_fec(50, 65, &[]));
+        assert!(send_with_fec(50, 65, &[1, 2, 5, 6]));
+        assert!(send_with_fec(50, 65, &[0, 3, 4]));
+    }
+
+    #[test]
+    fn fec_does_not_recover_two_lost_shards_of_a_group() {
+        assert!(!send_with_fec(50, 65, &[2, 3]));
+        assert!(!send_with_fec(50, 65, &[0, 1, 6]));
+    }
+
+    #[test]
+    fn fec_recovers_shards_of_the_last_partial_group() {
+        // Groups of 3 shards: {0, 1, 2}, {3, 4, 5}, {6, 7}. The last shard has 5 bytes of data.
+        assert!(send_with_fec(34, 75, &[6]));
+        assert!(send_with_fec(34, 75, &[7]));
+        assert!(send_with_fec(34, 75, &[2, 7]));
+        assert!(!send_with_fec(34, 75, &[6, 7]));
+
+        // A last group made of only the last shard, which is smaller than the others
+        assert!(send_with_fec(34, 65, &[6]));
+    }
+
+    #[test]
+    fn quic_records_fit_in_datagrams() {
+        for is_encrypted in [false, true] {
+            let shard_size = shard_size_for_protocol(1400, true, is_encrypted);
+            let record_size = if is_encrypted {
+                shard_size + encrypted::ENCRYPTION_OVERHEAD
+            } else {
+                shard_size
+            };
+
+            assert_eq!(record_size, quic::MAX_DATAGRAM_SIZE);
+        }
+
+        assert_eq!(shard_size_for_protocol(1400, false, false), 1404);
+        assert_eq!(
+            shard_size_for_protocol(1400, false, true),
+            1404 - encrypted::ENCRYPTION_OVERHEAD
+        );
+    }
+
+    #[test]
+    fn nacks_request_the_missing_shards() {
+        // A gap in the shard indices is requested as soon as it's detected
+        let (mut sockets, mut sender, _receiver) = SocketPair::with_retransmission(4);
+        send_packet(&mut sender, 0, &test_payload(0, 65));
+        sockets.forward.drop_data_shards(&[2, 4]);
+        sockets.deliver_shards();
+        assert_eq!(sockets.pending_nacks(), [(0, vec![2]), (0, vec![4])]);
+
+        // The missing trailing shards are requested when the next packet starts
+        let (mut sockets, mut sender, _receiver) = SocketPair::with_retransmission(4);
+        send_packet(&mut sender, 0, &test_payload(0, 65));
+        sockets.forward.drop_data_shards(&[5, 6]);
+        sockets.deliver_shards();
+        assert!(sockets.pending_nacks().is_empty());
+        send_packet(&mut sender, 1, &test_payload(1, 65));
+        sockets.deliver_shards();
+        assert_eq!(sockets.pending_nacks(), [(0, vec![5, 6])]);
+
+        // A packet skipped entirely is requested whole, with an empty list
+        let (mut sockets, mut sender, _receiver) = SocketPair::with_retransmission(4);
+        send_packet(&mut sender, 0, &test_payload(0, 65));
+        sockets.deliver_shards();
+        send_packet(&mut sender, 1, &test_payload(1, 65));
+        sockets.forward.drop_data_shards(&[0, 1, 2, 3, 4, 5, 6]);
+        send_packet(&mut sender, 2, &test_payload(2, 65));
+        sockets.deliver_shards();
+        assert_eq!(sockets.pending_nacks(), [(1, vec![])]);
+    }
+
+    #[test]
+    fn resent_shards_complete_the_packet() {
+        let (mut sockets, mut sender, mut receiver) = SocketPair::with_retransmission(4);
+
+        let payload = test_payload(0, 65);
+        send_packet(&mut sender, 0, &payload);
+        sockets.forward.drop_data_shards(&[2, 6]);
+        sockets.deliver_shards();
+        assert!(recv_packet(&mut receiver).is_none());
+
+        // Only the requested shards are resent
+        sockets.deliver_nacks();
+        assert_eq!(sockets.forward.0.lock().len(), 1);
+        sockets.deliver_shards();
+        assert!(recv_packet(&mut receiver).is_none());
+
+        send_packet(&mut sender, 1, &test_payload(1, 65));
+        sockets.deliver_shards();
+        sockets.deliver_nacks();
+        sockets.deliver_shards();
+
+        assert_eq!(recv_packet(&mut receiver), Some((1, test_payload(1, 65))));
+        assert_eq!(recv_packet(&mut receiver), Some((0, payload)));
+    }
+
+    #[test]
+    fn nacks_for_packets_out_of_the_window_are_ignored() {
+        let (mut sockets, mut sender, mut receiver) = SocketPair::with_retransmission(2);
+
+        send_packet(&mut sender, 0, &test_payload(0, 65));
+        sockets.forward.drop_data_shards(&[2]);
+        sockets.deliver_shards();
+
+        // The buffer of packet 0 is reused by packet 3 while the NACK is in flight
+        for packet_index in 1..4 {
+            send_packet(&mut sender, packet_index, &test_payload(packet_index, 65));
+            sockets.deliver_shards();
+        }
+
+        assert_eq!(sockets.pending_nacks(), [(0, vec![2])]);
+        sockets.deliver_nacks();
+        assert!(sockets.forward.is_empty());
+
+        for packet_index in 1..4 {
+            assert_eq!(
+                recv_packet(&mut receiver),
+                Some((packet_index, test_payload(packet_index, 65)))
+            );
+        }
+        assert!(recv_packet(&mut receiver).is_none());
+    }
+
+    #[test]
+    fn late_packets_are_delivered_after_newer_ones() {
+        let (mut sockets, mut sender, mut receiver) = SocketPair::with_retransmission(4);
+
+        send_packet(&mut sender, 0, &test_payload(0, 15));
+        sockets.deliver_shards();
+        send_packet(&mut sender, 1, &test_payload(1, 15));
+        sockets.forward.drop_data_shards(&[0, 1]);
+        send_packet(&mut sender, 2, &test_payload(2, 15));
+        sockets.deliver_shards();
+
+        assert_eq!(recv_packet_index(&mut receiver), Some((0, false)));
+        assert_eq!(recv_packet_index(&mut receiver), Some((2, true)));
+        assert!(recv_packet_index(&mut receiver).is_none());
+
+        // The whole packet is resent and delivered late, without marking a loss
+        sockets.deliver_nacks();
+        sockets.deliver_shards();
+        assert_eq!(recv_packet_index(&mut receiver), Some((1, false)));
+
+        // The late packet doesn't make the following packets look lost
+        send_packet(&mut sender, 3, &test_payload(3, 15));
+        sockets.deliver_shards();
+        assert_eq!(recv_packet_index(&mut receiver), Some((3, false)));
+    }
+
+    fn limited_pair(
+        memory_budget_bytes: usize,
+        stale_packet_timeout: Duration,
+    ) -> (SocketPair, StreamSender<u32>, StreamReceiver<u32>) {
+        let mut sockets = SocketPair::new();
+        sockets
+            .receiver
+            .set_receive_limits(STREAM_ID, memory_budget_bytes, stale_packet_timeout);
+
+        let sender = sockets.sender.request_stream(STREAM_ID);
+        let receiver = sockets.receiver.subscribe_to_stream(STREAM_ID, 8);
+
+        (sockets, sender, receiver)
+    }
+
+    #[test]
+    fn receive_limits_evict_the_oldest_packets() {
+        // Room for two packets of 7 shards
+        let (mut sockets, mut sender, mut receiver) =
+            limited_pair(2 * packet_buffer_size(7, 10), Duration::from_secs(10));
+        let statistics = sockets.receiver.receive_statistics();
+
+        let mut held_shards = vec![];
+        for packet_index in 0..3 {
+            send_packet(&mut sender, packet_index, &test_payload(packet_index, 65));
+            held_shards.push(sockets.forward.take_data_shards(&[6]));
+            sockets.deliver_shards();
+        }
+        assert_eq!(statistics.lock().evicted_packets, 1);
+        assert_eq!(statistics.lock().dropped_packets, 0);
+
+        // Packet 0 has been evicted, the newer packets can still be completed
+        for shards in held_shards.drain(1..) {
+            sockets.forward.push(shards);
+        }
+        sockets.deliver_shards();
+
+        assert_eq!(recv_packet(&mut receiver), Some((1, test_payload(1, 65))));
+        assert_eq!(recv_packet(&mut receiver), Some((2, test_payload(2, 65))));
+        assert!(recv_packet(&mut receiver).is_none());
+        assert_eq!(statistics.lock().evicted_packets, 1);
+    }
+
+    #[test]
+    fn receive_limits_evict_stale_packets() {
+        let (mut sockets, mut sender, mut receiver) =
+            limited_pair(10 * packet_buffer_size(7, 10), Duration::ZERO);
+        let statistics = sockets.receiver.receive_statistics();
+
+        send_packet(&mut sender, 0, &test_payload(0, 65));
+        sockets.forward.drop_data_shards(&[6]);
+        sockets.deliver_shards();
+
+        thread::sleep(Duration::from_millis(1));
+
+        // The packet in progress is evicted even if there is room for it
+        send_packet(&mut sender, 1, &test_payload(1, 65));
+        sockets.deliver_shards();
+        assert_eq!(statistics.lock().evicted_packets, 1);
+
+        assert_eq!(recv_packet(&mut receiver), Some((1, test_payload(1, 65))));
+    }
+
+    #[test]
+    fn receive_limits_drop_packets_over_the_budget() {
+        let (mut sockets, mut sender, mut receiver) =
+            limited_pair(packet_buffer_size(7, 10), Duration::from_secs(10));
+        let statistics = sockets.receiver.receive_statistics();
+
+        // A packet of 20 shards is counted as dropped once
+        send_packet(&mut sender, 0, &test_payload(0, 200));
+        sockets.deliver_shards();
+        assert_eq!(statistics.lock().dropped_packets, 1);
+
+        // A forged shard with an oversized shards count is dropped without allocating the packet
+        let mut shard = vec![0; MAX_PACKET_SIZE];
+        shard[0..4]
+            .copy_from_slice(&((MAX_PACKET_SIZE - mem::size_of::<u32>()) as u32).to_be_bytes());
+        shard[4..6].copy_from_slice(&STREAM_ID.to_be_bytes());
+        shard[6..10].copy_from_slice(&1_u32.to_be_bytes());
+        shard[10..14].copy_from_slice(&u32::MAX.to_be_bytes());
+        shard[14..18].copy_from_slice(&(u32::MAX - 1).to_be_bytes());
+        sockets.forward.push(vec![shard]);
+        sockets.deliver_shards();
+        assert_eq!(statistics.lock().dropped_packets, 2);
+
+        send_packet(&mut sender, 2, &test_payload(2, 65));
+        sockets.deliver_shards();
+
+        assert_eq!(recv_packet(&mut receiver), Some((2, test_payload(2, 65))));
+        assert!(recv_packet(&mut receiver).is_none());
+        assert_eq!(statistics.lock().evicted_packets, 0);
+    }
+}